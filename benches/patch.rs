@@ -0,0 +1,107 @@
+#![feature(test)]
+extern crate test;
+
+use gridiron::patch::Patch;
+
+const NI: i64 = 200;
+const NJ: i64 = 200;
+const NUM_FIELDS: usize = 4;
+
+
+
+
+// ============================================================================
+fn patch() -> Patch {
+    Patch::from_slice_function(0, (0..NI, 0..NJ), NUM_FIELDS, |(i, j), p| {
+        for (n, x) in p.iter_mut().enumerate() {
+            *x = (i * NJ + j) as f64 + n as f64
+        }
+    })
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn i_sweep_with_strided_traversal(b: &mut test::Bencher) {
+
+    let patch = patch();
+    let data = patch.data();
+    let nj = NJ as usize;
+
+    b.iter(|| {
+        let mut total = 0.0;
+        for j in 0..nj {
+            for i in 1..NI as usize {
+                let l = ((i - 1) * nj + j) * NUM_FIELDS;
+                let r = (i * nj + j) * NUM_FIELDS;
+                for n in 0..NUM_FIELDS {
+                    total += data[r + n] - data[l + n]
+                }
+            }
+        }
+        test::black_box(total)
+    });
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn i_sweep_with_transpose(b: &mut test::Bencher) {
+
+    let patch = patch();
+
+    b.iter(|| {
+        let transpose = patch.transpose();
+        let mut total = 0.0;
+        for row in transpose.data().chunks_exact(NI as usize * NUM_FIELDS) {
+            for (l, r) in row.chunks_exact(NUM_FIELDS).zip(row.chunks_exact(NUM_FIELDS).skip(1)) {
+                for n in 0..NUM_FIELDS {
+                    total += r[n] - l[n]
+                }
+            }
+        }
+        test::black_box(total)
+    });
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn map_into_with_dynamic_field_count(b: &mut test::Bencher) {
+
+    let source = patch();
+    let mut target = patch();
+
+    b.iter(|| {
+        source.map_into(&mut target, |a, b| {
+            for n in 0..NUM_FIELDS {
+                b[n] = 2.0 * a[n]
+            }
+        })
+    });
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn map_into_with_fixed_field_count(b: &mut test::Bencher) {
+
+    let source = patch();
+    let mut target = patch();
+
+    b.iter(|| {
+        source.map_into_fixed::<_, NUM_FIELDS>(&mut target, |a, b| {
+            for n in 0..NUM_FIELDS {
+                b[n] = 2.0 * a[n]
+            }
+        })
+    });
+}
@@ -0,0 +1,56 @@
+#![feature(test)]
+extern crate test;
+
+use gridiron::hydro::euler2d::{self, Primitive};
+use gridiron::hydro::geometry::Direction;
+
+const NUM_FACES: usize = 4096;
+const GAMMA_LAW_INDEX: f64 = 5.0 / 3.0;
+
+
+
+
+// ============================================================================
+fn states() -> (Vec<f64>, Vec<f64>) {
+    let state = |n: usize| {
+        let x = n as f64 / NUM_FACES as f64;
+        Primitive::new(1.0 + x, 0.1 * x, -0.2 * x, 1.0 - 0.5 * x).as_array()
+    };
+    let pl = (0..NUM_FACES).flat_map(state).collect();
+    let pr = (1..NUM_FACES + 1).flat_map(state).collect();
+    (pl, pr)
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn riemann_hlle_per_face(b: &mut test::Bencher) {
+
+    let (pl, pr) = states();
+    let mut flux = vec![0.0; NUM_FACES * 4];
+
+    b.iter(|| {
+        for (f, (pl, pr)) in flux.chunks_exact_mut(4).zip(pl.chunks_exact(4).zip(pr.chunks_exact(4))) {
+            euler2d::riemann_hlle(pl.into(), pr.into(), Direction::I, GAMMA_LAW_INDEX).write_to_slice(f)
+        }
+        test::black_box(&flux);
+    });
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn riemann_hlle_row(b: &mut test::Bencher) {
+
+    let (pl, pr) = states();
+    let mut flux = vec![0.0; NUM_FACES * 4];
+
+    b.iter(|| {
+        euler2d::riemann_hlle_row(&pl, &pr, &mut flux, Direction::I, GAMMA_LAW_INDEX);
+        test::black_box(&flux);
+    });
+}
@@ -1,10 +1,10 @@
-use clap::{AppSettings, Clap};
-use gridiron::automaton;
+use gridiron::appkit::{Args, Executor, RunOptions};
 use gridiron::hydro::euler2d::Primitive;
 use gridiron::index_space::range2d;
 use gridiron::meshing::GraphTopology;
 use gridiron::patch::Patch;
 use gridiron::rect_map::RectangleMap;
+use gridiron::solvers::autotune::{tune_block_size, TuneOptions};
 use gridiron::solvers::euler2d_pcm::{Mesh, PatchUpdate};
 
 /// The initial model
@@ -54,38 +54,25 @@ impl State {
     }
 }
 
-#[derive(Debug, Clap)]
-#[clap(version = "1.0", author = "J. Zrake <jzrake@clemson.edu>")]
-#[clap(setting = AppSettings::ColoredHelp)]
-struct Opts {
-    #[clap(short = 't', long, default_value = "1")]
-    num_threads: usize,
-
-    #[clap(short = 's', long, default_value = "serial", about = "serial|stupid|rayon")]
-    strategy: String,
-
-    #[clap(short = 'n', long, default_value = "1000")]
-    grid_resolution: usize,
-
-    #[clap(short = 'b', long, default_value = "100")]
-    block_size: usize,
-
-    #[clap(short = 'f', long, default_value = "1")]
-    fold: usize,
-
-    #[clap(long, default_value = "0.1")]
-    tfinal: f64,
-}
-
-enum Execution {
-    Serial,
-    Stupid(gridiron::thread_pool::ThreadPool),
-    Rayon(rayon::ThreadPool),
-}
-
-fn main() {
-    let opts = Opts::parse();
-    println!("{:?}", opts);
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = Args::from_env()?;
+    let mut opts = RunOptions::from_args(&mut args)?;
+    let autotune = args.flag("autotune")?;
+    args.finish()?;
+    opts.log(format!("{:?}", opts));
+
+    let executor = Executor::with_affinity(opts.strategy, opts.num_threads, opts.affinity.clone())?.with_spawn_order(opts.spawn_order);
+
+    if autotune {
+        let tune = TuneOptions {
+            grid_resolution: opts.grid_resolution,
+            num_workers: opts.num_threads,
+            ..TuneOptions::default()
+        };
+        let report = tune_block_size(&tune, |tasks| executor.run(tasks))?;
+        opts.log(format!("{}", report));
+        opts.block_size = report.best();
+    }
 
     let mesh = Mesh {
         area: (-1.0..1.0, -1.0..1.0),
@@ -105,72 +92,36 @@ fn main() {
     let edge_list = primitive_map.adjacency_list(1);
     let primitive: Vec<_> = primitive_map.into_iter().map(|(_, prim)| prim).collect();
 
-    println!("num blocks .... {}", primitive.len());
-    println!("num threads ... {}", opts.num_threads);
-    println!("");
+    opts.log(format!("num blocks .... {}", primitive.len()));
+    opts.log(format!("num threads ... {}\n", opts.num_threads));
 
     let mut task_list: Vec<_> = primitive
         .into_iter()
         .enumerate()
-        .map(|(n, patch)| PatchUpdate::new(patch, mesh.clone(), dt, Some(n % opts.num_threads), &edge_list))
+        .map(|(n, patch)| {
+            let mut task = PatchUpdate::new(patch, mesh.clone(), dt, Some(n % opts.num_threads), &edge_list);
+            task.set_local_neighbors(|_| true);
+            task
+        })
         .collect();
 
-    if opts.grid_resolution % opts.block_size != 0 {
-        eprintln!("Error: block size must divide the grid resolution");
-        return;
-    }
-
-    if opts.strategy == "serial" && opts.num_threads != 1 {
-        eprintln!("Error: serial option requires --num-threads=1");
-        return;
-    }
-
-    let executor = match opts.strategy.as_str() {
-        "serial" => Execution::Serial,
-        "stupid" => Execution::Stupid(gridiron::thread_pool::ThreadPool::new(opts.num_threads)),
-        "rayon" => Execution::Rayon(
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(opts.num_threads)
-                .build()
-                .unwrap(),
-        ),
-        _ => {
-            eprintln!("Error: --strategy options are [serial|stupid|rayon]");
-            return;
-        }
-    };
-
     while time < opts.tfinal {
         let start = std::time::Instant::now();
 
-        for _ in 0..opts.fold {
-            task_list = match &executor {
-                Execution::Serial => {
-                    automaton::execute(task_list).collect()
-                }
-                Execution::Stupid(pool) => {
-                    automaton::execute_par_stupid(&pool, task_list).collect()
-                }
-                Execution::Rayon(pool) => {
-                    pool.scope_fifo(|scope| {
-                        automaton::execute_par(scope, task_list)
-                    }).collect()
-                }
-            };
-            iteration += 1;
-            time += dt;
-        }
+        task_list = executor.run_folded(task_list, opts.fold);
+        iteration += opts.fold as u64;
+        time += dt * opts.fold as f64;
 
         let step_seconds = start.elapsed().as_secs_f64() / opts.fold as f64;
         let mzps = mesh.total_zones() as f64 / 1e6 / step_seconds;
 
-        println!(
+        opts.log(format!(
             "[{}] t={:.3} Mzps={:.2} ({:.2}-thread)",
             iteration,
             time,
             mzps,
             mzps / opts.num_threads as f64
-        );
+        ));
     }
 
     let primitive = task_list
@@ -183,7 +134,8 @@ fn main() {
         primitive,
     };
 
-    let file = std::fs::File::create("state.cbor").unwrap();
+    let file = std::fs::File::create("state.cbor")?;
     let mut buffer = std::io::BufWriter::new(file);
-    ciborium::ser::into_writer(&state, &mut buffer).unwrap();
+    ciborium::ser::into_writer(&state, &mut buffer)?;
+    Ok(())
 }
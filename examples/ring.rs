@@ -1,6 +1,8 @@
+use gridiron::message::cache::MessageCache;
 use gridiron::message::comm::Communicator;
-use gridiron::message::tcp::TcpCommunicator;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use gridiron::message::envelope::Envelope;
+use gridiron::message::tcp::{serve_rendezvous, TcpCommunicator};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
 use std::ops::Range;
 use std::thread;
 
@@ -8,27 +10,52 @@ fn peer(rank: usize) -> SocketAddr {
     SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8000 + rank as u16)
 }
 
+/// Create the communicators from a static list of peer addresses, or with
+/// `--rendezvous`, by registering with a rendezvous service on port 7999.
+fn communicators(ranks: Range<usize>) -> Vec<TcpCommunicator> {
+    if std::env::args().any(|arg| arg == "--rendezvous") {
+        let registry = SocketAddr::new(peer(0).ip(), 7999);
+        let listener = TcpListener::bind(registry).unwrap();
+        let size = ranks.len();
+        let service = thread::spawn(move || serve_rendezvous(listener, size).unwrap());
+        let handles: Vec<_> = ranks
+            .map(|_| thread::spawn(move || TcpCommunicator::rendezvous(registry, SocketAddr::new(peer(0).ip(), 0)).unwrap()))
+            .collect();
+        let comms = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        service.join().unwrap();
+        comms
+    } else {
+        let peers: Vec<_> = ranks.clone().map(|rank| peer(rank)).collect();
+        ranks
+            .map(|rank| TcpCommunicator::new(rank, peers.clone()).unwrap())
+            .collect()
+    }
+}
+
 fn main() {
-    let ranks: Range<usize> = 0..8;
-    let peers: Vec<_> = ranks.clone().map(|rank| peer(rank)).collect();
-    let comms: Vec<_> = ranks
-        .clone()
-        .map(|rank| TcpCommunicator::new(rank, peers.clone()))
-        .collect();
+    let comms = communicators(0..8);
     let procs: Vec<_> = comms
         .into_iter()
         .map(|comm| {
             thread::spawn(move || {
                 let dest = (comm.rank() + 1) % comm.size();
-                let message = format!("hello from {}", comm.rank());
-                comm.send(dest, message.into_bytes());
+                let mut cache = MessageCache::new();
+
+                // The greeting is the same every round, so only the first
+                // one is encoded, and later rounds re-use its encoding.
+                for round in 0..3 {
+                    let message = format!("hello from {}", comm.rank());
+                    comm.send(dest, cache.encode(dest, round, &dest, message));
 
-                let received = comm.recv();
-                println! {
-                    "{} received '{}'",
-                    comm.rank(),
-                    String::from_utf8(received).unwrap()
-                };
+                    let received: Envelope<usize, String> = Envelope::decode(&comm.recv()).unwrap();
+                    println! {
+                        "{} received '{}' in round {}",
+                        comm.rank(),
+                        received.payload,
+                        received.iteration
+                    };
+                }
+                println!("{} re-used {} encodings", comm.rank(), cache.num_hits());
             })
         })
         .collect();
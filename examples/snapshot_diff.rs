@@ -0,0 +1,31 @@
+//! Compare two checkpoint directories, and print the per-field differences.
+//!
+//! Usage: snapshot_diff <checkpoint-a> <checkpoint-b>
+
+use gridiron::io::diff::diff_snapshots;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() != 3 {
+        eprintln!("usage: {} <checkpoint-a> <checkpoint-b>", args[0]);
+        std::process::exit(2);
+    }
+
+    match diff_snapshots(&args[1], &args[2]) {
+        Ok(diff) => {
+            print!("{}", diff);
+
+            for ((di, dj), level) in &diff.unmatched {
+                println!("unmatched patch {}..{} {}..{} level {}", di.start, di.end, dj.start, dj.end, level);
+            }
+            if !diff.is_identical() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
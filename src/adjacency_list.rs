@@ -89,6 +89,73 @@ impl<K> AdjacencyList<K> where K: Hash + Eq + Clone {
     pub fn incoming_edges(&self, b: &K) -> impl Iterator<Item = &K> {
         self.incoming.get(b).into_iter().flat_map(|edges| edges.iter())
     }
+
+
+    /**
+     * Return an iterator over all the edges a -> b in the graph, in no
+     * particular order.
+     */
+    pub fn edges(&self) -> impl Iterator<Item = (&K, &K)> {
+        self.outgoing.iter().flat_map(|(a, edges)| edges.iter().map(move |b| (a, b)))
+    }
+
+
+    /**
+     * Return a Graphviz DOT representation of the graph, for inspecting
+     * message topologies. Vertices are labelled by the given function, and
+     * the output is sorted by label so it does not depend on hash order.
+     * Labels should be unique.
+     */
+    pub fn to_dot<L>(&self, labeler: L) -> String
+    where
+        L: Fn(&K) -> String,
+    {
+        self.to_dot_with(labeler, |_, _| String::new())
+    }
+
+
+    /**
+     * Like [`AdjacencyList::to_dot`], but the given function returns DOT
+     * attributes (e.g. `color=red, style=dashed`) for each edge a -> b. An
+     * empty string means no attributes.
+     */
+    pub fn to_dot_with<L, E>(&self, labeler: L, edge_attributes: E) -> String
+    where
+        L: Fn(&K) -> String,
+        E: Fn(&K, &K) -> String,
+    {
+        let mut vertices: Vec<_> = self
+            .outgoing
+            .iter()
+            .chain(self.incoming.iter())
+            .filter(|(_, edges)| !edges.is_empty())
+            .map(|(k, _)| (labeler(k), k))
+            .collect();
+        vertices.sort_by(|a, b| a.0.cmp(&b.0));
+        vertices.dedup_by(|a, b| a.0 == b.0);
+
+        let ids: HashMap<_, _> = vertices.iter().enumerate().map(|(n, (_, k))| (*k, n)).collect();
+        let (ids, edge_attributes) = (&ids, &edge_attributes);
+        let mut edges: Vec<_> = vertices
+            .iter()
+            .flat_map(|(_, a)| self.outgoing_edges(a).map(move |b| (ids[a], ids[b], edge_attributes(a, b))))
+            .collect();
+        edges.sort();
+
+        let mut dot = String::from("digraph {\n");
+        for (n, (label, _)) in vertices.iter().enumerate() {
+            dot.push_str(&format!("    n{} [label=\"{}\"];\n", n, label.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        for (a, b, attributes) in edges {
+            if attributes.is_empty() {
+                dot.push_str(&format!("    n{} -> n{};\n", a, b));
+            } else {
+                dot.push_str(&format!("    n{} -> n{} [{}];\n", a, b, attributes));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
 }
 
 impl<K> Default for AdjacencyList<K> {
@@ -103,6 +170,33 @@ impl<K> Default for AdjacencyList<K> {
 
 
 
+/**
+ * The graph is serialized as a sequence of its edges (a, b); the incoming
+ * edges are rebuilt when it's deserialized. This lets the adjacency list for
+ * a large mesh be saved and reloaded, instead of being re-computed from the
+ * patch geometry.
+ */
+impl<K> serde::Serialize for AdjacencyList<K> where K: serde::Serialize + Hash + Eq + Clone {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.edges())
+    }
+}
+
+impl<'de, K> serde::Deserialize<'de> for AdjacencyList<K> where K: serde::Deserialize<'de> + Hash + Eq + Clone {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let edges: Vec<(K, K)> = serde::Deserialize::deserialize(deserializer)?;
+        let mut result = Self::new();
+
+        for (a, b) in edges {
+            result.insert(a, b)
+        }
+        Ok(result)
+    }
+}
+
+
+
+
 // ============================================================================
 #[cfg(test)]
 mod test {
@@ -157,4 +251,36 @@ mod test {
         assert_eq!(edges.outgoing_edges(&0).count(), 3);
         assert_eq!(edges.outgoing_edges(&4).count(), 2);
     }
+
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn graph_round_trips_through_serde() {
+        let mut edges = AdjacencyList::new();
+        edges.insert((0..2, 3), (2..4, 3));
+        edges.insert((2..4, 3), (0..2, 3));
+        edges.insert((2..4, 3), (0..2, 3));
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&edges, &mut bytes).unwrap();
+        let loaded: AdjacencyList<(std::ops::Range<i64>, u32)> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.incoming_edges(&(0..2, 3)).count(), 2);
+        assert_eq!(loaded.outgoing_edges(&(0..2, 3)).collect::<Vec<_>>(), [&(2..4, 3)]);
+    }
+
+
+    #[test]
+    fn graph_can_be_exported_to_dot() {
+        let mut edges = AdjacencyList::new();
+        edges.insert(2, 1);
+        edges.insert(1, 0);
+        edges.insert(1, 2);
+        let dot = edges.to_dot_with(|k| format!("v\"{}", k), |a, b| if a < b { "color=red".to_string() } else { String::new() });
+        assert_eq!(
+            dot,
+            "digraph {\n    n0 [label=\"v\\\"0\"];\n    n1 [label=\"v\\\"1\"];\n    n2 [label=\"v\\\"2\"];\n    n1 -> n0;\n    n1 -> n2 [color=red];\n    n2 -> n1;\n}\n"
+        );
+    }
 }
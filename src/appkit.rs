@@ -0,0 +1,653 @@
+//! Reusable pieces for the `main` function of applications and benchmarks:
+//! a small command line parser, option structs for the settings most runs
+//! share (threads, execution strategy, grid and block size), a factory for
+//! executors from a strategy name, and helpers for parsing peer addresses
+//! and ranks. This module is behind the `appkit` feature.
+//!
+//! The parser is deliberately minimal, so the crate does not need to depend
+//! on an argument parsing crate. Options are given as `--name value` or
+//! `--name=value`, and flags as `--name`. Each option struct takes the
+//! arguments it recognizes, and [`Args::finish`] reports any that were left
+//! over:
+//!
+//! ```ignore
+//! let mut args = Args::from_env()?;
+//! let run = RunOptions::from_args(&mut args)?;
+//! let cfl: f64 = args.get("cfl", 0.4)?;
+//! args.finish()?;
+//!
+//! let executor = Executor::new(run.strategy, run.num_threads)?;
+//! tasks = executor.run_folded(tasks, run.fold);
+//! ```
+
+use crate::automaton::{self, Automaton};
+use crate::error::{Error, Result};
+use crate::thread_pool::{Affinity, SpawnPolicy, ThreadPool};
+use core::hash::Hash;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+/// Command line arguments, parsed into named options and flags.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    options: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    /// Parse the arguments of this process, skipping the program name.
+    ///
+    pub fn from_env() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parse a sequence of arguments. An argument `--name` followed by an
+    /// argument which does not start with `--` is an option with that
+    /// value; otherwise it is a flag. Positional arguments and repeated
+    /// names are errors.
+    ///
+    pub fn parse<I, S>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut args = args.into_iter().map(Into::into).peekable();
+        let mut options = HashMap::new();
+
+        while let Some(arg) = args.next() {
+            let arg = arg
+                .strip_prefix("--")
+                .ok_or_else(|| Error::Parameters(format!("unexpected positional argument '{}'", arg)))?;
+
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => match args.peek() {
+                    Some(next) if !next.starts_with("--") => (arg.to_string(), args.next()),
+                    _ => (arg.to_string(), None),
+                },
+            };
+            if options.insert(name.clone(), value).is_some() {
+                return Err(Error::Parameters(format!("argument '--{}' was given more than once", name)));
+            }
+        }
+        Ok(Self { options })
+    }
+
+    /// Take the value of the named option, or return the default if it was
+    /// not given. Returns an error if the option was given as a flag, or if
+    /// its value cannot be parsed.
+    ///
+    pub fn get<T>(&mut self, name: &str, default: T) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.options.remove(name) {
+            None => Ok(default),
+            Some(None) => Err(Error::Parameters(format!("argument '--{}' requires a value", name))),
+            Some(Some(value)) => value
+                .parse()
+                .map_err(|e| Error::Parameters(format!("invalid value '{}' for '--{}': {}", value, name, e))),
+        }
+    }
+
+    /// Take the named flag, and return whether it was given. Returns an
+    /// error if it was given a value.
+    ///
+    pub fn flag(&mut self, name: &str) -> Result<bool> {
+        match self.options.remove(name) {
+            None => Ok(false),
+            Some(None) => Ok(true),
+            Some(Some(value)) => Err(Error::Parameters(format!("flag '--{}' does not take a value (got '{}')", name, value))),
+        }
+    }
+
+    /// Return an error naming the arguments which were not taken by any of
+    /// the option structs or calls to [`Args::get`] and [`Args::flag`].
+    ///
+    pub fn finish(self) -> Result<()> {
+        if self.options.is_empty() {
+            Ok(())
+        } else {
+            let mut names: Vec<_> = self.options.into_keys().map(|name| format!("--{}", name)).collect();
+            names.sort();
+            Err(Error::Parameters(format!("unrecognized arguments: {}", names.join(" "))))
+        }
+    }
+}
+
+/// A strategy for executing a group of tasks.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Run the tasks on the calling thread, with [`automaton::execute`].
+    Serial,
+
+    /// Run the tasks on the crate's thread pool, with
+    /// [`automaton::execute_par_stupid`].
+    Stupid,
+
+    /// Run the tasks on a Rayon thread pool, with [`automaton::execute_par`].
+    Rayon,
+}
+
+impl FromStr for Strategy {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "serial" => Ok(Self::Serial),
+            "stupid" => Ok(Self::Stupid),
+            "rayon" => Ok(Self::Rayon),
+            _ => Err(Error::Parameters(format!(
+                "unknown strategy '{}' (options are serial|stupid|rayon)",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serial => write!(fmt, "serial"),
+            Self::Stupid => write!(fmt, "stupid"),
+            Self::Rayon => write!(fmt, "rayon"),
+        }
+    }
+}
+
+/// The order in which an executor is given the tasks of a group.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnOrder {
+    /// Take the tasks in the order they are passed in.
+    Given,
+
+    /// Take the costliest tasks first, with [`automaton::with_cost_order`],
+    /// so that large tasks (e.g. coarse patches mixed with fine ones) start
+    /// early rather than straggling at the end of the step.
+    Cost,
+}
+
+impl FromStr for SpawnOrder {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "given" => Ok(Self::Given),
+            "cost" => Ok(Self::Cost),
+            _ => Err(Error::Parameters(format!(
+                "unknown spawn order '{}' (options are given|cost)",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SpawnOrder {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Given => write!(fmt, "given"),
+            Self::Cost => write!(fmt, "cost"),
+        }
+    }
+}
+
+/// The options shared by most runs on a uniform grid of square blocks.
+///
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    /// The number of worker threads (`--num-threads`, default 1).
+    pub num_threads: usize,
+
+    /// The execution strategy (`--strategy`, default serial).
+    pub strategy: Strategy,
+
+    /// The order tasks are given to the executor (`--spawn-order`, default
+    /// given).
+    pub spawn_order: SpawnOrder,
+
+    /// How the crate's thread pool pins its workers to cores (`--affinity`,
+    /// default detect): `detect`, `none`, or a list of core ids such as
+    /// `0,2,4,6`.
+    pub affinity: Affinity,
+
+    /// The number of zones on each side of the grid (`--grid-resolution`,
+    /// default 1000).
+    pub grid_resolution: usize,
+
+    /// The number of zones on each side of a block (`--block-size`, default
+    /// 100).
+    pub block_size: usize,
+
+    /// The number of iterations executed between reports (`--fold`,
+    /// default 1).
+    pub fold: usize,
+
+    /// The time at which to stop (`--tfinal`, default 0.1).
+    pub tfinal: f64,
+
+    /// Whether to suppress progress messages (`--quiet`).
+    pub quiet: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            num_threads: 1,
+            strategy: Strategy::Serial,
+            spawn_order: SpawnOrder::Given,
+            affinity: Affinity::Detect,
+            grid_resolution: 1000,
+            block_size: 100,
+            fold: 1,
+            tfinal: 0.1,
+            quiet: false,
+        }
+    }
+}
+
+impl RunOptions {
+    /// Take the run options from the command line arguments, and check that
+    /// they are consistent.
+    ///
+    pub fn from_args(args: &mut Args) -> Result<Self> {
+        let defaults = Self::default();
+        let options = Self {
+            num_threads: args.get("num-threads", defaults.num_threads)?,
+            strategy: args.get("strategy", defaults.strategy)?,
+            spawn_order: args.get("spawn-order", defaults.spawn_order)?,
+            affinity: args.get("affinity", defaults.affinity)?,
+            grid_resolution: args.get("grid-resolution", defaults.grid_resolution)?,
+            block_size: args.get("block-size", defaults.block_size)?,
+            fold: args.get("fold", defaults.fold)?,
+            tfinal: args.get("tfinal", defaults.tfinal)?,
+            quiet: args.flag("quiet")?,
+        };
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Return an error if the options are inconsistent.
+    ///
+    pub fn validate(&self) -> Result<()> {
+        if self.block_size == 0 || !self.grid_resolution.is_multiple_of(self.block_size) {
+            return Err(Error::Parameters(format!(
+                "block size {} must divide the grid resolution {}",
+                self.block_size, self.grid_resolution
+            )));
+        }
+        if self.fold == 0 {
+            return Err(Error::Parameters("fold must be at least 1".to_string()));
+        }
+        check_threads(self.strategy, self.num_threads)
+    }
+
+    /// Print a progress message, unless the run is quiet.
+    ///
+    pub fn log<M: fmt::Display>(&self, message: M) {
+        if !self.quiet {
+            println!("{}", message)
+        }
+    }
+}
+
+/// The options which place a process in a group of communicating peers.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetOptions {
+    /// The rank of this process (`--rank`, default 0).
+    pub rank: usize,
+
+    /// The address of each rank. These are given either as a comma-separated
+    /// list (`--peers`), or as a number of ranks on the local host
+    /// (`--num-ranks`, default 1) listening on consecutive ports from
+    /// `--base-port` (default 8000).
+    pub peers: Vec<SocketAddr>,
+}
+
+impl NetOptions {
+    /// Take the network options from the command line arguments, and check
+    /// that the rank is one of the peers.
+    ///
+    pub fn from_args(args: &mut Args) -> Result<Self> {
+        let rank: String = args.get("rank", "0".to_string())?;
+        let peers: String = args.get("peers", String::new())?;
+        let num_ranks = args.get("num-ranks", 1)?;
+        let base_port = args.get("base-port", 8000)?;
+
+        let peers = if peers.is_empty() {
+            local_peers(base_port, num_ranks)?
+        } else {
+            parse_peers(&peers)?
+        };
+        let rank = parse_rank(&rank, peers.len())?;
+        Ok(Self { rank, peers })
+    }
+}
+
+/// Parse a comma-separated list of socket addresses, e.g.
+/// `10.0.0.1:8000,10.0.0.2:8000`.
+///
+pub fn parse_peers(peers: &str) -> Result<Vec<SocketAddr>> {
+    peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| {
+            peer.parse()
+                .map_err(|e| Error::Parameters(format!("invalid peer address '{}': {}", peer, e)))
+        })
+        .collect()
+}
+
+/// Return the addresses of `num_ranks` peers on the local host, listening on
+/// consecutive ports starting from `base_port`.
+///
+pub fn local_peers(base_port: u16, num_ranks: usize) -> Result<Vec<SocketAddr>> {
+    (0..num_ranks)
+        .map(|rank| {
+            u16::try_from(rank)
+                .ok()
+                .and_then(|rank| base_port.checked_add(rank))
+                .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+                .ok_or_else(|| Error::Parameters(format!("{} ranks from port {} exceed the port range", num_ranks, base_port)))
+        })
+        .collect()
+}
+
+/// Parse a rank, and check that it is less than the number of ranks.
+///
+pub fn parse_rank(rank: &str, num_ranks: usize) -> Result<usize> {
+    let parsed: usize = rank
+        .trim()
+        .parse()
+        .map_err(|e| Error::Parameters(format!("invalid rank '{}': {}", rank, e)))?;
+
+    if parsed < num_ranks {
+        Ok(parsed)
+    } else {
+        Err(Error::Parameters(format!("rank {} is out of range for {} ranks", parsed, num_ranks)))
+    }
+}
+
+fn check_threads(strategy: Strategy, num_threads: usize) -> Result<()> {
+    match strategy {
+        Strategy::Serial if num_threads != 1 => Err(Error::Parameters(format!(
+            "the serial strategy requires 1 thread (got {})",
+            num_threads
+        ))),
+        Strategy::Stupid | Strategy::Rayon if num_threads < 2 => Err(Error::Parameters(format!(
+            "the {} strategy requires at least 2 threads (got {})",
+            strategy, num_threads
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// An executor for a group of tasks, which owns its thread pool.
+///
+pub struct Executor {
+    pool: Pool,
+    spawn_order: SpawnOrder,
+}
+
+enum Pool {
+    Serial,
+    Stupid(ThreadPool),
+    Rayon(rayon::ThreadPool),
+}
+
+impl Executor {
+    /// Create an executor with the given strategy and number of threads.
+    /// The serial strategy requires one thread, and the others at least
+    /// two.
+    ///
+    pub fn new(strategy: Strategy, num_threads: usize) -> Result<Self> {
+        Self::with_affinity(strategy, num_threads, Affinity::Detect)
+    }
+
+    /// Like [`Executor::new`], but the crate's thread pool (used by the
+    /// stupid strategy) pins its workers according to the given affinity.
+    /// The affinity is ignored by the other strategies.
+    ///
+    pub fn with_affinity(strategy: Strategy, num_threads: usize, affinity: Affinity) -> Result<Self> {
+        check_threads(strategy, num_threads)?;
+
+        let pool = match strategy {
+            Strategy::Serial => Pool::Serial,
+            Strategy::Stupid => Pool::Stupid(ThreadPool::with_affinity(num_threads, SpawnPolicy::default(), affinity)),
+            Strategy::Rayon => rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .map(Pool::Rayon)
+                .map_err(|e| Error::Parameters(format!("could not start the rayon pool: {}", e)))?,
+        };
+        Ok(Self {
+            pool,
+            spawn_order: SpawnOrder::Given,
+        })
+    }
+
+    /// Return this executor, with the given number of communication workers
+    /// added to the crate's thread pool (see
+    /// [`ThreadPool::with_comm_threads`]). They are only added for the
+    /// stupid strategy; the others have no communication workers.
+    ///
+    pub fn with_comm_threads(self, count: usize) -> Self {
+        match self.pool {
+            Pool::Stupid(pool) => Self {
+                pool: Pool::Stupid(pool.with_comm_threads(count)),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Return the number of communication workers in the executor's pool.
+    ///
+    pub fn num_comm_threads(&self) -> usize {
+        self.thread_pool().map_or(0, ThreadPool::num_comm_threads)
+    }
+
+    /// Return the crate's thread pool, if the executor uses one, e.g. to
+    /// encode messages or checkpoints on its communication workers.
+    ///
+    pub fn thread_pool(&self) -> Option<&ThreadPool> {
+        match &self.pool {
+            Pool::Stupid(pool) => Some(pool),
+            _ => None,
+        }
+    }
+
+    /// Return this executor, with the given spawn order.
+    ///
+    pub fn with_spawn_order(self, spawn_order: SpawnOrder) -> Self {
+        Self { spawn_order, ..self }
+    }
+
+    /// Return the spawn order of this executor.
+    ///
+    pub fn spawn_order(&self) -> SpawnOrder {
+        self.spawn_order
+    }
+
+    /// Create an executor from the name of a strategy: `serial`, `stupid`,
+    /// or `rayon`.
+    ///
+    pub fn from_name(name: &str, num_threads: usize) -> Result<Self> {
+        Self::new(name.parse()?, num_threads)
+    }
+
+    /// Return the strategy of this executor.
+    ///
+    pub fn strategy(&self) -> Strategy {
+        match self.pool {
+            Pool::Serial => Strategy::Serial,
+            Pool::Stupid(_) => Strategy::Stupid,
+            Pool::Rayon(_) => Strategy::Rayon,
+        }
+    }
+
+    /// Execute the tasks once, and return their values. With the cost spawn
+    /// order, the values are returned in the order the tasks finish.
+    ///
+    pub fn run<A, K, V>(&self, tasks: Vec<A>) -> Vec<V>
+    where
+        A: 'static + Send + Automaton<Key = K, Value = V>,
+        K: 'static + Send + Hash + Eq + fmt::Debug,
+        V: 'static + Send,
+    {
+        let tasks: Vec<_> = match self.spawn_order {
+            SpawnOrder::Given => tasks,
+            SpawnOrder::Cost => automaton::with_cost_order(tasks).collect(),
+        };
+        match &self.pool {
+            Pool::Serial => automaton::execute(tasks).collect(),
+            Pool::Stupid(pool) => automaton::execute_par_stupid(pool, tasks).collect(),
+            Pool::Rayon(pool) => pool.scope_fifo(|scope| automaton::execute_par(scope, tasks).collect()),
+        }
+    }
+
+    /// Execute tasks whose values are the tasks for the next iteration
+    /// `fold` times in a row, and return the final tasks.
+    ///
+    pub fn run_folded<A, K>(&self, mut tasks: Vec<A>, fold: usize) -> Vec<A>
+    where
+        A: 'static + Send + Automaton<Key = K, Value = A>,
+        K: 'static + Send + Hash + Eq + fmt::Debug,
+    {
+        for _ in 0..fold {
+            tasks = self.run(tasks)
+        }
+        tasks
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{local_peers, parse_peers, parse_rank, Args, Executor, NetOptions, RunOptions, SpawnOrder, Strategy};
+    use crate::thread_pool::Affinity;
+    use crate::automaton::{Automaton, Status};
+
+    fn args(line: &str) -> Args {
+        Args::parse(line.split_whitespace()).unwrap()
+    }
+
+    /// A task on a ring, which adds its left neighbor's count to its own.
+    struct Ring {
+        key: usize,
+        size: usize,
+        count: usize,
+        received: usize,
+    }
+
+    impl Automaton for Ring {
+        type Key = usize;
+        type Message = usize;
+        type Value = Self;
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            vec![((self.key + 1) % self.size, self.count)]
+        }
+
+        fn receive(&mut self, message: Self::Message) -> Status {
+            self.received = message;
+            Status::Eligible
+        }
+
+        fn value(self) -> Self::Value {
+            Self { count: self.count + self.received, ..self }
+        }
+    }
+
+    #[test]
+    fn run_options_are_taken_from_arguments() {
+        let mut a = args("--num-threads 4 --strategy=rayon --block-size 50 --quiet --cfl 0.3");
+        let run = RunOptions::from_args(&mut a).unwrap();
+        assert_eq!((run.num_threads, run.strategy, run.block_size), (4, Strategy::Rayon, 50));
+        assert_eq!((run.grid_resolution, run.fold, run.quiet), (1000, 1, true));
+        assert_eq!(run.spawn_order, SpawnOrder::Given);
+        assert_eq!(run.affinity, Affinity::Detect);
+        assert_eq!(a.get("cfl", 0.4).unwrap(), 0.3);
+        assert!(a.finish().is_ok());
+
+        let mut a = args("--tfinal 1.0 --typo 3");
+        RunOptions::from_args(&mut a).unwrap();
+        assert!(a.finish().unwrap_err().to_string().contains("--typo"));
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported() {
+        assert!(Args::parse(["positional"]).is_err());
+        assert!(Args::parse(["--fold", "2", "--fold", "3"]).is_err());
+        assert!(args("--fold x").get("fold", 1).is_err());
+        assert!(args("--fold").get("fold", 1).is_err());
+        assert!(args("--quiet yes").flag("quiet").is_err());
+        assert!(RunOptions::from_args(&mut args("--strategy pthreads")).is_err());
+        assert!(RunOptions::from_args(&mut args("--spawn-order random")).is_err());
+        assert!(RunOptions::from_args(&mut args("--affinity 0,x")).is_err());
+        assert!(RunOptions::from_args(&mut args("--block-size 30")).is_err());
+        assert!(RunOptions::from_args(&mut args("--num-threads 2")).is_err());
+        assert!(RunOptions::from_args(&mut args("--strategy stupid")).is_err());
+        assert_eq!(args("--shift -1").get("shift", 0).unwrap(), -1);
+    }
+
+    #[test]
+    fn peers_and_ranks_are_parsed() {
+        let peers = parse_peers("10.0.0.1:8000, 10.0.0.2:8001").unwrap();
+        assert_eq!(peers[1].port(), 8001);
+        assert!(parse_peers("10.0.0.1").is_err());
+        assert_eq!(local_peers(9000, 3).unwrap()[2].port(), 9002);
+        assert!(local_peers(65535, 2).is_err());
+        assert_eq!(parse_rank("1", 2).unwrap(), 1);
+        assert!(parse_rank("2", 2).is_err());
+
+        let net = NetOptions::from_args(&mut args("--rank 2 --num-ranks 4 --base-port 7000")).unwrap();
+        assert_eq!((net.rank, net.peers.len(), net.peers[0].port()), (2, 4, 7000));
+        let net = NetOptions::from_args(&mut args("--rank 1 --peers 127.0.0.1:5000,127.0.0.1:5001")).unwrap();
+        assert_eq!(net.peers, parse_peers("127.0.0.1:5000,127.0.0.1:5001").unwrap());
+        assert!(NetOptions::from_args(&mut args("--rank 1")).is_err());
+    }
+
+    #[test]
+    fn executors_are_created_from_strategy_names() {
+        let executor = Executor::from_name("serial", 1).unwrap();
+        assert_eq!(executor.strategy(), Strategy::Serial);
+        assert!(Executor::from_name("rayon", 1).is_err());
+        assert!(Executor::from_name("fast", 1).is_err());
+
+        let size = 4;
+        let tasks = (0..size).map(|key| Ring { key, size, count: 1, received: 0 }).collect();
+        let mut tasks = executor.run_folded(tasks, 3);
+        tasks.sort_by_key(|task| task.key);
+        assert!(tasks.iter().all(|task| task.count == 8));
+
+        let executor = Executor::from_name("serial", 1).unwrap().with_spawn_order(SpawnOrder::Cost);
+        assert_eq!(executor.spawn_order(), SpawnOrder::Cost);
+        let mut tasks = executor.run_folded(tasks, 1);
+        tasks.sort_by_key(|task| task.key);
+        assert!(tasks.iter().all(|task| task.count == 16));
+    }
+
+    #[test]
+    fn only_the_stupid_strategy_has_comm_threads() {
+        let executor = Executor::with_affinity(Strategy::Stupid, 2, Affinity::Unpinned)
+            .unwrap()
+            .with_comm_threads(2);
+        assert_eq!(executor.num_comm_threads(), 2);
+        assert!(executor.thread_pool().is_some());
+
+        let executor = Executor::from_name("serial", 1).unwrap().with_comm_threads(2);
+        assert_eq!(executor.num_comm_threads(), 0);
+    }
+}
@@ -204,13 +204,15 @@ impl<T: Ord + Copy, V> Node<T, V> {
                     }
                     (Some(l), Some(r)) => {
                         if r.len() > l.len() {
-                            let (new_r, r_key) = r.take_lmost();
+                            let (new_r, r_key, r_value) = r.take_lmost();
                             n.key = r_key;
+                            n.value = r_value;
                             n.l = Some(l);
                             n.r = new_r;
                         } else {
-                            let (new_l, l_key) = l.take_rmost();
+                            let (new_l, l_key, l_value) = l.take_rmost();
                             n.key = l_key;
+                            n.value = l_value;
                             n.l = new_l;
                             n.r = Some(r);
                         }
@@ -228,22 +230,21 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
     /**
      * Return this sub-tree, but with the left-most descendant node removed.
-     * Also return the key of that node.
+     * Also return the key and value of that node. The removed node's right
+     * sub-tree takes its place.
      */
-    pub(crate) fn take_lmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>) {
-        if let Some(l) = self.l {
-            if l.l.is_none() {
-                self.l = None;
-                self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), l.key)
-            } else {
-                let (new_l, l_key) = l.take_lmost();
+    pub(crate) fn take_lmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>, V) {
+        match self.l.take() {
+            Some(l) => {
+                let (new_l, l_key, l_value) = l.take_lmost();
                 self.l = new_l;
                 self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), l_key)
+                (Some(self), l_key, l_value)
+            }
+            None => {
+                let Node { key, value, r, .. } = *self;
+                (r, key, value)
             }
-        } else {
-            (None, self.key)
         }
     }
 
@@ -252,22 +253,21 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
     /**
      * Return this sub-tree, but with the right-most descendant node removed.
-     * Also return the key of that node.
+     * Also return the key and value of that node. The removed node's left
+     * sub-tree takes its place.
      */
-    pub(crate) fn take_rmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>) {
-        if let Some(r) = self.r {
-            if r.r.is_none() {
-                self.r = None;
-                self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), r.key)
-            } else {
-                let (new_r, r_key) = r.take_rmost();
+    pub(crate) fn take_rmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>, V) {
+        match self.r.take() {
+            Some(r) => {
+                let (new_r, r_key, r_value) = r.take_rmost();
                 self.r = new_r;
                 self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), r_key)
+                (Some(self), r_key, r_value)
+            }
+            None => {
+                let Node { key, value, l, .. } = *self;
+                (l, key, value)
             }
-        } else {
-            (None, self.key)
         }
     }
 
@@ -438,14 +438,18 @@ impl<T: Ord + Copy, V> Iterator for IntoIterInOrder<T, V> {
     fn next(&mut self) -> Option<Self::Item> {
         Node::next(&mut self.stack).map(|n| (n.key, n.value))
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.stack.len(), None)
+    }
 }
 
 
 
 
 /**
- * Consuming iterator that does a pre-order traveral of the sub-tree, returning
- * key-value pairs.
+ * Consuming iterator that does an in-order traversal of the sub-tree,
+ * returning key-value pairs sorted by key.
  */
 pub struct IntoIter<T: Ord + Copy, V> {
     stack: Vec<Node<T, V>>
@@ -454,7 +458,7 @@ pub struct IntoIter<T: Ord + Copy, V> {
 impl<T: Ord + Copy, V> IntoIter<T, V> {
     pub(crate) fn new(node: Option<Box<Node<T, V>>>) -> Self {
         Self {
-            stack: node.into_iter().map(|n| *n).collect()
+            stack: node.map_or(Vec::new(), |node| node.into_lmost_path())
         }
     }
 }
@@ -463,15 +467,11 @@ impl<T: Ord + Copy, V> Iterator for IntoIter<T, V> {
     type Item = (Range<T>, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let node = self.stack.pop()?;
+        Node::next(&mut self.stack).map(|n| (n.key, n.value))
+    }
 
-        if let Some(r) = node.r {
-            self.stack.push(*r)
-        }
-        if let Some(l) = node.l {
-            self.stack.push(*l)
-        }
-        Some((node.key, node.value))
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.stack.len(), None)
     }
 }
 
@@ -479,8 +479,8 @@ impl<T: Ord + Copy, V> Iterator for IntoIter<T, V> {
 
 
 /**
- * Consuming iterator that does a pre-order traveral of the sub-tree, returning
- * only the keys.
+ * Consuming iterator that does an in-order traversal of the sub-tree,
+ * returning only the keys, in sorted order.
  */
 pub struct IntoIterKey<T: Ord + Copy, V> {
     stack: Vec<Node<T, V>>
@@ -489,7 +489,7 @@ pub struct IntoIterKey<T: Ord + Copy, V> {
 impl<T: Ord + Copy, V> IntoIterKey<T, V> {
     pub(crate) fn new(node: Option<Box<Node<T, V>>>) -> Self {
         Self {
-            stack: node.into_iter().map(|n| *n).collect()
+            stack: node.map_or(Vec::new(), |node| node.into_lmost_path())
         }
     }
 }
@@ -498,15 +498,11 @@ impl<T: Ord + Copy, V> Iterator for IntoIterKey<T, V> {
     type Item = Range<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let node = self.stack.pop()?;
+        Node::next(&mut self.stack).map(|n| n.key)
+    }
 
-        if let Some(r) = node.r {
-            self.stack.push(*r)
-        }
-        if let Some(l) = node.l {
-            self.stack.push(*l)
-        }
-        Some(node.key)
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.stack.len(), None)
     }
 }
 
@@ -514,7 +510,8 @@ impl<T: Ord + Copy, V> Iterator for IntoIterKey<T, V> {
 
 
 /**
- * Iterator over immutable values in this sub-tree. The traversal is pre-order.
+ * Iterator over immutable values in this sub-tree. The traversal is
+ * in-order, so the values are yielded sorted by key.
  */
 pub struct Iter<'a, T: Ord + Copy, V> {
     stack: Vec<&'a Node<T, V>>
@@ -522,8 +519,15 @@ pub struct Iter<'a, T: Ord + Copy, V> {
 
 impl<'a, T: Ord + Copy, V> Iter<'a, T, V> {
     pub(crate) fn new(node: &'a Option<Box<Node<T, V>>>) -> Self {
-        Self {
-            stack: node.iter().map(|n| &**n).collect()
+        let mut iter = Self { stack: Vec::new() };
+        iter.push_lmost_path(node.as_deref());
+        iter
+    }
+
+    fn push_lmost_path(&mut self, mut node: Option<&'a Node<T, V>>) {
+        while let Some(n) = node {
+            self.stack.push(n);
+            node = n.l.as_deref();
         }
     }
 }
@@ -533,31 +537,40 @@ impl<'a, T: Ord + Copy, V> Iterator for Iter<'a, T, V> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let node = self.stack.pop()?;
-
-        if let Some(r) = &node.r {
-            self.stack.push(r)
-        }
-        if let Some(l) = &node.l {
-            self.stack.push(l)
-        }
+        self.push_lmost_path(node.r.as_deref());
         Some((&node.key, &node.value))
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.stack.len(), None)
+    }
 }
 
 
 
 
 /**
- * Iterator over mutable values in this sub-tree. The traversal is pre-order.
+ * Iterator over mutable values in this sub-tree. The traversal is in-order,
+ * so the values are yielded sorted by key. Each node on the stack is split
+ * into its key, value, and right sub-tree when it is pushed.
  */
 pub struct IterMut<'a, T: Ord + Copy, V> {
-    stack: Vec<&'a mut Node<T, V>>
+    stack: Vec<IterMutFrame<'a, T, V>>
 }
 
+type IterMutFrame<'a, T, V> = (&'a Range<T>, &'a mut V, Option<&'a mut Node<T, V>>);
+
 impl<'a, T: Ord + Copy, V> IterMut<'a, T, V> {
     pub(crate) fn new(node: &'a mut Option<Box<Node<T, V>>>) -> Self {
-        Self {
-            stack: node.iter_mut().map(|n| &mut **n).collect()
+        let mut iter = Self { stack: Vec::new() };
+        iter.push_lmost_path(node.as_deref_mut());
+        iter
+    }
+
+    fn push_lmost_path(&mut self, mut node: Option<&'a mut Node<T, V>>) {
+        while let Some(Node { key, value, l, r, .. }) = node {
+            self.stack.push((key, value, r.as_deref_mut()));
+            node = l.as_deref_mut();
         }
     }
 }
@@ -566,15 +579,13 @@ impl<'a, T: Ord + Copy, V> Iterator for IterMut<'a, T, V> {
     type Item = (&'a Range<T>, &'a mut V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let node = self.stack.pop()?;
+        let (key, value, r) = self.stack.pop()?;
+        self.push_lmost_path(r);
+        Some((key, value))
+    }
 
-        if let Some(r) = &mut node.r {
-            self.stack.push(r)
-        }
-        if let Some(l) = &mut node.l {
-            self.stack.push(l)
-        }
-        Some((&node.key, &mut node.value))
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.stack.len(), None)
     }
 }
 
@@ -671,6 +682,42 @@ impl<'a, T: Ord + Copy, V, R: RangeBounds<T>> Iterator for IterRangeQuery<'a, T,
 
 
 
+// ============================================================================
+/**
+ * A deterministic pseudo-random number generator (a 64-bit linear
+ * congruential generator), for the randomized tests of the tree-based
+ * containers against naive models.
+ */
+#[cfg(test)]
+pub(crate) struct StupidRandom(u64);
+
+#[cfg(test)]
+impl StupidRandom {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /**
+     * Return a number in `0..n`.
+     */
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % n
+    }
+
+    /**
+     * Return a non-empty range starting in `0..starts`, no longer than
+     * `max_len`.
+     */
+    pub(crate) fn range(&mut self, starts: u64, max_len: u64) -> Range<i64> {
+        let start = self.below(starts) as i64;
+        start..start + 1 + self.below(max_len) as i64
+    }
+}
+
+
+
+
 // ============================================================================
 #[cfg(test)]
 mod test {
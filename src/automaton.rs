@@ -1,5 +1,14 @@
+use crate::clock::{Clock, SystemClock};
+use crate::error::{self, Error};
+use crate::stats::Metrics;
+use crate::thread_pool::{current_worker_id, panic_message};
 use core::hash::Hash;
+use std::cell::Cell;
+use std::fmt;
 use std::collections::hash_map::{Entry, HashMap};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 /// Returned by [`Automaton::receive`] to indicate whether a task is eligible
 /// to be evaluated.
@@ -75,11 +84,686 @@ pub trait Automaton {
     /// minimize creating or dropping memory buffers.
     fn value(self) -> Self::Value;
 
+    /// This method may be implemented to do the part of the work which does
+    /// not depend on incoming messages (e.g. updating the interior zones of a
+    /// patch) before the messages arrive, so computation is overlapped with
+    /// communication. It is called at most once before `value`, and only
+    /// when the group is run in speculative mode (see [`with_speculation`]),
+    /// in which case it runs on a worker thread while the task's messages
+    /// are being exchanged. `value` must complete whatever work remains.
+    fn speculate(&mut self) {}
+
+    /// This method may be implemented to return the number of messages the
+    /// task receives before it is eligible. In speculative mode, a task
+    /// which reports it can be sent messages while it is speculating: they
+    /// are held and passed to `receive` just before `value`. Otherwise,
+    /// delivering a message to the task waits until it has finished
+    /// speculating.
+    fn num_incoming(&self) -> Option<usize> {
+        None
+    }
+
     /// This method may be implemented to hint the executor which worker
     /// thread it wants to run on. The executor is allowed to ignore the hint.
     fn worker_hint(&self) -> Option<usize> {
         None
     }
+
+    /// This method may be implemented to give the executor a coordinate
+    /// describing where the task sits in the domain (e.g. the start of its
+    /// patch), for spawn policies which place nearby tasks together.
+    fn locality(&self) -> Option<(i64, i64)> {
+        None
+    }
+
+    /// This method may be implemented to tell the executor how urgently the
+    /// task should run, relative to the other tasks which become eligible at
+    /// the same time: tasks with a higher priority are spawned first. If this
+    /// method returns `None` (the default), the executor uses the number of
+    /// messages the task sends, so tasks which unblock many peers run early.
+    fn priority(&self) -> Option<usize> {
+        None
+    }
+
+    /// This method may be implemented to estimate how long `value` takes to
+    /// run, in any unit consistent across the group (e.g. the number of zones
+    /// in the task's patch). Executors running in cost order (see
+    /// [`with_cost_order`]) start the costliest tasks first, so that large
+    /// tasks do not straggle at the end of a step.
+    fn cost(&self) -> Option<f64> {
+        None
+    }
+
+    /// Return the approximate number of bytes held by a message. This is
+    /// used by the executor to account for the memory held in messages that
+    /// could not yet be delivered. The default implementation returns the
+    /// size of the message type, which is only correct if the message does
+    /// not own any heap memory.
+    fn message_size(message: &Self::Message) -> usize {
+        core::mem::size_of_val(message)
+    }
+}
+
+/// Bounds on the messages which may be held by an executor for recipients it
+/// has not seen yet. Messages addressed to a task that is never yielded by
+/// the input iterator would otherwise accumulate without bound. The default
+/// value places no limits.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// The maximum number of undelivered messages.
+    pub max_messages: usize,
+
+    /// The maximum number of bytes held in undelivered messages, as reported
+    /// by `Automaton::message_size`.
+    pub max_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_messages: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
+/// A handle given to a task, through which it can emit auxiliary values
+/// (e.g. diagnostics like the maximum wave speed on a patch) while its
+/// `value` method is still running. Values sent on the side channel are
+/// available to the receiver immediately, without waiting for the task or
+/// the rest of the group to finish.
+///
+pub struct SideChannel<T> {
+    sender: crossbeam_channel::Sender<T>,
+}
+
+impl<T> Clone for SideChannel<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> SideChannel<T> {
+    /// Emit an auxiliary value. Values are silently dropped if the receiver
+    /// has been dropped.
+    pub fn send(&self, value: T) {
+        self.sender.send(value).ok();
+    }
+}
+
+/// An automaton which can emit auxiliary values on a side channel while it
+/// computes its value. Wrap a group of these with [`with_side_channel`] to
+/// run them on any of the executors in this module.
+///
+pub trait Streaming: Automaton {
+    /// The type of the auxiliary values emitted by the task.
+    type Aux;
+
+    /// Run the task, like [`Automaton::value`], emitting any auxiliary
+    /// values on the given side channel.
+    fn value_streaming(self, side: &SideChannel<Self::Aux>) -> Self::Value;
+}
+
+/// An adapter which makes a [`Streaming`] task into a regular automaton, by
+/// attaching a side channel to it. Created by [`with_side_channel`].
+///
+pub struct Streamed<A: Streaming> {
+    automaton: A,
+    side: SideChannel<A::Aux>,
+}
+
+impl<A: Streaming> Automaton for Streamed<A> {
+    type Key = A::Key;
+    type Message = A::Message;
+    type Value = A::Value;
+
+    fn key(&self) -> Self::Key {
+        self.automaton.key()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.automaton.messages()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        self.automaton.receive(message)
+    }
+
+    fn value(self) -> Self::Value {
+        self.automaton.value_streaming(&self.side)
+    }
+
+    fn speculate(&mut self) {
+        self.automaton.speculate()
+    }
+
+    fn num_incoming(&self) -> Option<usize> {
+        self.automaton.num_incoming()
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        self.automaton.worker_hint()
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        self.automaton.locality()
+    }
+
+    fn priority(&self) -> Option<usize> {
+        self.automaton.priority()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        self.automaton.cost()
+    }
+
+    fn message_size(message: &Self::Message) -> usize {
+        A::message_size(message)
+    }
+}
+
+/// Attach a side channel to each task in a group. Returns the wrapped tasks,
+/// which can be passed to any executor, and the receiving end of the side
+/// channel. The receiver yields auxiliary values as the tasks emit them, and
+/// is disconnected once all of the tasks have completed.
+///
+pub fn with_side_channel<I, A>(flow: I) -> (impl Iterator<Item = Streamed<A>>, crossbeam_channel::Receiver<A::Aux>)
+where
+    I: IntoIterator<Item = A>,
+    A: Streaming,
+{
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let side = SideChannel { sender };
+    let flow = flow.into_iter().map(move |automaton| Streamed {
+        automaton,
+        side: side.clone(),
+    });
+    (flow, receiver)
+}
+
+/// An accelerator (e.g. a GPU) which can run compute kernels on behalf of
+/// tasks. The crate contains no device code; applications implement this
+/// trait to hand a task's buffers to their own device runtime. See
+/// [`Offload`] and [`with_devices`].
+///
+pub trait DeviceExecutor: Send + Sync {
+    /// Run the named kernel on the given input buffers, writing the
+    /// results to the output buffers. Returns false if the device does not
+    /// support the kernel or failed to run it, in which case the task falls
+    /// back to computing on the CPU.
+    fn run(&self, kernel: &str, inputs: &[&[f64]], outputs: &mut [&mut [f64]]) -> bool;
+}
+
+/// An automaton whose heavy compute can be offloaded to a
+/// [`DeviceExecutor`]. Wrap a group of these with [`with_devices`] to run
+/// them on any of the executors in this module.
+///
+pub trait Offload: Automaton {
+    /// This method may be implemented to request a device, by its index in
+    /// the list given to [`with_devices`]. Tasks with no preference, or
+    /// which prefer a device that does not exist, run on the CPU.
+    fn device_preference(&self) -> Option<usize> {
+        None
+    }
+
+    /// Run the task, like [`Automaton::value`], passing its buffers to the
+    /// device for the heavy compute. Implementations must fall back to the
+    /// CPU if [`DeviceExecutor::run`] returns false.
+    fn value_offloaded(self, device: &dyn DeviceExecutor) -> Self::Value;
+}
+
+/// An adapter which makes an [`Offload`] task into a regular automaton,
+/// which is evaluated on its preferred device if there is one. Created by
+/// [`with_devices`].
+///
+pub struct Offloaded<A: Offload> {
+    automaton: A,
+    devices: Arc<Vec<Arc<dyn DeviceExecutor>>>,
+}
+
+impl<A: Offload> Automaton for Offloaded<A> {
+    type Key = A::Key;
+    type Message = A::Message;
+    type Value = A::Value;
+
+    fn key(&self) -> Self::Key {
+        self.automaton.key()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.automaton.messages()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        self.automaton.receive(message)
+    }
+
+    fn value(self) -> Self::Value {
+        let Self { automaton, devices } = self;
+
+        match automaton.device_preference().and_then(|n| devices.get(n)) {
+            Some(device) => automaton.value_offloaded(device.as_ref()),
+            None => automaton.value(),
+        }
+    }
+
+    fn speculate(&mut self) {
+        self.automaton.speculate()
+    }
+
+    fn num_incoming(&self) -> Option<usize> {
+        self.automaton.num_incoming()
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        self.automaton.worker_hint()
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        self.automaton.locality()
+    }
+
+    fn priority(&self) -> Option<usize> {
+        self.automaton.priority()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        self.automaton.cost()
+    }
+
+    fn message_size(message: &Self::Message) -> usize {
+        A::message_size(message)
+    }
+}
+
+/// Attach a list of devices to each task in a group. The returned tasks can
+/// be passed to any executor; each one is evaluated on the device it
+/// prefers, or on the CPU.
+///
+pub fn with_devices<I, A>(flow: I, devices: Vec<Arc<dyn DeviceExecutor>>) -> impl Iterator<Item = Offloaded<A>>
+where
+    I: IntoIterator<Item = A>,
+    A: Offload,
+{
+    let devices = Arc::new(devices);
+    flow.into_iter().map(move |automaton| Offloaded {
+        automaton,
+        devices: devices.clone(),
+    })
+}
+
+/// Per-key execution times and worker assignments, for executors in
+/// self-tuning mode (see [`with_tuning`]). Each time a tuned group is
+/// created, the times recorded while running the previous group are packed
+/// greedily onto the workers (longest task first, onto the least loaded
+/// worker), and each task is hinted to its assigned worker. Tasks whose key
+/// has no recorded time keep their own hint. Tuning is only effective with
+/// executors and spawn policies which follow worker hints, e.g.
+/// [`execute_par_stupid`] with the default policy.
+///
+pub struct WorkerTuner<K> {
+    num_workers: usize,
+    enabled: AtomicBool,
+    state: Mutex<TunerState<K>>,
+    clock: Arc<dyn Clock>,
+}
+
+struct TunerState<K> {
+    times: HashMap<K, f64>,
+    assignment: HashMap<K, usize>,
+}
+
+impl<K: Hash + Eq + Clone> WorkerTuner<K> {
+    /// Create an enabled tuner for the given number of workers.
+    pub fn new(num_workers: usize) -> Self {
+        Self::with_clock(num_workers, Arc::new(SystemClock))
+    }
+
+    /// Create an enabled tuner for the given number of workers, which times
+    /// tuned tasks with the given clock.
+    pub fn with_clock(num_workers: usize, clock: Arc<dyn Clock>) -> Self {
+        assert!(num_workers > 0, "a worker tuner needs at least one worker");
+        Self {
+            num_workers,
+            enabled: AtomicBool::new(true),
+            state: Mutex::new(TunerState {
+                times: HashMap::new(),
+                assignment: HashMap::new(),
+            }),
+            clock,
+        }
+    }
+
+    /// Turn self-tuning on or off. While it's off, tasks keep their own
+    /// worker hints, but execution times are still recorded.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed)
+    }
+
+    /// Return true if self-tuning is on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record the execution time, in seconds, of the task with the given
+    /// key. Tuned tasks do this automatically.
+    pub fn record(&self, key: K, seconds: f64) {
+        self.state.lock().unwrap().times.insert(key, seconds);
+    }
+
+    /// Return the worker assigned to the given key by the last rebalance, if
+    /// tuning is on and the key had a recorded time.
+    pub fn assignment(&self, key: &K) -> Option<usize> {
+        if self.is_enabled() {
+            self.state.lock().unwrap().assignment.get(key).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Pack the recorded times onto the workers, replacing the previous
+    /// assignment, and clear the recorded times. If `metrics` is given, the
+    /// predicted load imbalance (maximum over mean worker load) is recorded
+    /// as `tuning.imbalance`, and the number of keys moved to a different
+    /// worker as `tuning.moved`.
+    ///
+    pub fn rebalance(&self, metrics: Option<&Metrics>) {
+        let mut state = self.state.lock().unwrap();
+        let mut times: Vec<_> = state.times.drain().collect();
+        times.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut loads = vec![0.0_f64; self.num_workers];
+        let mut assignment = HashMap::with_capacity(times.len());
+
+        for (key, time) in times {
+            let worker = (0..self.num_workers).min_by(|&a, &b| loads[a].total_cmp(&loads[b])).unwrap();
+            loads[worker] += time;
+            assignment.insert(key, worker);
+        }
+
+        if let Some(metrics) = metrics {
+            let moved = assignment
+                .iter()
+                .filter(|(key, worker)| state.assignment.get(*key).is_some_and(|w| w != *worker))
+                .count();
+            let total: f64 = loads.iter().sum();
+
+            if total > 0.0 {
+                let max = loads.iter().cloned().fold(0.0, f64::max);
+                metrics.record("tuning.imbalance", max * self.num_workers as f64 / total);
+            }
+            metrics.record("tuning.moved", moved as f64);
+        }
+        state.assignment = assignment;
+    }
+}
+
+/// An adapter which times a task's evaluation, and hints it to the worker
+/// chosen by a [`WorkerTuner`]. Created by [`with_tuning`].
+///
+pub struct Tuned<A: Automaton> {
+    automaton: A,
+    tuner: Arc<WorkerTuner<A::Key>>,
+    hint: Option<usize>,
+}
+
+impl<A> Automaton for Tuned<A>
+where
+    A: Automaton,
+    A::Key: Hash + Eq + Clone,
+{
+    type Key = A::Key;
+    type Message = A::Message;
+    type Value = A::Value;
+
+    fn key(&self) -> Self::Key {
+        self.automaton.key()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.automaton.messages()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        self.automaton.receive(message)
+    }
+
+    fn value(self) -> Self::Value {
+        let Self { automaton, tuner, .. } = self;
+        let key = automaton.key();
+        let start = tuner.clock.now();
+        let value = automaton.value();
+        tuner.record(key, (tuner.clock.now() - start).as_secs_f64());
+        value
+    }
+
+    fn speculate(&mut self) {
+        self.automaton.speculate()
+    }
+
+    fn num_incoming(&self) -> Option<usize> {
+        self.automaton.num_incoming()
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        self.hint.or_else(|| self.automaton.worker_hint())
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        self.automaton.locality()
+    }
+
+    fn priority(&self) -> Option<usize> {
+        self.automaton.priority()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        self.automaton.cost()
+    }
+
+    fn message_size(message: &Self::Message) -> usize {
+        A::message_size(message)
+    }
+}
+
+/// Run a group of tasks in self-tuning mode. The tuner is first rebalanced
+/// using the times recorded for the previous group (see
+/// [`WorkerTuner::rebalance`]), and then each task is timed and hinted to
+/// its assigned worker. The returned tasks can be passed to any executor.
+///
+pub fn with_tuning<I, A>(flow: I, tuner: &Arc<WorkerTuner<A::Key>>, metrics: Option<&Metrics>) -> impl Iterator<Item = Tuned<A>>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton,
+    A::Key: Hash + Eq + Clone,
+{
+    tuner.rebalance(metrics);
+    let tuner = tuner.clone();
+    flow.into_iter().map(move |automaton| Tuned {
+        hint: tuner.assignment(&automaton.key()),
+        tuner: tuner.clone(),
+        automaton,
+    })
+}
+
+/// Order a group of tasks by decreasing [`Automaton::cost`], so that
+/// executors start the longest tasks first. Tasks with no cost estimate go
+/// last, and tasks with equal costs keep their order. The returned iterator
+/// can be passed to any executor.
+///
+pub fn with_cost_order<I, A>(flow: I) -> impl Iterator<Item = A>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton,
+{
+    with_cost_order_by(flow, A::cost)
+}
+
+/// Like [`with_cost_order`], but with the cost of each task given by a
+/// function, e.g. one which looks up execution times measured on a previous
+/// step.
+///
+pub fn with_cost_order_by<I, A, F>(flow: I, cost: F) -> impl Iterator<Item = A>
+where
+    I: IntoIterator<Item = A>,
+    F: Fn(&A) -> Option<f64>,
+{
+    let mut tasks: Vec<_> = flow.into_iter().map(|a| (cost(&a), a)).collect();
+    tasks.sort_by(|(a, _), (b, _)| {
+        let a = a.unwrap_or(f64::NEG_INFINITY);
+        let b = b.unwrap_or(f64::NEG_INFINITY);
+        b.total_cmp(&a)
+    });
+    tasks.into_iter().map(|(_, a)| a)
+}
+
+/// The state of a task shared with the job which runs its speculation. The
+/// task is taken out when it is evaluated, so a job which has not started
+/// by then does nothing.
+///
+struct Speculation<A> {
+    automaton: Option<A>,
+    panic: Option<String>,
+}
+
+/// An adapter which runs a task's [`Automaton::speculate`] method on the
+/// Rayon thread pool while its messages are being exchanged. Created by
+/// [`with_speculation`].
+///
+/// The task's messages, key, and scheduling hints are taken before the
+/// speculation starts, so the executor never waits for it to send messages.
+/// Incoming messages are held until the task is evaluated if the task
+/// reports [`Automaton::num_incoming`], and are otherwise received as they
+/// arrive, once the speculation has finished. Evaluating the task waits for
+/// a speculation which is still running, and skips one which has not
+/// started yet.
+///
+pub struct Speculative<A: Automaton> {
+    key: A::Key,
+    messages: Cell<Vec<(A::Key, A::Message)>>,
+    num_incoming: Option<usize>,
+    inbox: Vec<A::Message>,
+    worker_hint: Option<usize>,
+    locality: Option<(i64, i64)>,
+    priority: Option<usize>,
+    cost: Option<f64>,
+    shared: Arc<Mutex<Speculation<A>>>,
+}
+
+impl<A: Automaton> Speculative<A> {
+    fn lock(&self) -> MutexGuard<'_, Speculation<A>> {
+        self.shared.lock().unwrap()
+    }
+}
+
+impl<A> Automaton for Speculative<A>
+where
+    A: Automaton,
+    A::Key: Clone,
+{
+    type Key = A::Key;
+    type Message = A::Message;
+    type Value = A::Value;
+
+    fn key(&self) -> Self::Key {
+        self.key.clone()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.messages.take()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        match self.num_incoming {
+            Some(num_incoming) => {
+                self.inbox.push(message);
+                Status::eligible_if(self.inbox.len() == num_incoming)
+            }
+            None => self.lock().automaton.as_mut().unwrap().receive(message),
+        }
+    }
+
+    fn value(self) -> Self::Value {
+        let (automaton, panic) = {
+            let mut speculation = self.lock();
+            (speculation.automaton.take(), speculation.panic.take())
+        };
+        if let Some(message) = panic {
+            panic!("{}", message)
+        }
+        let mut automaton = automaton.unwrap();
+
+        for message in self.inbox {
+            automaton.receive(message);
+        }
+        automaton.value()
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        self.worker_hint
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        self.locality
+    }
+
+    fn priority(&self) -> Option<usize> {
+        self.priority
+    }
+
+    fn cost(&self) -> Option<f64> {
+        self.cost
+    }
+
+    fn message_size(message: &Self::Message) -> usize {
+        A::message_size(message)
+    }
+}
+
+/// Run a group of tasks in speculative mode. As each task is yielded from
+/// the input iterator, its messages are taken, and its
+/// [`Automaton::speculate`] method is spawned on the Rayon thread pool (the
+/// current one if this is called from within a pool, and otherwise the
+/// global one), so the speculation overlaps with the delivery of messages
+/// by the executor. The returned iterator can be passed to any executor.
+/// See [`Speculative`] for how incoming messages are handled.
+///
+pub fn with_speculation<I, A>(flow: I) -> impl Iterator<Item = Speculative<A>>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton + Send + 'static,
+    A::Key: Clone,
+{
+    flow.into_iter().map(|automaton| {
+        let speculative = Speculative {
+            key: automaton.key(),
+            messages: Cell::new(automaton.messages()),
+            num_incoming: automaton.num_incoming(),
+            inbox: Vec::new(),
+            worker_hint: automaton.worker_hint(),
+            locality: automaton.locality(),
+            priority: automaton.priority(),
+            cost: automaton.cost(),
+            shared: Arc::new(Mutex::new(Speculation {
+                automaton: Some(automaton),
+                panic: None,
+            })),
+        };
+        let shared = speculative.shared.clone();
+
+        rayon::spawn(move || {
+            let Speculation { automaton, panic } = &mut *shared.lock().unwrap();
+
+            if let Some(automaton) = automaton {
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| automaton.speculate()));
+                *panic = result.err().map(|payload| panic_message(&*payload));
+            }
+        });
+        speculative
+    })
 }
 
 /// Execute a group of tasks in serial.
@@ -97,6 +781,23 @@ where
     eligible_source.into_iter().map(|peer: A| peer.value())
 }
 
+/// Execute a group of tasks in serial, with messages injected from outside
+/// the group. See [`coordinate_injected`].
+///
+pub fn execute_injected<I, A, K, V>(stage: I, injected: &crossbeam_channel::Receiver<(K, A::Message)>) -> impl Iterator<Item = V>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton<Key = K, Value = V>,
+    K: Hash + Eq,
+{
+    let (eligible_sink, eligible_source) = crossbeam_channel::unbounded();
+
+    coordinate_injected(stage, injected, |a: A| eligible_sink.send(a).unwrap(), &Limits::default(), None)
+        .expect("the default limits are never exceeded");
+
+    eligible_source.into_iter().map(|peer: A| peer.value())
+}
+
 /// Execute a group of tasks in parallel on the Rayon thread pool. As tasks
 /// are yielded from the input iterator (`flow`), their messages are gathered
 /// and delivered to any pending tasks. Those tasks which become eligible upon
@@ -127,7 +828,47 @@ where
     source.into_iter()
 }
 
+/// A panic which occurred while evaluating a task, identified by the task's
+/// key.
+///
+#[derive(Clone, Debug)]
+pub struct TaskPanic<K> {
+    pub key: K,
+
+    /// The thread pool worker which evaluated the task, if it ran on one.
+    pub worker_id: Option<usize>,
+
+    /// The panic message, if the panic payload was a string.
+    pub message: String,
+}
+
+impl<K: fmt::Debug> fmt::Display for TaskPanic<K> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.worker_id {
+            Some(worker_id) => write!(fmt, "task {:?} panicked on worker {}: {}", self.key, worker_id, self.message),
+            None => write!(fmt, "task {:?} panicked: {}", self.key, self.message),
+        }
+    }
+}
+
+/// Evaluate a task, catching a panic and reporting it with the task's key.
+///
+fn evaluate_contained<A: Automaton>(a: A) -> Result<A::Value, TaskPanic<A::Key>> {
+    let key = a.key();
+
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| a.value())).map_err(|payload| TaskPanic {
+        key,
+        worker_id: current_worker_id(),
+        message: panic_message(&*payload),
+    })
+}
+
 /// Execute a group of tasks in parallel using `gridiron`'s stupid scheduler.
+/// Tasks are placed on workers according to the pool's spawn policy.
+///
+/// If a task panics, the panic is caught on the worker and sent back with
+/// the task's key, and the returned iterator panics with a message naming
+/// the task, rather than waiting forever for its result.
 ///
 pub fn execute_par_stupid<I, A, K, V>(
     pool: &crate::thread_pool::ThreadPool,
@@ -136,7 +877,7 @@ pub fn execute_par_stupid<I, A, K, V>(
 where
     I: IntoIterator<Item = A>,
     A: 'static + Send + Automaton<Key = K, Value = V>,
-    K: 'static + Hash + Eq,
+    K: 'static + Send + Hash + Eq + fmt::Debug,
     V: 'static + Send,
 {
     assert! {
@@ -148,11 +889,11 @@ where
 
     coordinate(flow, |a: A| {
         let sink = sink.clone();
-        pool.spawn_on(a.worker_hint(), move || {
-            sink.send(a.value()).unwrap();
+        pool.spawn_placed(a.worker_hint(), a.locality(), move || {
+            sink.send(evaluate_contained(a)).unwrap();
         });
     });
-    source.into_iter()
+    source.into_iter().map(|result| result.unwrap_or_else(|panic| panic!("{}", panic)))
 }
 
 fn coordinate<I, A, K, V, S>(flow: I, sink: S)
@@ -162,48 +903,945 @@ where
     K: Hash + Eq,
     S: Fn(A),
 {
-    let mut seen: HashMap<K, A> = HashMap::new();
-    let mut undelivered = HashMap::new();
-
-    for mut a in flow {
-        // For each of A's messages, either deliver it to the recipient peer,
-        // if the peer has already been seen, or otherwise put it in the
-        // undelivered box.
-        //
-        // If any of the recipient peers became eligible upon receiving a
-        // message, then send those peers off to be executed.
-        //
+    coordinate_bounded(flow, sink, &Limits::default(), None).expect("the default limits are never exceeded")
+}
+
+/// Deliver messages between the tasks yielded by `flow`, and pass each task
+/// to `sink` as soon as it becomes eligible. This is the event loop shared by
+/// the executors in this module, and it may be used to write new ones.
+///
+/// Tasks which become eligible while processing the same input task are
+/// passed to `sink` in order of decreasing [`Automaton::priority`].
+///
+/// Messages addressed to tasks which have not been yielded yet are held in
+/// an undelivered box. If the undelivered box exceeds the given limits, the
+/// rest of the group is abandoned and an [`Error::Undelivered`] is returned;
+/// the tasks already passed to `sink` are unaffected. If `metrics` is given,
+/// the number of undelivered messages and bytes after each task is admitted
+/// are recorded as `automaton.held_messages` and `automaton.held_bytes` (so
+/// their last value is the current count), and the peak numbers as
+/// `automaton.undelivered_messages` and `automaton.undelivered_bytes`.
+///
+pub fn coordinate_bounded<I, A, K, V, S>(flow: I, sink: S, limits: &Limits, metrics: Option<&Metrics>) -> error::Result<()>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton<Key = K, Value = V>,
+    K: Hash + Eq,
+    S: Fn(A),
+{
+    let mut coordinator = Coordinator::new(sink, limits, metrics);
+
+    for a in flow {
+        coordinator.admit(a)?
+    }
+    coordinator.finish();
+    Ok(())
+}
+
+/// Like [`coordinate_bounded`], but messages may also be injected into the
+/// group from outside of it (e.g. by a thread relaying parameters broadcast
+/// by a controller rank), by sending `(key, message)` pairs to the
+/// `injected` channel. Injected messages are delivered through the same path
+/// as messages from peers: to the recipient's [`Automaton::receive`] if it
+/// has been yielded, and otherwise to the undelivered box. The recipient
+/// must count them among its incoming messages.
+///
+/// Once `flow` is exhausted, this function blocks on the channel until
+/// every task has become eligible, and it panics if the channel is
+/// disconnected first. Like [`coordinate_bounded`], it returns an error if
+/// the undelivered box exceeds the limits. Messages still in the channel when the group is
+/// complete are left there for the next call, so a message is delivered in
+/// the iteration which is current when it's received.
+///
+pub fn coordinate_injected<I, A, K, V, S>(
+    flow: I,
+    injected: &crossbeam_channel::Receiver<(K, A::Message)>,
+    sink: S,
+    limits: &Limits,
+    metrics: Option<&Metrics>,
+) -> error::Result<()>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton<Key = K, Value = V>,
+    K: Hash + Eq,
+    S: Fn(A),
+{
+    let mut coordinator = Coordinator::new(sink, limits, metrics);
+
+    for a in flow {
+        for (dest, data) in injected.try_iter() {
+            coordinator.deliver(dest, data)?
+        }
+        coordinator.admit(a)?
+    }
+    while !coordinator.seen.is_empty() {
+        match injected.recv() {
+            Ok((dest, data)) => coordinator.deliver(dest, data)?,
+            Err(_) => break,
+        }
+        coordinator.flush()
+    }
+    coordinator.finish();
+    Ok(())
+}
+
+/// The state of the event loop run by [`coordinate_bounded`] and
+/// [`coordinate_injected`].
+///
+struct Coordinator<'a, A: Automaton, S> {
+    sink: S,
+    limits: &'a Limits,
+    metrics: Option<&'a Metrics>,
+    seen: HashMap<A::Key, (A, usize)>,
+    eligible: Vec<(usize, A)>,
+    undelivered: HashMap<A::Key, Vec<A::Message>>,
+    num_messages: usize,
+    num_bytes: usize,
+    peak_messages: usize,
+    peak_bytes: usize,
+}
+
+impl<'a, A, K, S> Coordinator<'a, A, S>
+where
+    A: Automaton<Key = K>,
+    K: Hash + Eq,
+    S: Fn(A),
+{
+    fn new(sink: S, limits: &'a Limits, metrics: Option<&'a Metrics>) -> Self {
+        Self {
+            sink,
+            limits,
+            metrics,
+            seen: HashMap::new(),
+            eligible: Vec::new(),
+            undelivered: HashMap::new(),
+            num_messages: 0,
+            num_bytes: 0,
+            peak_messages: 0,
+            peak_bytes: 0,
+        }
+    }
+
+    /// Deliver a message to the recipient peer, if the peer has already been
+    /// seen, or otherwise put it in the undelivered box. If the recipient
+    /// became eligible upon receiving the message, then queue it to be
+    /// executed. An error is returned if the message would take the
+    /// undelivered box over the limits.
+    fn deliver(&mut self, dest: K, data: A::Message) -> error::Result<()> {
+        match self.seen.entry(dest) {
+            Entry::Occupied(mut entry) => {
+                if let Status::Eligible = entry.get_mut().0.receive(data) {
+                    let (peer, out_degree) = entry.remove();
+                    self.eligible.push((peer.priority().unwrap_or(out_degree), peer))
+                }
+            }
+            Entry::Vacant(none) => {
+                let num_messages = self.num_messages + 1;
+                let num_bytes = self.num_bytes.saturating_add(A::message_size(&data));
+
+                if num_messages > self.limits.max_messages || num_bytes > self.limits.max_bytes {
+                    self.record_held(num_messages, num_bytes);
+                    return Err(Error::Undelivered {
+                        num_messages,
+                        num_bytes,
+                        max_messages: self.limits.max_messages,
+                        max_bytes: self.limits.max_bytes,
+                    });
+                }
+                self.num_messages = num_messages;
+                self.num_bytes = num_bytes;
+                self.peak_messages = self.peak_messages.max(num_messages);
+                self.peak_bytes = self.peak_bytes.max(num_bytes);
+                self.undelivered.entry(none.into_key()).or_default().push(data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Deliver each of A's messages. Then deliver any messages addressed to
+    /// A that had arrived previously. If A is eligible after receiving its
+    /// messages, then queue it to be executed. Otherwise mark it as seen.
+    /// Then send the queued tasks off to be executed.
+    fn admit(&mut self, mut a: A) -> error::Result<()> {
+        let messages = a.messages();
+        let out_degree = messages.len();
+
+        for (dest, data) in messages {
+            self.deliver(dest, data)?
+        }
+
+        let is_eligible = self.undelivered.remove_entry(&a.key()).map_or(false, |(_, messages)| {
+            self.num_messages -= messages.len();
+            self.num_bytes -= messages.iter().map(A::message_size).sum::<usize>();
+            messages.into_iter().any(|m| a.receive(m).is_eligible())
+        });
+
+        if is_eligible {
+            self.eligible.push((a.priority().unwrap_or(out_degree), a))
+        } else {
+            self.seen.insert(a.key(), (a, out_degree));
+        }
+        self.record_held(self.num_messages, self.num_bytes);
+        self.flush();
+        Ok(())
+    }
+
+    fn record_held(&self, num_messages: usize, num_bytes: usize) {
+        if let Some(metrics) = self.metrics {
+            metrics.record("automaton.held_messages", num_messages as f64);
+            metrics.record("automaton.held_bytes", num_bytes as f64);
+        }
+    }
+
+    /// Send the queued tasks off to be executed, highest priority first.
+    fn flush(&mut self) {
+        self.eligible.sort_by(|(p, _), (q, _)| q.cmp(p));
+
+        for (_, task) in self.eligible.drain(..) {
+            (self.sink)(task)
+        }
+    }
+
+    fn finish(self) {
+        assert_eq!(self.seen.len(), 0);
+
+        if let Some(metrics) = self.metrics {
+            metrics.record("automaton.undelivered_messages", self.peak_messages as f64);
+            metrics.record("automaton.undelivered_bytes", self.peak_bytes as f64);
+        }
+    }
+}
+
+/// The state of a group of tasks being advanced through several iterations
+/// with [`execute_pipelined`] or [`execute_pipelined_par`]. Each message is
+/// tagged with the iteration it belongs to, so a task which has moved ahead
+/// to the next iteration does not receive messages meant for a peer that is
+/// still on the previous one.
+///
+struct Pipeline<A: Automaton> {
+    num_tasks: usize,
+    num_iterations: usize,
+    depth: usize,
+    frontier: usize,
+    completed: Vec<usize>,
+    pending: HashMap<A::Key, (A, usize)>,
+    inbox: HashMap<(usize, A::Key), Vec<A::Message>>,
+    ready: VecDeque<(A, usize)>,
+    deferred: Vec<(A, usize)>,
+    finished: Vec<A>,
+    in_flight: usize,
+}
+
+impl<A, K> Pipeline<A>
+where
+    A: Automaton<Key = K, Value = A>,
+    K: Hash + Eq,
+{
+    fn new<I: IntoIterator<Item = A>>(tasks: I, num_iterations: usize, depth: usize) -> Self {
+        assert!(depth > 0, "the pipeline depth must be at least one");
+
+        let mut pipeline = Self {
+            num_tasks: 0,
+            num_iterations,
+            depth,
+            frontier: 0,
+            completed: vec![0; num_iterations],
+            pending: HashMap::new(),
+            inbox: HashMap::new(),
+            ready: VecDeque::new(),
+            deferred: Vec::new(),
+            finished: Vec::new(),
+            in_flight: 0,
+        };
+        for task in tasks {
+            pipeline.num_tasks += 1;
+            pipeline.admit(task, 0)
+        }
+        pipeline
+    }
+
+    /// Send the messages of a task starting the given iteration, and then
+    /// deliver the messages already sent to it in that iteration.
+    fn admit(&mut self, mut a: A, iteration: usize) {
+        if iteration == self.num_iterations {
+            self.finished.push(a);
+            return;
+        }
         for (dest, data) in a.messages() {
-            match seen.entry(dest) {
-                Entry::Occupied(mut entry) => {
-                    if let Status::Eligible = entry.get_mut().receive(data) {
-                        sink(entry.remove())
+            match self.pending.get_mut(&dest) {
+                Some((peer, n)) if *n == iteration => {
+                    if peer.receive(data).is_eligible() {
+                        let (peer, _) = self.pending.remove(&dest).unwrap();
+                        self.schedule(peer, iteration)
                     }
                 }
-                Entry::Vacant(none) => {
-                    undelivered
-                        .entry(none.into_key())
-                        .or_insert_with(Vec::new)
-                        .push(data);
-                }
+                _ => self.inbox.entry((iteration, dest)).or_default().push(data),
             }
         }
+        let is_eligible = self
+            .inbox
+            .remove(&(iteration, a.key()))
+            .is_some_and(|messages| messages.into_iter().any(|m| a.receive(m).is_eligible()));
 
-        // Deliver any messages addressed to A that had arrived previously. If
-        // A is eligible after receiving its messages, then send it off to be
-        // executed. Otherwise mark it as seen and process the next automaton.
-        //
-        let eligible = undelivered
-            .remove_entry(&a.key())
-            .map_or(false, |(_, messages)| {
-                messages.into_iter().any(|m| a.receive(m).is_eligible())
-            });
+        if is_eligible {
+            self.schedule(a, iteration)
+        } else {
+            self.pending.insert(a.key(), (a, iteration));
+        }
+    }
 
-        if eligible {
-            sink(a)
+    /// Queue an eligible task to be run, or defer it if its iteration is too
+    /// far ahead of the oldest unfinished one.
+    fn schedule(&mut self, a: A, iteration: usize) {
+        if iteration < self.frontier + self.depth {
+            self.ready.push_back((a, iteration))
         } else {
-            seen.insert(a.key(), a);
+            self.deferred.push((a, iteration))
+        }
+    }
+
+    /// Take the next task to run, with its iteration number.
+    fn pop_ready(&mut self) -> Option<(A, usize)> {
+        let next = self.ready.pop_front();
+        if next.is_some() {
+            self.in_flight += 1;
+        }
+        next
+    }
+
+    /// Record that a task has finished the given iteration, yielding the
+    /// task for the next one.
+    fn complete(&mut self, a: A, iteration: usize) {
+        self.in_flight -= 1;
+        self.completed[iteration] += 1;
+
+        while self.frontier < self.num_iterations && self.completed[self.frontier] == self.num_tasks {
+            self.frontier += 1;
+        }
+        for (a, iteration) in std::mem::take(&mut self.deferred) {
+            self.schedule(a, iteration)
+        }
+        self.admit(a, iteration + 1)
+    }
+
+    fn into_finished(self) -> Vec<A> {
+        assert!(self.pending.is_empty(), "{} tasks never became eligible", self.pending.len());
+        self.finished
+    }
+}
+
+/// Advance a group of tasks through `num_iterations` iterations in serial,
+/// where the value of each task is the task for its next iteration. Rather
+/// than executing the whole group once per iteration, a task starts its next
+/// iteration as soon as it has the messages for it, so different parts of
+/// the group may be on different iterations. At most `depth` iterations are
+/// in flight at once: no task may start iteration `n + depth` until every
+/// task has finished iteration `n`. A depth of one is equivalent to calling
+/// [`execute`] once per iteration. Returns the tasks after the final
+/// iteration, in order of completion.
+///
+pub fn execute_pipelined<I, A, K>(tasks: I, num_iterations: usize, depth: usize) -> Vec<A>
+where
+    I: IntoIterator<Item = A>,
+    A: Automaton<Key = K, Value = A>,
+    K: Hash + Eq,
+{
+    let mut pipeline = Pipeline::new(tasks, num_iterations, depth);
+
+    while let Some((a, iteration)) = pipeline.pop_ready() {
+        pipeline.complete(a.value(), iteration)
+    }
+    pipeline.into_finished()
+}
+
+/// Like [`execute_pipelined`], but running the tasks on the Rayon thread
+/// pool. The calling thread delivers messages and spawns tasks as they
+/// become eligible, and blocks until all the iterations are complete.
+///
+pub fn execute_pipelined_par<'a, I, A, K>(scope: &rayon::ScopeFifo<'a>, tasks: I, num_iterations: usize, depth: usize) -> Vec<A>
+where
+    I: IntoIterator<Item = A>,
+    A: Send + Automaton<Key = K, Value = A> + 'a,
+    K: Hash + Eq,
+{
+    assert! {
+        rayon::current_num_threads() >= 2,
+        "automaton::execute_pipelined_par requires the Rayon pool to be running at least two threads"
+    };
+
+    let (sink, source) = crossbeam_channel::unbounded();
+    let mut pipeline = Pipeline::new(tasks, num_iterations, depth);
+
+    loop {
+        while let Some((a, iteration)) = pipeline.pop_ready() {
+            let sink = sink.clone();
+            scope.spawn_fifo(move |_| sink.send((a.value(), iteration)).unwrap())
+        }
+        if pipeline.in_flight == 0 {
+            break;
+        }
+        let (a, iteration) = source.recv().unwrap();
+        pipeline.complete(a, iteration)
+    }
+    pipeline.into_finished()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        coordinate_bounded, evaluate_contained, execute, execute_injected, execute_pipelined, with_cost_order, with_cost_order_by, with_devices,
+        with_side_channel, with_speculation, with_tuning, Automaton, DeviceExecutor, Limits, Offload, SideChannel, Status, Streaming, WorkerTuner,
+    };
+    use crate::clock::{Clock, MockClock};
+    use crate::error::Error;
+    use crate::stats::Metrics;
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// A task which sends its key to every other task in the group, and
+    /// becomes eligible once it has heard from all of them.
+    struct AllToAll {
+        key: usize,
+        size: usize,
+        received: Vec<usize>,
+    }
+
+    impl Automaton for AllToAll {
+        type Key = usize;
+        type Message = usize;
+        type Value = usize;
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            (0..self.size)
+                .filter(|&key| key != self.key)
+                .map(|key| (key, self.key))
+                .collect()
+        }
+
+        fn receive(&mut self, message: Self::Message) -> Status {
+            self.received.push(message);
+            Status::eligible_if(self.received.len() == self.size - 1)
+        }
+
+        fn value(self) -> Self::Value {
+            self.received.iter().sum()
+        }
+
+        fn priority(&self) -> Option<usize> {
+            Some(self.key)
+        }
+
+        fn cost(&self) -> Option<f64> {
+            Some((self.key % 3) as f64)
         }
     }
-    assert_eq!(seen.len(), 0);
+
+    impl Streaming for AllToAll {
+        type Aux = (usize, usize);
+
+        fn value_streaming(self, side: &SideChannel<Self::Aux>) -> Self::Value {
+            side.send((self.key, self.received.len()));
+            self.value()
+        }
+    }
+
+    impl Offload for AllToAll {
+        fn device_preference(&self) -> Option<usize> {
+            Some(self.key % 3)
+        }
+
+        fn value_offloaded(self, device: &dyn DeviceExecutor) -> Self::Value {
+            let input: Vec<_> = self.received.iter().map(|&x| x as f64).collect();
+            let mut output = [0.0];
+
+            if device.run("sum", &[&input], &mut [&mut output]) {
+                output[0] as usize
+            } else {
+                self.value()
+            }
+        }
+    }
+
+    /// A device which sums its input, and counts the kernels it has run.
+    struct Summer(AtomicUsize, bool);
+
+    impl DeviceExecutor for Summer {
+        fn run(&self, kernel: &str, inputs: &[&[f64]], outputs: &mut [&mut [f64]]) -> bool {
+            if kernel != "sum" || !self.1 {
+                return false;
+            }
+            self.0.fetch_add(1, Ordering::Relaxed);
+            outputs[0][0] = inputs[0].iter().sum();
+            true
+        }
+    }
+
+    fn group(size: usize) -> impl Iterator<Item = AllToAll> {
+        (0..size).map(move |key| AllToAll {
+            key,
+            size,
+            received: Vec::new(),
+        })
+    }
+
+    /// A task which panics when it is evaluated.
+    struct Failing;
+
+    impl Automaton for Failing {
+        type Key = &'static str;
+        type Message = ();
+        type Value = ();
+
+        fn key(&self) -> Self::Key {
+            "failing"
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            Vec::new()
+        }
+
+        fn receive(&mut self, _: Self::Message) -> Status {
+            Status::Eligible
+        }
+
+        fn value(self) -> Self::Value {
+            panic!("out of range")
+        }
+    }
+
+    #[test]
+    fn task_panics_are_reported_with_the_task_key() {
+        let panic = evaluate_contained(Failing).unwrap_err();
+        assert_eq!(panic.key, "failing");
+        assert_eq!(panic.to_string(), "task \"failing\" panicked: out of range");
+        assert_eq!(evaluate_contained(group(1).next().unwrap()).unwrap(), 0);
+    }
+
+    #[test]
+    fn undelivered_messages_are_accounted() {
+        let metrics = Metrics::new();
+        let limits = Limits::default();
+        coordinate_bounded(group(4), |_| {}, &limits, Some(&metrics)).unwrap();
+        assert_eq!(metrics.get("automaton.undelivered_messages").unwrap().max, 5.0);
+        assert_eq!(metrics.get("automaton.undelivered_bytes").unwrap().max, 40.0);
+        assert_eq!(metrics.get("automaton.held_messages").unwrap().max, 4.0);
+        assert_eq!(metrics.get("automaton.held_messages").unwrap().last, 0.0);
+        assert_eq!(metrics.get("automaton.held_bytes").unwrap().count, 4);
+    }
+
+    #[test]
+    fn undelivered_message_limit_is_enforced() {
+        let metrics = Metrics::new();
+        let limits = Limits {
+            max_messages: 4,
+            ..Limits::default()
+        };
+        match coordinate_bounded(group(4), |_| {}, &limits, Some(&metrics)) {
+            Err(Error::Undelivered {
+                num_messages: 5,
+                max_messages: 4,
+                ..
+            }) => {}
+            _ => panic!("expected the undelivered message limit to be exceeded"),
+        }
+        assert_eq!(metrics.get("automaton.held_messages").unwrap().last, 5.0);
+        assert!(metrics.get("automaton.undelivered_messages").is_none());
+    }
+
+    /// A task which waits for a scale factor injected from outside the
+    /// group, and a message from its left neighbor on a ring.
+    struct Scaled {
+        key: usize,
+        size: usize,
+        scale: Option<usize>,
+        left: Option<usize>,
+    }
+
+    impl Automaton for Scaled {
+        type Key = usize;
+        type Message = (bool, usize);
+        type Value = usize;
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            vec![((self.key + 1) % self.size, (false, self.key))]
+        }
+
+        fn receive(&mut self, (injected, value): Self::Message) -> Status {
+            if injected {
+                self.scale = Some(value)
+            } else {
+                self.left = Some(value)
+            }
+            Status::eligible_if(self.scale.is_some() && self.left.is_some())
+        }
+
+        fn value(self) -> Self::Value {
+            self.scale.unwrap() * self.left.unwrap()
+        }
+    }
+
+    fn scaled(size: usize) -> impl Iterator<Item = Scaled> {
+        (0..size).map(move |key| Scaled {
+            key,
+            size,
+            scale: None,
+            left: None,
+        })
+    }
+
+    #[test]
+    fn injected_messages_are_delivered_with_peer_messages() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        sender.send((0, (true, 10))).unwrap();
+
+        let controller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            for key in 1..4 {
+                sender.send((key, (true, 10))).unwrap();
+            }
+            sender
+        });
+        let mut values: Vec<_> = execute_injected(scaled(4), &receiver).collect();
+        values.sort_unstable();
+        assert_eq!(values, [0, 10, 20, 30]);
+
+        // Messages injected after the group completes wait for the next one.
+        let sender = controller.join().unwrap();
+        sender.send((0, (true, 1))).unwrap();
+        assert_eq!(receiver.len(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn injection_channel_closing_with_pending_tasks_panics() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        sender.send((0, (true, 1))).unwrap();
+        drop(sender);
+        execute_injected(scaled(2), &receiver).for_each(drop);
+    }
+
+    #[test]
+    fn tasks_are_ordered_by_decreasing_cost() {
+        let keys: Vec<_> = with_cost_order(group(4)).map(|a| a.key).collect();
+        assert_eq!(keys, [2, 1, 0, 3]);
+
+        let keys: Vec<_> = with_cost_order_by(group(4), |a| (a.key != 1).then_some(a.key as f64))
+            .map(|a| a.key)
+            .collect();
+        assert_eq!(keys, [3, 2, 0, 1]);
+        assert_eq!(execute(with_cost_order(group(4))).sum::<usize>(), 18);
+    }
+
+    #[test]
+    fn tasks_eligible_together_are_spawned_by_priority() {
+        let order = RefCell::new(Vec::new());
+        coordinate_bounded(group(4), |a| order.borrow_mut().push(a.key), &Limits::default(), None).unwrap();
+        assert_eq!(order.into_inner(), [3, 2, 1, 0]);
+    }
+
+    /// A task on a ring which, each iteration, adds its neighbors' values to
+    /// its own. Each task logs the number of iterations it has completed.
+    struct Ring<'a> {
+        key: usize,
+        size: usize,
+        value: u64,
+        iteration: usize,
+        received: Vec<u64>,
+        log: &'a RefCell<Vec<usize>>,
+    }
+
+    impl<'a> Automaton for Ring<'a> {
+        type Key = usize;
+        type Message = u64;
+        type Value = Self;
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            let size = self.size;
+            vec![((self.key + 1) % size, self.value), ((self.key + size - 1) % size, self.value)]
+        }
+
+        fn receive(&mut self, message: Self::Message) -> Status {
+            self.received.push(message);
+            Status::eligible_if(self.received.len() == 2)
+        }
+
+        fn value(mut self) -> Self::Value {
+            self.value = self.value.wrapping_mul(31) + self.received.drain(..).sum::<u64>();
+            self.iteration += 1;
+            self.log.borrow_mut()[self.key] = self.iteration;
+            self
+        }
+    }
+
+    fn ring(size: usize, log: &RefCell<Vec<usize>>) -> Vec<Ring<'_>> {
+        *log.borrow_mut() = vec![0; size];
+        (0..size)
+            .map(|key| Ring {
+                key,
+                size,
+                value: key as u64,
+                iteration: 0,
+                received: Vec::new(),
+                log,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pipelined_execution_matches_repeated_execution() {
+        let log = RefCell::new(Vec::new());
+        let mut serial = ring(7, &log);
+
+        for _ in 0..5 {
+            serial = execute(serial).collect();
+        }
+        let mut expected: Vec<_> = serial.iter().map(|a| (a.key, a.value)).collect();
+        expected.sort_unstable();
+
+        for depth in 1..4 {
+            let mut pipelined: Vec<_> = execute_pipelined(ring(7, &log), 5, depth)
+                .iter()
+                .map(|a| (a.key, a.value))
+                .collect();
+            pipelined.sort_unstable();
+            assert_eq!(pipelined, expected);
+        }
+    }
+
+    #[test]
+    fn pipelined_iterations_in_flight_are_bounded() {
+        struct Bounded<'a>(Ring<'a>, usize);
+
+        impl<'a> Automaton for Bounded<'a> {
+            type Key = usize;
+            type Message = u64;
+            type Value = Self;
+
+            fn key(&self) -> Self::Key {
+                self.0.key
+            }
+
+            fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+                self.0.messages()
+            }
+
+            fn receive(&mut self, message: Self::Message) -> Status {
+                self.0.receive(message)
+            }
+
+            fn value(self) -> Self::Value {
+                let frontier = *self.0.log.borrow().iter().min().unwrap();
+                assert!(self.0.iteration < frontier + self.1);
+                Self(self.0.value(), self.1)
+            }
+        }
+
+        let log = RefCell::new(Vec::new());
+        for depth in 1..4 {
+            let tasks = ring(6, &log).into_iter().map(|a| Bounded(a, depth));
+            assert_eq!(execute_pipelined(tasks, 8, depth).len(), 6);
+        }
+    }
+
+    #[test]
+    fn tasks_run_on_their_preferred_device_or_fall_back() {
+        let working = Arc::new(Summer(AtomicUsize::new(0), true));
+        let failing = Arc::new(Summer(AtomicUsize::new(0), false));
+        let devices: Vec<Arc<dyn DeviceExecutor>> = vec![working.clone(), failing.clone()];
+
+        let mut values: Vec<_> = execute(with_devices(group(5), devices)).collect();
+        values.sort_unstable();
+
+        assert_eq!(values, [6, 7, 8, 9, 10]);
+        assert_eq!(working.0.load(Ordering::Relaxed), 2);
+        assert_eq!(failing.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn side_channel_values_arrive_before_the_group_finishes() {
+        let (flow, aux) = with_side_channel(group(4));
+        let mut values = execute(flow);
+
+        assert!(aux.try_recv().is_err());
+        values.next().unwrap();
+        assert_eq!(aux.try_recv().unwrap().1, 3);
+        assert!(aux.try_recv().is_err());
+        assert_eq!(values.count(), 3);
+
+        let mut keys: Vec<_> = aux.iter().map(|(key, _)| key).collect();
+        keys.sort_unstable();
+        assert_eq!(keys.len(), 3);
+    }
+
+    /// A task on a ring which, when speculating, waits for a while until the
+    /// input iterator is exhausted, and records whether it was.
+    struct Eager {
+        key: usize,
+        size: usize,
+        received: Option<usize>,
+        started: Arc<AtomicUsize>,
+        exhausted: Arc<AtomicBool>,
+        overlapped: bool,
+    }
+
+    impl Automaton for Eager {
+        type Key = usize;
+        type Message = usize;
+        type Value = (usize, usize, bool);
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            vec![((self.key + 1) % self.size, self.key)]
+        }
+
+        fn receive(&mut self, message: Self::Message) -> Status {
+            self.received = Some(message);
+            Status::Eligible
+        }
+
+        fn value(self) -> Self::Value {
+            (self.key, self.received.unwrap(), self.overlapped)
+        }
+
+        fn speculate(&mut self) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let start = Instant::now();
+
+            while !self.exhausted.load(Ordering::SeqCst) && start.elapsed() < Duration::from_secs(2) {
+                thread::sleep(Duration::from_millis(1))
+            }
+            self.overlapped = self.exhausted.load(Ordering::SeqCst)
+        }
+
+        fn num_incoming(&self) -> Option<usize> {
+            Some(1)
+        }
+    }
+
+    #[test]
+    fn speculation_overlaps_with_message_delivery() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let started = Arc::new(AtomicUsize::new(0));
+        let exhausted = Arc::new(AtomicBool::new(false));
+        let tasks: Vec<_> = (0..3)
+            .map(|key| Eager {
+                key,
+                size: 3,
+                received: None,
+                started: started.clone(),
+                exhausted: exhausted.clone(),
+                overlapped: false,
+            })
+            .collect();
+
+        // Every message has been delivered by the time the flow is
+        // exhausted. Waiting there for the speculations to start ensures
+        // that none of them is skipped.
+        let flow = with_speculation(tasks).chain(std::iter::from_fn(move || {
+            let start = Instant::now();
+
+            while started.load(Ordering::SeqCst) < 3 && start.elapsed() < Duration::from_secs(2) {
+                thread::sleep(Duration::from_millis(1))
+            }
+            exhausted.store(true, Ordering::SeqCst);
+            None
+        }));
+        let mut values: Vec<_> = pool.install(|| execute(flow).collect());
+        values.sort_unstable();
+
+        assert_eq!(values, [(0, 2, true), (1, 0, true), (2, 1, true)]);
+    }
+
+    #[test]
+    fn tuner_packs_recorded_times_onto_workers() {
+        let tuner = Arc::new(WorkerTuner::new(2));
+        let metrics = Metrics::new();
+
+        for (key, time) in [(0, 4.0), (1, 3.0), (2, 2.0), (3, 1.0)] {
+            tuner.record(key, time);
+        }
+        tuner.rebalance(Some(&metrics));
+        let workers: Vec<_> = (0..4).map(|key| tuner.assignment(&key)).collect();
+        assert_eq!(workers, [Some(0), Some(1), Some(1), Some(0)]);
+        assert_eq!(metrics.get("tuning.imbalance").unwrap().last, 1.0);
+
+        tuner.set_enabled(false);
+        assert_eq!(tuner.assignment(&0), None);
+        tuner.set_enabled(true);
+
+        let hints: Vec<_> = with_tuning(group(5), &tuner, None).map(|task| task.worker_hint()).collect();
+        assert_eq!(hints, [None; 5]);
+
+        let mut values: Vec<_> = execute(with_tuning(group(5), &tuner, None)).collect();
+        values.sort_unstable();
+        assert_eq!(values, [6, 7, 8, 9, 10]);
+
+        let hints: Vec<_> = with_tuning(group(5), &tuner, None).map(|task| task.worker_hint()).collect();
+        assert!(hints.iter().all(|hint| hint.is_some_and(|worker| worker < 2)));
+    }
+
+    /// A task which takes a given number of milliseconds on a mock clock.
+    struct Timed {
+        key: usize,
+        millis: u64,
+        clock: Arc<MockClock>,
+    }
+
+    impl Automaton for Timed {
+        type Key = usize;
+        type Message = ();
+        type Value = ();
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            vec![(self.key, ())]
+        }
+
+        fn receive(&mut self, _: Self::Message) -> Status {
+            Status::Eligible
+        }
+
+        fn value(self) -> Self::Value {
+            self.clock.advance(Duration::from_millis(self.millis))
+        }
+    }
+
+    #[test]
+    fn tuned_tasks_are_timed_with_the_tuner_clock() {
+        let clock = Arc::new(MockClock::new());
+        let tuner = Arc::new(WorkerTuner::with_clock(2, clock.clone()));
+        let tasks = [4, 3, 2, 1].iter().enumerate().map(|(key, &millis)| Timed {
+            key,
+            millis,
+            clock: clock.clone(),
+        });
+        execute(with_tuning(tasks, &tuner, None)).for_each(drop);
+        tuner.rebalance(None);
+
+        let workers: Vec<_> = (0..4).map(|key| tuner.assignment(&key)).collect();
+        assert_eq!(workers, [Some(0), Some(1), Some(1), Some(0)]);
+        assert_eq!(clock.now(), Duration::from_millis(10));
+    }
 }
@@ -0,0 +1,848 @@
+//! Parallel checkpoint files. Each rank writes the patches it owns to its own
+//! file, and rank 0 writes a small index file recording the number of ranks
+//! that participated. Patch serialization is carried out on the worker
+//! threads of a [`ThreadPool`], so the simulation does not have to stall
+//! while a checkpoint is being written.
+//!
+//! A rank file is a sequence of length-prefixed CBOR records, one per patch.
+//! Because the records are independent, chunks of patches can be encoded
+//! concurrently and concatenated in order. On restart the files can be read
+//! back by the same number of ranks, or re-partitioned over a different
+//! number of ranks.
+//!
+//! The index file also records the simulation [`Parameters`], which should be
+//! checked against the configuration of the restarted run using
+//! [`check_parameters`].
+//!
+//! In a multi-rank run, the index can instead be written with
+//! `write_index_quiesced` at an iteration boundary. This confirms that no
+//! messages are in flight, and records the iteration number of the ordered
+//! communicator, so that a restarted run resumes at the same iteration on
+//! every rank.
+//!
+//! A run can also be restarted with a different block size, by re-tiling the
+//! loaded patches with [`reblock`], or at a different resolution, with
+//! [`coarsen_all`] and [`refine_all`].
+//!
+//! The message topology of the mesh (its adjacency list, and the edge
+//! translations of a periodic domain) can be saved alongside the patches as
+//! a [`SavedTopology`], so a restarted run with the same patches can skip
+//! rebuilding it.
+//!
+//! Full checkpoints are too large to write often. For frequent, small
+//! outputs, named sub-regions of the domain can be defined as [`ZoomBox`]es.
+//! Their data is flattened to a single level and written by rank 0 with
+//! [`write_zoom_boxes`], in the same record format as the rank files.
+
+use crate::adjacency_list::AdjacencyList;
+use crate::error::{Error, Result};
+use crate::index_space::IndexSpace;
+use crate::meshing::{EdgeTranslations, PatchKey};
+#[cfg(feature = "net")]
+use crate::message::comm::Communicator;
+#[cfg(feature = "net")]
+use crate::message::ordered::OrderedCommunicator;
+use crate::parameters::Parameters;
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+use crate::thread_pool::ThreadPool;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// The name of the index file written by rank 0 into a checkpoint directory.
+///
+pub const INDEX_FILE_NAME: &str = "index.cbor";
+
+/// The name of the file written by rank 0 into a checkpoint directory to
+/// save the message topology.
+///
+pub const TOPOLOGY_FILE_NAME: &str = "topology.cbor";
+
+/// Contents of the checkpoint index file.
+///
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Index {
+    /// The number of ranks that wrote the checkpoint.
+    pub num_ranks: usize,
+
+    /// The rank file names, relative to the checkpoint directory, in rank
+    /// order.
+    pub files: Vec<String>,
+
+    /// The simulation parameters of the run that wrote the checkpoint.
+    #[serde(default)]
+    pub parameters: Parameters,
+
+    /// The iteration number of the communicator when the checkpoint was
+    /// written, if it was written with [`write_index_quiesced`]. A restarted
+    /// run should resume its communicator at this iteration.
+    #[serde(default)]
+    pub iteration: Option<u64>,
+}
+
+impl Index {
+    /// Create an index for a checkpoint written by the given number of
+    /// ranks, with the given simulation parameters.
+    ///
+    pub fn new(num_ranks: usize, parameters: Parameters) -> Self {
+        Self {
+            num_ranks,
+            files: (0..num_ranks).map(rank_file_name).collect(),
+            parameters,
+            iteration: None,
+        }
+    }
+}
+
+/// A handle to a rank file being written in the background. The file is
+/// complete once [`PendingWrite::wait`] returns.
+///
+pub struct PendingWrite {
+    handle: thread::JoinHandle<io::Result<()>>,
+}
+
+impl PendingWrite {
+    /// Block until the rank file has been written, and return any I/O error
+    /// which occurred.
+    ///
+    pub fn wait(self) -> Result<()> {
+        Ok(self.handle.join().unwrap()?)
+    }
+}
+
+/// Return the name of the file written by the given rank.
+///
+pub fn rank_file_name(rank: usize) -> String {
+    format!("patches.{:04}.cbor", rank)
+}
+
+/// Write the patches owned by this rank to its rank file in the given
+/// directory. The patches are split into one chunk per worker in the pool,
+/// and each chunk is encoded on one of the pool's communication workers
+/// (see [`ThreadPool::spawn_comm`]), or on a compute worker if the pool has
+/// none. The encoded chunks are
+/// concatenated in order and written to disk on a background thread. This
+/// function returns immediately. If a chunk fails to encode (e.g. the job
+/// panics), no file is written, and [`PendingWrite::wait`] returns an error.
+///
+pub fn write_rank<P: AsRef<Path>>(
+    pool: &ThreadPool,
+    directory: P,
+    rank: usize,
+    patches: Vec<Patch>,
+) -> PendingWrite {
+    let path = directory.as_ref().join(rank_file_name(rank));
+    let chunk_size = patches.len().div_ceil(pool.num_threads());
+    let (sink, source) = crossbeam_channel::unbounded();
+    let mut patches = patches.into_iter().peekable();
+    let mut num_chunks = 0;
+
+    while patches.peek().is_some() {
+        let chunk: Vec<_> = patches.by_ref().take(chunk_size).collect();
+        let sink = sink.clone();
+        let n = num_chunks;
+        pool.spawn_comm(move || {
+            sink.send((n, encode_records(&chunk))).unwrap();
+        });
+        num_chunks += 1;
+    }
+
+    let handle = thread::spawn(move || {
+        let mut chunks: Vec<_> = source.iter().take(num_chunks).collect();
+        chunks.sort_by_key(|(n, _)| *n);
+
+        if chunks.len() != num_chunks {
+            return Err(io::Error::other(format!(
+                "only {} of {} chunks of the rank file were encoded",
+                chunks.len(),
+                num_chunks
+            )));
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+
+        for (_, bytes) in chunks {
+            file.write_all(&bytes?)?;
+        }
+        file.flush()
+    });
+    PendingWrite { handle }
+}
+
+/// Write the checkpoint index file, including the simulation parameters.
+/// This should be called by rank 0 only.
+///
+pub fn write_index<P: AsRef<Path>>(directory: P, num_ranks: usize, parameters: &Parameters) -> Result<()> {
+    write_index_file(directory, &Index::new(num_ranks, parameters.clone()))
+}
+
+/// Write the checkpoint index file at an iteration boundary of a
+/// multi-rank run. This is a collective operation: every rank calls it once
+/// it has advanced its communicator past the last iteration in the
+/// checkpoint, and before sending any messages for the next one. The group
+/// is first confirmed to be quiet (see [`OrderedCommunicator::quiesce`]),
+/// and then rank 0 writes the index, including the communicator's iteration
+/// number. Returns the iteration number on every rank.
+///
+#[cfg(feature = "net")]
+pub fn write_index_quiesced<C, P>(comm: &OrderedCommunicator<C>, directory: P, parameters: &Parameters) -> Result<u64>
+where
+    C: Communicator,
+    P: AsRef<Path>,
+{
+    let iteration = comm.quiesce()?;
+
+    if comm.rank() == 0 {
+        let index = Index {
+            iteration: Some(iteration),
+            ..Index::new(comm.size(), parameters.clone())
+        };
+        write_index_file(directory, &index)?;
+    }
+    Ok(iteration)
+}
+
+fn write_index_file<P: AsRef<Path>>(directory: P, index: &Index) -> Result<()> {
+    let file = File::create(directory.as_ref().join(INDEX_FILE_NAME))?;
+    ciborium::ser::into_writer(index, BufWriter::new(file)).map_err(invalid_data)?;
+    Ok(())
+}
+
+/// Read the checkpoint index file from the given directory.
+///
+pub fn read_index<P: AsRef<Path>>(directory: P) -> Result<Index> {
+    let file = File::open(directory.as_ref().join(INDEX_FILE_NAME))?;
+    Ok(ciborium::de::from_reader(BufReader::new(file)).map_err(invalid_data)?)
+}
+
+/// Check that the parameters of a restarted run are compatible with the
+/// parameters saved in the checkpoint (see [`Parameters::check_restart`]).
+///
+pub fn check_parameters<P: AsRef<Path>>(directory: P, parameters: &Parameters) -> Result<()> {
+    parameters.check_restart(&read_index(directory)?.parameters)
+}
+
+/// The message topology of a mesh: its adjacency list, and the translations
+/// of its edges if the domain is periodic. Building these takes noticeable
+/// time for very large meshes, so they can be saved with a checkpoint and
+/// reloaded on restart. The patch keys and number of guard zones the
+/// topology was built for are saved with it, so that a topology which no
+/// longer matches the mesh is not reused.
+///
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SavedTopology {
+    /// The keys of every patch in the mesh (on all ranks), sorted.
+    pub keys: Vec<PatchKey>,
+
+    /// The number of guard zones the adjacency list was built with.
+    pub num_guard: i64,
+
+    /// The adjacency list of the patch keys.
+    pub edges: AdjacencyList<PatchKey>,
+
+    /// The edge translations, which are empty unless the domain is periodic.
+    #[serde(default)]
+    pub translations: EdgeTranslations<PatchKey>,
+}
+
+impl SavedTopology {
+    /// Create a topology to be saved, from the keys of every patch in the
+    /// mesh and the adjacency list built from them.
+    ///
+    pub fn new<I>(keys: I, num_guard: i64, edges: AdjacencyList<PatchKey>, translations: EdgeTranslations<PatchKey>) -> Self
+    where
+        I: IntoIterator<Item = PatchKey>,
+    {
+        Self {
+            keys: sorted_keys(keys),
+            num_guard,
+            edges,
+            translations,
+        }
+    }
+
+    /// Return whether this topology was built for the given patch keys and
+    /// number of guard zones.
+    ///
+    pub fn matches<I>(&self, keys: I, num_guard: i64) -> bool
+    where
+        I: IntoIterator<Item = PatchKey>,
+    {
+        self.num_guard == num_guard && self.keys == sorted_keys(keys)
+    }
+}
+
+fn sorted_keys<I: IntoIterator<Item = PatchKey>>(keys: I) -> Vec<PatchKey> {
+    let mut keys: Vec<_> = keys.into_iter().collect();
+    keys.sort_by_key(|((di, dj), level)| (*level, di.start, di.end, dj.start, dj.end));
+    keys
+}
+
+/// Write the message topology to the given checkpoint directory. This
+/// should be called by rank 0 only.
+///
+pub fn write_topology<P: AsRef<Path>>(directory: P, topology: &SavedTopology) -> Result<()> {
+    let file = File::create(directory.as_ref().join(TOPOLOGY_FILE_NAME))?;
+    let mut file = BufWriter::new(file);
+    ciborium::ser::into_writer(topology, &mut file).map_err(invalid_data)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Read the message topology from the given checkpoint directory, if it was
+/// saved there and it matches the given patch keys and number of guard
+/// zones. Returns `None` otherwise, in which case the topology needs to be
+/// rebuilt.
+///
+pub fn read_topology<P, I>(directory: P, keys: I, num_guard: i64) -> Result<Option<SavedTopology>>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = PatchKey>,
+{
+    let file = match File::open(directory.as_ref().join(TOPOLOGY_FILE_NAME)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let topology: SavedTopology = ciborium::de::from_reader(BufReader::new(file)).map_err(invalid_data)?;
+
+    if topology.matches(keys, num_guard) {
+        Ok(Some(topology))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Read all of the patches in a single rank file.
+///
+pub fn read_rank_file<P: AsRef<Path>>(path: P) -> Result<Vec<Patch>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut patches = Vec::new();
+
+    while let Some(size) = read_record_size(&mut file)? {
+        let mut buffer = vec![0; size];
+        file.read_exact(&mut buffer)?;
+        patches.push(ciborium::de::from_reader(&buffer[..]).map_err(invalid_data)?);
+    }
+    Ok(patches)
+}
+
+/// Read every patch in the checkpoint, in rank order.
+///
+pub fn read_all<P: AsRef<Path>>(directory: P) -> Result<Vec<Patch>> {
+    let index = read_index(&directory)?;
+    let mut patches = Vec::new();
+
+    for path in rank_file_paths(directory.as_ref(), &index) {
+        patches.extend(read_rank_file(path)?);
+    }
+    Ok(patches)
+}
+
+/// Read the patches to be owned by the given rank, when the checkpoint is
+/// loaded by `num_ranks` ranks. If the number of ranks is unchanged, each
+/// rank reads back its own file. Otherwise the patches from all the rank
+/// files are concatenated in rank order, and divided into `num_ranks`
+/// contiguous, nearly equal-sized groups. An error is returned if the rank
+/// is not less than `num_ranks`, or if the index lists no file for it.
+///
+pub fn read_partition<P: AsRef<Path>>(
+    directory: P,
+    rank: usize,
+    num_ranks: usize,
+) -> Result<Vec<Patch>> {
+    if rank >= num_ranks {
+        let message = format!("rank {} is out of range for {} ranks", rank, num_ranks);
+        return Err(io::Error::new(ErrorKind::InvalidInput, message).into());
+    }
+    let index = read_index(&directory)?;
+
+    if index.num_ranks == num_ranks {
+        let file = index.files.get(rank).ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidData, format!("the checkpoint index lists no file for rank {}", rank))
+        })?;
+        return read_rank_file(directory.as_ref().join(file));
+    }
+
+    let mut patches = Vec::new();
+
+    for path in rank_file_paths(directory.as_ref(), &index) {
+        patches.extend(read_rank_file(path)?);
+    }
+    let total = patches.len();
+
+    Ok(patches
+        .into_iter()
+        .enumerate()
+        .filter(|(n, _)| n * num_ranks / total == rank)
+        .map(|(_, patch)| patch)
+        .collect())
+}
+
+/// Re-tile a set of patches to a new block size, keeping each patch on its
+/// level. On each level, the new blocks are the cells of a grid with the
+/// given block size (aligned to the origin), clipped to the extent of the
+/// original patches overlapping them. Data and solid zone flags are copied
+/// from the overlapping original patches, which are assumed to be disjoint
+/// on each level. The new patches are returned in order of level and then
+/// row-major order of the blocks. A mesh error is returned if a new block is
+/// not completely covered by the original patches, or if the patches on a
+/// level have different numbers of fields.
+///
+pub fn reblock(patches: &[Patch], block_size: (i64, i64)) -> Result<Vec<Patch>> {
+    assert!(block_size.0 > 0 && block_size.1 > 0, "block size must be positive");
+
+    let mut levels: BTreeMap<u32, Vec<&Patch>> = BTreeMap::new();
+
+    for patch in patches {
+        levels.entry(patch.level()).or_default().push(patch)
+    }
+
+    let mut result = Vec::new();
+
+    for (level, patches) in levels {
+        let num_fields = patches[0].num_fields();
+
+        if patches.iter().any(|p| p.num_fields() != num_fields) {
+            return Err(Error::Mesh(format!("patches on level {} have different numbers of fields", level)));
+        }
+
+        let lookup: RectangleMap<i64, &Patch> = patches.iter().map(|&p| (p.local_rect().clone(), p)).collect();
+        let mut cells = BTreeSet::new();
+
+        for patch in &patches {
+            let (i0, j0) = patch.index_space().start();
+            let (i1, j1) = patch.index_space().end();
+
+            for ci in i0.div_euclid(block_size.0)..(i1 - 1).div_euclid(block_size.0) + 1 {
+                for cj in j0.div_euclid(block_size.1)..(j1 - 1).div_euclid(block_size.1) + 1 {
+                    cells.insert((ci, cj));
+                }
+            }
+        }
+
+        for (ci, cj) in cells {
+            let cell = IndexSpace::new(
+                ci * block_size.0..(ci + 1) * block_size.0,
+                cj * block_size.1..(cj + 1) * block_size.1,
+            );
+            let sources: Vec<&Patch> = lookup
+                .query_rect(cell.clone())
+                .map(|(_, &p)| p)
+                .filter(|p| !p.index_space().intersect(cell.clone()).is_empty())
+                .collect();
+            let block = cell.intersect(bounding_space(&sources));
+            let covered: usize = sources.iter().map(|p| p.index_space().intersect(block.clone()).len()).sum();
+
+            if covered != block.len() {
+                let ((i0, j0), (i1, j1)) = (block.start(), block.end());
+                return Err(Error::Mesh(format!(
+                    "block ({}..{} {}..{}) on level {} is not covered by the original patches",
+                    i0, i1, j0, j1, level
+                )));
+            }
+
+            let mut patch = Patch::zeros(level, num_fields, block.clone());
+
+            for source in sources {
+                source.map_into(&mut patch, |a, b| b.copy_from_slice(a));
+
+                if source.mask().is_some() {
+                    for index in source.index_space().intersect(block.clone()).iter() {
+                        patch.set_solid(index, source.is_solid(index))
+                    }
+                }
+            }
+            result.push(patch)
+        }
+    }
+    Ok(result)
+}
+
+/// Change the resolution of a set of patches, by coarsening every patch by
+/// the given factor (see [`Patch::try_coarsen_by`]). Patches keep their
+/// levels, so the result is a consistent hierarchy on a mesh with `factor`
+/// times fewer zones on each axis. A mesh error is returned if any patch is
+/// not divisible by the factor.
+///
+pub fn coarsen_all(patches: &[Patch], factor: u32) -> Result<Vec<Patch>> {
+    patches.iter().map(|patch| patch.try_coarsen_by(factor)).collect()
+}
+
+/// Change the resolution of a set of patches, by refining every patch by
+/// the given factor (see [`Patch::refine_by`]). Patches keep their levels,
+/// so the result is a consistent hierarchy on a mesh with `factor` times
+/// more zones on each axis.
+///
+pub fn refine_all(patches: &[Patch], factor: u32) -> Vec<Patch> {
+    patches.iter().map(|patch| patch.refine_by(factor)).collect()
+}
+
+/// Return the smallest index space containing all of the given patches.
+fn bounding_space(patches: &[&Patch]) -> IndexSpace {
+    let i0 = patches.iter().map(|p| p.index_space().start().0).min().unwrap();
+    let j0 = patches.iter().map(|p| p.index_space().start().1).min().unwrap();
+    let i1 = patches.iter().map(|p| p.index_space().end().0).max().unwrap();
+    let j1 = patches.iter().map(|p| p.index_space().end().1).max().unwrap();
+    IndexSpace::new(i0..i1, j0..j1)
+}
+
+/// A named sub-region of the domain, written every `every` steps. The region
+/// is given on the index space of `level`, and the data of all the patches
+/// overlapping it is flattened to that level: finer data is averaged and
+/// coarser data is injected. Where patches on several levels cover a zone,
+/// the finest one is used.
+///
+#[derive(Clone, Debug)]
+pub struct ZoomBox {
+    pub name: String,
+    pub region: IndexSpace,
+    pub level: u32,
+    pub every: usize,
+}
+
+impl ZoomBox {
+    /// Create a zoom box with the given name, region (at the given level),
+    /// and output interval in steps.
+    ///
+    pub fn new<I: Into<IndexSpace>>(name: &str, region: I, level: u32, every: usize) -> Self {
+        assert!(every > 0, "the zoom box output interval must be positive");
+        Self {
+            name: name.to_string(),
+            region: region.into(),
+            level,
+            every,
+        }
+    }
+
+    /// Return whether this box is to be written at the given step.
+    ///
+    pub fn is_due(&self, step: usize) -> bool {
+        step.is_multiple_of(self.every)
+    }
+
+    /// Return the name of the file this box is written to at the given step.
+    ///
+    pub fn file_name(&self, step: usize) -> String {
+        format!("zoom.{}.{:06}.cbor", self.name, step)
+    }
+
+    /// Extract the part of the box covered by each of the given patches,
+    /// flattened to the box level. Each fragment covers the zones of the box
+    /// which lie entirely within its patch, and is returned with its
+    /// patch's level. The fragments are extracted in parallel.
+    ///
+    pub fn extract(&self, patches: &[Patch]) -> Vec<(u32, Patch)> {
+        patches
+            .par_iter()
+            .filter_map(|patch| {
+                let space = self.covered_by(patch);
+
+                if space.is_empty() {
+                    None
+                } else {
+                    let fragment = Patch::from_slice_function(self.level, space, patch.num_fields(), |index, slice| {
+                        patch.sample_slice(self.level, index, slice)
+                    });
+                    Some((patch.level(), fragment))
+                }
+            })
+            .collect()
+    }
+
+    /// Combine fragments (from [`ZoomBox::extract`], possibly gathered from
+    /// several ranks) into a single patch covering the box. Fragments from
+    /// finer patches take precedence, and zones not covered by any fragment
+    /// are NaN. A mesh error is returned if there are no fragments, or if
+    /// they have different numbers of fields.
+    ///
+    pub fn assemble(&self, mut fragments: Vec<(u32, Patch)>) -> Result<Patch> {
+        let num_fields = match fragments.first() {
+            Some((_, fragment)) => fragment.num_fields(),
+            None => return Err(Error::Mesh(format!("zoom box {} is not covered by any patch", self.name))),
+        };
+        if fragments.iter().any(|(_, fragment)| fragment.num_fields() != num_fields) {
+            return Err(Error::Mesh(format!("zoom box {} has fragments with different numbers of fields", self.name)));
+        }
+        fragments.sort_by_key(|(level, _)| std::cmp::Reverse(*level));
+
+        let mut result = Patch::zeros(self.level, num_fields, self.region.clone());
+        result.iter_data_mut().for_each(|slice| slice.fill(f64::NAN));
+
+        for (_, fragment) in &fragments {
+            fragment.map_into(&mut result, |source, target| target.copy_from_slice(source));
+        }
+        Ok(result)
+    }
+
+    fn covered_by(&self, patch: &Patch) -> IndexSpace {
+        let f = 1 << self.level;
+        let (i0, j0) = patch.high_resolution_space().start();
+        let (i1, j1) = patch.high_resolution_space().end();
+        let inner = IndexSpace::new(
+            (i0 + f - 1).div_euclid(f)..i1.div_euclid(f),
+            (j0 + f - 1).div_euclid(f)..j1.div_euclid(f),
+        );
+        inner.intersect(self.region.clone())
+    }
+}
+
+/// Write the zoom boxes which are due at the given step. Every rank extracts
+/// its fragments of each box from the patches it owns, and the fragments are
+/// gathered to rank 0, which assembles the boxes and writes each one to its
+/// own file in the given directory. This must be called by every rank.
+///
+#[cfg(feature = "net")]
+pub fn write_zoom_boxes<C, P>(comm: &C, directory: P, boxes: &[ZoomBox], step: usize, patches: &[Patch]) -> Result<()>
+where
+    C: Communicator,
+    P: AsRef<Path>,
+{
+    for zoom in boxes.iter().filter(|zoom| zoom.is_due(step)) {
+        if let Some(gathered) = comm.gather_values(&zoom.extract(patches)) {
+            let patch = zoom.assemble(gathered.into_iter().flatten().collect())?;
+            let mut file = BufWriter::new(File::create(directory.as_ref().join(zoom.file_name(step)))?);
+            file.write_all(&encode_records(&[patch])?)?;
+            file.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn rank_file_paths<'a>(directory: &'a Path, index: &'a Index) -> impl Iterator<Item = PathBuf> + 'a {
+    index.files.iter().map(move |file| directory.join(file))
+}
+
+fn encode_records(patches: &[Patch]) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for patch in patches {
+        let mut record = Vec::new();
+        ciborium::ser::into_writer(patch, &mut record).map_err(invalid_data)?;
+        bytes.extend_from_slice(&(record.len() as u64).to_le_bytes());
+        bytes.extend(record);
+    }
+    Ok(bytes)
+}
+
+fn read_record_size<R: Read>(reader: &mut R) -> io::Result<Option<usize>> {
+    let mut buffer = [0; 8];
+
+    match reader.read_exact(&mut buffer) {
+        Ok(()) => Ok(Some(u64::from_le_bytes(buffer) as usize)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn invalid_data<E: std::fmt::Debug>(error: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{:?}", error))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        check_parameters, coarsen_all, read_all, read_partition, read_topology, reblock, refine_all, write_index,
+        write_rank, write_topology, SavedTopology, ZoomBox,
+    };
+    use crate::index_space::{range2d, IndexSpace};
+    use crate::meshing::{periodic_adjacency_list, Periodicity};
+    use crate::parameters::Parameters;
+    use crate::patch::Patch;
+    use crate::thread_pool::ThreadPool;
+
+    fn patches(range: std::ops::Range<i64>) -> Vec<Patch> {
+        range
+            .map(|i| Patch::from_scalar_function(0, (i * 10..(i + 1) * 10, 0..10), |(i, j)| (i + j) as f64))
+            .collect()
+    }
+
+    #[test]
+    fn checkpoint_can_be_repartitioned() {
+        let directory = std::env::temp_dir().join(format!("gridiron-checkpoint-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let pool = ThreadPool::new(2);
+        let w0 = write_rank(&pool, &directory, 0, patches(0..5));
+        let w1 = write_rank(&pool, &directory, 1, patches(5..8));
+        write_index(&directory, 2, &Parameters::new().fixed("num_guard", 1_i64)).unwrap();
+        w0.wait().unwrap();
+        w1.wait().unwrap();
+
+        assert_eq!(read_partition(&directory, 1, 2).unwrap().len(), 3);
+        assert_eq!(read_all(&directory).unwrap().len(), 8);
+
+        let counts: Vec<_> = (0..3)
+            .map(|rank| read_partition(&directory, rank, 3).unwrap().len())
+            .collect();
+        assert_eq!(counts.iter().sum::<usize>(), 8);
+        assert!(read_partition(&directory, 2, 2).is_err());
+        assert!(read_partition(&directory, 3, 3).is_err());
+        assert!(read_partition(&directory, 11, 12).unwrap().is_empty());
+
+        let patch = &read_all(&directory).unwrap()[7];
+        assert_eq!(patch.index_space().start(), range2d(70..80, 0..10).start());
+        assert_eq!(patch.sample(0, (71, 2), 0), 73.0);
+
+        assert!(check_parameters(&directory, &Parameters::new().fixed("num_guard", 1_i64)).is_ok());
+        assert!(check_parameters(&directory, &Parameters::new().fixed("num_guard", 2_i64)).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn patches_can_be_reblocked() {
+        let original = patches(0..3);
+        let reblocked = reblock(&original, (16, 4)).unwrap();
+
+        assert_eq!(reblocked.len(), 6);
+        assert_eq!(reblocked.iter().map(|p| p.index_space().len()).sum::<usize>(), 300);
+        assert_eq!(reblocked[0].index_space().into_rect(), (0..16, 0..4));
+        assert_eq!(reblocked[5].index_space().into_rect(), (16..30, 8..10));
+
+        for patch in &reblocked {
+            for (i, j) in patch.index_space().iter() {
+                assert_eq!(patch.sample(0, (i, j), 0), (i + j) as f64);
+            }
+        }
+
+        let holes = vec![original[0].clone(), original[2].clone()];
+        assert!(reblock(&holes, (30, 10)).is_err());
+    }
+
+    #[test]
+    fn resolution_changes_conserve_totals() {
+        let original = patches(0..2);
+        let total = |patches: &[Patch], factor: f64| patches.iter().flat_map(|p| p.data()).sum::<f64>() * factor;
+
+        let coarse = coarsen_all(&original, 2).unwrap();
+        assert_eq!(coarse[1].index_space().into_rect(), (5..10, 0..5));
+        assert_eq!(coarse[0].sample(0, (1, 2), 0), 7.0);
+        assert_eq!(total(&coarse, 4.0), total(&original, 1.0));
+        assert!(coarsen_all(&original, 3).is_err());
+
+        let fine = refine_all(&coarse, 2);
+        assert_eq!(fine[1].index_space().into_rect(), (10..20, 0..10));
+        assert_eq!(fine[0].sample(0, (3, 5), 0), 7.0);
+        assert_eq!(total(&fine, 1.0), total(&original, 1.0));
+        assert_eq!(coarsen_all(&fine, 2).unwrap()[0].data(), coarse[0].data());
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn zoom_boxes_are_gathered_and_flattened_on_rank_zero() {
+        use super::{read_rank_file, write_zoom_boxes};
+        use crate::message::comm::Communicator;
+        use crate::message::local::LocalCommunicator;
+
+        let directory = std::env::temp_dir().join(format!("gridiron-zoom-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        // Rank 0 has a coarse patch under the whole box, and rank 1 has a
+        // fine patch over its left half.
+        let boxes = [ZoomBox::new("left", range2d(0..4, 0..2), 1, 5)];
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(|comm| {
+                let directory = directory.clone();
+                let boxes = boxes.clone();
+                std::thread::spawn(move || {
+                    let patches = match comm.rank() {
+                        0 => vec![Patch::from_scalar_function(1, range2d(0..8, 0..8), |_| 100.0)],
+                        _ => vec![Patch::from_scalar_function(0, range2d(0..4, 0..4), |(i, _)| i as f64)],
+                    };
+                    write_zoom_boxes(&comm, &directory, &boxes, 3, &patches).unwrap();
+                    write_zoom_boxes(&comm, &directory, &boxes, 10, &patches).unwrap();
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        assert!(!directory.join(boxes[0].file_name(3)).exists());
+        let zoom = read_rank_file(directory.join(boxes[0].file_name(10))).unwrap();
+        assert_eq!(zoom.len(), 1);
+        assert_eq!(zoom[0].level(), 1);
+        assert_eq!(zoom[0].local_rect(), &(0..4, 0..2));
+        assert_eq!(zoom[0].data(), &vec![0.5, 0.5, 2.5, 2.5, 100.0, 100.0, 100.0, 100.0]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn quiesced_checkpoints_record_the_iteration() {
+        use super::{read_index, write_index_quiesced};
+        use crate::message::local::LocalCommunicator;
+        use crate::message::ordered::OrderedCommunicator;
+
+        let directory = std::env::temp_dir().join(format!("gridiron-quiesced-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(|comm| {
+                let directory = directory.clone();
+                std::thread::spawn(move || {
+                    let comm = OrderedCommunicator::new(comm);
+                    comm.next_iteration();
+                    comm.next_iteration();
+                    write_index_quiesced(&comm, &directory, &Parameters::new()).unwrap()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 2);
+        }
+        let index = read_index(&directory).unwrap();
+        assert_eq!((index.num_ranks, index.iteration), (2, Some(2)));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn zoom_boxes_mark_uncovered_zones() {
+        let zoom = ZoomBox::new("edge", range2d(-1..1, 0..1), 0, 1);
+        let patches = vec![Patch::from_scalar_function(0, range2d(0..4, 0..4), |_| 1.0)];
+        let patch = zoom.assemble(zoom.extract(&patches)).unwrap();
+
+        assert!(patch.data()[0].is_nan());
+        assert_eq!(patch.data()[1], 1.0);
+        assert!(zoom.assemble(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn topology_is_reused_only_for_the_same_mesh() {
+        let directory = std::env::temp_dir().join(format!("gridiron-topology-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let patches: crate::rect_map::RectangleMap<_, _> =
+            patches(0..3).into_iter().map(|p| (p.high_resolution_rect(), p)).collect();
+        let keys: Vec<_> = patches.iter().map(|(_, p)| (p.high_resolution_rect(), p.level())).collect();
+        let periodicity = Periodicity {
+            domain: IndexSpace::new(0..30, 0..10),
+            axes: (true, false),
+        };
+        assert!(read_topology(&directory, keys.clone(), 1).unwrap().is_none());
+
+        let (edges, translations) = periodic_adjacency_list(&patches, 1, &periodicity);
+        let (num_edges, num_translations) = (edges.len(), translations.len());
+        write_topology(&directory, &SavedTopology::new(keys.iter().rev().cloned(), 1, edges, translations)).unwrap();
+
+        let saved = read_topology(&directory, keys.clone(), 1).unwrap().unwrap();
+        let stale_guard = read_topology(&directory, keys.clone(), 2).unwrap();
+        let stale_keys = read_topology(&directory, keys[1..].to_vec(), 1).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(saved.edges.len(), num_edges);
+        assert_eq!(saved.translations.len(), num_translations);
+        assert_eq!(saved.translations[&(keys[0].clone(), keys[2].clone())], [(30, 0)]);
+        assert!(stale_guard.is_none());
+        assert!(stale_keys.is_none());
+    }
+}
@@ -0,0 +1,94 @@
+//! A source of time for logic which measures durations or waits, such as
+//! retries with backoff and execution timing. Code which takes a [`Clock`]
+//! can be tested deterministically with a [`MockClock`], which only moves
+//! when told to, and which returns from `sleep` immediately.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A monotonic clock which can also wait.
+///
+pub trait Clock: Send + Sync {
+    /// Return the time elapsed since an arbitrary, fixed epoch.
+    fn now(&self) -> Duration;
+
+    /// Wait for the given duration.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real clock, measuring time from the first time it's read in this
+/// process.
+///
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// A clock for tests, which starts at zero and advances only when
+/// [`MockClock::advance`] or `sleep` is called. Sleeping returns immediately
+/// and is recorded, so tests can check how long code would have waited.
+///
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Mutex<Duration>,
+    sleeps: Mutex<Vec<Duration>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by the given duration.
+    ///
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration
+    }
+
+    /// Return the durations of the calls to `sleep`, in order.
+    ///
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.sleeps.lock().unwrap().push(duration);
+        self.advance(duration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Clock, MockClock, SystemClock};
+    use std::time::Duration;
+
+    #[test]
+    fn mock_clocks_move_only_when_told() {
+        let clock = MockClock::new();
+        assert_eq!(clock.now(), Duration::ZERO);
+
+        clock.advance(Duration::from_secs(2));
+        clock.sleep(Duration::from_millis(500));
+        assert_eq!(clock.now(), Duration::from_millis(2500));
+        assert_eq!(clock.sleeps(), [Duration::from_millis(500)]);
+
+        let t0 = SystemClock.now();
+        assert!(SystemClock.now() >= t0);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::adjacency_list::AdjacencyList;
+use crate::automaton::{Automaton, Status};
+use core::hash::Hash;
+
+/// A stencil-style compute task, in the peer-pull model: the task names the
+/// peers whose data it needs to read, and its value is computed from itself
+/// together with copies of those peers. This is often the more natural way
+/// to write a stencil, because each task only needs to know its upstream
+/// neighbors. A `Compute` task can be run by any of the `automaton`
+/// executors by wrapping it in a [`ComputeTask`], which generates the
+/// messages (copies of the task) from the edge list.
+///
+pub trait Compute: Clone {
+    /// The type of the key to uniquely identify this task within a group.
+    type Key;
+
+    /// The type of the value yielded by this task.
+    type Value;
+
+    /// Return the key to uniquely identify this task within the group.
+    fn key(&self) -> Self::Key;
+
+    /// Return the keys of the peers whose data is needed to compute this
+    /// task's value.
+    fn peer_keys(&self) -> Vec<Self::Key>;
+
+    /// Run the task, given copies of the peers named by `Self::peer_keys`.
+    /// The order of the peers is not guaranteed to match the order of the
+    /// keys.
+    fn run(&self, peers: Vec<Self>) -> Self::Value;
+}
+
+/// An adapter to run a [`Compute`] task as an [`Automaton`]. The messages
+/// sent by this task are copies of the compute task, addressed to each of
+/// the downstream tasks in the edge list. The task becomes eligible once it
+/// has received a copy of each of its peers.
+///
+/// Note that tasks with no peers are never eligible, so every task in the
+/// group must have at least one peer.
+///
+pub struct ComputeTask<C: Compute> {
+    compute: C,
+    downstream: Vec<C::Key>,
+    num_peers: usize,
+    peers: Vec<C>,
+}
+
+impl<C> ComputeTask<C>
+where
+    C: Compute,
+    C::Key: Hash + Eq + Clone,
+{
+    /// Wrap a compute task. The edge list is used to determine which tasks
+    /// are downstream of this one; it would normally be generated from the
+    /// whole group using [`adjacency_list`].
+    ///
+    pub fn new(compute: C, edge_list: &AdjacencyList<C::Key>) -> Self {
+        let downstream = edge_list.outgoing_edges(&compute.key()).cloned().collect();
+        let num_peers = compute.peer_keys().len();
+        Self {
+            compute,
+            downstream,
+            num_peers,
+            peers: Vec::new(),
+        }
+    }
+}
+
+impl<C> Automaton for ComputeTask<C>
+where
+    C: Compute,
+    C::Key: Hash + Eq + Clone,
+{
+    type Key = C::Key;
+    type Message = C;
+    type Value = C::Value;
+
+    fn key(&self) -> Self::Key {
+        self.compute.key()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.downstream
+            .iter()
+            .map(|key| (key.clone(), self.compute.clone()))
+            .collect()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        self.peers.push(message);
+        Status::eligible_if(self.peers.len() == self.num_peers)
+    }
+
+    fn value(self) -> Self::Value {
+        self.compute.run(self.peers)
+    }
+}
+
+/// Return an adjacency list for a group of compute tasks. There is an edge
+/// from `A` to `B` if `A` is one of `B`'s peers, which is the direction that
+/// copies of `A` need to be sent in.
+///
+pub fn adjacency_list<'a, I, C>(group: I) -> AdjacencyList<C::Key>
+where
+    I: IntoIterator<Item = &'a C>,
+    C: 'a + Compute,
+    C::Key: Hash + Eq + Clone,
+{
+    let mut edges = AdjacencyList::new();
+
+    for task in group {
+        for peer in task.peer_keys() {
+            edges.insert(peer, task.key())
+        }
+    }
+    edges
+}
+
+/// Wrap each task in a group of compute tasks as an automaton, ready to be
+/// passed to one of the executors in the `automaton` module.
+///
+pub fn into_automata<C>(group: Vec<C>) -> Vec<ComputeTask<C>>
+where
+    C: Compute,
+    C::Key: Hash + Eq + Clone,
+{
+    let edges = adjacency_list(&group);
+    group
+        .into_iter()
+        .map(|task| ComputeTask::new(task, &edges))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{into_automata, Compute};
+    use crate::automaton::execute;
+
+    #[derive(Clone)]
+    struct Smooth {
+        key: i32,
+        value: f64,
+        size: i32,
+    }
+
+    impl Compute for Smooth {
+        type Key = i32;
+        type Value = (i32, f64);
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn peer_keys(&self) -> Vec<Self::Key> {
+            vec![
+                (self.key + self.size - 1) % self.size,
+                (self.key + 1) % self.size,
+            ]
+        }
+
+        fn run(&self, peers: Vec<Self>) -> Self::Value {
+            let total: f64 = peers.iter().map(|p| p.value).sum();
+            (self.key, (self.value + total) / 3.0)
+        }
+    }
+
+    #[test]
+    fn compute_tasks_run_as_automata() {
+        let size = 10;
+        let group = (0..size)
+            .map(|key| Smooth {
+                key,
+                value: if key == 0 { 3.0 } else { 0.0 },
+                size,
+            })
+            .collect();
+
+        let mut result: Vec<_> = execute(into_automata(group)).collect();
+        result.sort_by_key(|(key, _)| *key);
+
+        assert_eq!(result.len(), 10);
+        assert_eq!(result[0].1, 1.0);
+        assert_eq!(result[1].1, 1.0);
+        assert_eq!(result[5].1, 0.0);
+        assert_eq!(result[9].1, 1.0);
+    }
+}
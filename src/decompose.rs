@@ -0,0 +1,233 @@
+//! Domain decomposition: splitting an index space into rectangular blocks,
+//! and covering tagged zones with blocks for regridding. Both respect a set
+//! of [`BlockConstraints`], so that the blocks they produce are large enough
+//! to be efficient, and line up with the vector width of the update schemes.
+
+use crate::error::{Error, Result};
+use crate::index_space::IndexSpace;
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// Constraints on the shape of the blocks produced by [`partition`] and
+/// [`cluster`].
+///
+#[derive(Clone, Copy, Debug)]
+pub struct BlockConstraints {
+    /// The minimum number of zones in each direction.
+    pub min_size: i64,
+
+    /// The start and end indexes of each block, on each axis, must be
+    /// multiples of this number. This also makes the block size a multiple of
+    /// it.
+    pub alignment: i64,
+}
+
+impl Default for BlockConstraints {
+    fn default() -> Self {
+        Self {
+            min_size: 1,
+            alignment: 1,
+        }
+    }
+}
+
+impl BlockConstraints {
+    /// Create constraints with the given minimum size and alignment. Panics
+    /// unless both are positive.
+    ///
+    pub fn new(min_size: i64, alignment: i64) -> Self {
+        assert!(min_size > 0 && alignment > 0, "block constraints must be positive");
+        Self { min_size, alignment }
+    }
+
+    /// The smallest aligned block size that is not below the minimum size.
+    pub fn granularity(&self) -> i64 {
+        round_up(self.min_size, self.alignment)
+    }
+
+    /// Return true if the given index space satisfies these constraints.
+    pub fn is_satisfied_by(&self, space: &IndexSpace) -> bool {
+        let (di, dj) = space.to_rect_ref();
+        self.axis_is_valid(di) && self.axis_is_valid(dj)
+    }
+
+    /// Return a mesh error if the given index space does not satisfy these
+    /// constraints.
+    ///
+    pub fn validate(&self, space: &IndexSpace) -> Result<()> {
+        if self.is_satisfied_by(space) {
+            Ok(())
+        } else {
+            let (di, dj) = space.to_rect_ref();
+            Err(Error::Mesh(format!(
+                "block ({}..{} {}..{}) must be at least {} zones wide and aligned to multiples of {}",
+                di.start, di.end, dj.start, dj.end, self.min_size, self.alignment
+            )))
+        }
+    }
+
+    /// Return the smallest index space satisfying these constraints which
+    /// contains the given one, as far as possible within the given bounds.
+    /// Blocks are expanded outward to aligned indexes, and then grown to the
+    /// minimum size, toward the lower end if they would otherwise leave the
+    /// bounds. A mesh error is returned if the bounds are too small or not
+    /// aligned.
+    ///
+    pub fn adjust(&self, space: &IndexSpace, bounds: &IndexSpace) -> Result<IndexSpace> {
+        let (di, dj) = space.to_rect_ref();
+        let (bi, bj) = bounds.to_rect_ref();
+        let adjusted = IndexSpace::new(self.adjust_axis(di, bi), self.adjust_axis(dj, bj));
+        self.validate(&adjusted)?;
+        Ok(adjusted)
+    }
+
+    fn axis_is_valid(&self, range: &Range<i64>) -> bool {
+        range.end - range.start >= self.min_size
+            && range.start.rem_euclid(self.alignment) == 0
+            && range.end.rem_euclid(self.alignment) == 0
+    }
+
+    fn adjust_axis(&self, range: &Range<i64>, bounds: &Range<i64>) -> Range<i64> {
+        let a = self.alignment;
+        let mut start = range.start.div_euclid(a) * a;
+        let mut end = round_up(range.end, a);
+
+        if end - start < self.granularity() {
+            end = start + self.granularity();
+        }
+        if end > bounds.end {
+            start -= end - bounds.end;
+            end = bounds.end;
+        }
+        start.max(bounds.start)..end
+    }
+}
+
+/// Split a domain into blocks of (nominally) the given size, in row-major
+/// order. The block size is rounded up to satisfy the constraints. Where the
+/// domain is not a multiple of the block size, the remainder on each axis
+/// forms a smaller block if it satisfies the minimum size, and is otherwise
+/// merged into its neighbor. A mesh error is returned if the domain is not
+/// aligned, or is smaller than the minimum size.
+///
+pub fn partition(domain: &IndexSpace, block_size: (i64, i64), constraints: BlockConstraints) -> Result<Vec<IndexSpace>> {
+    constraints.validate(domain)?;
+
+    let (di, dj) = domain.to_rect_ref();
+    let si = split_axis(di, block_size.0, &constraints);
+    let sj = split_axis(dj, block_size.1, &constraints);
+
+    Ok(si
+        .iter()
+        .flat_map(|i| sj.iter().map(move |j| IndexSpace::new(i.clone(), j.clone())))
+        .collect())
+}
+
+fn split_axis(range: &Range<i64>, block_size: i64, constraints: &BlockConstraints) -> Vec<Range<i64>> {
+    let block_size = round_up(block_size.max(constraints.min_size), constraints.alignment);
+    let mut pieces = Vec::new();
+    let mut start = range.start;
+
+    while start < range.end {
+        let end = (start + block_size).min(range.end);
+
+        if end - start < constraints.min_size && !pieces.is_empty() {
+            let last: &mut Range<i64> = pieces.last_mut().unwrap();
+            last.end = end;
+        } else {
+            pieces.push(start..end);
+        }
+        start = end;
+    }
+    pieces
+}
+
+/// Cover the tagged zones with blocks for a new refinement level. The domain
+/// is divided into cells of the constraints' granularity, and each row of
+/// contiguous cells containing tagged zones becomes one block. Tags outside
+/// the bounds are ignored. Each block is clipped to the bounds, and adjusted
+/// (see [`BlockConstraints::adjust`]) if the clipping violates the
+/// constraints. Blocks are disjoint unless such an adjustment was needed.
+///
+pub fn cluster<I>(tags: I, bounds: &IndexSpace, constraints: BlockConstraints) -> Result<Vec<IndexSpace>>
+where
+    I: IntoIterator<Item = (i64, i64)>,
+{
+    let g = constraints.granularity();
+    let cells: BTreeSet<_> = tags
+        .into_iter()
+        .filter(|&index| bounds.contains(index))
+        .map(|(i, j)| (j.div_euclid(g), i.div_euclid(g)))
+        .collect();
+
+    let mut runs: Vec<(i64, Range<i64>)> = Vec::new();
+
+    for (cj, ci) in cells {
+        match runs.last_mut() {
+            Some((j, r)) if *j == cj && r.end == ci => r.end += 1,
+            _ => runs.push((cj, ci..ci + 1)),
+        }
+    }
+
+    runs.into_iter()
+        .map(|(cj, ci)| {
+            let block = IndexSpace::new(ci.start * g..ci.end * g, cj * g..(cj + 1) * g).intersect(bounds.clone());
+            if constraints.is_satisfied_by(&block) {
+                Ok(block)
+            } else {
+                constraints.adjust(&block, bounds)
+            }
+        })
+        .collect()
+}
+
+fn round_up(n: i64, multiple: i64) -> i64 {
+    (n + multiple - 1).div_euclid(multiple) * multiple
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{cluster, partition, BlockConstraints};
+    use crate::index_space::IndexSpace;
+
+    #[test]
+    fn partition_merges_small_remainders() {
+        let constraints = BlockConstraints::new(16, 8);
+        let blocks = partition(&IndexSpace::new(0..104, 0..64), (32, 40), constraints).unwrap();
+
+        assert_eq!(blocks.len(), 6);
+        assert!(blocks.iter().all(|b| constraints.is_satisfied_by(b)));
+        assert_eq!(blocks[4].clone().into_rect(), (64..104, 0..40));
+        assert_eq!(blocks[5].clone().into_rect(), (64..104, 40..64));
+        assert!(partition(&IndexSpace::new(0..100, 0..64), (32, 32), constraints).is_err());
+    }
+
+    #[test]
+    fn adjusted_blocks_stay_in_bounds() {
+        let constraints = BlockConstraints::new(16, 8);
+        let bounds = IndexSpace::new(0..64, 0..64);
+
+        assert_eq!(
+            constraints.adjust(&IndexSpace::new(3..5, 58..61), &bounds).unwrap().into_rect(),
+            (0..16, 48..64)
+        );
+        assert!(constraints.adjust(&IndexSpace::new(0..4, 0..4), &IndexSpace::new(0..8, 0..64)).is_err());
+    }
+
+    #[test]
+    fn clustered_blocks_cover_tags_and_satisfy_constraints() {
+        let constraints = BlockConstraints::new(16, 8);
+        let bounds = IndexSpace::new(0..56, 0..64);
+        let tags = vec![(1, 1), (20, 2), (50, 3), (50, 40), (100, 100)];
+        let blocks = cluster(tags.clone(), &bounds, constraints).unwrap();
+
+        assert_eq!(
+            blocks.iter().cloned().map(IndexSpace::into_rect).collect::<Vec<_>>(),
+            vec![(0..32, 0..16), (40..56, 0..16), (40..56, 32..48)]
+        );
+        for index in tags.into_iter().filter(|&index| bounds.contains(index)) {
+            assert!(blocks.iter().any(|b| b.contains(index)));
+        }
+    }
+}
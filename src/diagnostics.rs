@@ -0,0 +1,218 @@
+//! Debugging utilities for developing new update schemes. These are too slow
+//! to run every step of a production simulation.
+
+use crate::index_space::IndexSpace;
+use crate::meshing::PatchKey;
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+use std::fmt;
+
+/// Options for checking that the guard zones of patches agree with the valid
+/// data of the neighbors they were exchanged with. Each patch given to
+/// [`GuardCheck::check`] is extended with `num_guard` guard zones on every
+/// side, as done by the update schemes in [`crate::solvers`].
+///
+#[derive(Clone, Copy, Debug)]
+pub struct GuardCheck {
+    /// The number of guard zones on each side of the patches.
+    pub num_guard: i64,
+
+    /// Whether to check the corner guard zones. Not all schemes fill them.
+    pub include_corners: bool,
+
+    /// Differences larger than this are reported as discrepancies.
+    pub tolerance: f64,
+}
+
+/// A guard zone value which differs from the valid data of a neighbor.
+///
+#[derive(Clone, Debug)]
+pub struct Discrepancy {
+    /// The patch whose guard zone was checked, by its high-resolution valid
+    /// rectangle and level.
+    pub patch: PatchKey,
+
+    /// The neighbor whose valid data covers the guard zone.
+    pub neighbor: PatchKey,
+
+    /// The index of the guard zone, at the level of the patch.
+    pub index: (i64, i64),
+
+    /// The field which differs.
+    pub field: usize,
+
+    /// The absolute difference between the guard zone and neighbor values.
+    pub difference: f64,
+}
+
+/// The result of a [`GuardCheck`].
+///
+#[derive(Clone, Debug, Default)]
+pub struct GuardReport {
+    /// The number of guard zone values compared with neighbor data.
+    pub num_compared: usize,
+
+    /// The values which differ by more than the tolerance.
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl GuardReport {
+    /// Return the discrepancy with the largest difference, if any.
+    pub fn max(&self) -> Option<&Discrepancy> {
+        self.discrepancies
+            .iter()
+            .max_by(|a, b| a.difference.total_cmp(&b.difference))
+    }
+
+    /// Return true if no discrepancies were found.
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ((pi, pj), pl) = &self.patch;
+        let ((ni, nj), nl) = &self.neighbor;
+        write!(
+            fmt,
+            "field {} at ({} {}) of patch ({}..{} {}..{}) level {} differs by {:e} from patch ({}..{} {}..{}) level {}",
+            self.field,
+            self.index.0,
+            self.index.1,
+            pi.start,
+            pi.end,
+            pj.start,
+            pj.end,
+            pl,
+            self.difference,
+            ni.start,
+            ni.end,
+            nj.start,
+            nj.end,
+            nl
+        )
+    }
+}
+
+impl Default for GuardCheck {
+    fn default() -> Self {
+        Self {
+            num_guard: 1,
+            include_corners: false,
+            tolerance: 0.0,
+        }
+    }
+}
+
+impl GuardCheck {
+    /// Compare every guard zone of the given extended patches with the valid
+    /// data of each neighbor covering it. Neighbors on the same level are
+    /// compared zone by zone. Coarser neighbors are compared by
+    /// piecewise-constant injection, and finer neighbors by their average
+    /// over the guard zone (only where they cover the whole zone).
+    ///
+    pub fn check(&self, patches: &[Patch]) -> GuardReport {
+        let valid: Vec<_> = patches
+            .iter()
+            .map(|p| p.index_space().trim_all(self.num_guard))
+            .collect();
+        let lookup: RectangleMap<i64, usize> = valid
+            .iter()
+            .zip(patches)
+            .enumerate()
+            .map(|(n, (v, p))| (v.refine_by(1 << p.level()).into_rect(), n))
+            .collect();
+        let key = |n: usize| (valid[n].refine_by(1 << patches[n].level()).into_rect(), patches[n].level());
+
+        let mut report = GuardReport::default();
+
+        for (a, patch) in patches.iter().enumerate() {
+            let level = patch.level();
+
+            for index in patch.index_space().iter() {
+                if valid[a].contains(index) || (!self.include_corners && self.is_corner(&valid[a], index)) {
+                    continue;
+                }
+                let zone = IndexSpace::new(index.0..index.0 + 1, index.1..index.1 + 1).refine_by(1 << level);
+
+                for (_, &b) in lookup.query_rect(zone.clone()) {
+                    let neighbor = &patches[b];
+                    let covered = valid[b].refine_by(1 << neighbor.level());
+
+                    if b == a || (neighbor.level() < level && !covered.contains_space(&zone)) {
+                        continue;
+                    }
+                    if neighbor.level() >= level && !covered.contains(zone.start()) {
+                        continue;
+                    }
+                    for field in 0..patch.num_fields() {
+                        let expected = neighbor.sample(level, index, field);
+                        let difference = (patch.get_slice(index)[field] - expected).abs();
+                        report.num_compared += 1;
+
+                        if difference > self.tolerance || difference.is_nan() {
+                            report.discrepancies.push(Discrepancy {
+                                patch: key(a),
+                                neighbor: key(b),
+                                index,
+                                field,
+                                difference,
+                            })
+                        }
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    fn is_corner(&self, valid: &IndexSpace, index: (i64, i64)) -> bool {
+        let (i0, j0) = valid.start();
+        let (i1, j1) = valid.end();
+        !(i0..i1).contains(&index.0) && !(j0..j1).contains(&index.1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::GuardCheck;
+    use crate::meshing;
+    use crate::patch::Patch;
+
+    fn exchanged() -> Vec<Patch> {
+        let valid: Vec<_> = (0..2)
+            .map(|n| Patch::from_scalar_function(0, (n * 4..n * 4 + 4, 0..4), |(i, j)| (i * 10 + j) as f64))
+            .collect();
+        valid
+            .iter()
+            .map(|p| {
+                let mut extended = Patch::extract_from(p, p.index_space().extend_all(1));
+                meshing::extend_patch_mut(&mut extended, &p.index_space(), |_, x| x[0] = -1.0, &valid);
+                extended
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exchanged_guard_zones_are_consistent() {
+        let report = GuardCheck::default().check(&exchanged());
+        assert!(report.is_consistent());
+        assert_eq!(report.num_compared, 8);
+    }
+
+    #[test]
+    fn corrupted_guard_zone_is_located() {
+        let mut patches = exchanged();
+        patches[0].get_slice_mut((4, 2))[0] += 0.5;
+
+        let report = GuardCheck::default().check(&patches);
+        let max = report.max().unwrap();
+        assert_eq!(report.discrepancies.len(), 1);
+        assert_eq!(max.patch, ((0..4, 0..4), 0));
+        assert_eq!(max.neighbor, ((4..8, 0..4), 0));
+        assert_eq!(max.index, (4, 2));
+        assert_eq!(max.difference, 0.5);
+    }
+}
@@ -0,0 +1,132 @@
+//! The crate-wide error type, returned from fallible public APIs so that
+//! applications can handle and report failures, rather than having them
+//! surface as panics on worker threads.
+
+#[cfg(feature = "hydro")]
+use crate::{hydro, rect_map::Rectangle};
+use std::{error, fmt, io};
+
+/// A failure in one of the crate's subsystems.
+///
+#[derive(Debug)]
+pub enum Error {
+    /// An index space or patch layout is invalid.
+    Mesh(String),
+
+    /// A message could not be exchanged with a peer process. The peer rank
+    /// is `None` if the failure was not specific to one peer (e.g. the local
+    /// listener could not be bound).
+    Transport { peer: Option<usize>, source: io::Error },
+
+    /// An update scheme produced invalid data in a zone. The patch is
+    /// identified by its high-resolution rectangle and level, and the index
+    /// is measured at the patch level.
+    #[cfg(feature = "hydro")]
+    Solver {
+        patch: Rectangle<i64>,
+        level: u32,
+        index: (i64, i64),
+        source: hydro::error::Error,
+    },
+
+    /// A time step could not be taken within the CFL limit, even after the
+    /// time step size was reduced as many times as allowed. The CFL number
+    /// is that of the last attempt, which used the given time step size.
+    #[cfg(feature = "hydro")]
+    Stability { cfl: f64, threshold: f64, time_step_size: f64 },
+
+    /// The messages held by an executor for tasks it has not seen yet
+    /// exceeded its limits (see `automaton::Limits`), e.g. because a peer
+    /// sent messages to a task which does not exist. The counts are those
+    /// which broke the limits.
+    #[cfg(feature = "exec")]
+    Undelivered {
+        num_messages: usize,
+        num_bytes: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    },
+
+    /// A simulation parameter is missing or has the wrong type, or the
+    /// parameters are incompatible with those saved in a checkpoint.
+    Parameters(String),
+
+    /// A file could not be read or written, or its contents could not be
+    /// encoded or decoded.
+    Io(io::Error),
+}
+
+/// A result whose error type is the crate-wide [`Error`].
+///
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+
+        match self {
+            Mesh(message) => write!(fmt, "mesh error: {}", message),
+            Parameters(message) => write!(fmt, "parameter error: {}", message),
+            Transport { peer: Some(peer), source } => write!(fmt, "transport error with rank {}: {}", peer, source),
+            Transport { peer: None, source } => write!(fmt, "transport error: {}", source),
+            #[cfg(feature = "hydro")]
+            Solver {
+                patch,
+                level,
+                index,
+                source,
+            } => write!(
+                fmt,
+                "solver error at zone ({} {}) of patch ({}..{} {}..{}) on level {}: {}",
+                index.0, index.1, patch.0.start, patch.0.end, patch.1.start, patch.1.end, level, source
+            ),
+            #[cfg(feature = "hydro")]
+            Stability {
+                cfl,
+                threshold,
+                time_step_size,
+            } => write!(
+                fmt,
+                "stability error: CFL number {} exceeds {} with time step size {}",
+                cfl, threshold, time_step_size
+            ),
+            #[cfg(feature = "exec")]
+            Undelivered {
+                num_messages,
+                num_bytes,
+                max_messages,
+                max_bytes,
+            } => write!(
+                fmt,
+                "undelivered messages ({} messages, {} bytes) exceed the limits ({} messages, {} bytes)",
+                num_messages, num_bytes, max_messages, max_bytes
+            ),
+            Io(source) => write!(fmt, "i/o error: {}", source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use Error::*;
+
+        match self {
+            Mesh(_) => None,
+            Parameters(_) => None,
+            Transport { source, .. } => Some(source),
+            #[cfg(feature = "hydro")]
+            Solver { source, .. } => Some(source),
+            #[cfg(feature = "hydro")]
+            Stability { .. } => None,
+            #[cfg(feature = "exec")]
+            Undelivered { .. } => None,
+            Io(source) => Some(source),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
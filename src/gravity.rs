@@ -0,0 +1,270 @@
+//! Integration points for self-gravity. This module does not implement a
+//! Poisson solver; instead it defines the interfaces through which an
+//! application-supplied solver is run between time steps, and through which
+//! an update scheme queries the resulting gravitational acceleration:
+//!
+//! - A [`PoissonSolver`] maps the mass density on each patch to the
+//!   gravitational potential on the same patch.
+//! - [`solve_potential`] runs the solver and then exchanges guard zones of
+//!   the potential between patches, using [`PotentialExchange`] tasks.
+//! - The resulting [`PotentialField`] on each patch is a
+//!   [`PotentialProvider`], which an update scheme queries for the
+//!   acceleration in each zone.
+
+use crate::adjacency_list::AdjacencyList;
+use crate::automaton::{execute, Automaton, Status};
+use crate::index_space::{Axis, IndexSpace};
+use crate::meshing::{self, Mesh};
+use crate::patch::Patch;
+use crate::rect_map::{Rectangle, RectangleMap};
+use std::collections::HashMap;
+
+/// The number of guard zones of potential data needed to compute the
+/// acceleration. The adjacency list used with [`solve_potential`] should be
+/// built with this many guard zones.
+pub const NUM_GUARD: i64 = 1;
+
+/// A source of gravitational acceleration, which an update scheme queries
+/// for each zone it updates.
+///
+pub trait PotentialProvider {
+    /// Return the gravitational acceleration at the center of the zone with
+    /// the given index. The index is at the level of the patch being
+    /// updated.
+    fn acceleration(&self, index: (i64, i64)) -> (f64, f64);
+}
+
+/// A user-supplied solver for the gravitational potential.
+///
+pub trait PoissonSolver {
+    /// Return the gravitational potential on each patch, given the mass
+    /// density on each patch. The returned map must have the same keys as
+    /// the density map, and each potential patch must cover the same index
+    /// space as the corresponding density patch, with one field.
+    fn solve(&mut self, mesh: &Mesh, density: &RectangleMap<i64, Patch>) -> RectangleMap<i64, Patch>;
+}
+
+/// The gravitational potential on a patch, extended with guard zones from
+/// the neighboring patches. The acceleration is minus the gradient of the
+/// potential, computed with centered differences, or one-sided differences
+/// at the edges of the domain where there is no neighbor data.
+///
+pub struct PotentialField {
+    potential: Patch,
+    spacing: (f64, f64),
+}
+
+impl PotentialField {
+    /// Return the potential, including guard zones. Guard zones outside the
+    /// domain are NaN.
+    pub fn potential(&self) -> &Patch {
+        &self.potential
+    }
+
+    fn derivative(&self, index: (i64, i64), axis: Axis, spacing: f64) -> f64 {
+        let space = self.potential.index_space();
+        let phi = |delta| {
+            let index = match axis {
+                Axis::I => (index.0 + delta, index.1),
+                Axis::J => (index.0, index.1 + delta),
+            };
+            if space.contains(index) {
+                self.potential.get_slice(index)[0]
+            } else {
+                f64::NAN
+            }
+        };
+        let (l, c, r) = (phi(-1), phi(0), phi(1));
+
+        match (l.is_nan(), r.is_nan()) {
+            (false, false) => (r - l) / (2.0 * spacing),
+            (true, false) => (r - c) / spacing,
+            (false, true) => (c - l) / spacing,
+            (true, true) => 0.0,
+        }
+    }
+}
+
+impl PotentialProvider for PotentialField {
+    fn acceleration(&self, index: (i64, i64)) -> (f64, f64) {
+        let gi = -self.derivative(index, Axis::I, self.spacing.0);
+        let gj = -self.derivative(index, Axis::J, self.spacing.1);
+        (gi, gj)
+    }
+}
+
+/// A task which fills the guard zones of the potential on one patch, by
+/// exchanging messages with its neighbors in the same way the hydrodynamics
+/// update exchanges guard zones.
+///
+pub struct PotentialExchange {
+    extended: Patch,
+    index_space: IndexSpace,
+    level: u32,
+    spacing: (f64, f64),
+    incoming_count: usize,
+    outgoing_edges: Vec<(Rectangle<i64>, u32)>,
+    neighbor_patches: Vec<Patch>,
+}
+
+impl PotentialExchange {
+    pub fn new(potential: Patch, mesh: &Mesh, edge_list: &AdjacencyList<(Rectangle<i64>, u32)>) -> Self {
+        let key = (potential.high_resolution_rect(), potential.level());
+        let index_space = potential.index_space();
+        Self {
+            extended: Patch::extract_from(&potential, index_space.extend_all(NUM_GUARD)),
+            index_space,
+            level: potential.level(),
+            spacing: mesh.cell_spacing_at_level(potential.level()),
+            incoming_count: edge_list.incoming_edges(&key).count(),
+            outgoing_edges: edge_list.outgoing_edges(&key).cloned().collect(),
+            neighbor_patches: Vec::new(),
+        }
+    }
+}
+
+impl Automaton for PotentialExchange {
+    type Key = Rectangle<i64>;
+    type Message = Patch;
+    type Value = (Rectangle<i64>, PotentialField);
+
+    fn key(&self) -> Self::Key {
+        self.index_space.refine_by(1 << self.level).into_rect()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.outgoing_edges
+            .iter()
+            .cloned()
+            .map(|(rect, level)| {
+                let overlap = IndexSpace::from(rect.clone())
+                    .extend_all(NUM_GUARD * (1 << level))
+                    .coarsen_by(1 << self.level)
+                    .intersect(self.index_space.clone());
+                (rect, self.extended.extract(overlap))
+            })
+            .collect()
+    }
+
+    fn receive(&mut self, patch: Self::Message) -> Status {
+        self.neighbor_patches.push(patch);
+        Status::eligible_if(self.neighbor_patches.len() == self.incoming_count)
+    }
+
+    fn value(mut self) -> Self::Value {
+        let key = self.key();
+        meshing::extend_patch_mut(
+            &mut self.extended,
+            &self.index_space,
+            |_, phi| phi[0] = f64::NAN,
+            &self.neighbor_patches,
+        );
+        let field = PotentialField {
+            potential: self.extended,
+            spacing: self.spacing,
+        };
+        (key, field)
+    }
+
+    fn message_size(patch: &Self::Message) -> usize {
+        patch.data().len() * core::mem::size_of::<f64>()
+    }
+}
+
+/// Run the Poisson solver on the given mass density, and then exchange guard
+/// zones of the potential between patches. This is meant to be called
+/// between time steps; the returned fields are keyed by the high-resolution
+/// rectangle of each patch, and can be handed to the update tasks for the
+/// next step.
+///
+pub fn solve_potential<S: PoissonSolver>(
+    solver: &mut S,
+    mesh: &Mesh,
+    density: &RectangleMap<i64, Patch>,
+    edge_list: &AdjacencyList<(Rectangle<i64>, u32)>,
+) -> HashMap<Rectangle<i64>, PotentialField> {
+    let tasks: Vec<_> = solver
+        .solve(mesh, density)
+        .into_iter()
+        .map(|(_, potential)| PotentialExchange::new(potential, mesh, edge_list))
+        .collect();
+    execute(tasks).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{solve_potential, PoissonSolver, PotentialProvider, NUM_GUARD};
+    use crate::automaton::execute;
+    use crate::meshing::{GraphTopology, Mesh};
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+    use crate::solvers::euler2d_pcm::PatchUpdate;
+
+    /// A stand-in solver that returns the potential of a uniform field.
+    struct UniformField(f64);
+
+    impl PoissonSolver for UniformField {
+        fn solve(&mut self, mesh: &Mesh, density: &RectangleMap<i64, Patch>) -> RectangleMap<i64, Patch> {
+            density
+                .iter()
+                .map(|(rect, p)| {
+                    let phi = Patch::from_scalar_function(p.level(), p.index_space(), |index| {
+                        -self.0 * mesh.cell_center_at_level(p.level(), index).0
+                    });
+                    ((rect.0.clone(), rect.1.clone()), phi)
+                })
+                .collect()
+        }
+    }
+
+    fn setup() -> (Mesh, RectangleMap<i64, Patch>) {
+        let mesh = Mesh {
+            area: (0.0..1.0, 0.0..1.0),
+            size: (8, 8),
+        };
+        let patches = (0..2)
+            .map(|n| Patch::from_vector_function(0, (n * 4..n * 4 + 4, 0..8), |_| [0.1, 0.0, 0.0, 0.125]))
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+        (mesh, patches)
+    }
+
+    #[test]
+    fn potential_guard_zones_are_exchanged() {
+        let (mesh, patches) = setup();
+        let edge_list = patches.adjacency_list(NUM_GUARD);
+        let fields = solve_potential(&mut UniformField(2.0), &mesh, &patches, &edge_list);
+
+        assert_eq!(fields.len(), 2);
+        let field = &fields[&(0..4, 0..8)];
+        assert!(field.potential().get_slice((4, 3))[0].is_finite());
+        assert!(field.potential().get_slice((-1, 3))[0].is_nan());
+
+        for index in [(0, 0), (3, 4), (2, 7)] {
+            let (gi, gj) = field.acceleration(index);
+            assert!((gi - 2.0).abs() < 1e-12 && gj.abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn update_scheme_applies_gravity() {
+        let (mesh, patches) = setup();
+        let edge_list = patches.adjacency_list(1);
+        let mut fields = solve_potential(&mut UniformField(2.0), &mesh, &patches, &edge_list);
+        let tasks: Vec<_> = patches
+            .into_iter()
+            .map(|(rect, p)| {
+                let mut task = PatchUpdate::new(p, mesh.clone(), 0.01, None, &edge_list);
+                task.set_gravity(Box::new(fields.remove(&rect).unwrap()));
+                task
+            })
+            .collect();
+
+        for task in execute(tasks) {
+            for p in task.primitive().data().chunks(4) {
+                assert!((p[1] - 0.02).abs() < 1e-12 && p[2].abs() < 1e-12);
+            }
+        }
+    }
+}
@@ -5,7 +5,7 @@ use std::error;
 
 
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 
 
 /**
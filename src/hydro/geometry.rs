@@ -1,3 +1,6 @@
+use crate::index_space::Axis;
+use crate::num_vec::Vector;
+use core::ops::{Index, IndexMut};
 
 
 
@@ -5,15 +8,72 @@
 /**
  * A 3D vector
  */
-pub struct Vector3d(f64, f64, f64);
+pub type Vector3d = Vector<f64, 3>;
 
 
 
 
 // ============================================================================
-impl Vector3d {
+impl Vector<f64, 3> {
     pub fn new(x: f64, y: f64, z: f64) -> Self {
-        Vector3d(x, y, z)
+        Self::from([x, y, z])
+    }
+
+    pub fn x(&self) -> f64 {
+        self[0]
+    }
+
+    pub fn y(&self) -> f64 {
+        self[1]
+    }
+
+    pub fn z(&self) -> f64 {
+        self[2]
+    }
+
+    /**
+     * The cross product of this vector with another, `self x other`.
+     */
+    pub fn cross(&self, other: &Self) -> Self {
+        Self::new(
+            self[1] * other[2] - self[2] * other[1],
+            self[2] * other[0] - self[0] * other[2],
+            self[0] * other[1] - self[1] * other[0])
+    }
+
+    /**
+     * The component of this vector along the given direction.
+     */
+    pub fn component(&self, direction: Direction) -> f64 {
+        self[direction]
+    }
+
+    /**
+     * Return this vector scaled to unit length. The zero vector has no
+     * direction, and is returned unchanged.
+     */
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+
+        if norm == 0.0 {
+            *self
+        } else {
+            *self / norm
+        }
+    }
+}
+
+impl Index<Direction> for Vector3d {
+    type Output = f64;
+
+    fn index(&self, direction: Direction) -> &f64 {
+        &self[direction.index()]
+    }
+}
+
+impl IndexMut<Direction> for Vector3d {
+    fn index_mut(&mut self, direction: Direction) -> &mut f64 {
+        &mut self[direction.index()]
     }
 }
 
@@ -21,9 +81,11 @@ impl Vector3d {
 
 
 /**
- * Enum to hold a unit vector in 3D space
+ * Enum to hold a unit vector in 3D space. The directions are named after
+ * the index axes (I, J, K); the Cartesian names X, Y, Z are aliases for
+ * them.
  */
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum Direction { I, J, K }
 
 
@@ -31,12 +93,127 @@ pub enum Direction { I, J, K }
 
 // ============================================================================
 impl Direction {
+    pub const X: Direction = Direction::I;
+    pub const Y: Direction = Direction::J;
+    pub const Z: Direction = Direction::K;
+
+    /**
+     * All of the directions, in order.
+     */
+    pub const ALL: [Direction; 3] = [Direction::I, Direction::J, Direction::K];
+
     pub fn along(&self, other: Direction) -> f64 {
-        match (self, other) {
-            (Direction::I, Direction::I) => 1.0,
-            (Direction::J, Direction::J) => 1.0,
-            (Direction::K, Direction::K) => 1.0,
-            _ => 0.0,
+        if *self == other {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    pub fn unit_vector(&self) -> Vector3d {
+        Vector3d::new(
+            self.along(Direction::I),
+            self.along(Direction::J),
+            self.along(Direction::K))
+    }
+
+    /**
+     * The index of the vector component in this direction: 0, 1, or 2.
+     */
+    pub fn index(&self) -> usize {
+        match self {
+            Direction::I => 0,
+            Direction::J => 1,
+            Direction::K => 2,
+        }
+    }
+
+    /**
+     * The direction of the vector component with the given index, or `None`
+     * if the index is not 0, 1, or 2.
+     */
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    /**
+     * The next direction in cyclic order (I -> J -> K -> I), so that `d`,
+     * `d.next()`, and `d.next().next()` form a right-handed basis.
+     */
+    pub fn next(&self) -> Self {
+        Self::ALL[(self.index() + 1) % 3]
+    }
+
+    /**
+     * The index space axis in this direction, or `None` for K, which has no
+     * axis on a 2D mesh.
+     */
+    pub fn axis(&self) -> Option<Axis> {
+        match self {
+            Direction::I => Some(Axis::I),
+            Direction::J => Some(Axis::J),
+            Direction::K => None,
+        }
+    }
+}
+
+impl From<Axis> for Direction {
+    fn from(axis: Axis) -> Self {
+        match axis {
+            Axis::I => Direction::I,
+            Axis::J => Direction::J,
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod test {
+
+    use super::{Direction, Vector3d};
+    use crate::index_space::Axis;
+
+    #[test]
+    fn cross_products_follow_the_right_hand_rule() {
+        for d in Direction::ALL {
+            let (a, b, c) = (d.unit_vector(), d.next().unit_vector(), d.next().next().unit_vector());
+            assert_eq!(a.cross(&b), c);
+            assert_eq!(b.cross(&a), -c);
+            assert_eq!(a.cross(&a), Vector3d::new(0.0, 0.0, 0.0));
         }
+        let u = Vector3d::new(1.0, 2.0, 3.0);
+        let v = Vector3d::new(-2.0, 0.5, 4.0);
+        let w = u.cross(&v);
+        assert_eq!(w.dot(&u), 0.0);
+        assert_eq!(w.dot(&v), 0.0);
+        assert_eq!(Vector3d::new(3.0, 0.0, 4.0).normalized().norm(), 1.0);
+    }
+
+    #[test]
+    fn vectors_can_be_indexed_by_direction() {
+        let mut v = Vector3d::new(1.0, 2.0, 3.0);
+        v[Direction::Z] = 4.0;
+        assert_eq!((v.x(), v.y(), v.z()), (1.0, 2.0, 4.0));
+        assert_eq!(v.component(Direction::J), v[Direction::Y]);
+
+        for (n, d) in Direction::ALL.iter().enumerate() {
+            assert_eq!(Direction::from_index(n), Some(*d));
+            assert_eq!(d.index(), n);
+        }
+        assert_eq!(Direction::from_index(3), None);
+        assert_eq!(Direction::from(Axis::J), Direction::J);
+        assert!(Direction::K.axis().is_none());
+    }
+
+    #[test]
+    fn vectors_and_directions_round_trip_through_serde() {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&(Vector3d::new(1.0, -2.0, 0.5), Direction::K), &mut bytes).unwrap();
+        let (v, d): (Vector3d, Direction) = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(v, Vector3d::new(1.0, -2.0, 0.5));
+        assert_eq!(d, Direction::Z);
     }
 }
@@ -0,0 +1,483 @@
+use std::ops::{Add, Sub, Mul, Div};
+use super::error::Error;
+use super::geometry::Direction;
+
+
+
+
+/**
+ * Conserved variables of ideal MHD: mass density, three momentum components,
+ * total energy density, and three magnetic field components. Units are such
+ * that the magnetic pressure is B^2 / 2.
+ */
+#[derive(Clone, Copy)]
+pub struct Conserved(f64, f64, f64, f64, f64, f64, f64, f64);
+
+
+
+
+/**
+ * Primitive variables of ideal MHD: mass density, three velocity components,
+ * gas pressure, and three magnetic field components.
+ */
+#[derive(Clone, Copy)]
+pub struct Primitive(f64, f64, f64, f64, f64, f64, f64, f64);
+
+
+
+
+// ============================================================================
+impl Conserved {
+
+    fn from_slice(cons: &[f64]) -> Self {
+        Self(cons[0], cons[1], cons[2], cons[3], cons[4], cons[5], cons[6], cons[7])
+    }
+
+    pub fn write_to_slice(&self, cons: &mut [f64]) {
+        cons.clone_from_slice(&self.as_array())
+    }
+
+    pub fn as_array(&self) -> [f64; 8] {
+        [self.0, self.1, self.2, self.3, self.4, self.5, self.6, self.7]
+    }
+
+    pub fn mass_density(&self) -> f64 {
+        self.0
+    }
+
+    pub fn momentum(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::I => self.1,
+            Direction::J => self.2,
+            Direction::K => self.3,
+        }
+    }
+
+    pub fn energy_density(&self) -> f64 {
+        self.4
+    }
+
+    pub fn magnetic_field(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::I => self.5,
+            Direction::J => self.6,
+            Direction::K => self.7,
+        }
+    }
+
+    pub fn momentum_squared(&self) -> f64 {
+        self.1 * self.1 + self.2 * self.2 + self.3 * self.3
+    }
+
+    pub fn magnetic_pressure(&self) -> f64 {
+        0.5 * (self.5 * self.5 + self.6 * self.6 + self.7 * self.7)
+    }
+
+    pub fn to_primitive(&self, gamma_law_index: f64) -> Result<Primitive, Error> {
+        let d = self.mass_density();
+        let ek = 0.5 * self.momentum_squared() / d;
+        let pg = (self.energy_density() - ek - self.magnetic_pressure()) * (gamma_law_index - 1.0);
+
+        if d < 0.0 {
+            Err(Error::NegativeMassDensity(d))
+        } else if pg < 0.0 {
+            Err(Error::NegativeGasPressure(pg))
+        } else {
+            Ok(Primitive(d, self.1 / d, self.2 / d, self.3 / d, pg, self.5, self.6, self.7))
+        }
+    }
+
+    /**
+     * Rotate from a frame where the first vector components are aligned with
+     * the given direction, back to the (I, J, K) frame.
+     */
+    fn rotate_from_normal(self, direction: Direction) -> Self {
+        let Self(d, mn, m1, m2, e, bn, b1, b2) = self;
+        match direction {
+            Direction::I => self,
+            Direction::J => Self(d, m2, mn, m1, e, b2, bn, b1),
+            Direction::K => Self(d, m1, m2, mn, e, b1, b2, bn),
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl Primitive {
+
+    fn from_slice(prim: &[f64]) -> Self {
+        Self(prim[0], prim[1], prim[2], prim[3], prim[4], prim[5], prim[6], prim[7])
+    }
+
+    pub fn write_to_slice(&self, prim: &mut [f64]) {
+        prim.clone_from_slice(&self.as_array())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(d0: f64, u0: f64, v0: f64, w0: f64, p0: f64, b1: f64, b2: f64, b3: f64) -> Self {
+        Self(d0, u0, v0, w0, p0, b1, b2, b3)
+    }
+
+    pub fn as_array(&self) -> [f64; 8] {
+        [self.0, self.1, self.2, self.3, self.4, self.5, self.6, self.7]
+    }
+
+    pub fn mass_density(&self) -> f64 {
+        self.0
+    }
+
+    pub fn velocity(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::I => self.1,
+            Direction::J => self.2,
+            Direction::K => self.3,
+        }
+    }
+
+    pub fn gas_pressure(&self) -> f64 {
+        self.4
+    }
+
+    pub fn magnetic_field(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::I => self.5,
+            Direction::J => self.6,
+            Direction::K => self.7,
+        }
+    }
+
+    /**
+     * Return a copy of this state with the given component of the magnetic
+     * field replaced.
+     */
+    pub fn with_magnetic_field(&self, direction: Direction, b: f64) -> Self {
+        let mut p = *self;
+        match direction {
+            Direction::I => p.5 = b,
+            Direction::J => p.6 = b,
+            Direction::K => p.7 = b,
+        }
+        p
+    }
+
+    pub fn velocity_squared(&self) -> f64 {
+        self.1 * self.1 + self.2 * self.2 + self.3 * self.3
+    }
+
+    pub fn magnetic_pressure(&self) -> f64 {
+        0.5 * (self.5 * self.5 + self.6 * self.6 + self.7 * self.7)
+    }
+
+    pub fn total_pressure(&self) -> f64 {
+        self.gas_pressure() + self.magnetic_pressure()
+    }
+
+    pub fn velocity_dot_magnetic_field(&self) -> f64 {
+        self.1 * self.5 + self.2 * self.6 + self.3 * self.7
+    }
+
+    pub fn sound_speed_squared(&self, gamma_law_index: f64) -> f64 {
+        gamma_law_index * self.gas_pressure() / self.mass_density()
+    }
+
+    /**
+     * Return the fast magnetosonic speed for propagation along the given
+     * direction.
+     */
+    pub fn fast_magnetosonic_speed(&self, direction: Direction, gamma_law_index: f64) -> f64 {
+        let d = self.mass_density();
+        let gp = gamma_law_index * self.gas_pressure();
+        let bsq = 2.0 * self.magnetic_pressure();
+        let bn = self.magnetic_field(direction);
+        let a = gp + bsq;
+        (0.5 * (a + (a * a - 4.0 * gp * bn * bn).max(0.0).sqrt()) / d).sqrt()
+    }
+
+    pub fn outer_wavespeeds(&self, direction: Direction, gamma_law_index: f64) -> (f64, f64) {
+        let cf = self.fast_magnetosonic_speed(direction, gamma_law_index);
+        let vn = self.velocity(direction);
+        (vn - cf, vn + cf)
+    }
+
+    pub fn max_signal_speed(&self, gamma_law_index: f64) -> f64 {
+        [Direction::I, Direction::J, Direction::K]
+            .iter()
+            .map(|&n| self.velocity(n).abs() + self.fast_magnetosonic_speed(n, gamma_law_index))
+            .fold(0.0, f64::max)
+    }
+
+    pub fn to_conserved(&self, gamma_law_index: f64) -> Conserved {
+        let d = self.mass_density();
+        let p = self.gas_pressure();
+
+        Conserved(
+            d,
+            d * self.1,
+            d * self.2,
+            d * self.3,
+            0.5 * d * self.velocity_squared() + p / (gamma_law_index - 1.0) + self.magnetic_pressure(),
+            self.5,
+            self.6,
+            self.7,
+        )
+    }
+
+    pub fn flux_vector(&self, direction: Direction, gamma_law_index: f64) -> Conserved {
+        self.rotate_to_normal(direction)
+            .flux_vector_normal(gamma_law_index)
+            .rotate_from_normal(direction)
+    }
+
+    /**
+     * Rotate to a frame where the first vector components are aligned with
+     * the given direction.
+     */
+    fn rotate_to_normal(self, direction: Direction) -> Self {
+        let Self(d, v1, v2, v3, p, b1, b2, b3) = self;
+        match direction {
+            Direction::I => self,
+            Direction::J => Self(d, v2, v3, v1, p, b2, b3, b1),
+            Direction::K => Self(d, v3, v1, v2, p, b3, b1, b2),
+        }
+    }
+
+    /**
+     * The flux vector in the direction of the first vector component.
+     */
+    fn flux_vector_normal(&self, gamma_law_index: f64) -> Conserved {
+        let Self(_, vn, v1, v2, _, bn, b1, b2) = *self;
+        let u = self.to_conserved(gamma_law_index);
+        let pt = self.total_pressure();
+        let vb = self.velocity_dot_magnetic_field();
+
+        Conserved(
+            u.0 * vn,
+            u.1 * vn + pt - bn * bn,
+            u.2 * vn - bn * b1,
+            u.3 * vn - bn * b2,
+            (u.4 + pt) * vn - bn * vb,
+            0.0,
+            b1 * vn - bn * v1,
+            b2 * vn - bn * v2,
+        )
+    }
+}
+
+
+
+
+// ============================================================================
+impl From<&[f64]> for Conserved {
+    fn from(cons: &[f64]) -> Self {
+        Self::from_slice(cons)
+    }
+}
+
+impl From<&[f64]> for Primitive {
+    fn from(prim: &[f64]) -> Self {
+        Self::from_slice(prim)
+    }
+}
+
+
+
+
+// ============================================================================
+impl Add<Conserved> for Conserved {
+    type Output = Self;
+    fn add(self, u: Self) -> Self {
+        Self(self.0 + u.0, self.1 + u.1, self.2 + u.2, self.3 + u.3, self.4 + u.4, self.5 + u.5, self.6 + u.6, self.7 + u.7)
+    }
+}
+
+impl Sub<Conserved> for Conserved {
+    type Output = Self;
+    fn sub(self, u: Self) -> Self {
+        Self(self.0 - u.0, self.1 - u.1, self.2 - u.2, self.3 - u.3, self.4 - u.4, self.5 - u.5, self.6 - u.6, self.7 - u.7)
+    }
+}
+
+impl Mul<f64> for Conserved {
+    type Output = Self;
+    fn mul(self, a: f64) -> Self {
+        Self(self.0 * a, self.1 * a, self.2 * a, self.3 * a, self.4 * a, self.5 * a, self.6 * a, self.7 * a)
+    }
+}
+
+impl Div<f64> for Conserved {
+    type Output = Self;
+    fn div(self, a: f64) -> Self {
+        Self(self.0 / a, self.1 / a, self.2 / a, self.3 / a, self.4 / a, self.5 / a, self.6 / a, self.7 / a)
+    }
+}
+
+
+
+
+/**
+ * Intermediate state in the HLLD fan, in the normal frame.
+ */
+struct StarState {
+    d: f64,
+    v: (f64, f64),
+    b: (f64, f64),
+    e: f64,
+}
+
+impl StarState {
+    fn to_conserved(&self, sm: f64, bn: f64) -> Conserved {
+        Conserved(self.d, self.d * sm, self.d * self.v.0, self.d * self.v.1, self.e, bn, self.b.0, self.b.1)
+    }
+
+    fn v_dot_b(&self, sm: f64, bn: f64) -> f64 {
+        sm * bn + self.v.0 * self.b.0 + self.v.1 * self.b.1
+    }
+}
+
+
+
+
+// ============================================================================
+/**
+ * The HLLD approximate Riemann solver of Miyoshi & Kusano (2005). The normal
+ * component of the magnetic field must be continuous across the interface;
+ * it is taken to be `bn`, which overrides the normal field in both states.
+ * In constrained transport schemes `bn` is the face-centered field.
+ */
+pub fn riemann_hlld(pl: Primitive, pr: Primitive, bn: f64, direction: Direction, gamma_law_index: f64) -> Conserved {
+    let pl = pl.with_magnetic_field(direction, bn).rotate_to_normal(direction);
+    let pr = pr.with_magnetic_field(direction, bn).rotate_to_normal(direction);
+
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector_normal(gamma_law_index);
+    let fr = pr.flux_vector_normal(gamma_law_index);
+
+    let cf = pl.fast_magnetosonic_speed(Direction::I, gamma_law_index)
+        .max(pr.fast_magnetosonic_speed(Direction::I, gamma_law_index));
+    let sl = pl.1.min(pr.1) - cf;
+    let sr = pl.1.max(pr.1) + cf;
+
+    if sl >= 0.0 {
+        return fl.rotate_from_normal(direction);
+    }
+    if sr <= 0.0 {
+        return fr.rotate_from_normal(direction);
+    }
+
+    let (dl, dr) = (pl.0, pr.0);
+    let (vl, vr) = (pl.1, pr.1);
+    let (ptl, ptr) = (pl.total_pressure(), pr.total_pressure());
+    let wl = (sl - vl) * dl;
+    let wr = (sr - vr) * dr;
+    let sm = (wr * vr - wl * vl - ptr + ptl) / (wr - wl);
+    let pt = (wr * ptl - wl * ptr + wl * wr * (vr - vl)) / (wr - wl);
+
+    let star = |p: &Primitive, u: &Conserved, s: f64| {
+        let d = p.0 * (s - p.1) / (s - sm);
+        let denom = p.0 * (s - p.1) * (s - sm) - bn * bn;
+
+        let (v, b) = if denom.abs() < 1e-12 * (pt + bn * bn) {
+            ((p.2, p.3), (p.6, p.7))
+        } else {
+            let a = bn * (sm - p.1) / denom;
+            let c = (p.0 * (s - p.1) * (s - p.1) - bn * bn) / denom;
+            ((p.2 - p.6 * a, p.3 - p.7 * a), (p.6 * c, p.7 * c))
+        };
+        let mut state = StarState { d, v, b, e: 0.0 };
+        state.e = ((s - p.1) * u.4 - p.total_pressure() * p.1 + pt * sm
+            + bn * (p.velocity_dot_magnetic_field() - state.v_dot_b(sm, bn))) / (s - sm);
+        state
+    };
+
+    let ls = star(&pl, &ul, sl);
+    let rs = star(&pr, &ur, sr);
+    let uls = ls.to_conserved(sm, bn);
+    let urs = rs.to_conserved(sm, bn);
+    let sls = sm - bn.abs() / ls.d.sqrt();
+    let srs = sm + bn.abs() / rs.d.sqrt();
+
+    let flux = if sls >= 0.0 {
+        fl + (uls - ul) * sl
+    } else if srs <= 0.0 {
+        fr + (urs - ur) * sr
+    } else {
+        let (ql, qr) = (ls.d.sqrt(), rs.d.sqrt());
+        let sgn = if bn >= 0.0 { 1.0 } else { -1.0 };
+        let v = (
+            (ql * ls.v.0 + qr * rs.v.0 + (rs.b.0 - ls.b.0) * sgn) / (ql + qr),
+            (ql * ls.v.1 + qr * rs.v.1 + (rs.b.1 - ls.b.1) * sgn) / (ql + qr),
+        );
+        let b = (
+            (ql * rs.b.0 + qr * ls.b.0 + ql * qr * (rs.v.0 - ls.v.0) * sgn) / (ql + qr),
+            (ql * rs.b.1 + qr * ls.b.1 + ql * qr * (rs.v.1 - ls.v.1) * sgn) / (ql + qr),
+        );
+        let vb = sm * bn + v.0 * b.0 + v.1 * b.1;
+
+        if sm >= 0.0 {
+            let lss = StarState { d: ls.d, v, b, e: ls.e - ql * (ls.v_dot_b(sm, bn) - vb) * sgn };
+            fl + (uls - ul) * sl + (lss.to_conserved(sm, bn) - uls) * sls
+        } else {
+            let rss = StarState { d: rs.d, v, b, e: rs.e + qr * (rs.v_dot_b(sm, bn) - vb) * sgn };
+            fr + (urs - ur) * sr + (rss.to_conserved(sm, bn) - urs) * srs
+        }
+    };
+    flux.rotate_from_normal(direction)
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod test {
+
+    use super::{riemann_hlld, Primitive};
+    use crate::hydro::geometry::Direction;
+
+    const GAMMA: f64 = 5.0 / 3.0;
+
+    fn assert_close(a: [f64; 8], b: [f64; 8]) {
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-12, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn hlld_flux_is_consistent() {
+        let p = Primitive::new(1.2, 0.3, -0.2, 0.1, 0.8, 0.5, 0.7, -0.3);
+
+        for &n in &[Direction::I, Direction::J, Direction::K] {
+            let f = riemann_hlld(p, p, p.magnetic_field(n), n, GAMMA);
+            assert_close(f.as_array(), p.flux_vector(n, GAMMA).as_array());
+        }
+    }
+
+    #[test]
+    fn hlld_flux_is_upwind_for_supersonic_flow() {
+        let pl = Primitive::new(1.0, 10.0, 0.0, 0.0, 1.0, 0.75, 1.0, 0.0);
+        let pr = Primitive::new(0.125, 10.0, 0.0, 0.0, 0.1, 0.75, -1.0, 0.0);
+        let f = riemann_hlld(pl, pr, 0.75, Direction::I, GAMMA);
+        assert_close(f.as_array(), pl.flux_vector(Direction::I, GAMMA).as_array());
+    }
+
+    #[test]
+    fn hlld_flux_is_symmetric_under_reflection() {
+        let pl = Primitive::new(1.0, 0.0, 0.0, 0.0, 1.0, 0.75, 1.0, 0.0);
+        let pr = Primitive::new(0.125, 0.0, 0.0, 0.0, 0.1, 0.75, -1.0, 0.0);
+        let f = riemann_hlld(pl, pr, 0.75, Direction::I, GAMMA).as_array();
+        let g = riemann_hlld(pr.mirror(), pl.mirror(), -0.75, Direction::I, GAMMA).as_array();
+
+        assert!((f[0] + g[0]).abs() < 1e-12);
+        assert!((f[1] - g[1]).abs() < 1e-12);
+        assert!((f[4] + g[4]).abs() < 1e-12);
+    }
+
+    impl Primitive {
+        fn mirror(&self) -> Self {
+            Primitive::new(self.0, -self.1, self.2, self.3, self.4, -self.5, self.6, self.7)
+        }
+    }
+}
@@ -2,3 +2,4 @@ pub mod euler2d;
 pub mod euler3d;
 pub mod error;
 pub mod geometry;
+pub mod mhd2d;
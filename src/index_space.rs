@@ -1,3 +1,4 @@
+use crate::error::{Error, Result};
 use core::ops::Range;
 
 /// Identifier for a Cartesian axis
@@ -18,6 +19,12 @@ impl Axis {
 }
 
 /// Describes a rectangular index space. The index type is signed 64-bit integer.
+///
+/// Either axis may be empty, in which case the whole space is empty: it has
+/// zero length, its iterator yields nothing, it contains no indexes, and it
+/// is a subset of every other index space. Operations which would give an
+/// axis negative length (intersecting disjoint spaces, or trimming more
+/// elements than there are) produce an empty axis instead.
 /// 
 #[derive(Clone, Debug)]
 pub struct IndexSpace {
@@ -27,18 +34,36 @@ pub struct IndexSpace {
 
 impl IndexSpace {
 
-    /// Construct a new index space from the given ranges. The ranges are
+    /// Construct a new index space from the given ranges. Either range is
     /// allowed to be empty but this function panics if either has negative
-    /// length.
+    /// length. The check is skipped in release builds unless the `checks`
+    /// feature is enabled.
     /// 
     pub fn new(di: Range<i64>, dj: Range<i64>) -> Self {
-        assert!{
-            di.start <= di.end && dj.start < dj.end,
-            "index space has negative volume"
-        };
+        if crate::CHECKS {
+            assert!{
+                di.start <= di.end && dj.start <= dj.end,
+                "index space has negative volume"
+            };
+        }
         Self { di, dj }
     }
 
+    /// Construct a new index space without checking the ranges, for use
+    /// where they are known to be valid.
+    pub(crate) fn new_unchecked(di: Range<i64>, dj: Range<i64>) -> Self {
+        Self { di, dj }
+    }
+
+    /// Construct an index space from ranges which may have negative length,
+    /// collapsing each such range to an empty one at its start.
+    fn new_clamped(di: Range<i64>, dj: Range<i64>) -> Self {
+        Self {
+            di: di.start..di.end.max(di.start),
+            dj: dj.start..dj.end.max(dj.start),
+        }
+    }
+
     /// Determine whether this index space is empty.
     /// 
     pub fn is_empty(&self) -> bool {
@@ -49,8 +74,8 @@ impl IndexSpace {
     /// 
     pub fn dim(&self) -> (usize, usize) {
         (
-            (self.di.end - self.di.start) as usize,
-            (self.dj.end - self.dj.start) as usize,
+            (self.di.end - self.di.start).max(0) as usize,
+            (self.dj.end - self.dj.start).max(0) as usize,
         )
     }
 
@@ -92,16 +117,19 @@ impl IndexSpace {
         self.di.contains(&index.0) && self.dj.contains(&index.1)
     }
 
-    /// Determine whether another index space is a subset of this one.
+    /// Determine whether another index space is a subset of this one. An
+    /// empty index space is a subset of every index space.
     /// 
     pub fn contains_space(&self, other: &Self) -> bool {
-        other.di.start >= self.di.start
+        other.is_empty()
+            || other.di.start >= self.di.start
             && other.di.end <= self.di.end
             && other.dj.start >= self.dj.start
             && other.dj.end <= self.dj.end
     }
 
-    /// Return the overlapping region between two index spaces.
+    /// Return the overlapping region between two index spaces. If they are
+    /// disjoint the result is empty.
     /// 
     pub fn intersect<I: Into<Self>>(&self, other: I) -> Self {
         let other = other.into();
@@ -109,14 +137,15 @@ impl IndexSpace {
         let j0 = self.dj.start.max(other.dj.start);
         let i1 = self.di.end.min(other.di.end);
         let j1 = self.dj.end.min(other.dj.end);
-        Self::new(i0..i1, j0..j1)
+        Self::new_clamped(i0..i1, j0..j1)
     }
 
     /// Extend this index space by the given number of elements on both sides
-    /// of each axis.
+    /// of each axis. A negative delta shrinks the space, but never past
+    /// empty.
     /// 
     pub fn extend_all(&self, delta: i64) -> Self {
-        Self::new(
+        Self::new_clamped(
             self.di.start - delta..self.di.end + delta,
             self.dj.start - delta..self.dj.end + delta,
         )
@@ -127,8 +156,8 @@ impl IndexSpace {
     /// 
     pub fn extend(&self, delta: i64, axis: Axis) -> Self {
         match axis {
-            Axis::I => Self::new(self.di.start - delta..self.di.end + delta, self.dj.clone()),
-            Axis::J => Self::new(self.di.clone(), self.dj.start - delta..self.dj.end + delta),
+            Axis::I => Self::new_clamped(self.di.start - delta..self.di.end + delta, self.dj.clone()),
+            Axis::J => Self::new_clamped(self.di.clone(), self.dj.start - delta..self.dj.end + delta),
         }
     }
 
@@ -137,8 +166,8 @@ impl IndexSpace {
     /// 
     pub fn extend_lower(&self, delta: i64, axis: Axis) -> Self {
         match axis {
-            Axis::I => Self::new(self.di.start - delta..self.di.end, self.dj.clone()),
-            Axis::J => Self::new(self.di.clone(), self.dj.start - delta..self.dj.end),
+            Axis::I => Self::new_clamped(self.di.start - delta..self.di.end, self.dj.clone()),
+            Axis::J => Self::new_clamped(self.di.clone(), self.dj.start - delta..self.dj.end),
         }
     }
 
@@ -147,13 +176,14 @@ impl IndexSpace {
     /// 
     pub fn extend_upper(&self, delta: i64, axis: Axis) -> Self {
         match axis {
-            Axis::I => Self::new(self.di.start..self.di.end + delta, self.dj.clone()),
-            Axis::J => Self::new(self.di.clone(), self.dj.start..self.dj.end + delta),
+            Axis::I => Self::new_clamped(self.di.start..self.di.end + delta, self.dj.clone()),
+            Axis::J => Self::new_clamped(self.di.clone(), self.dj.start..self.dj.end + delta),
         }
     }
 
     /// Trim this index space by the given number of elements on both sides of
-    /// each axis.
+    /// each axis. Trimming more elements than there are leaves an empty
+    /// space.
     /// 
     pub fn trim_all(&self, delta: i64) -> Self {
         self.extend_all(-delta)
@@ -189,11 +219,23 @@ impl IndexSpace {
         }
     }
 
+    /// Translate this index space by the given offset on both axes.
+    /// 
+    pub fn translate_by(&self, delta: (i64, i64)) -> Self {
+        self.translate(delta.0, Axis::I).translate(delta.1, Axis::J)
+    }
+
+    /// Return the index space with the `i` and `j` axes exchanged.
+    /// 
+    pub fn swap_axes(&self) -> Self {
+        Self::new(self.dj.clone(), self.di.clone())
+    }
+
     /// Increase the size of this index space by the given factor.
     /// 
     pub fn refine_by(&self, factor: u32) -> Self {
         let factor = factor as i64;
-        Self::new(
+        Self::new_unchecked(
             self.di.start * factor..self.di.end * factor,
             self.dj.start * factor..self.dj.end * factor,
         )
@@ -202,20 +244,30 @@ impl IndexSpace {
     /// Increase the size of this index space by the given factor.
     /// 
     pub fn coarsen_by(&self, factor: u32) -> Self {
+        self.try_coarsen_by(factor).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`IndexSpace::coarsen_by`], except that a mesh error is returned
+    /// if the coarsening factor does not divide the index space.
+    /// 
+    pub fn try_coarsen_by(&self, factor: u32) -> Result<Self> {
         let factor = factor as i64;
 
-        assert! {
-            self.di.start % factor == 0 &&
-            self.dj.start % factor == 0 &&
-            self.di.end % factor == 0 &&
-            self.dj.end % factor == 0,
-            "index space must divide the coarsening factor"
-        };
+        if self.di.start % factor != 0
+            || self.dj.start % factor != 0
+            || self.di.end % factor != 0
+            || self.dj.end % factor != 0
+        {
+            return Err(Error::Mesh(format!(
+                "index space ({}..{} {}..{}) must divide the coarsening factor {}",
+                self.di.start, self.di.end, self.dj.start, self.dj.end, factor
+            )));
+        }
 
-        Self::new(
+        Ok(Self::new(
             self.di.start / factor..self.di.end / factor,
             self.dj.start / factor..self.dj.end / factor,
-        )
+        ))
     }
 
     /// Return the linear offset for the given index, in a row-major memory
@@ -267,6 +319,53 @@ impl IndexSpace {
             .map(move |i| self.dj.clone().map(move |j| (i, j)))
             .flatten()
     }
+
+    /// Return an iterator over tiles of the given shape which cover this
+    /// index space, in row-major order. Tiles on the upper edges of each
+    /// axis are truncated if the tile shape does not divide the space. Kernels
+    /// can loop over the indexes of each tile to keep their working set in
+    /// cache. This function panics if either side of the tile shape is zero.
+    /// 
+    pub fn tiles(&self, tile_shape: (usize, usize)) -> impl Iterator<Item = Self> + '_ {
+        let (ti, tj) = Self::tile_steps(tile_shape);
+        self.di
+            .clone()
+            .step_by(ti as usize)
+            .flat_map(move |i| self.dj.clone().step_by(tj as usize).map(move |j| self.tile_at((i, j), (ti, tj))))
+    }
+
+    /// Return an iterator over tiles of the given shape which cover this
+    /// index space, in row-panel order: the space is divided into panels of
+    /// `panel_height` rows of tiles, and the tiles in each panel are visited
+    /// column by column. A flux sweep along the `i` axis then reuses the rows
+    /// loaded for the tile above, while the working set stays within one
+    /// panel. This function panics if either side of the tile shape, or the
+    /// panel height, is zero.
+    /// 
+    pub fn tiles_by_panel(&self, tile_shape: (usize, usize), panel_height: usize) -> impl Iterator<Item = Self> + '_ {
+        assert!(panel_height > 0, "panel height must be positive");
+        let (ti, tj) = Self::tile_steps(tile_shape);
+        let panel = ti * panel_height as i64;
+        self.di
+            .clone()
+            .step_by(panel as usize)
+            .flat_map(move |p| {
+                self.dj.clone().step_by(tj as usize).flat_map(move |j| {
+                    (p..(p + panel).min(self.di.end))
+                        .step_by(ti as usize)
+                        .map(move |i| self.tile_at((i, j), (ti, tj)))
+                })
+            })
+    }
+
+    fn tile_steps(tile_shape: (usize, usize)) -> (i64, i64) {
+        assert!(tile_shape.0 > 0 && tile_shape.1 > 0, "tile shape must be positive");
+        (tile_shape.0 as i64, tile_shape.1 as i64)
+    }
+
+    fn tile_at(&self, (i, j): (i64, i64), (ti, tj): (i64, i64)) -> Self {
+        Self::new_unchecked(i..(i + ti).min(self.di.end), j..(j + tj).min(self.dj.end))
+    }
 }
 
 // The impl's below enable syntactic sugar for iteration, but since the
@@ -328,7 +427,7 @@ pub fn range2d(di: Range<i64>, dj: Range<i64>) -> IndexSpace {
 /**
  * A 2D memory region within a contiguous buffer.
  */
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct MemoryRegion {
     start: (usize, usize),
     count: (usize, usize),
@@ -371,6 +470,46 @@ impl MemoryRegion {
             .chunks_exact_mut(q)
             .flat_map(move |j| j[start.1 * r..(start.1 + count.1) * r].chunks_exact_mut(r))
     }
+
+    /// Like [`MemoryRegion::iter_slice`], except that the iterator yields the
+    /// contiguous part of each row within the region, rather than each
+    /// element.
+    pub fn iter_rows(self, slice: &[f64], chunk: usize) -> impl Iterator<Item = &'_ [f64]> {
+        let Self {
+            start,
+            shape,
+            count,
+        } = self;
+        let r = chunk;
+        let q = shape.1 * r;
+
+        assert!(slice.len() == shape.0 * shape.1 * chunk);
+
+        slice[start.0 * q..(start.0 + count.0) * q]
+            .chunks_exact(q)
+            .map(move |j| &j[start.1 * r..(start.1 + count.1) * r])
+    }
+
+    /// Like [`MemoryRegion::iter_rows`], but yielding mutable row slices.
+    pub fn iter_rows_mut(
+        self,
+        slice: &mut [f64],
+        chunk: usize,
+    ) -> impl Iterator<Item = &'_ mut [f64]> {
+        let Self {
+            start,
+            shape,
+            count,
+        } = self;
+        let r = chunk;
+        let q = shape.1 * r;
+
+        assert!(slice.len() == shape.0 * shape.1 * chunk);
+
+        slice[start.0 * q..(start.0 + count.0) * q]
+            .chunks_exact_mut(q)
+            .map(move |j| &mut j[start.1 * r..(start.1 + count.1) * r])
+    }
 }
 
 /// This is an access pattern iterator for a 3D hyperslab selection. *Experimental*.
@@ -451,6 +590,8 @@ pub fn iter_slice_3d_v3(
 #[cfg(test)]
 mod test {
 
+    use super::IndexSpace;
+
     const NI: usize = 100;
     const NJ: usize = 100;
     const NK: usize = 100;
@@ -475,4 +616,45 @@ mod test {
             1000
         );
     }
+
+    #[test]
+    fn empty_spaces_have_no_indexes() {
+        let space = IndexSpace::new(0..4, 2..2);
+        assert!(space.is_empty());
+        assert_eq!(space.len(), 0);
+        assert_eq!(space.iter().count(), 0);
+        assert!(!space.contains((0, 2)));
+        assert!(IndexSpace::new(10..12, 10..12).contains_space(&space));
+        assert_eq!(IndexSpace::new(3..3, 0..4).iter().count(), 0);
+    }
+
+    #[test]
+    fn shrinking_past_empty_gives_an_empty_space() {
+        let a = IndexSpace::new(0..4, 0..4);
+        let b = IndexSpace::new(6..8, 2..6);
+        assert!(a.intersect(b.clone()).is_empty());
+        assert_eq!(b.intersect(a.clone()).len(), 0);
+        assert_eq!(a.intersect((2..6, 1..3)).into_rect(), (2..4, 1..3));
+        assert!(a.trim_all(3).is_empty());
+        assert_eq!(a.trim_all(3).dim(), (0, 0));
+        assert_eq!(a.trim_lower(5, super::Axis::J).dim(), (4, 0));
+    }
+
+    #[test]
+    fn tiles_cover_the_space_exactly_once() {
+        let space = IndexSpace::new(1..11, -2..5);
+        let tiles: Vec<_> = space.tiles((4, 3)).map(|tile| tile.into_rect()).collect();
+        let panels: Vec<_> = space.tiles_by_panel((4, 3), 2).map(|tile| tile.into_rect()).collect();
+
+        assert_eq!(&tiles[..4], [(1..5, -2..1), (1..5, 1..4), (1..5, 4..5), (5..9, -2..1)]);
+        assert_eq!(&panels[..4], [(1..5, -2..1), (5..9, -2..1), (1..5, 1..4), (5..9, 1..4)]);
+        assert_eq!(tiles.len(), 9);
+        assert_eq!(panels.len(), 9);
+        assert_eq!(panels[6..], [(9..11, -2..1), (9..11, 1..4), (9..11, 4..5)]);
+
+        let mut indexes: Vec<_> = panels.into_iter().flat_map(|tile| IndexSpace::from(tile).iter().collect::<Vec<_>>()).collect();
+        indexes.sort_unstable();
+        assert_eq!(indexes, space.iter().collect::<Vec<_>>());
+        assert_eq!(IndexSpace::new(0..0, 0..4).tiles((2, 2)).count(), 0);
+    }
 }
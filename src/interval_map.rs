@@ -7,7 +7,9 @@ use crate::aug_node::{self, Node};
 
 /**
  * An associative map where the keys are `Range` objects. Supports point and
- * range-based queries to iterate over key-value pairs.
+ * range-based queries to iterate over key-value pairs. Iteration over the
+ * whole map (`iter`, `iter_mut`, `keys`, and `into_iter`) is in order of the
+ * key's start and then its end, regardless of the shape of the tree.
  */
 #[derive(Clone)]
 pub struct IntervalMap<T: Ord + Copy, V> {
@@ -81,6 +83,40 @@ impl<T: Ord + Copy, V> IntervalMap<T, V> {
         self.iter().map(|(k, _)| k)
     }
 
+    /**
+     * Combine this map with another one, in time linear in the total number
+     * of entries. Where both maps have the same key, the two values are
+     * combined by the given function, which receives this map's value first.
+     * The result is balanced.
+     */
+    pub fn merge_by<F>(self, other: Self, mut combine: F) -> Self
+    where
+        F: FnMut(V, V) -> V,
+    {
+        let mut a = self.into_sorted().peekable();
+        let mut b = other.into_sorted().peekable();
+        let mut data = Vec::new();
+
+        loop {
+            let order = match (a.peek(), b.peek()) {
+                (Some((ka, _)), Some((kb, _))) => (ka.start, ka.end).cmp(&(kb.start, kb.end)),
+                (Some(_), None) => core::cmp::Ordering::Less,
+                (None, Some(_)) => core::cmp::Ordering::Greater,
+                (None, None) => break,
+            };
+            data.push(Some(match order {
+                core::cmp::Ordering::Less => a.next().unwrap(),
+                core::cmp::Ordering::Greater => b.next().unwrap(),
+                core::cmp::Ordering::Equal => {
+                    let (key, va) = a.next().unwrap();
+                    let (_, vb) = b.next().unwrap();
+                    (key, combine(va, vb))
+                }
+            }))
+        }
+        Self { root: Node::from_sorted_slice(&mut data[..]) }
+    }
+
     pub fn query_point(&self, point: T) -> impl Iterator<Item = (&Range<T>, &V)> + '_ {
         aug_node::IterPointQuery::new(&self.root, point)
     }
@@ -88,6 +124,24 @@ impl<T: Ord + Copy, V> IntervalMap<T, V> {
     pub fn query_range<R: RangeBounds<T>>(&self, range: R) -> impl Iterator<Item = (&Range<T>, &V)> {
         aug_node::IterRangeQuery::new(&self.root, range)
     }
+
+
+
+
+    // ========================================================================
+    #[cfg(test)]
+    pub(crate) fn validate_max(&self) {
+        if let Some(root) = &self.root {
+            root.validate_max()
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn validate_order(&self) {
+        if let Some(root) = &self.root {
+            root.validate_order()
+        }
+    }
 }
 
 
@@ -150,3 +204,87 @@ impl<T: Ord + Copy, V> FromIterator<(Range<T>, V)> for IntervalMap<T, V> {
         }
     }
 }
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod test {
+
+    use core::ops::Range;
+    use super::IntervalMap;
+    use crate::aug_node::StupidRandom;
+    use crate::overlap::Overlap;
+
+    fn sorted<'a, I: Iterator<Item = (&'a Range<i64>, &'a usize)>>(iter: I) -> Vec<(Range<i64>, usize)> {
+        let mut result: Vec<_> = iter.map(|(k, v)| (k.clone(), *v)).collect();
+        result.sort_by_key(|(k, _)| (k.start, k.end));
+        result
+    }
+
+    fn check_queries(map: &IntervalMap<i64, usize>, model: &[(Range<i64>, usize)], rng: &mut StupidRandom) {
+        let everything = sorted(model.iter().map(|(k, v)| (k, v)));
+        assert_eq!(map.iter().map(|(k, v)| (k.clone(), *v)).collect::<Vec<_>>(), everything);
+
+        for _ in 0..10 {
+            let p = rng.below(90) as i64 - 5;
+            let r = rng.range(90, 20);
+            let by_point = sorted(map.query_point(p));
+            let matching = |f: &dyn Fn(&Range<i64>) -> bool| sorted(model.iter().filter(|(k, _)| f(k)).map(|(k, v)| (k, v)));
+            assert_eq!(by_point, matching(&|k| k.contains(&p)));
+            assert_eq!(by_point, sorted(map.query_range(p..p + 1)));
+            assert_eq!(sorted(map.query_range(r.clone())), matching(&|k| k.overlaps(&r)));
+            assert_eq!(sorted(map.query_range(..r.end)), matching(&|k| k.overlaps(&(..r.end))));
+        }
+    }
+
+    #[test]
+    fn map_agrees_with_a_naive_model_under_random_operations() {
+        for seed in 0..8 {
+            let mut rng = StupidRandom::new(seed);
+            let mut map = IntervalMap::new();
+            let mut model: Vec<(Range<i64>, usize)> = Vec::new();
+
+            for op in 0..2000 {
+                match rng.below(4) {
+                    0 | 1 => {
+                        let key = rng.range(64, 16);
+                        map.insert(key.clone(), op);
+                        match model.iter_mut().find(|(k, _)| *k == key) {
+                            Some(entry) => entry.1 = op,
+                            None => model.push((key, op)),
+                        }
+                    }
+                    2 => {
+                        let key = rng.range(64, 16);
+                        map.remove(&key);
+                        model.retain(|(k, _)| *k != key);
+                    }
+                    _ => {
+                        if !model.is_empty() {
+                            let (key, _) = model.swap_remove(rng.below(model.len() as u64) as usize);
+                            assert!(map.contains(&key));
+                            map.remove(&key);
+                            assert!(!map.contains(&key));
+                        }
+                    }
+                }
+                assert_eq!(map.len(), model.len());
+                map.validate_max();
+                map.validate_order();
+
+                if op % 20 == 0 {
+                    check_queries(&map, &model, &mut rng);
+                }
+            }
+            for (key, value) in &model {
+                assert_eq!(map.get(key), Some(value));
+            }
+            let map = map.into_balanced();
+            map.validate_max();
+            map.validate_order();
+            check_queries(&map, &model, &mut rng);
+        }
+    }
+}
@@ -136,6 +136,8 @@ mod test {
 
     use core::ops::Range;
     use super::IntervalSet;
+    use crate::aug_node::StupidRandom;
+    use crate::overlap::Overlap;
 
     /**
      * A simple deterministic linear congruential generator:
@@ -244,6 +246,63 @@ mod test {
         assert_eq!(set.query_point(11).collect::<Vec<_>>(), [&(1..17), &(8..12)]);
     }
 
+    fn sorted<'a, I: Iterator<Item = &'a Range<i64>>>(iter: I) -> Vec<Range<i64>> {
+        let mut result: Vec<_> = iter.cloned().collect();
+        result.sort_by_key(|k| (k.start, k.end));
+        result
+    }
+
+    #[test]
+    fn set_agrees_with_a_naive_model_under_random_operations() {
+        for seed in 0..8 {
+            let mut rng = StupidRandom::new(seed);
+            let mut set = IntervalSet::new();
+            let mut model: Vec<Range<i64>> = Vec::new();
+
+            for op in 0..2000 {
+                match rng.below(4) {
+                    0 | 1 => {
+                        let key = rng.range(64, 16);
+                        set.insert(key.clone());
+                        if !model.contains(&key) {
+                            model.push(key)
+                        }
+                    }
+                    2 => {
+                        let key = rng.range(64, 16);
+                        set.remove(&key);
+                        model.retain(|k| *k != key);
+                    }
+                    _ => {
+                        if !model.is_empty() {
+                            let key = model.swap_remove(rng.below(model.len() as u64) as usize);
+                            set.remove(&key);
+                            assert!(!set.contains(&key));
+                        }
+                    }
+                }
+                assert_eq!(set.len(), model.len());
+                set.validate_max();
+                set.validate_order();
+
+                if op % 20 == 0 {
+                    assert_eq!(set.iter().cloned().collect::<Vec<_>>(), sorted(model.iter()));
+
+                    for _ in 0..10 {
+                        let p = rng.below(90) as i64 - 5;
+                        let r = rng.range(90, 20);
+                        let by_point = sorted(set.query_point(p));
+                        assert_eq!(by_point, sorted(model.iter().filter(|k| k.contains(&p))));
+                        assert_eq!(by_point, sorted(set.query_range(p..p + 1)));
+                        assert_eq!(sorted(set.query_range(r.clone())), sorted(model.iter().filter(|k| k.overlaps(&r))));
+                        assert_eq!(sorted(set.query_range(r.start..)), sorted(model.iter().filter(|k| k.overlaps(&(r.start..)))));
+                    }
+                }
+            }
+            assert_eq!(set.into_balanced().into_sorted().collect::<Vec<_>>(), sorted(model.iter()));
+        }
+    }
+
     #[test]
     fn overlap_query_works() {
         let mut set = IntervalSet::new();
@@ -0,0 +1,262 @@
+//! Running time averages of selected fields over the mesh hierarchy, e.g.
+//! the mean velocity and its variance for turbulence statistics. The
+//! averages are kept on patches which mirror the simulation's patches, and
+//! follow them across regrids by resampling.
+
+use crate::index_space::IndexSpace;
+use crate::meshing::PatchKey;
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+use std::collections::HashMap;
+
+/// An accumulator of weighted running means (and optionally second moments)
+/// of some fields of a set of patches. Samples are usually weighted by the
+/// time step size, so the result is a time average. The means are updated
+/// with Welford's algorithm, which stays accurate over many samples.
+///
+/// The accumulator only sees the patches it is given, so in a multi-rank
+/// run each rank averages its own patches, and a regrid which moves
+/// patches between ranks loses the history of the moved zones.
+///
+#[derive(Clone)]
+pub struct TimeAverage {
+    fields: Vec<usize>,
+    second_moments: bool,
+    total_weight: f64,
+    num_samples: usize,
+    patches: HashMap<PatchKey, Patch>,
+}
+
+impl TimeAverage {
+    /// Create an accumulator for the given fields, which is empty until the
+    /// first call to [`TimeAverage::accumulate`].
+    ///
+    pub fn new(fields: Vec<usize>) -> Self {
+        Self {
+            fields,
+            second_moments: false,
+            total_weight: 0.0,
+            num_samples: 0,
+            patches: HashMap::new(),
+        }
+    }
+
+    /// Return this accumulator with second moments enabled, so that
+    /// [`TimeAverage::variances`] is available.
+    ///
+    pub fn with_second_moments(mut self) -> Self {
+        self.second_moments = true;
+        self
+    }
+
+    /// Return the fields being averaged, in the order they appear in the
+    /// averaged patches.
+    ///
+    pub fn fields(&self) -> &[usize] {
+        &self.fields
+    }
+
+    /// Return the sum of the weights of the samples accumulated so far.
+    ///
+    pub fn total_weight(&self) -> f64 {
+        self.total_weight
+    }
+
+    /// Return the number of samples accumulated so far.
+    ///
+    pub fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    /// Add a sample of the given patches with the given weight (e.g. the
+    /// time step size). If the patches differ from those of the previous
+    /// sample, the averages are first resampled onto them (see
+    /// [`TimeAverage::regrid`]). This method panics if a patch does not
+    /// have one of the averaged fields.
+    ///
+    pub fn accumulate(&mut self, patches: &[Patch], weight: f64) {
+        if patches.iter().any(|p| !self.patches.contains_key(&key(p))) || patches.len() != self.patches.len() {
+            self.regrid(patches.iter().map(key))
+        }
+        let n = self.fields.len();
+        let total_weight = self.total_weight + weight;
+
+        for patch in patches {
+            assert! {
+                self.fields.iter().all(|&f| f < patch.num_fields()),
+                "averaged fields {:?} out of range on patch with {} fields",
+                self.fields,
+                patch.num_fields()
+            };
+            let average = self.patches.get_mut(&key(patch)).unwrap();
+
+            for (zone, stored) in patch.data().chunks_exact(patch.num_fields()).zip(average.iter_data_mut()) {
+                let (mean, moment) = stored.split_at_mut(n);
+
+                for (m, &f) in self.fields.iter().enumerate() {
+                    let delta = zone[f] - mean[m];
+                    mean[m] += delta * weight / total_weight;
+
+                    if self.second_moments {
+                        moment[m] += weight * delta * (zone[f] - mean[m]);
+                    }
+                }
+            }
+        }
+        self.total_weight = total_weight;
+        self.num_samples += 1;
+    }
+
+    /// Resample the averages onto a new set of patches, given by their
+    /// keys, e.g. after a regrid. Each zone of a new patch takes its
+    /// averages from the finest old patch which covers the whole zone:
+    /// averaged over the old zones if the old patch is finer, or copied from
+    /// the old zone if it is coarser. The second moments are resampled the
+    /// same way, which neglects the spread between the means of merged
+    /// zones. Zones which are not covered by any old patch have no history,
+    /// and their averages are NaN.
+    ///
+    pub fn regrid<I: IntoIterator<Item = PatchKey>>(&mut self, keys: I) {
+        let mut old: RectangleMap<i64, Vec<Patch>> = RectangleMap::new();
+        let num_stored = self.fields.len() * if self.second_moments { 2 } else { 1 };
+        let is_empty = self.num_samples == 0;
+
+        for ((rect, _), patch) in self.patches.drain() {
+            old.require(rect).push(patch)
+        }
+
+        for (rect, level) in keys {
+            let space = IndexSpace::from(rect.clone()).coarsen_by(1 << level);
+            let patch = Patch::from_slice_function(level, space, num_stored, |(i, j), slice| {
+                let zone = IndexSpace::new(i << level..(i + 1) << level, j << level..(j + 1) << level);
+                let source = old
+                    .query_point(zone.start())
+                    .flat_map(|(_, patches)| patches)
+                    .filter(|p| p.high_resolution_space().contains_space(&zone))
+                    .min_by_key(|p| p.level());
+
+                match source {
+                    _ if is_empty => slice.fill(0.0),
+                    Some(source) => source.sample_slice(level, (i, j), slice),
+                    None => slice.fill(f64::NAN),
+                }
+            });
+            self.patches.insert((rect, level), patch);
+        }
+    }
+
+    /// Return the means of the averaged fields, as patches with one field
+    /// per averaged field, sorted by level and position.
+    ///
+    pub fn means(&self) -> Vec<Patch> {
+        self.sorted().map(|p| p.fields(0..self.fields.len())).collect()
+    }
+
+    /// Return the variances of the averaged fields, as patches like those
+    /// returned by [`TimeAverage::means`], or `None` if second moments are
+    /// not enabled.
+    ///
+    pub fn variances(&self) -> Option<Vec<Patch>> {
+        if !self.second_moments {
+            return None;
+        }
+        let n = self.fields.len();
+        let total_weight = self.total_weight;
+        let variances = self
+            .sorted()
+            .map(|p| {
+                p.fields(n..2 * n).map(|m2, v| {
+                    for (v, m2) in v.iter_mut().zip(m2) {
+                        *v = m2 / total_weight
+                    }
+                })
+            })
+            .collect();
+        Some(variances)
+    }
+
+    /// Return the averaged hierarchy as a snapshot: one patch per averaged
+    /// patch, whose fields are the means, followed by the variances if
+    /// second moments are enabled. The snapshot can be written with
+    /// [`crate::checkpoint::write_rank`], and compared with others with
+    /// [`crate::io::diff::diff_snapshots`].
+    ///
+    pub fn snapshot(&self) -> Vec<Patch> {
+        let n = self.fields.len();
+
+        match self.variances() {
+            None => self.means(),
+            Some(variances) => self
+                .means()
+                .into_iter()
+                .zip(variances)
+                .map(|(mean, variance)| {
+                    Patch::from_slice_function(mean.level(), mean.index_space(), 2 * n, |index, slice| {
+                        slice[..n].copy_from_slice(mean.get_slice(index));
+                        slice[n..].copy_from_slice(variance.get_slice(index));
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn sorted(&self) -> impl Iterator<Item = &Patch> {
+        let mut keys: Vec<_> = self.patches.keys().collect();
+        keys.sort_by_key(|((di, dj), level)| (*level, di.start, dj.start));
+        keys.into_iter().map(move |key| &self.patches[key])
+    }
+}
+
+fn key(patch: &Patch) -> PatchKey {
+    (patch.high_resolution_rect(), patch.level())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::TimeAverage;
+    use crate::index_space::range2d;
+    use crate::patch::Patch;
+
+    fn constant(level: u32, space: std::ops::Range<i64>, value: f64) -> Patch {
+        Patch::from_slice_function(level, range2d(space.clone(), space), 3, |(i, _), s| {
+            s.copy_from_slice(&[value, -1.0, value + i as f64])
+        })
+    }
+
+    #[test]
+    fn averages_are_weighted_means_and_variances() {
+        let mut average = TimeAverage::new(vec![2, 0]).with_second_moments();
+
+        for (value, weight) in [(1.0, 1.0), (2.0, 2.0), (4.0, 1.0)] {
+            average.accumulate(&[constant(0, 0..4, value)], weight);
+        }
+        let mean = &average.means()[0];
+        let variance = &average.variances().unwrap()[0];
+
+        assert_eq!(average.num_samples(), 3);
+        assert_eq!(average.total_weight(), 4.0);
+        assert_eq!(mean.get_slice((3, 0)), [5.25, 2.25]);
+        assert!((variance.get_slice((3, 0))[1] - 1.1875).abs() < 1e-12);
+        assert_eq!(average.snapshot()[0].num_fields(), 4);
+    }
+
+    #[test]
+    fn averages_follow_regridded_patches() {
+        let mut average = TimeAverage::new(vec![0]);
+        average.accumulate(&[constant(1, 0..4, 3.0)], 1.0);
+
+        // The level 1 patch is replaced by a refined patch over part of it
+        // and a patch extending past it, which has no history.
+        let fine = constant(0, 0..4, 5.0);
+        let beyond = Patch::zeros(1, 3, range2d(4..6, 0..2));
+        average.accumulate(&[fine, beyond], 1.0);
+
+        let means = average.means();
+        assert_eq!(means.len(), 2);
+        assert_eq!(means[0].level(), 0);
+        assert!(means[0].data().iter().all(|&m| m == 4.0));
+        assert!(means[1].data().iter().all(|m| m.is_nan()));
+        assert!(average.variances().is_none());
+    }
+}
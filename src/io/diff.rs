@@ -0,0 +1,205 @@
+//! Comparison of two snapshots of a simulation, e.g. the output of a run
+//! before and after a refactor. Patches are matched by their
+//! high-resolution rectangle and level, and the absolute differences of
+//! each field are summarized per patch and over the whole snapshot.
+
+use crate::checkpoint;
+use crate::error::{Error, Result};
+use crate::meshing::PatchKey;
+use crate::patch::Patch;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// The maximum and mean absolute difference of one field.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FieldDifference {
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// The differences of each field on one pair of matched patches.
+///
+#[derive(Clone, Debug)]
+pub struct PatchDifference {
+    /// The high-resolution rectangle and level of the patches.
+    pub patch: PatchKey,
+
+    /// The differences of each field, in field order.
+    pub fields: Vec<FieldDifference>,
+}
+
+/// The result of comparing two snapshots, returned by [`diff_patches`] and
+/// [`diff_snapshots`].
+///
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotDiff {
+    /// The differences on each pair of matched patches, sorted by level and
+    /// position.
+    pub patches: Vec<PatchDifference>,
+
+    /// The differences of each field over all the matched patches. The mean
+    /// is weighted by the number of zones in each patch.
+    pub global: Vec<FieldDifference>,
+
+    /// The patches which are in only one of the two snapshots.
+    pub unmatched: Vec<PatchKey>,
+}
+
+impl SnapshotDiff {
+    /// Return true if the snapshots have the same patches, with bitwise
+    /// equal data (treating NaNs as equal to each other).
+    pub fn is_identical(&self) -> bool {
+        self.unmatched.is_empty() && self.global.iter().all(|d| d.max == 0.0)
+    }
+
+    /// Return the patch with the largest difference in the given field, if
+    /// any patches were matched.
+    pub fn worst_patch(&self, field: usize) -> Option<&PatchDifference> {
+        self.patches
+            .iter()
+            .max_by(|a, b| a.fields[field].max.total_cmp(&b.fields[field].max))
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(fmt, "{} patches compared, {} unmatched", self.patches.len(), self.unmatched.len())?;
+
+        for (field, d) in self.global.iter().enumerate() {
+            write!(fmt, "field {}: max {:e} mean {:e}", field, d.max, d.mean)?;
+
+            if let Some(PatchDifference { patch: ((di, dj), level), .. }) = self.worst_patch(field) {
+                write!(fmt, " (worst at {}..{} {}..{} level {})", di.start, di.end, dj.start, dj.end, level)?;
+            }
+            writeln!(fmt)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two sets of patches. A mesh error is returned if two matched
+/// patches have a different number of fields.
+///
+pub fn diff_patches(a: &[Patch], b: &[Patch]) -> Result<SnapshotDiff> {
+    let key = |p: &Patch| (p.high_resolution_rect(), p.level());
+    let a_map: HashMap<_, _> = a.iter().map(|p| (key(p), p)).collect();
+    let b_map: HashMap<_, _> = b.iter().map(|p| (key(p), p)).collect();
+    let mut a_keys: Vec<_> = a_map.keys().collect();
+    a_keys.sort_by_key(|k| sort_key(k));
+
+    let mut diff = SnapshotDiff::default();
+    let mut totals: Vec<(f64, f64)> = Vec::new();
+    let mut num_zones = 0;
+
+    for k in a_keys {
+        let pa = a_map[k];
+        let pb = match b_map.get(k) {
+            Some(pb) => pb,
+            None => {
+                diff.unmatched.push(k.clone());
+                continue;
+            }
+        };
+        let nq = pa.num_fields();
+
+        if pb.num_fields() != nq {
+            return Err(Error::Mesh(format!(
+                "patch ({}..{} {}..{}) has {} fields in one snapshot and {} in the other",
+                k.0 .0.start,
+                k.0 .0.end,
+                k.0 .1.start,
+                k.0 .1.end,
+                nq,
+                pb.num_fields()
+            )));
+        }
+        totals.resize(nq.max(totals.len()), (0.0, 0.0));
+
+        let mut fields = vec![FieldDifference::default(); nq];
+        let n = pa.index_space().len();
+
+        for (x, y) in pa.data().chunks_exact(nq).zip(pb.data().chunks_exact(nq)) {
+            for (field, (x, y)) in fields.iter_mut().zip(x.iter().zip(y)) {
+                let d = difference(*x, *y);
+                field.max = field.max.max(d);
+                field.mean += d;
+            }
+        }
+        for (field, total) in fields.iter_mut().zip(&mut totals) {
+            total.0 = total.0.max(field.max);
+            total.1 += field.mean;
+            field.mean /= n.max(1) as f64;
+        }
+        num_zones += n;
+        diff.patches.push(PatchDifference { patch: k.clone(), fields });
+    }
+    diff.unmatched.extend(b_map.keys().filter(|k| !a_map.contains_key(k)).cloned());
+    diff.unmatched.sort_by_key(sort_key);
+    diff.global = totals
+        .into_iter()
+        .map(|(max, total)| FieldDifference {
+            max,
+            mean: total / num_zones.max(1) as f64,
+        })
+        .collect();
+    Ok(diff)
+}
+
+/// Load two checkpoints (see [`crate::checkpoint`]) and compare their
+/// patches.
+///
+pub fn diff_snapshots<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<SnapshotDiff> {
+    diff_patches(&checkpoint::read_all(a)?, &checkpoint::read_all(b)?)
+}
+
+fn sort_key(((di, dj), level): &PatchKey) -> (u32, i64, i64, i64, i64) {
+    (*level, di.start, dj.start, di.end, dj.end)
+}
+
+/// The absolute difference of two values. Two NaNs are considered equal, and
+/// a NaN differs from any number by infinity.
+fn difference(x: f64, y: f64) -> f64 {
+    match (x.is_nan(), y.is_nan()) {
+        (true, true) => 0.0,
+        (false, false) => (x - y).abs(),
+        _ => f64::INFINITY,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::diff_patches;
+    use crate::patch::Patch;
+
+    fn snapshot(perturbation: f64) -> Vec<Patch> {
+        (0..3)
+            .map(|n| {
+                Patch::from_vector_function(0, (n * 4..n * 4 + 4, 0..4), |(i, j)| {
+                    let p = if (i, j) == (9, 2) { perturbation } else { 0.0 };
+                    [1.0, (i + j) as f64 + p]
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn differences_are_located_and_summarized() {
+        assert!(diff_patches(&snapshot(0.0), &snapshot(0.0)).unwrap().is_identical());
+
+        let mut other = snapshot(0.5);
+        other.push(Patch::zeros(1, 2, (0..2, 0..2)));
+        let diff = diff_patches(&snapshot(0.0), &other).unwrap();
+
+        assert!(!diff.is_identical());
+        assert_eq!(diff.unmatched, [((0..4, 0..4), 1)]);
+        assert_eq!(diff.global[0].max, 0.0);
+        assert_eq!(diff.global[1].max, 0.5);
+        assert_eq!(diff.global[1].mean, 0.5 / 48.0);
+        assert_eq!(diff.worst_patch(1).unwrap().patch, ((8..12, 0..4), 0));
+        assert_eq!(diff.worst_patch(1).unwrap().fields[1].mean, 0.5 / 16.0);
+        assert!(diff_patches(&snapshot(0.0), &[Patch::zeros(0, 1, (0..4, 0..4))]).is_err());
+    }
+}
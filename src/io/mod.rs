@@ -0,0 +1,13 @@
+//! Output utilities for inspecting simulation data. Full-featured data
+//! input/output is left to applications; these are meant for debugging, and
+//! for light-weight statistics like time series and time averages.
+
+pub mod average;
+
+#[cfg(feature = "exec")]
+pub mod diff;
+
+#[cfg(feature = "quicklook")]
+pub mod quicklook;
+
+pub mod series;
@@ -0,0 +1,355 @@
+//! Quick-look images of a single field, for debugging. The field is
+//! rasterized at a uniform resolution level, mapped to 8-bit colors with a
+//! built-in colormap, and written as a PNG file. The PNG encoder is
+//! implemented here (with uncompressed deflate blocks), so this module adds
+//! no dependencies.
+//!
+//! The `i` axis runs left to right in the image, and the `j` axis runs bottom
+//! to top. Zones not covered by any patch are drawn black.
+//!
+//! For watching refinement evolve during a run, [`print_level_map`] draws the
+//! level of the finest patch covering each zone as text in the terminal.
+
+use crate::error::{Error, Result};
+use crate::index_space::IndexSpace;
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::Path;
+
+/// A built-in colormap.
+///
+#[derive(Clone, Copy, Debug)]
+pub enum Colormap {
+    /// Black for the minimum value, white for the maximum.
+    Grayscale,
+
+    /// Dark blue through green to yellow, perceptually ordered.
+    Viridis,
+
+    /// Blue for the minimum value, white in the middle, red for the maximum.
+    /// Suitable for signed quantities.
+    BlueRed,
+}
+
+impl Colormap {
+    /// Return the color for a value in the range `[0, 1]`.
+    pub fn color(&self, x: f64) -> [u8; 3] {
+        let x = x.clamp(0.0, 1.0);
+        let stops: &[[f64; 3]] = match self {
+            Self::Grayscale => &[[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]],
+            Self::Viridis => &[
+                [0.267, 0.005, 0.329],
+                [0.231, 0.322, 0.545],
+                [0.129, 0.569, 0.549],
+                [0.369, 0.788, 0.384],
+                [0.993, 0.906, 0.144],
+            ],
+            Self::BlueRed => &[[0.0, 0.0, 1.0], [1.0, 1.0, 1.0], [1.0, 0.0, 0.0]],
+        };
+        let s = x * (stops.len() - 1) as f64;
+        let n = (s as usize).min(stops.len() - 2);
+        let t = s - n as f64;
+        let mut rgb = [0; 3];
+
+        for (c, (a, b)) in rgb.iter_mut().zip(stops[n].iter().zip(&stops[n + 1])) {
+            *c = ((a + (b - a) * t) * 255.0).round() as u8
+        }
+        rgb
+    }
+}
+
+/// A source of field data which can be rasterized: a single patch, or a
+/// map of patches keyed by their high-resolution rectangles, possibly on
+/// several levels.
+///
+pub trait Quicklook {
+    /// Return the index space at the given level covering all of the data.
+    fn extent(&self, level: u32) -> IndexSpace;
+
+    /// Sample a field at the given index, measured at the given level.
+    /// Returns `None` if the data does not cover the index.
+    fn sample(&self, level: u32, index: (i64, i64), field: usize) -> Option<f64>;
+}
+
+/// Return whether the patch covers all of the high-resolution zones in the
+/// zone with the given index at the given level, so it can be sampled there.
+fn covers(patch: &Patch, level: u32, index: (i64, i64)) -> bool {
+    let zone = IndexSpace::new(index.0..index.0 + 1, index.1..index.1 + 1).refine_by(1 << level);
+    patch.high_resolution_space().contains_space(&zone)
+}
+
+/// Sample the patch if it covers the zone. The index is validated by
+/// [`covers`], so the per-zone checks in [`Patch::sample`] are skipped.
+fn sample_covered(patch: &Patch, level: u32, index: (i64, i64), field: usize) -> Option<f64> {
+    assert!(field < patch.num_fields(), "field {} out of range", field);
+
+    if covers(patch, level, index) {
+        Some(patch.sample_unchecked(level, index, field))
+    } else {
+        None
+    }
+}
+
+fn coarsen_outward(space: IndexSpace, level: u32) -> IndexSpace {
+    let f = 1 << level;
+    let (i0, j0) = space.start();
+    let (i1, j1) = space.end();
+    IndexSpace::new(
+        i0.div_euclid(f)..(i1 + f - 1).div_euclid(f),
+        j0.div_euclid(f)..(j1 + f - 1).div_euclid(f),
+    )
+}
+
+impl Quicklook for Patch {
+    fn extent(&self, level: u32) -> IndexSpace {
+        coarsen_outward(self.high_resolution_space(), level)
+    }
+
+    fn sample(&self, level: u32, index: (i64, i64), field: usize) -> Option<f64> {
+        sample_covered(self, level, index, field)
+    }
+}
+
+impl Quicklook for RectangleMap<i64, Patch> {
+    fn extent(&self, level: u32) -> IndexSpace {
+        let (mut i0, mut j0) = (i64::MAX, i64::MAX);
+        let (mut i1, mut j1) = (i64::MIN, i64::MIN);
+
+        for (rect, _) in self.iter() {
+            i0 = i0.min(rect.0.start);
+            j0 = j0.min(rect.1.start);
+            i1 = i1.max(rect.0.end);
+            j1 = j1.max(rect.1.end);
+        }
+        if i0 > i1 {
+            return IndexSpace::new(0..0, 0..0);
+        }
+        coarsen_outward(IndexSpace::new(i0..i1, j0..j1), level)
+    }
+
+    fn sample(&self, level: u32, index: (i64, i64), field: usize) -> Option<f64> {
+        let f = 1 << level;
+        self.query_point((index.0 * f, index.1 * f))
+            .map(|(_, p)| p)
+            .filter(|p| covers(p, level, index))
+            .min_by_key(|p| p.level())
+            .and_then(|p| sample_covered(p, level, index, field))
+    }
+}
+
+/// Rasterize a field at the given level, and write it to a PNG file with the
+/// given colormap. The colormap range spans the minimum and maximum finite
+/// values of the field. A mesh error is returned if the source is empty,
+/// since a PNG must have at least one pixel.
+///
+pub fn write_png<Q, P>(path: P, source: &Q, field: usize, colormap: Colormap, level: u32) -> Result<()>
+where
+    Q: Quicklook,
+    P: AsRef<Path>,
+{
+    let space = source.extent(level);
+    let (width, height) = space.dim();
+    let (i0, j0) = space.start();
+
+    if width == 0 || height == 0 {
+        return Err(Error::Mesh("cannot write an image of an empty extent".to_string()));
+    }
+    let mut values = Vec::with_capacity(width * height);
+
+    for j in (0..height as i64).rev() {
+        for i in 0..width as i64 {
+            values.push(source.sample(level, (i0 + i, j0 + j), field))
+        }
+    }
+
+    let finite = values.iter().flatten().filter(|x| x.is_finite());
+    let lower = finite.clone().cloned().fold(f64::INFINITY, f64::min);
+    let upper = finite.cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = if upper > lower { upper - lower } else { 1.0 };
+
+    let rgb: Vec<u8> = values
+        .into_iter()
+        .flat_map(|x| match x {
+            Some(x) if x.is_finite() => colormap.color((x - lower) / range),
+            _ => [0, 0, 0],
+        })
+        .collect();
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&encode_png(width as u32, height as u32, &rgb))?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Render the level of the finest patch covering each zone, rasterized at
+/// the given level, as one character per zone: the level's digit (or letter,
+/// above 9), or `.` where no patch covers the zone. Rows run from the top
+/// (highest `j`) down, like [`write_png`]. If `ansi` is true, each level is
+/// also given a background color with ANSI escape codes.
+///
+pub fn level_map(patches: &RectangleMap<i64, Patch>, level: u32, ansi: bool) -> String {
+    let space = patches.extent(level);
+    let (width, height) = space.dim();
+    let (i0, j0) = space.start();
+    let f = 1 << level;
+    let mut text = String::with_capacity((width + 1) * height);
+
+    for j in (j0..j0 + height as i64).rev() {
+        for i in i0..i0 + width as i64 {
+            let finest = patches.query_point((i * f, j * f)).map(|(_, p)| p.level()).min();
+
+            match (finest, ansi) {
+                (Some(l), true) => text.push_str(&format!("\x1b[{}m{}", 41 + l % 6, level_char(l))),
+                (Some(l), false) => text.push(level_char(l)),
+                (None, true) => text.push_str("\x1b[0m."),
+                (None, false) => text.push('.'),
+            }
+        }
+        if ansi {
+            text.push_str("\x1b[0m");
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Print the level map (see [`level_map`]) to standard output, if the frame
+/// number is a multiple of `every`. Colors are used if standard output is a
+/// terminal.
+///
+pub fn print_level_map(patches: &RectangleMap<i64, Patch>, level: u32, frame: usize, every: usize) {
+    if every > 0 && frame.is_multiple_of(every) {
+        let stdout = std::io::stdout();
+        print!("{}", level_map(patches, level, stdout.is_terminal()));
+    }
+}
+
+fn level_char(level: u32) -> char {
+    std::char::from_digit(level, 36).unwrap_or('+')
+}
+
+/// Encode an 8-bit RGB image as a PNG file. The image data is stored in
+/// uncompressed deflate blocks.
+fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width as usize * height as usize * 3);
+
+    let mut raw = Vec::with_capacity(rgb.len() + height as usize);
+    for row in rgb.chunks_exact((width as usize * 3).max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc32(kind.iter().chain(data)).to_be_bytes());
+}
+
+fn crc32<'a, I: IntoIterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{crc32, encode_png, level_map, write_png, Colormap, Quicklook};
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+
+    #[test]
+    fn png_chunks_have_valid_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+
+        let png = encode_png(2, 1, &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+    }
+
+    #[test]
+    fn multi_level_data_is_flattened_to_one_level() {
+        let coarse = Patch::from_scalar_function(1, (0..4, 0..4), |_| 1.0);
+        let fine = Patch::from_scalar_function(0, (0..4, 0..4), |(i, j)| (i + j) as f64);
+        let patches: RectangleMap<_, _> = vec![coarse, fine]
+            .into_iter()
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+
+        assert_eq!(patches.extent(0).dim(), (8, 8));
+        assert_eq!(patches.sample(0, (3, 2), 0), Some(5.0));
+        assert_eq!(patches.sample(0, (6, 2), 0), Some(1.0));
+        assert_eq!(patches.sample(1, (0, 1), 0), Some(3.0));
+        assert_eq!(patches.sample(0, (8, 0), 0), None);
+
+        let path = std::env::temp_dir().join(format!("gridiron-quicklook-{}.png", std::process::id()));
+        write_png(&path, &patches, 0, Colormap::Viridis, 0).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 8 + 25 + 12 + 2 + 5 + 8 * 25 + 4 + 12);
+        std::fs::remove_file(&path).unwrap();
+
+        let empty = RectangleMap::<i64, Patch>::new();
+        assert!(write_png(&path, &empty, 0, Colormap::Viridis, 0).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn level_map_shows_the_finest_level() {
+        let patches: RectangleMap<_, _> = vec![
+            Patch::zeros(1, 1, (0..3, 0..2)),
+            Patch::zeros(0, 1, (0..2, 2..4)),
+        ]
+        .into_iter()
+        .map(|p| (p.high_resolution_rect(), p))
+        .collect();
+
+        assert_eq!(level_map(&patches, 1, false), "011\n111\n");
+        assert_eq!(level_map(&patches, 1, true).lines().count(), 2);
+        assert!(level_map(&patches, 1, true).contains("\x1b[41m0"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Time series of scalar diagnostics, such as the total mass or the maximum
+//! density, computed over the mesh hierarchy every step and appended to a
+//! CSV or JSON-lines file. This gives a quick look at the evolution of a run
+//! without post-processing snapshots.
+
+use crate::error::Result;
+use crate::index_space::IndexSpace;
+use crate::meshing::PatchKey;
+#[cfg(feature = "net")]
+use crate::message::comm::Communicator;
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// How the per-zone values of a series are combined.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reduction {
+    /// The sum of the values weighted by the zone area, measured in units of
+    /// level 0 (finest) zones. Multiply by the area of a level 0 zone to get
+    /// an integral over the domain.
+    Sum,
+
+    /// The minimum value.
+    Min,
+
+    /// The maximum value.
+    Max,
+}
+
+impl Reduction {
+    fn identity(self) -> f64 {
+        match self {
+            Reduction::Sum => 0.0,
+            Reduction::Min => f64::INFINITY,
+            Reduction::Max => f64::NEG_INFINITY,
+        }
+    }
+
+    fn combine(self, a: f64, b: f64) -> f64 {
+        match self {
+            Reduction::Sum => a + b,
+            Reduction::Min => a.min(b),
+            Reduction::Max => a.max(b),
+        }
+    }
+}
+
+/// The format of a time series file.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Comma-separated values, with a header row written when the file is
+    /// created.
+    Csv,
+
+    /// One JSON object per line. Non-finite values are written as `null`.
+    JsonLines,
+}
+
+type Integrand = Box<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// A set of named scalar reductions, evaluated over the zones of the mesh
+/// hierarchy and appended as one row per step to a file. Zones which are
+/// covered by a finer patch are skipped, so each part of the domain is
+/// counted once, at its finest available resolution.
+///
+pub struct TimeSeries {
+    path: PathBuf,
+    format: Format,
+    series: Vec<(String, Reduction, Integrand)>,
+}
+
+impl TimeSeries {
+    /// Create a time series with no reductions, to be written to the given
+    /// file.
+    ///
+    pub fn new<P: AsRef<Path>>(path: P, format: Format) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            format,
+            series: Vec::new(),
+        }
+    }
+
+    /// Return this time series with a reduction added. The integrand maps
+    /// the fields of a zone to the value to be reduced.
+    ///
+    pub fn with<F>(mut self, name: &str, reduction: Reduction, integrand: F) -> Self
+    where
+        F: Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    {
+        self.series.push((name.to_string(), reduction, Box::new(integrand)));
+        self
+    }
+
+    /// Return the names of the reductions, in order.
+    ///
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.series.iter().map(|(name, _, _)| name.as_str())
+    }
+
+    /// Evaluate the reductions over the given patches. The keys of all the
+    /// patches in the hierarchy (including those owned by other ranks) are
+    /// needed to determine which zones are covered by finer patches.
+    ///
+    pub fn evaluate(&self, patches: &[Patch], hierarchy: &[PatchKey]) -> Vec<f64> {
+        let finer: RectangleMap<i64, u32> = hierarchy.iter().cloned().collect();
+        let mut values: Vec<_> = self.series.iter().map(|(_, r, _)| r.identity()).collect();
+
+        for patch in patches {
+            let level = patch.level();
+            let f = 1 << level;
+            let area = (f * f) as f64;
+            let cover: Vec<IndexSpace> = finer
+                .query_rect(patch.high_resolution_rect())
+                .filter(|(_, &l)| l < level)
+                .map(|(rect, _)| IndexSpace::from(rect))
+                .collect();
+
+            for (index, zone) in patch.index_space().iter().zip(patch.data().chunks_exact(patch.num_fields())) {
+                let (i, j) = index;
+                let zone_space = IndexSpace::new(i * f..(i + 1) * f, j * f..(j + 1) * f);
+
+                if cover.iter().any(|space| space.contains_space(&zone_space)) {
+                    continue;
+                }
+                for (value, (_, reduction, integrand)) in values.iter_mut().zip(&self.series) {
+                    let x = match reduction {
+                        Reduction::Sum => integrand(zone) * area,
+                        _ => integrand(zone),
+                    };
+                    *value = reduction.combine(*value, x);
+                }
+            }
+        }
+        values
+    }
+
+    /// Combine partial values (e.g. one set from each rank) into the values
+    /// for the whole hierarchy.
+    ///
+    pub fn combine<I>(&self, partials: I) -> Vec<f64>
+    where
+        I: IntoIterator<Item = Vec<f64>>,
+    {
+        partials.into_iter().fold(
+            self.series.iter().map(|(_, r, _)| r.identity()).collect(),
+            |values: Vec<f64>, partial| {
+                values
+                    .iter()
+                    .zip(partial)
+                    .zip(&self.series)
+                    .map(|((a, b), (_, reduction, _))| reduction.combine(*a, b))
+                    .collect()
+            },
+        )
+    }
+
+    /// Append one row with the given step, time, and values to the file.
+    /// The file is created if it does not exist, in which case a CSV header
+    /// is written first.
+    ///
+    pub fn append(&self, step: usize, time: f64, values: &[f64]) -> Result<()> {
+        let is_new = !self.path.exists();
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut file = BufWriter::new(file);
+
+        match self.format {
+            Format::Csv => {
+                if is_new {
+                    let names: Vec<_> = self.names().collect();
+                    writeln!(file, "step,time,{}", names.join(","))?;
+                }
+                let values: Vec<_> = values.iter().map(|x| x.to_string()).collect();
+                writeln!(file, "{},{},{}", step, time, values.join(","))?;
+            }
+            Format::JsonLines => {
+                let entries: Vec<_> = self
+                    .names()
+                    .zip(values)
+                    .map(|(name, x)| format!("\"{}\": {}", name.replace('\\', "\\\\").replace('"', "\\\""), json_number(*x)))
+                    .collect();
+                writeln!(file, "{{\"step\": {}, \"time\": {}, {}}}", step, json_number(time), entries.join(", "))?;
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Evaluate the reductions on every rank, combine them on rank 0, and
+    /// append them to the file. Rank 0 returns the combined values, and the
+    /// other ranks return `None`. This must be called by every rank.
+    ///
+    #[cfg(feature = "net")]
+    pub fn record<C: Communicator>(
+        &self,
+        comm: &C,
+        step: usize,
+        time: f64,
+        patches: &[Patch],
+        hierarchy: &[PatchKey],
+    ) -> Result<Option<Vec<f64>>> {
+        match comm.gather_values(&self.evaluate(patches, hierarchy)) {
+            Some(partials) => {
+                let values = self.combine(partials);
+                self.append(step, time, &values)?;
+                Ok(Some(values))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        format!("{:?}", x)
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Format, Reduction, TimeSeries};
+    use crate::index_space::range2d;
+    use crate::meshing::PatchKey;
+    use crate::patch::Patch;
+
+    fn hierarchy() -> (Vec<Patch>, Vec<PatchKey>) {
+        let coarse = Patch::from_scalar_function(1, range2d(0..4, 0..4), |_| 1.0);
+        let fine = Patch::from_scalar_function(0, range2d(0..4, 0..4), |_| 2.0);
+        let keys = vec![(coarse.high_resolution_rect(), 1), (fine.high_resolution_rect(), 0)];
+        (vec![coarse, fine], keys)
+    }
+
+    fn series(path: &std::path::Path, format: Format) -> TimeSeries {
+        TimeSeries::new(path, format)
+            .with("mass", Reduction::Sum, |u| u[0])
+            .with("min", Reduction::Min, |u| u[0])
+            .with("max", Reduction::Max, |u| u[0])
+    }
+
+    #[test]
+    fn reductions_skip_zones_covered_by_finer_patches() {
+        let path = std::env::temp_dir().join(format!("gridiron-series-{}.csv", std::process::id()));
+        let series = series(&path, Format::Csv);
+        let (patches, keys) = hierarchy();
+
+        // 12 uncovered coarse zones of area 4, and 16 fine zones of area 1.
+        let values = series.evaluate(&patches, &keys);
+        assert_eq!(values, [12.0 * 4.0 * 1.0 + 16.0 * 2.0, 1.0, 2.0]);
+
+        let partials = vec![series.evaluate(&patches[..1], &keys), series.evaluate(&patches[1..], &keys)];
+        assert_eq!(series.combine(partials), values);
+
+        series.append(0, 0.0, &values).unwrap();
+        series.append(1, 0.5, &values).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text, "step,time,mass,min,max\n0,0,80,1,2\n1,0.5,80,1,2\n");
+    }
+
+    #[test]
+    fn json_lines_write_non_finite_values_as_null() {
+        let path = std::env::temp_dir().join(format!("gridiron-series-{}.jsonl", std::process::id()));
+        let series = series(&path, Format::JsonLines);
+        let values = series.evaluate(&[], &[]);
+
+        series.append(3, 1.0, &values).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text, "{\"step\": 3, \"time\": 1.0, \"mass\": 0.0, \"min\": null, \"max\": null}\n");
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn reductions_are_combined_on_rank_zero() {
+        use crate::message::comm::Communicator;
+        use crate::message::local::LocalCommunicator;
+
+        let path = std::env::temp_dir().join(format!("gridiron-series-ranks-{}.csv", std::process::id()));
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(|comm| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let series = series(&path, Format::Csv);
+                    let (mut patches, keys) = hierarchy();
+                    let mine = patches.remove(comm.rank());
+                    series.record(&comm, 7, 2.0, &[mine], &keys).unwrap()
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(results[0], Some(vec![80.0, 1.0, 2.0]));
+        assert_eq!(results[1], None);
+        assert_eq!(text, "step,time,mass,min,max\n7,2,80,1,2\n");
+    }
+}
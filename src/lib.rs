@@ -21,9 +21,9 @@
 //! - Not to depend on other work-in-progress crates. Many HPC-oriented Rust
 //!   crates look promising, but are still in flux; this crate should not
 //!   depend on things like `ndarray`, `hdf5`, or `rsmpi`. Dependencies are
-//!   currently limited to `rayon` and `crossbeam_channel`. `serde` is
-//!   presently required in the library to support the examples, but it should
-//!   be made optional.
+//!   currently limited to `rayon` and `crossbeam_channel`, and `serde` for
+//!   patch and checkpoint data. All of these are optional; see the features
+//!   below.
 //! - Have fast compile times. The debug cycle for physics simulations often
 //!   requires frequent recompilation and inspection of results. Compile times
 //!   of 1-2 seconds are fine, but the code should not take 30 seconds to
@@ -44,19 +44,107 @@
 //!   particles, radiative transfer, self-gravity, and reaction networks.
 //!   However, this library does not try to implement these things. The focus
 //!   is on abstractions for meshing and execution.
+//!
+//! # Features
+//!
+//! The spatial containers ([`interval_map`], [`interval_set`],
+//! [`rect_map`], [`index_space`]), the [`decompose`] module and the
+//! [`clock`] abstraction have no dependencies and are always built. The
+//! heavier subsystems are behind cargo features, all of which are on by
+//! default:
+//!
+//! - `mesh`: patches (dense and sparse), meshing, parameters and diagnostics
+//!   (adds `serde`)
+//! - `exec`: automata, windowed patch updates, thread pools, statistics and
+//!   checkpoints (adds `rayon`, `crossbeam_channel`, `core_affinity` and
+//!   `ciborium`)
+//! - `net`: message passing between processes
+//! - `hydro`: hydrodynamics, solvers, gravity and particles
+//! - `quicklook`: quick-look PNG images and terminal level maps of patch
+//!   data
+//! - `test-support`: helpers for writing tests of update schemes, such as
+//!   `Patch::from_rows` and `assert_patch_eq!` (off by default)
+//! - `appkit`: command line options and executor selection for the `main`
+//!   function of applications and benchmarks (off by default)
+//!
+//! Applications which only need the containers can depend on gridiron with
+//! `default-features = false`.
+//!
+//! # Stability
+//!
+//! The [`prelude`] re-exports the stable core: patches, index spaces and
+//! rectangle maps, the automaton trait and its executors, and the
+//! communicator trait. Applications and libraries which import from the
+//! prelude are insulated from changes in the layout of the modules. The
+//! following modules are experimental, and may change in any release:
+//! `appkit`, `compute`, `memory`, `overlap`, `schedule`,
+//! `sidecar`, `sparse`, `trace`, `window`, `solvers::autotune`,
+//! `message::cache` and `message::control`.
 
+/// Whether argument checks in hot loops (e.g. index bounds checks in
+/// [`patch::Patch::sample`]) are enabled. They are on in debug builds, and
+/// can be enabled in release builds with the `checks` feature.
+///
+pub(crate) const CHECKS: bool = cfg!(any(debug_assertions, feature = "checks"));
+
+#[cfg(feature = "mesh")]
 pub mod adjacency_list;
+#[cfg(feature = "appkit")]
+pub mod appkit;
 pub mod aug_node;
+#[cfg(feature = "exec")]
 pub mod automaton;
+#[cfg(feature = "exec")]
+pub mod checkpoint;
+pub mod clock;
+#[cfg(feature = "exec")]
+pub mod compute;
+pub mod decompose;
+#[cfg(feature = "mesh")]
+pub mod diagnostics;
+pub mod error;
+#[cfg(feature = "hydro")]
+pub mod gravity;
+#[cfg(feature = "hydro")]
 pub mod hydro;
 pub mod index_space;
 pub mod interval_map;
 pub mod interval_set;
+#[cfg(feature = "mesh")]
+pub mod io;
+#[cfg(feature = "exec")]
+pub mod memory;
+#[cfg(feature = "mesh")]
 pub mod meshing;
+#[cfg(feature = "net")]
 pub mod message;
+#[cfg(feature = "mesh")]
+pub mod multigrid;
 pub mod num_vec;
 pub mod overlap;
+#[cfg(feature = "mesh")]
+pub mod parameters;
+#[cfg(feature = "hydro")]
+pub mod particles;
+#[cfg(feature = "mesh")]
 pub mod patch;
+pub mod prelude;
 pub mod rect_map;
+#[cfg(feature = "mesh")]
+pub mod schedule;
+#[cfg(feature = "mesh")]
+pub mod sidecar;
+#[cfg(feature = "hydro")]
 pub mod solvers;
+#[cfg(feature = "mesh")]
+pub mod sparse;
+#[cfg(feature = "exec")]
+pub mod stats;
+#[cfg(feature = "exec")]
 pub mod thread_pool;
+#[cfg(feature = "exec")]
+pub mod trace;
+#[cfg(feature = "mesh")]
+pub mod util;
+#[cfg(feature = "exec")]
+pub mod window;
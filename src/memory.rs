@@ -0,0 +1,239 @@
+//! Accounting of the memory held by a simulation: patch data on each level,
+//! message buffers in flight between ranks, and messages held by the
+//! executor for recipients it has not seen yet. The counters are updated by
+//! the driver (and by the executors, through [`crate::stats::Metrics`]), and
+//! summarized once per frame with [`MemoryAccount::memory_report`].
+
+use crate::patch::Patch;
+use crate::stats::Metrics;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Byte counts above which a [`MemoryReport`] includes a warning. The
+/// default value places no limits.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryThresholds {
+    /// The maximum number of bytes in all categories.
+    pub total: usize,
+
+    /// The maximum number of bytes held by patches, on all levels.
+    pub patches: usize,
+
+    /// The maximum number of bytes held in messages, either in flight or
+    /// undelivered.
+    pub messages: usize,
+}
+
+impl Default for MemoryThresholds {
+    fn default() -> Self {
+        Self {
+            total: usize::MAX,
+            patches: usize::MAX,
+            messages: usize::MAX,
+        }
+    }
+}
+
+/// A thread-safe set of memory counters. Message counters are updated as
+/// buffers are sent and received, so they can be shared by communication
+/// threads; patch counters are replaced each time the patches are recorded.
+///
+#[derive(Default)]
+pub struct MemoryAccount {
+    patches: Mutex<BTreeMap<u32, usize>>,
+    in_flight: AtomicUsize,
+    undelivered: AtomicUsize,
+    thresholds: MemoryThresholds,
+}
+
+/// A summary of the memory held by a simulation at one time, returned by
+/// [`MemoryAccount::memory_report`]. The `Display` implementation formats it
+/// as a single line, followed by any warnings, for logging.
+///
+#[derive(Clone, Debug, Default)]
+pub struct MemoryReport {
+    /// The bytes held by patches, keyed by level.
+    pub patches: BTreeMap<u32, usize>,
+
+    /// The bytes held in message buffers which have been sent, but not yet
+    /// received.
+    pub in_flight: usize,
+
+    /// The bytes held in messages which the executor could not yet deliver.
+    pub undelivered: usize,
+
+    /// A description of each threshold which was exceeded.
+    pub warnings: Vec<String>,
+}
+
+impl MemoryAccount {
+    /// Create an account with no warning thresholds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an account which warns when the given thresholds are
+    /// exceeded.
+    pub fn with_thresholds(thresholds: MemoryThresholds) -> Self {
+        Self {
+            thresholds,
+            ..Self::default()
+        }
+    }
+
+    /// Replace the patch counters with the bytes held by the given patches.
+    pub fn record_patches<'a, I>(&self, patches: I)
+    where
+        I: IntoIterator<Item = &'a Patch>,
+    {
+        let mut levels = BTreeMap::new();
+
+        for patch in patches {
+            *levels.entry(patch.level()).or_insert(0) += patch.size_in_bytes();
+        }
+        *self.patches.lock().unwrap() = levels;
+    }
+
+    /// Add a message buffer of the given size to the in-flight counter.
+    pub fn message_sent(&self, bytes: usize) {
+        self.in_flight.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Remove a message buffer of the given size from the in-flight counter.
+    pub fn message_received(&self, bytes: usize) {
+        self.in_flight.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Set the number of bytes held in undelivered messages.
+    pub fn set_undelivered(&self, bytes: usize) {
+        self.undelivered.store(bytes, Ordering::Relaxed)
+    }
+
+    /// Take the undelivered message bytes from the peak recorded by the most
+    /// recent execution in the given metrics, as
+    /// `automaton.undelivered_bytes` (see
+    /// [`crate::automaton::coordinate_bounded`]).
+    ///
+    pub fn observe_metrics(&self, metrics: &Metrics) {
+        if let Some(summary) = metrics.get("automaton.undelivered_bytes") {
+            self.set_undelivered(summary.last as usize)
+        }
+    }
+
+    /// Return a summary of the current counters, with a warning for each
+    /// threshold that is exceeded.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport {
+            patches: self.patches.lock().unwrap().clone(),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            undelivered: self.undelivered.load(Ordering::Relaxed),
+            warnings: Vec::new(),
+        };
+        let t = &self.thresholds;
+
+        for (name, bytes, limit) in [
+            ("total", report.total(), t.total),
+            ("patch", report.patch_bytes(), t.patches),
+            ("message", report.message_bytes(), t.messages),
+        ] {
+            if bytes > limit {
+                report.warnings.push(format!("{} memory {} exceeds the threshold {}", name, bytes, limit))
+            }
+        }
+        report
+    }
+
+    /// Record the current counters in the given metrics, as
+    /// `memory.patches.<level>`, `memory.in_flight`, and
+    /// `memory.undelivered`.
+    ///
+    pub fn record(&self, metrics: &Metrics) {
+        let report = self.memory_report();
+
+        for (level, bytes) in &report.patches {
+            metrics.record(&format!("memory.patches.{}", level), *bytes as f64)
+        }
+        metrics.record("memory.in_flight", report.in_flight as f64);
+        metrics.record("memory.undelivered", report.undelivered as f64);
+    }
+}
+
+impl MemoryReport {
+    /// Return the bytes held by patches on all levels.
+    pub fn patch_bytes(&self) -> usize {
+        self.patches.values().sum()
+    }
+
+    /// Return the bytes held in messages, in flight or undelivered.
+    pub fn message_bytes(&self) -> usize {
+        self.in_flight + self.undelivered
+    }
+
+    /// Return the bytes held in all categories.
+    pub fn total(&self) -> usize {
+        self.patch_bytes() + self.message_bytes()
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "memory: {} total, patches", self.total())?;
+
+        for (level, bytes) in &self.patches {
+            write!(fmt, " [{}]={}", level, bytes)?;
+        }
+        write!(fmt, ", in flight {}, undelivered {}", self.in_flight, self.undelivered)?;
+
+        for warning in &self.warnings {
+            write!(fmt, "\nwarning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{MemoryAccount, MemoryThresholds};
+    use crate::patch::Patch;
+    use crate::stats::Metrics;
+
+    #[test]
+    fn memory_is_accounted_by_category() {
+        let account = MemoryAccount::with_thresholds(MemoryThresholds {
+            messages: 1000,
+            ..MemoryThresholds::default()
+        });
+        let patches = vec![
+            Patch::zeros(0, 4, (0..8, 0..8)),
+            Patch::zeros(0, 4, (8..16, 0..8)),
+            Patch::zeros(1, 4, (0..4, 0..4)),
+        ];
+        let metrics = Metrics::new();
+        metrics.record("automaton.undelivered_bytes", 640.0);
+
+        account.record_patches(&patches);
+        account.observe_metrics(&metrics);
+        account.message_sent(200);
+        account.message_sent(300);
+        account.message_received(200);
+
+        let report = account.memory_report();
+        assert_eq!(report.patches.get(&0), Some(&4096));
+        assert_eq!(report.patches.get(&1), Some(&512));
+        assert_eq!(report.message_bytes(), 940);
+        assert_eq!(report.total(), 5548);
+        assert!(report.warnings.is_empty());
+
+        account.message_sent(100);
+        let report = account.memory_report();
+        assert_eq!(report.warnings, ["message memory 1040 exceeds the threshold 1000"]);
+        assert!(report.to_string().ends_with("\nwarning: message memory 1040 exceeds the threshold 1000"));
+
+        account.record(&metrics);
+        assert_eq!(metrics.get("memory.patches.1").unwrap().last, 512.0);
+    }
+}
@@ -1,15 +1,108 @@
 use crate::adjacency_list::AdjacencyList;
-use crate::index_space::IndexSpace;
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use crate::index_space::{Axis, IndexSpace};
+use crate::parameters::Parameters;
 use crate::patch::Patch;
-use crate::rect_map::{Rectangle, RectangleMap};
+use crate::rect_map::{Rectangle, RectangleMap, RectangleRef};
+#[cfg(feature = "net")]
+use crate::message::comm::Communicator;
+use std::iter::FromIterator;
+use std::ops::Range;
+
+/// A simple rectilinear structured mesh
+///
+#[derive(Clone)]
+pub struct Mesh {
+    pub area: Rectangle<f64>,
+    pub size: (usize, usize),
+}
+
+impl Mesh {
+    pub fn cell_spacing(&self) -> (f64, f64) {
+        let d0 = (self.area.0.end - self.area.0.start) / self.size.0 as f64;
+        let d1 = (self.area.1.end - self.area.1.start) / self.size.1 as f64;
+        (d0, d1)
+    }
+
+    /// Return the cell spacing at the given granularity level. Level 0 is
+    /// the finest level, where the mesh has `self.size` cells.
+    pub fn cell_spacing_at_level(&self, level: u32) -> (f64, f64) {
+        let (d0, d1) = self.cell_spacing();
+        let factor = (1 << level) as f64;
+        (d0 * factor, d1 * factor)
+    }
+
+    pub fn cell_center(&self, index: (i64, i64)) -> (f64, f64) {
+        self.cell_center_at_level(0, index)
+    }
+
+    /// Return the physical coordinates of the center of the cell with the
+    /// given index, where the index measures ticks at the given level.
+    pub fn cell_center_at_level(&self, level: u32, index: (i64, i64)) -> (f64, f64) {
+        let (d0, d1) = self.cell_spacing_at_level(level);
+        let x0 = self.area.0.start + d0 * (index.0 as f64 + 0.5);
+        let x1 = self.area.1.start + d1 * (index.1 as f64 + 0.5);
+        (x0, x1)
+    }
+
+    /// Return the index, at the given level, of the cell containing the
+    /// given physical position. Positions outside the mesh area yield
+    /// indexes outside the mesh index space.
+    pub fn index_at(&self, position: (f64, f64), level: u32) -> (i64, i64) {
+        let (d0, d1) = self.cell_spacing_at_level(level);
+        let i = ((position.0 - self.area.0.start) / d0).floor() as i64;
+        let j = ((position.1 - self.area.1.start) / d1).floor() as i64;
+        (i, j)
+    }
+
+    /// Return the physical area covered by the given index space, with
+    /// indexes measuring ticks at the given level.
+    pub fn area_of(&self, level: u32, space: &IndexSpace) -> Rectangle<f64> {
+        let (d0, d1) = self.cell_spacing_at_level(level);
+        let (i0, j0) = space.start();
+        let (i1, j1) = space.end();
+        let x = self.area.0.start;
+        let y = self.area.1.start;
+        (
+            x + d0 * i0 as f64..x + d0 * i1 as f64,
+            y + d1 * j0 as f64..y + d1 * j1 as f64,
+        )
+    }
+
+    /// Return the physical bounding box of a patch.
+    pub fn patch_area(&self, patch: &Patch) -> Rectangle<f64> {
+        self.area_of(patch.level(), &patch.index_space())
+    }
+
+    /// Return the physical coordinates of the center of a cell in a patch,
+    /// where the index is with respect to the patch's own level.
+    pub fn patch_cell_center(&self, patch: &Patch, index: (i64, i64)) -> (f64, f64) {
+        self.cell_center_at_level(patch.level(), index)
+    }
+
+    pub fn total_zones(&self) -> usize {
+        self.size.0 * self.size.1
+    }
+}
 
 /// A trait for a container that can respond to queries for a patch overlying
-/// a point.
+/// a point. Points are indexes on the high-resolution (level 0) index space.
 /// 
 pub trait PatchQuery {
     /// Return a patch containing the given point, if one exists.
     /// 
     fn patch_containing_point(&self, point: (i64, i64)) -> Option<&Patch>;
+
+    /// Return the finest patch (the one with the lowest level) containing
+    /// the given point, if one exists.
+    ///
+    fn finest_patch_containing(&self, point: (i64, i64)) -> Option<&Patch>;
+
+    /// Return a patch at the given level containing the given point, if one
+    /// exists.
+    ///
+    fn patch_at_level(&self, point: (i64, i64), level: u32) -> Option<&Patch>;
 }
 
 impl PatchQuery for Vec<Patch> {
@@ -17,17 +110,37 @@ impl PatchQuery for Vec<Patch> {
         self.iter()
             .find(|p| p.high_resolution_space().contains(point))
     }
+
+    fn finest_patch_containing(&self, point: (i64, i64)) -> Option<&Patch> {
+        self.iter()
+            .filter(|p| p.high_resolution_space().contains(point))
+            .min_by_key(|p| p.level())
+    }
+
+    fn patch_at_level(&self, point: (i64, i64), level: u32) -> Option<&Patch> {
+        self.iter()
+            .find(|p| p.level() == level && p.high_resolution_space().contains(point))
+    }
 }
 
 impl PatchQuery for RectangleMap<i64, Patch> {
     fn patch_containing_point(&self, point: (i64, i64)) -> Option<&Patch> {
         self.query_point(point).next().map(|(_, p)| p)
     }
+
+    fn finest_patch_containing(&self, point: (i64, i64)) -> Option<&Patch> {
+        self.query_point(point).map(|(_, p)| p).min_by_key(|p| p.level())
+    }
+
+    fn patch_at_level(&self, point: (i64, i64), level: u32) -> Option<&Patch> {
+        self.query_point(point).map(|(_, p)| p).find(|p| p.level() == level)
+    }
 }
 
 /// Fill guard zone values in a mutable patch by sampling data from other
 /// patches in `PatchQuery` object. Indexes contained in the
-/// `valid_index_space` are not touched.
+/// `valid_index_space` are not touched. Solid zones in the neighbors (see
+/// [`Patch::with_mask`]) are marked solid in the guard zones as well.
 ///
 /// __WARNING__: this function is currently implemented only for patches at
 /// uniform refinement level.
@@ -55,13 +168,115 @@ pub fn extend_patch_mut<P, G>(
     let rj = IndexSpace::new(i0..i1, j1..y1);
 
     for index in li.iter().chain(lj.iter()).chain(ri.iter()).chain(rj.iter()) {
-        let slice = patch.get_slice_mut(index);
         if let Some(neigh) = neighbors.patch_containing_point(index) {
-            slice.clone_from_slice(neigh.get_slice(index))
+            patch.get_slice_mut(index).clone_from_slice(neigh.get_slice(index));
+            patch.set_solid(index, neigh.is_solid(index))
         } else {
-            boundary_value(index, slice)
+            boundary_value(index, patch.get_slice_mut(index))
+        }
+    }
+}
+
+/// Interpolate a field to an arbitrary physical position, using bilinear
+/// interpolation of the cell-centered data on the finest patch which covers
+/// the position. Near the edges of that patch, the interpolation stencil is
+/// clamped to the patch, so the result there is a one-sided (constant)
+/// extrapolation. The patch map is keyed by the high-resolution rectangle of
+/// each patch, like the one used to build the adjacency list. Returns `None`
+/// if no patch covers the position.
+///
+pub fn interpolate(
+    patches: &RectangleMap<i64, Patch>,
+    mesh: &Mesh,
+    position: (f64, f64),
+    field: usize,
+) -> Option<f64> {
+    let patch = patches.finest_patch_containing(mesh.index_at(position, 0))?;
+    Some(Stencil::new(patch, mesh, field).interpolate(position))
+}
+
+/// Interpolate a field to many physical positions, with the same results as
+/// calling [`interpolate`] for each position, in the same order as the
+/// positions. The positions are first grouped by the patch which covers
+/// them, and each group is then interpolated in one pass over that patch's
+/// data, so the layout of the patch and its mesh spacing are worked out
+/// once per patch rather than once per position.
+///
+pub fn interpolate_many(
+    patches: &RectangleMap<i64, Patch>,
+    mesh: &Mesh,
+    positions: &[(f64, f64)],
+    field: usize,
+) -> Vec<Option<f64>> {
+    let mut groups: HashMap<PatchKey, (&Patch, Vec<usize>)> = HashMap::new();
+
+    for (n, &position) in positions.iter().enumerate() {
+        if let Some(patch) = patches.finest_patch_containing(mesh.index_at(position, 0)) {
+            groups
+                .entry((patch.high_resolution_rect(), patch.level()))
+                .or_insert_with(|| (patch, Vec::new()))
+                .1
+                .push(n)
+        }
+    }
+    let mut values = vec![None; positions.len()];
+
+    for (patch, group) in groups.into_values() {
+        let stencil = Stencil::new(patch, mesh, field);
+
+        for n in group {
+            values[n] = Some(stencil.interpolate(positions[n]))
         }
     }
+    values
+}
+
+/// The bilinear interpolation stencil of one field of a patch: the patch
+/// layout and mesh spacing which are shared by every position interpolated
+/// on that patch.
+struct Stencil<'a> {
+    data: &'a [f64],
+    num_fields: usize,
+    field: usize,
+    start: (i64, i64),
+    end: (i64, i64),
+    origin: (f64, f64),
+    spacing: (f64, f64),
+}
+
+impl<'a> Stencil<'a> {
+    fn new(patch: &'a Patch, mesh: &Mesh, field: usize) -> Self {
+        assert!(field < patch.num_fields(), "field {} out of range", field);
+        Self {
+            data: patch.data(),
+            num_fields: patch.num_fields(),
+            field,
+            start: patch.index_space().start(),
+            end: patch.index_space().end(),
+            origin: (mesh.area.0.start, mesh.area.1.start),
+            spacing: mesh.cell_spacing_at_level(patch.level()),
+        }
+    }
+
+    /// Interpolate to a position which the patch covers. Near the edges of
+    /// the patch the stencil is clamped to it.
+    fn interpolate(&self, position: (f64, f64)) -> f64 {
+        let (i0, j0) = self.start;
+        let (i1, j1) = self.end;
+        let s = (position.0 - self.origin.0) / self.spacing.0 - 0.5;
+        let t = (position.1 - self.origin.1) / self.spacing.1 - 0.5;
+        let i = (s.floor() as i64).max(i0).min(i1 - 1);
+        let j = (t.floor() as i64).max(j0).min(j1 - 1);
+        let ip = (i + 1).min(i1 - 1);
+        let jp = (j + 1).min(j1 - 1);
+        let a = (s - i as f64).clamp(0.0, 1.0);
+        let b = (t - j as f64).clamp(0.0, 1.0);
+        let y = |i: i64, j: i64| {
+            let n = (i - i0) as usize * (j1 - j0) as usize + (j - j0) as usize;
+            self.data[n * self.num_fields + self.field]
+        };
+        (1.0 - a) * ((1.0 - b) * y(i, j) + b * y(i, jp)) + a * ((1.0 - b) * y(ip, j) + b * y(ip, jp))
+    }
 }
 
 /// A trait for a container that can yield an adjacency list (the container
@@ -86,6 +301,20 @@ pub trait GraphTopology {
     /// Return an adjacency list derived from this container.
     /// 
     fn adjacency_list(&self, parameter: Self::Parameter) -> AdjacencyList<Self::Key>;
+
+    /// Return the part of the adjacency list which is relevant to one rank:
+    /// the edges with at least one end on a vertex for which `is_local`
+    /// returns true. Implementations should build this without building
+    /// the whole adjacency list. The default implementation returns the
+    /// whole adjacency list.
+    /// 
+    fn local_adjacency_list<F>(&self, parameter: Self::Parameter, is_local: F) -> AdjacencyList<Self::Key>
+    where
+        F: Fn(&Self::Key) -> bool,
+    {
+        let _ = is_local;
+        self.adjacency_list(parameter)
+    }
 }
 
 impl GraphTopology for RectangleMap<i64, Patch> {
@@ -107,4 +336,1051 @@ impl GraphTopology for RectangleMap<i64, Patch> {
         }
         edges
     }
+
+    /// Incoming edges are found by querying around each local patch, as in
+    /// [`GraphTopology::adjacency_list`]. Outgoing edges are found by
+    /// querying the same neighborhood for candidate downstream patches, and
+    /// keeping those whose own query finds the local patch (edges between
+    /// two local patches are found once, as incoming edges). Only local patches
+    /// are queried, and only their edges are stored.
+    fn local_adjacency_list<F>(&self, num_guard: Self::Parameter, is_local: F) -> AdjacencyList<Self::Key>
+    where
+        F: Fn(&Self::Key) -> bool,
+    {
+        let mut edges = AdjacencyList::new();
+        let key = |rect: RectangleRef<i64>, patch: &Patch| (IndexSpace::from(rect).into(), patch.level());
+
+        for (b, q) in self.iter() {
+            if !is_local(&key(b, q)) {
+                continue;
+            }
+            let neighborhood = q.index_space().extend_all(num_guard);
+
+            for (a, p) in self.query_rect(neighborhood.clone()) {
+                if a != b {
+                    edges.insert(key(a, p), key(b, q))
+                }
+            }
+            for (c, r) in self.query_rect(neighborhood) {
+                if c != b && !is_local(&key(c, r)) && self.query_rect(r.index_space().extend_all(num_guard)).any(|(a, _)| a == b) {
+                    edges.insert(key(b, q), key(c, r))
+                }
+            }
+        }
+        edges
+    }
+}
+
+/// An offset on the high-resolution index space, to be applied to a patch
+/// received from across a periodic boundary before it is used to fill guard
+/// zones.
+///
+pub type Translation = (i64, i64);
+
+/// The translations associated with the edges of a periodic topology, keyed
+/// by the edge's `(source, target)` vertices. A pair of patches may be
+/// neighbors through more than one periodic image, so each edge may carry
+/// several translations; the adjacency list has one edge for each of them.
+///
+pub type EdgeTranslations<K> = HashMap<(K, K), Vec<Translation>>;
+
+/// The vertex key used in the adjacency lists of patch maps: a patch's
+/// high-resolution rectangle and its level.
+///
+pub type PatchKey = (Rectangle<i64>, u32);
+
+/// Describes which axes of a domain are periodic. The domain is given on the
+/// high-resolution index space.
+///
+#[derive(Clone, Debug)]
+pub struct Periodicity {
+    pub domain: IndexSpace,
+    pub axes: (bool, bool),
+}
+
+impl Periodicity {
+    /// Return the translations which map the domain onto its periodic
+    /// images, including the identity.
+    pub fn images(&self) -> Vec<Translation> {
+        let (ni, nj) = self.domain.dim();
+        let shifts = |periodic: bool, n: usize| {
+            if periodic {
+                vec![0, -(n as i64), n as i64]
+            } else {
+                vec![0]
+            }
+        };
+        let si = shifts(self.axes.0, ni);
+        let sj = shifts(self.axes.1, nj);
+        si.iter()
+            .flat_map(|&di| sj.iter().map(move |&dj| (di, dj)))
+            .collect()
+    }
+
+    /// Describe a region of the high-resolution index space, which may
+    /// extend past the edges of the domain (e.g. a patch extended with guard
+    /// zones), by pieces inside the domain. Along periodic axes, the parts
+    /// of the region outside the domain are wrapped around to the zones
+    /// they are images of; along other axes they are left out.
+    pub fn wrap(&self, region: &IndexSpace) -> WrappedRegion {
+        let (ri, rj) = region.clone().into_rect();
+        let (di, dj) = self.domain.clone().into_rect();
+        let si = wrap_axis(ri, di, self.axes.0);
+        let sj = wrap_axis(rj, dj, self.axes.1);
+        let pieces = si
+            .iter()
+            .flat_map(|(i, ti)| sj.iter().map(move |(j, tj)| (IndexSpace::new(i.clone(), j.clone()), (*ti, *tj))))
+            .collect();
+        WrappedRegion { pieces }
+    }
+}
+
+/// Split a range along one axis of a domain into the pieces inside the
+/// domain, each with the offset which carries it back to its place in the
+/// range.
+fn wrap_axis(range: Range<i64>, domain: Range<i64>, periodic: bool) -> Vec<(Range<i64>, i64)> {
+    let n = domain.end - domain.start;
+
+    if !periodic || n == 0 || range.is_empty() {
+        let piece = range.start.max(domain.start)..range.end.min(domain.end);
+        return if piece.is_empty() { vec![] } else { vec![(piece, 0)] };
+    }
+    let k0 = (range.start - domain.start).div_euclid(n);
+    let k1 = (range.end - 1 - domain.start).div_euclid(n);
+
+    (k0..=k1)
+        .map(|k| {
+            let image = domain.start + k * n..domain.end + k * n;
+            let piece = range.start.max(image.start)..range.end.min(image.end);
+            (piece.start - k * n..piece.end - k * n, k * n)
+        })
+        .collect()
+}
+
+/// A region of the high-resolution index space which may straddle the edges
+/// of a periodic domain, so that it can't be described by a single index
+/// space. It is made of pieces inside the domain, each with the
+/// [`Translation`] which carries it to its place in the region. Created by
+/// [`Periodicity::wrap`]; see [`Patch::extract_wrapped`] to extract the data
+/// in the region from a patch.
+///
+#[derive(Clone, Debug, Default)]
+pub struct WrappedRegion {
+    pieces: Vec<(IndexSpace, Translation)>,
+}
+
+impl WrappedRegion {
+    /// Create a region from pieces on the domain, each with the translation
+    /// which carries it to its place in the region.
+    pub fn from_pieces(pieces: Vec<(IndexSpace, Translation)>) -> Self {
+        Self { pieces }
+    }
+
+    /// Return the pieces of the region, on the domain, with their
+    /// translations.
+    pub fn pieces(&self) -> &[(IndexSpace, Translation)] {
+        &self.pieces
+    }
+
+    /// Return the number of zones in the region.
+    pub fn len(&self) -> usize {
+        self.pieces.iter().map(|(space, _)| space.len()).sum()
+    }
+
+    /// Return true if no part of the region is in the domain.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the non-empty intersections of the given index space (on the
+    /// domain) with the pieces of the region, with their translations.
+    pub fn overlaps<'a>(&'a self, space: &'a IndexSpace) -> impl Iterator<Item = (IndexSpace, Translation)> + 'a {
+        self.pieces
+            .iter()
+            .map(move |(piece, t)| (piece.intersect(space.clone()), *t))
+            .filter(|(overlap, _)| !overlap.is_empty())
+    }
+
+    /// Return the part of this region whose pieces lie in the given index
+    /// space (on the domain), e.g. the part a given patch can supply.
+    pub fn restrict(&self, space: &IndexSpace) -> Self {
+        Self {
+            pieces: self.overlaps(space).collect(),
+        }
+    }
+}
+
+/// The global description of a simulation domain: its physical extent, its
+/// resolution, and which axes are periodic. Components which need any of
+/// these (the solver's [`Mesh`], the [`Periodicity`] used to build adjacency
+/// lists, the index extent at each level) should derive them from one
+/// `Domain`, so they can't disagree.
+///
+/// The resolution is given at level 0, the finest level, where the domain
+/// covers the high-resolution index space `0..size.0` by `0..size.1`. Each
+/// level is coarser than the one before by [`Domain::refinement_ratio`].
+///
+#[derive(Clone, Debug)]
+pub struct Domain {
+    pub area: Rectangle<f64>,
+    pub size: (usize, usize),
+    pub periodic: (bool, bool),
+}
+
+impl Domain {
+    /// Create a non-periodic domain with the given physical extent and
+    /// level 0 resolution.
+    pub fn new(area: Rectangle<f64>, size: (usize, usize)) -> Self {
+        Self {
+            area,
+            size,
+            periodic: (false, false),
+        }
+    }
+
+    /// Return this domain with the given axes made periodic.
+    pub fn with_periodicity(mut self, i: bool, j: bool) -> Self {
+        self.periodic = (i, j);
+        self
+    }
+
+    /// The ratio of the zone sizes on neighboring levels. This is fixed at 2
+    /// throughout the crate.
+    pub fn refinement_ratio(&self) -> u32 {
+        2
+    }
+
+    /// Return the mesh used by the solvers to map indexes to coordinates.
+    pub fn mesh(&self) -> Mesh {
+        Mesh {
+            area: self.area.clone(),
+            size: self.size,
+        }
+    }
+
+    /// Return the index space of the domain on the high-resolution (level
+    /// 0) index space.
+    pub fn high_resolution_space(&self) -> IndexSpace {
+        IndexSpace::new(0..self.size.0 as i64, 0..self.size.1 as i64)
+    }
+
+    /// Return the index space of the domain at the given level, or a mesh
+    /// error if the resolution is not divisible at that level.
+    pub fn index_space(&self, level: u32) -> Result<IndexSpace> {
+        self.high_resolution_space().try_coarsen_by(self.refinement_ratio().pow(level))
+    }
+
+    /// Return the periodicity of the domain, for building adjacency lists
+    /// with [`periodic_adjacency_list`].
+    pub fn periodicity(&self) -> Periodicity {
+        Periodicity {
+            domain: self.high_resolution_space(),
+            axes: self.periodic,
+        }
+    }
+
+    /// Return the domain as fixed parameters, to be saved with checkpoints
+    /// and checked on restart.
+    pub fn parameters(&self) -> Parameters {
+        Parameters::new()
+            .fixed("domain.x0", self.area.0.start)
+            .fixed("domain.x1", self.area.0.end)
+            .fixed("domain.y0", self.area.1.start)
+            .fixed("domain.y1", self.area.1.end)
+            .fixed("domain.ni", self.size.0 as i64)
+            .fixed("domain.nj", self.size.1 as i64)
+            .fixed("domain.periodic_i", self.periodic.0)
+            .fixed("domain.periodic_j", self.periodic.1)
+            .fixed("domain.refinement_ratio", self.refinement_ratio() as i64)
+    }
+}
+
+/// Return an adjacency list for a map of patches on a periodic domain,
+/// together with the translation carried by each edge. An edge from `A` to
+/// `B` with translation `t` means that `A`, translated by `t`, is needed to
+/// extend `B`. A patch may be upstream of itself through a periodic image.
+/// The translation should be applied to the patch received by `B`, with
+/// [`Patch::translate`], before it's passed to [`extend_patch_mut`].
+///
+pub fn periodic_adjacency_list(
+    patches: &RectangleMap<i64, Patch>,
+    num_guard: i64,
+    periodicity: &Periodicity,
+) -> (AdjacencyList<PatchKey>, EdgeTranslations<PatchKey>) {
+    let mut edges = AdjacencyList::new();
+    let mut translations = EdgeTranslations::new();
+
+    for (b, q) in patches.iter() {
+        let extended = q.high_resolution_space().extend_all(num_guard * (1 << q.level()));
+
+        for t in periodicity.images() {
+            let query = extended.translate_by((-t.0, -t.1));
+
+            for (a, p) in patches.query_rect(query) {
+                if a != b || t != (0, 0) {
+                    let a = (IndexSpace::from(a).into(), p.level());
+                    let b = (IndexSpace::from(b).into(), q.level());
+                    translations.entry((a.clone(), b.clone())).or_insert_with(Vec::new).push(t);
+                    edges.insert(a, b)
+                }
+            }
+        }
+    }
+    (edges, translations)
+}
+
+/// Return a Graphviz DOT representation of a patch adjacency list, for
+/// debugging message topologies. Each vertex is labelled by its
+/// high-resolution rectangle, its level, and the rank which owns it, as
+/// given by `rank_of`. Edges between patches on different ranks (those
+/// whose messages go over the network) are red. Edges from a coarser to a
+/// finer patch are dashed, and edges from a finer to a coarser patch are
+/// dotted.
+///
+pub fn topology_to_dot<F>(edges: &AdjacencyList<PatchKey>, rank_of: F) -> String
+where
+    F: Fn(&PatchKey) -> usize,
+{
+    edges.to_dot_with(
+        |key| {
+            let ((di, dj), level) = key;
+            format!(
+                "[{}, {}) x [{}, {}) L{} r{}",
+                di.start, di.end, dj.start, dj.end, level, rank_of(key)
+            )
+        },
+        |a, b| {
+            let mut attributes = Vec::new();
+            if rank_of(a) != rank_of(b) {
+                attributes.push("color=red")
+            }
+            if a.1 > b.1 {
+                attributes.push("style=dashed")
+            } else if a.1 < b.1 {
+                attributes.push("style=dotted")
+            }
+            attributes.join(", ")
+        },
+    )
+}
+
+/// A record of which rank owns each patch in a distributed hierarchy. It
+/// answers the questions which come up in steering and probes: which rank
+/// owns a given patch, and which patch (and rank) contains a given point.
+/// Patches are keyed by their high-resolution rectangle and their level, as
+/// in the message topology. Where patches on different levels overlap, point
+/// queries resolve to the finest one.
+///
+/// Each rank only knows its own patches; use [`PatchOwners::gather`] to
+/// build a map on every rank which can also answer for remote points.
+///
+#[derive(Clone, Default)]
+pub struct PatchOwners {
+    owners: RectangleMap<i64, Vec<(u32, usize)>>,
+}
+
+impl PatchOwners {
+    /// Create an empty ownership map.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the given patch is owned by the given rank. If the patch
+    /// was already recorded, its owner is replaced.
+    ///
+    pub fn insert(&mut self, key: PatchKey, rank: usize) {
+        let (rect, level) = key;
+
+        match self.owners.get_mut((&rect.0, &rect.1)) {
+            Some(owners) => {
+                owners.retain(|&(l, _)| l != level);
+                owners.push((level, rank));
+            }
+            None => {
+                self.owners.insert(rect, vec![(level, rank)]);
+            }
+        }
+    }
+
+    /// Return the number of patches in the map.
+    ///
+    pub fn len(&self) -> usize {
+        self.owners.iter().map(|(_, owners)| owners.len()).sum()
+    }
+
+    /// Return whether the map has no patches.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the rank which owns the given patch, if the patch is known.
+    ///
+    pub fn owner_of(&self, key: &PatchKey) -> Option<usize> {
+        let (rect, level) = key;
+        self.owners
+            .get((&rect.0, &rect.1))?
+            .iter()
+            .find(|&&(l, _)| l == *level)
+            .map(|&(_, rank)| rank)
+    }
+
+    /// Return the key of the finest patch containing the given index on the
+    /// high-resolution (level 0) index space, and the rank which owns it.
+    ///
+    pub fn owner_at(&self, index: (i64, i64)) -> Option<(PatchKey, usize)> {
+        self.owners
+            .query_point(index)
+            .flat_map(|(rect, owners)| owners.iter().map(move |&(level, rank)| ((rect, level), rank)))
+            .min_by_key(|&((rect, level), _)| (level, (rect.0.start, rect.1.start)))
+            .map(|((rect, level), rank)| (((rect.0.clone(), rect.1.clone()), level), rank))
+    }
+
+    /// Like [`PatchOwners::owner_at`], but for a physical position on the
+    /// given mesh.
+    ///
+    pub fn owner_at_position(&self, mesh: &Mesh, position: (f64, f64)) -> Option<(PatchKey, usize)> {
+        self.owner_at(mesh.index_at(position, 0))
+    }
+
+    /// Return the keys of the patches owned by the given rank, sorted.
+    ///
+    pub fn keys_owned_by(&self, rank: usize) -> Vec<PatchKey> {
+        let mut keys: Vec<_> = self.iter().filter(|&(_, r)| r == rank).map(|(key, _)| key).collect();
+        keys.sort_by_key(|(rect, level)| (rect.0.start, rect.0.end, rect.1.start, rect.1.end, *level));
+        keys
+    }
+
+    /// Iterate over the patch keys in the map, and their owners.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (PatchKey, usize)> + '_ {
+        self.owners.iter().flat_map(|(rect, owners)| {
+            owners
+                .iter()
+                .map(move |&(level, rank)| (((rect.0.clone(), rect.1.clone()), level), rank))
+        })
+    }
+
+    /// Build the ownership map of the whole hierarchy on every rank. This is
+    /// a collective operation: each rank passes the keys of the patches it
+    /// owns, and the keys are gathered on rank 0 and broadcast back to all
+    /// ranks.
+    ///
+    #[cfg(feature = "net")]
+    pub fn gather<C: Communicator>(comm: &C, local_keys: &[PatchKey]) -> Self {
+        let all_keys = comm.gather_values(&local_keys.to_vec()).map(|keys| {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(&keys, &mut bytes).unwrap();
+            bytes
+        });
+        let all_keys: Vec<Vec<PatchKey>> = ciborium::de::from_reader(&comm.broadcast(all_keys)[..]).unwrap();
+
+        all_keys
+            .into_iter()
+            .enumerate()
+            .flat_map(|(rank, keys)| keys.into_iter().map(move |key| (key, rank)))
+            .collect()
+    }
+}
+
+impl FromIterator<(PatchKey, usize)> for PatchOwners {
+    fn from_iter<I: IntoIterator<Item = (PatchKey, usize)>>(iter: I) -> Self {
+        let mut owners = Self::new();
+        for (key, rank) in iter {
+            owners.insert(key, rank)
+        }
+        owners
+    }
+}
+
+/// A domain composed of several disjoint rectangular boxes on the
+/// high-resolution index space, such as an L-shaped region. Where two boxes
+/// touch along an edge, patches in one box are neighbors of patches in the
+/// other, and guard zones are filled across the shared edge. The remaining
+/// box edges are _walls_: guard zones beyond them lie outside the domain and
+/// are filled by boundary conditions, even if another box lies just beyond
+/// the wall.
+///
+#[derive(Clone, Debug)]
+pub struct MultiDomain {
+    boxes: Vec<IndexSpace>,
+}
+
+impl MultiDomain {
+    /// Create a domain from a list of boxes. A mesh error is returned if any
+    /// box is empty, or if two boxes overlap.
+    ///
+    pub fn new(boxes: Vec<IndexSpace>) -> Result<Self> {
+        for (n, a) in boxes.iter().enumerate() {
+            if a.is_empty() {
+                return Err(Error::Mesh(format!("domain box {} is empty", n)));
+            }
+            for (m, b) in boxes.iter().enumerate().skip(n + 1) {
+                if overlap_volume(a, b) > 0 {
+                    return Err(Error::Mesh(format!("domain boxes {} and {} overlap", n, m)));
+                }
+            }
+        }
+        Ok(Self { boxes })
+    }
+
+    /// Return the boxes making up this domain.
+    pub fn boxes(&self) -> &[IndexSpace] {
+        &self.boxes
+    }
+
+    /// Return true if the given high-resolution index is inside the domain.
+    pub fn contains(&self, index: (i64, i64)) -> bool {
+        self.boxes.iter().any(|b| b.contains(index))
+    }
+
+    /// Return true if the given high-resolution index space is entirely
+    /// inside the domain. It may span several boxes.
+    pub fn contains_space(&self, space: &IndexSpace) -> bool {
+        let covered: i64 = self.boxes.iter().map(|b| overlap_volume(b, space)).sum();
+        covered == space.len() as i64
+    }
+
+    /// Return true if the given high-resolution index lies outside the
+    /// domain, so that a guard zone there should be filled by a boundary
+    /// condition. This can be used from the boundary value function passed
+    /// to [`extend_patch_mut`], to apply e.g. reflecting conditions at
+    /// internal walls.
+    ///
+    pub fn is_wall(&self, index: (i64, i64)) -> bool {
+        !self.contains(index)
+    }
+
+    /// Return a mesh error if any of the patches extends outside the
+    /// domain, including into the space between boxes.
+    ///
+    pub fn check_patches(&self, patches: &RectangleMap<i64, Patch>) -> Result<()> {
+        for (rect, _) in patches.iter() {
+            let space = IndexSpace::from(rect);
+
+            if !self.contains_space(&space) {
+                let (i0, j0) = space.start();
+                let (i1, j1) = space.end();
+                return Err(Error::Mesh(format!(
+                    "patch ({}..{} {}..{}) is not inside the domain",
+                    i0, i1, j0, j1
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the adjacency list of the patches in this domain. This is like
+    /// [`GraphTopology::adjacency_list`], except that two patches are only
+    /// neighbors if they are in the same box, or in boxes which share an
+    /// edge. In particular, patches on opposite sides of a gap between boxes
+    /// narrower than the guard zones are not neighbors.
+    ///
+    pub fn adjacency_list(&self, patches: &RectangleMap<i64, Patch>, num_guard: i64) -> AdjacencyList<PatchKey> {
+        let boxes_of = |space: &IndexSpace| -> Vec<usize> {
+            (0..self.boxes.len())
+                .filter(|&n| overlap_volume(&self.boxes[n], space) > 0)
+                .collect()
+        };
+        let connected = |a: &[usize], b: &[usize]| {
+            a.iter().any(|&m| b.iter().any(|&n| m == n || self.boxes_touch(m, n)))
+        };
+        let mut edges = AdjacencyList::new();
+
+        for (b, q) in patches.iter() {
+            let q_space = q.high_resolution_space();
+            let q_boxes = boxes_of(&q_space);
+
+            for (a, p) in patches.query_rect(q_space.extend_all(num_guard * (1 << q.level()))) {
+                if a != b && connected(&boxes_of(&p.high_resolution_space()), &q_boxes) {
+                    let a = (IndexSpace::from(a).into(), p.level());
+                    let b = (IndexSpace::from(b).into(), q.level());
+                    edges.insert(a, b)
+                }
+            }
+        }
+        edges
+    }
+
+    /// Return true if two boxes share an edge of positive length. Boxes which
+    /// only meet at a corner do not touch.
+    fn boxes_touch(&self, m: usize, n: usize) -> bool {
+        let (a, b) = (&self.boxes[m], &self.boxes[n]);
+        overlap_volume(&a.extend(1, Axis::I), b) > 0 || overlap_volume(&a.extend(1, Axis::J), b) > 0
+    }
+}
+
+/// Add refinement to a hierarchy of blocks so that neighboring blocks differ
+/// by at most `max_jump` levels (one level gives the usual 2:1 balance).
+/// This is meant to run after clustering, before patch data is created.
+///
+/// Each block is given as an index space at its own level, together with
+/// that level; level `n + 1` is coarser than level `n` by a factor of two, and
+/// blocks on finer levels overlay the coarser ones. The neighborhood of a
+/// block at level `n` is the block extended by `buffer` zones at its level.
+/// Wherever the neighborhood overlaps a block coarser than level `n +
+/// max_jump`, and is not already covered by blocks at level `n + max_jump` or
+/// finer (together), blocks at level `n + max_jump` covering the gap are
+/// added. They fill the gap's bounding box, except for any blocks already
+/// at their level: the box is split into runs of free zones along each
+/// row, as in [`crate::decompose::cluster`], and runs spanning the same
+/// columns in consecutive rows are merged, so the added blocks never
+/// overlap each other, or the blocks already at their level. The
+/// added blocks may themselves be unbalanced, so the pass is repeated until
+/// no more blocks are needed. Returns the number of blocks added.
+///
+pub fn enforce_level_balance(hierarchy: &mut Vec<(IndexSpace, u32)>, max_jump: u32, buffer: i64) -> usize {
+    assert!(max_jump > 0, "the maximum level jump must be positive");
+
+    let high_res = |(space, level): &(IndexSpace, u32)| space.refine_by(1 << level);
+    let mut num_added = 0;
+
+    loop {
+        let mut additions: Vec<(IndexSpace, u32)> = Vec::new();
+
+        for block in hierarchy.iter() {
+            let target = block.1 + max_jump;
+            let f = 1_i64 << target;
+            let neighborhood = high_res(block).extend_all(buffer << block.1);
+
+            for coarse in hierarchy.iter().filter(|c| c.1 > target) {
+                let region = high_res(coarse).intersect(neighborhood.clone());
+
+                if region.is_empty() {
+                    continue;
+                }
+                let (i0, j0) = region.start();
+                let (i1, j1) = region.end();
+                let zones = IndexSpace::new(
+                    i0.div_euclid(f)..(i1 + f - 1).div_euclid(f),
+                    j0.div_euclid(f)..(j1 + f - 1).div_euclid(f),
+                );
+                let zone = |(i, j): (i64, i64)| IndexSpace::new(i * f..(i + 1) * f, j * f..(j + 1) * f);
+                let uncovered: Vec<_> = zones
+                    .iter()
+                    .filter(|&index| {
+                        let finer = hierarchy.iter().chain(&additions).filter(|b| b.1 <= target);
+                        !is_covered(&zone(index), finer.map(high_res))
+                    })
+                    .collect();
+
+                if uncovered.is_empty() {
+                    continue;
+                }
+                let i0 = uncovered.iter().map(|z| z.0).min().unwrap();
+                let j0 = uncovered.iter().map(|z| z.1).min().unwrap();
+                let i1 = uncovered.iter().map(|z| z.0).max().unwrap() + 1;
+                let j1 = uncovered.iter().map(|z| z.1).max().unwrap() + 1;
+
+                // The zones of the gap's bounding box which are free of
+                // blocks at the target level, in runs along each row.
+                let mut runs: Vec<(Range<i64>, Range<i64>)> = Vec::new();
+
+                for j in j0..j1 {
+                    for i in i0..i1 {
+                        let occupied = hierarchy
+                            .iter()
+                            .chain(&additions)
+                            .any(|b| b.1 == target && overlap_volume(&high_res(b), &zone((i, j))) > 0);
+
+                        if occupied {
+                            continue;
+                        }
+                        match runs.last_mut() {
+                            Some((di, dj)) if dj.start == j && di.end == i => di.end += 1,
+                            _ => runs.push((i..i + 1, j..j + 1)),
+                        }
+                    }
+                }
+                let mut gap: Vec<(Range<i64>, Range<i64>)> = Vec::new();
+
+                for (di, dj) in runs {
+                    match gap.iter_mut().find(|(gi, gj)| *gi == di && gj.end == dj.start) {
+                        Some((_, gj)) => gj.end = dj.end,
+                        None => gap.push((di, dj)),
+                    }
+                }
+                additions.extend(gap.into_iter().map(|(di, dj)| (IndexSpace::new(di, dj), target)))
+            }
+        }
+        if additions.is_empty() {
+            return num_added;
+        }
+        num_added += additions.len();
+        hierarchy.extend(additions);
+    }
+}
+
+/// Determine whether the union of the given index spaces contains every
+/// index of the given space.
+fn is_covered<I: IntoIterator<Item = IndexSpace>>(space: &IndexSpace, blocks: I) -> bool {
+    let mut remaining = vec![space.clone()];
+
+    for block in blocks {
+        remaining = remaining.iter().flat_map(|r| subtract(r, &block)).collect();
+
+        if remaining.is_empty() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Return the parts of `a` outside of `b`, as at most four disjoint index
+/// spaces.
+fn subtract(a: &IndexSpace, b: &IndexSpace) -> Vec<IndexSpace> {
+    let overlap = a.intersect(b.clone());
+
+    if overlap.is_empty() {
+        return vec![a.clone()];
+    }
+    let ((ai0, aj0), (ai1, aj1)) = (a.start(), a.end());
+    let ((oi0, oj0), (oi1, oj1)) = (overlap.start(), overlap.end());
+
+    vec![
+        IndexSpace::new(ai0..oi0, aj0..aj1),
+        IndexSpace::new(oi1..ai1, aj0..aj1),
+        IndexSpace::new(oi0..oi1, aj0..oj0),
+        IndexSpace::new(oi0..oi1, oj1..aj1),
+    ]
+    .into_iter()
+    .filter(|piece| !piece.is_empty())
+    .collect()
+}
+
+/// Return the number of indexes in both of two index spaces.
+fn overlap_volume(a: &IndexSpace, b: &IndexSpace) -> i64 {
+    a.intersect(b.clone()).len() as i64
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        enforce_level_balance, extend_patch_mut, interpolate, interpolate_many, periodic_adjacency_list,
+        topology_to_dot, Domain, GraphTopology, Mesh, MultiDomain, PatchOwners, PatchQuery, Periodicity,
+    };
+    use crate::adjacency_list::AdjacencyList;
+    use crate::index_space::IndexSpace;
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+
+    fn mesh() -> Mesh {
+        Mesh {
+            area: (-1.0..1.0, 0.0..1.0),
+            size: (200, 100),
+        }
+    }
+
+    #[test]
+    fn mesh_coordinates_are_level_aware() {
+        let mesh = mesh();
+        assert_eq!(mesh.cell_center_at_level(0, (0, 0)), (-0.995, 0.005));
+        assert_eq!(mesh.cell_center_at_level(1, (0, 0)), (-0.99, 0.01));
+        assert_eq!(mesh.index_at((0.0, 0.5), 0), (100, 50));
+        assert_eq!(mesh.index_at((0.0, 0.5), 2), (25, 12));
+        assert_eq!(mesh.index_at(mesh.cell_center_at_level(3, (7, 4)), 3), (7, 4));
+    }
+
+    #[test]
+    fn mesh_gives_patch_area() {
+        let mesh = mesh();
+        let patch = Patch::zeros(1, 1, (10..20, 0..50));
+        let area = mesh.patch_area(&patch);
+        assert!((area.0.start + 0.8).abs() < 1e-12 && (area.0.end + 0.6).abs() < 1e-12);
+        assert!(area.1.start.abs() < 1e-12 && (area.1.end - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn interpolation_is_exact_for_linear_fields() {
+        let mesh = mesh();
+        let mut patches = RectangleMap::new();
+
+        for level in 0..2 {
+            let space = (0..100 >> level, 0..100 >> level);
+            let patch = Patch::from_scalar_function(level, space, |index| {
+                let (x, y) = mesh.cell_center_at_level(level, index);
+                2.0 * x + 3.0 * y
+            });
+            patches.insert(patch.high_resolution_space(), patch);
+        }
+
+        let fine = Patch::from_scalar_function(0, (0..20, 0..20), |_| 0.0);
+        patches.insert(fine.high_resolution_space(), fine);
+
+        let (x, y) = (-0.3, 0.47);
+        assert!((interpolate(&patches, &mesh, (x, y), 0).unwrap() - (2.0 * x + 3.0 * y)).abs() < 1e-12);
+        assert_eq!(interpolate(&patches, &mesh, (-0.95, 0.05), 0), Some(0.0));
+        assert_eq!(interpolate(&patches, &mesh, (2.0, 0.5), 0), None);
+        assert_eq!(interpolate_many(&patches, &mesh, &[(-0.95, 0.05), (2.0, 0.5)], 0), [Some(0.0), None]);
+
+        let positions: Vec<_> = (0..50).map(|n| (-1.0 + 0.061 * n as f64, 0.013 * n as f64)).collect();
+        let one_by_one: Vec<_> = positions.iter().map(|&p| interpolate(&patches, &mesh, p, 0)).collect();
+        assert_eq!(interpolate_many(&patches, &mesh, &positions, 0), one_by_one);
+    }
+
+    #[test]
+    fn patch_queries_can_ask_for_a_level() {
+        let patches = vec![
+            Patch::zeros(2, 1, (0..4, 0..4)),
+            Patch::zeros(0, 1, (4..8, 4..8)),
+            Patch::zeros(1, 1, (0..4, 0..4)),
+        ];
+        let map: RectangleMap<_, _> = patches.iter().map(|p| (p.high_resolution_rect(), p.clone())).collect();
+
+        fn check<P: PatchQuery>(patches: &P) {
+            assert_eq!(patches.finest_patch_containing((5, 5)).map(Patch::level), Some(0));
+            assert_eq!(patches.finest_patch_containing((7, 1)).map(Patch::level), Some(1));
+            assert_eq!(patches.finest_patch_containing((12, 3)).map(Patch::level), Some(2));
+            assert_eq!(patches.finest_patch_containing((16, 0)).map(Patch::level), None);
+            assert_eq!(patches.patch_at_level((5, 5), 2).map(Patch::level), Some(2));
+            assert_eq!(patches.patch_at_level((12, 3), 1).map(Patch::level), None);
+        }
+        check(&patches);
+        check(&map);
+    }
+
+    #[test]
+    fn periodic_neighbors_are_translated_into_place() {
+        let patches: RectangleMap<_, _> = vec![
+            Patch::from_scalar_function(0, (0..10, 0..10), |(i, _)| i as f64),
+            Patch::from_scalar_function(0, (10..20, 0..10), |(i, _)| i as f64),
+        ]
+        .into_iter()
+        .map(|p| (p.high_resolution_rect(), p))
+        .collect();
+
+        let periodicity = Periodicity {
+            domain: IndexSpace::new(0..20, 0..10),
+            axes: (true, false),
+        };
+        let (edges, translations) = periodic_adjacency_list(&patches, 1, &periodicity);
+        let a = ((0..10, 0..10), 0);
+        let b = ((10..20, 0..10), 0);
+
+        assert_eq!(edges.len(), 4);
+        assert_eq!(translations[&(a.clone(), b.clone())], [(0, 0), (20, 0)]);
+        assert_eq!(translations[&(b.clone(), a.clone())], [(0, 0), (-20, 0)]);
+
+        let received: Vec<_> = translations[&(a.clone(), b.clone())]
+            .iter()
+            .map(|&t| patches.get((&a.0 .0, &a.0 .1)).unwrap().translate(t))
+            .collect();
+        let valid = IndexSpace::new(10..20, 0..10);
+        let mut extended = Patch::zeros(0, 1, valid.extend_all(1));
+        extend_patch_mut(&mut extended, &valid, |_, p| p[0] = -1.0, &received);
+
+        assert_eq!(extended.get_slice((9, 5))[0], 9.0);
+        assert_eq!(extended.get_slice((20, 5))[0], 0.0);
+        assert_eq!(extended.get_slice((15, 10))[0], -1.0);
+    }
+
+    #[test]
+    fn wrapped_regions_straddle_periodic_edges() {
+        let periodicity = Periodicity {
+            domain: IndexSpace::new(0..20, 0..10),
+            axes: (true, false),
+        };
+        let region = periodicity.wrap(&IndexSpace::new(-2..3, 8..12));
+        let pieces: Vec<_> = region.pieces().iter().map(|(s, t)| (s.clone().into_rect(), *t)).collect();
+
+        assert_eq!(pieces, [((18..20, 8..10), (-20, 0)), ((0..3, 8..10), (0, 0))]);
+        assert_eq!(region.len(), 10);
+        assert!(periodicity.wrap(&IndexSpace::new(0..4, 12..14)).is_empty());
+
+        // A coarse patch on the right edge is extracted into the guard zones
+        // left of the domain.
+        let patch = Patch::from_scalar_function(1, (5..10, 0..5), |(i, _)| i as f64);
+        let extended = periodicity.wrap(&IndexSpace::new(0..20, 0..10).extend_all(2));
+        let received = patch.extract_wrapped(&extended);
+        let spaces: Vec<_> = received.iter().map(|p| p.index_space().into_rect()).collect();
+
+        assert_eq!(spaces, [(-1..0, 0..5), (5..10, 0..5)]);
+        assert_eq!(received[0].get_slice((-1, 3)), [9.0]);
+    }
+
+    #[test]
+    fn local_adjacency_list_has_only_edges_touching_local_patches() {
+        let patches: RectangleMap<_, _> = (0..4)
+            .map(|n| Patch::zeros(0, 1, (n * 4..n * 4 + 4, 0..4)))
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+        let key = |n: i64| ((n * 4..n * 4 + 4, 0..4), 0);
+        let mut global = patches.adjacency_list(1);
+        let mut local = patches.local_adjacency_list(1, |(rect, _)| rect.0.start < 8);
+
+        assert_eq!(global.len(), 6);
+        assert_eq!(local.len(), 4);
+
+        for (a, b) in [(0, 1), (1, 0), (1, 2), (2, 1)] {
+            assert!(global.contains(&key(a), &key(b)));
+            assert!(local.contains(&key(a), &key(b)));
+        }
+        assert!(!local.contains(&key(2), &key(3)));
+        assert!(!local.contains(&key(3), &key(2)));
+    }
+
+    #[test]
+    fn topology_exports_ranks_and_edge_kinds_to_dot() {
+        let mut edges = AdjacencyList::new();
+        let coarse = ((0..20, 0..10), 1);
+        let fine = ((0..10, 0..10), 0);
+        let remote = ((20..40, 0..10), 1);
+        edges.insert(coarse.clone(), fine.clone());
+        edges.insert(fine.clone(), coarse.clone());
+        edges.insert(remote.clone(), coarse.clone());
+
+        let dot = topology_to_dot(&edges, |key| if key.0 .0.start < 20 { 0 } else { 1 });
+        assert!(dot.contains("n0 [label=\"[0, 10) x [0, 10) L0 r0\"];"));
+        assert!(dot.contains("n1 [label=\"[0, 20) x [0, 10) L1 r0\"];"));
+        assert!(dot.contains("n2 [label=\"[20, 40) x [0, 10) L1 r1\"];"));
+        assert!(dot.contains("n0 -> n1 [style=dotted];"));
+        assert!(dot.contains("n1 -> n0 [style=dashed];"));
+        assert!(dot.contains("n2 -> n1 [color=red];"));
+    }
+
+    #[test]
+    fn patch_owners_resolve_points_to_the_finest_patch() {
+        let coarse = ((0..20, 0..10), 1);
+        let fine = ((0..10, 0..10), 0);
+        let other = ((20..40, 0..10), 1);
+        let owners: PatchOwners = vec![(coarse.clone(), 0), (fine.clone(), 1), (other.clone(), 2)].into_iter().collect();
+
+        assert_eq!(owners.len(), 3);
+        assert_eq!(owners.owner_of(&coarse), Some(0));
+        assert_eq!(owners.owner_of(&((0..20, 0..10), 0)), None);
+        assert_eq!(owners.owner_at((5, 5)), Some((fine, 1)));
+        assert_eq!(owners.owner_at((15, 5)), Some((coarse, 0)));
+        assert_eq!(owners.owner_at((40, 5)), None);
+        assert_eq!(owners.keys_owned_by(2), vec![other.clone()]);
+
+        let mesh = Mesh {
+            area: (0.0..4.0, 0.0..1.0),
+            size: (40, 10),
+        };
+        assert_eq!(owners.owner_at_position(&mesh, (3.5, 0.5)), Some((other, 2)));
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn patch_owners_are_gathered_on_every_rank() {
+        use crate::message::local::LocalCommunicator;
+
+        let handles: Vec<_> = LocalCommunicator::group(3)
+            .into_iter()
+            .map(|comm| {
+                std::thread::spawn(move || {
+                    use crate::message::comm::Communicator;
+                    let r = comm.rank() as i64;
+                    let owners = PatchOwners::gather(&comm, &[((r * 10..r * 10 + 10, 0..10), 0)]);
+                    (0..3).map(|i| owners.owner_at((i * 10 + 5, 5)).map(|(_, rank)| rank)).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), vec![Some(0), Some(1), Some(2)]);
+        }
+    }
+
+    #[test]
+    fn domain_derives_consistent_geometry() {
+        let domain = Domain::new((-1.0..1.0, 0.0..1.0), (64, 32)).with_periodicity(true, false);
+        let mesh = domain.mesh();
+
+        assert_eq!(mesh.cell_spacing(), (2.0 / 64.0, 1.0 / 32.0));
+        assert_eq!(domain.index_space(2).unwrap().into_rect(), (0..16, 0..8));
+        assert!(domain.index_space(6).is_err());
+        assert_eq!(domain.periodicity().images().len(), 3);
+        assert_eq!(domain.periodicity().domain.dim(), (64, 32));
+        assert!(domain.parameters().check_restart(&domain.with_periodicity(true, true).parameters()).is_err());
+    }
+
+    #[test]
+    fn level_balance_adds_intermediate_levels() {
+        let mut hierarchy = vec![(IndexSpace::new(0..8, 0..8), 2), (IndexSpace::new(12..16, 12..16), 0)];
+        assert_eq!(enforce_level_balance(&mut hierarchy, 1, 1), 1);
+        assert_eq!(hierarchy[2].0.clone().into_rect(), (5..9, 5..9));
+        assert_eq!(hierarchy[2].1, 1);
+        assert_eq!(enforce_level_balance(&mut hierarchy, 1, 1), 0);
+
+        let mut hierarchy = vec![(IndexSpace::new(0..4, 0..4), 3), (IndexSpace::new(12..16, 12..16), 0)];
+        assert_eq!(enforce_level_balance(&mut hierarchy, 1, 1), 2);
+        assert_eq!(hierarchy.iter().map(|b| b.1).collect::<Vec<_>>(), [3, 0, 1, 2]);
+
+        let mut hierarchy = vec![(IndexSpace::new(0..4, 0..4), 3), (IndexSpace::new(12..16, 12..16), 0)];
+        assert_eq!(enforce_level_balance(&mut hierarchy, 2, 1), 1);
+        assert_eq!(hierarchy[2].1, 2);
+    }
+
+    #[test]
+    fn level_balance_fills_non_rectangular_gaps_with_disjoint_blocks() {
+        // Two level 0 blocks which together cover level 1 zone 5 along i,
+        // and a level 1 block in their neighborhood, which leaves a gap
+        // around it.
+        let fine = vec![
+            (IndexSpace::new(0..8, 0..8), 2),
+            (IndexSpace::new(8..11, 8..12), 0),
+            (IndexSpace::new(11..14, 8..12), 0),
+        ];
+        let high_res = |(space, level): &(IndexSpace, u32)| space.refine_by(1 << level);
+
+        let mut hierarchy = fine.clone();
+        hierarchy.push((IndexSpace::new(7..8, 4..5), 1));
+        assert!(enforce_level_balance(&mut hierarchy, 1, 1) > 0);
+        assert_eq!(enforce_level_balance(&mut hierarchy, 1, 1), 0);
+
+        let level_one: Vec<_> = hierarchy.iter().filter(|b| b.1 == 1).map(high_res).collect();
+        for (n, a) in level_one.iter().enumerate() {
+            for b in &level_one[n + 1..] {
+                assert!(a.intersect(b.clone()).is_empty());
+            }
+        }
+        for (i, j) in IndexSpace::new(7..15, 7..13).iter() {
+            assert!(hierarchy.iter().any(|b| b.1 <= 1 && high_res(b).contains((i, j))));
+        }
+
+        // A ring of level 1 blocks around the level 0 blocks leaves no gap.
+        let mut hierarchy = fine;
+        hierarchy.extend(vec![
+            (IndexSpace::new(3..8, 3..4), 1),
+            (IndexSpace::new(3..8, 6..7), 1),
+            (IndexSpace::new(3..4, 4..6), 1),
+            (IndexSpace::new(7..8, 4..6), 1),
+        ]);
+        assert_eq!(enforce_level_balance(&mut hierarchy, 1, 1), 0);
+    }
+
+    #[test]
+    fn multi_domain_neighbors_only_cross_shared_edges() {
+        let domain = MultiDomain::new(vec![
+            IndexSpace::new(0..10, 0..10),
+            IndexSpace::new(10..20, 0..10),
+            IndexSpace::new(0..10, 11..20),
+        ])
+        .unwrap();
+        let patches: RectangleMap<_, _> = vec![(0..10, 0..10), (10..20, 0..10), (0..10, 11..20)]
+            .into_iter()
+            .map(|rect| Patch::zeros(0, 1, rect))
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+
+        let (a, b, c) = (((0..10, 0..10), 0), ((10..20, 0..10), 0), ((0..10, 11..20), 0));
+        let mut edges = domain.adjacency_list(&patches, 2);
+        assert_eq!(edges.len(), 2);
+        assert!(edges.contains(&a, &b) && edges.contains(&b, &a));
+        assert!(!edges.contains(&a, &c));
+
+        assert!(domain.check_patches(&patches).is_ok());
+        assert!(domain.contains_space(&IndexSpace::new(5..15, 0..5)));
+        assert!(domain.is_wall((5, 10)));
+        assert!(MultiDomain::new(vec![IndexSpace::new(0..10, 0..10), IndexSpace::new(5..6, 5..6)]).is_err());
+
+        let outside: RectangleMap<_, _> = vec![Patch::zeros(0, 1, (0..10, 5..15))]
+            .into_iter()
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+        assert!(domain.check_patches(&outside).is_err());
+    }
 }
@@ -0,0 +1,87 @@
+use crate::clock::Clock;
+use std::time::Duration;
+
+/// A policy for retrying a fallible operation, waiting between attempts for
+/// a delay which doubles after each failure.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct ExponentialBackoff {
+    initial_delay: Duration,
+    attempts: u32,
+}
+
+impl ExponentialBackoff {
+    /// Create a policy which makes at most `attempts` attempts, waiting
+    /// `initial_delay` after the first failure.
+    ///
+    pub fn new(initial_delay: Duration, attempts: u32) -> Self {
+        assert!(attempts > 0, "a backoff policy needs at least one attempt");
+        Self { initial_delay, attempts }
+    }
+
+    /// Return the delays waited between attempts if every attempt fails.
+    ///
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let initial_delay = self.initial_delay;
+        (0..self.attempts - 1).map(move |n| initial_delay * 2u32.pow(n))
+    }
+
+    /// Call `attempt` until it succeeds or the attempts are used up,
+    /// sleeping on the given clock between attempts. Returns the result of
+    /// the last attempt.
+    ///
+    pub fn retry<T, E, F>(&self, clock: &dyn Clock, mut attempt: F) -> Result<T, E>
+    where
+        F: FnMut() -> Result<T, E>,
+    {
+        let mut result = attempt();
+
+        for delay in self.delays() {
+            if result.is_ok() {
+                break;
+            }
+            clock.sleep(delay);
+            result = attempt();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::ExponentialBackoff;
+    use crate::clock::{Clock, MockClock};
+    use std::time::Duration;
+
+    #[test]
+    fn delays_double_until_an_attempt_succeeds() {
+        let clock = MockClock::new();
+        let backoff = ExponentialBackoff::new(Duration::from_millis(10), 10);
+        let mut failures = 3;
+        let result: Result<(), ()> = backoff.retry(&clock, || {
+            failures -= 1;
+            if failures < 0 { Ok(()) } else { Err(()) }
+        });
+        let ms = |ms: &[u64]| ms.iter().copied().map(Duration::from_millis).collect::<Vec<_>>();
+
+        assert!(result.is_ok());
+        assert_eq!(clock.sleeps(), ms(&[10, 20, 40]));
+        assert_eq!(clock.now(), Duration::from_millis(70));
+    }
+
+    #[test]
+    fn the_last_error_is_returned_when_attempts_run_out() {
+        let clock = MockClock::new();
+        let backoff = ExponentialBackoff::new(Duration::from_millis(1), 4);
+        let mut attempts = 0;
+        let result: Result<(), usize> = backoff.retry(&clock, || {
+            attempts += 1;
+            Err(attempts)
+        });
+
+        assert_eq!(result, Err(4));
+        assert_eq!(clock.sleeps().len(), 3);
+        assert_eq!(clock.now(), Duration::from_millis(7));
+    }
+}
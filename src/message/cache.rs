@@ -0,0 +1,242 @@
+use super::envelope::Envelope;
+use crate::patch::{Patch, StoredPatch};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A message whose content can be compared exactly with another's. This is
+/// used by [`MessageCache`] to detect that a message is the same as the one
+/// sent on the same edge the last time, so its encoding can be reused.
+/// Floating point values are compared by their bit patterns, so that
+/// values which compare equal but encode differently (like `0.0` and
+/// `-0.0`) are told apart.
+///
+pub trait ContentEq {
+    fn content_eq(&self, other: &Self) -> bool;
+}
+
+impl ContentEq for f64 {
+    fn content_eq(&self, other: &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+impl ContentEq for f32 {
+    fn content_eq(&self, other: &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+impl ContentEq for i64 {
+    fn content_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl ContentEq for usize {
+    fn content_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl ContentEq for String {
+    fn content_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl<T: ContentEq> ContentEq for [T] {
+    fn content_eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.content_eq(b))
+    }
+}
+
+impl<T: ContentEq> ContentEq for Vec<T> {
+    fn content_eq(&self, other: &Self) -> bool {
+        self[..].content_eq(&other[..])
+    }
+}
+
+impl<A: ContentEq, B: ContentEq> ContentEq for (A, B) {
+    fn content_eq(&self, other: &Self) -> bool {
+        self.0.content_eq(&other.0) && self.1.content_eq(&other.1)
+    }
+}
+
+impl ContentEq for Patch {
+    fn content_eq(&self, other: &Self) -> bool {
+        (self.level(), self.local_rect(), self.num_fields(), self.mask())
+            == (other.level(), other.local_rect(), other.num_fields(), other.mask())
+            && self.data().content_eq(other.data())
+    }
+}
+
+impl ContentEq for StoredPatch {
+    fn content_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (StoredPatch::Double(a), StoredPatch::Double(b)) => a.content_eq(b),
+            (
+                StoredPatch::Single {
+                    level,
+                    rect,
+                    num_fields,
+                    data,
+                    mask,
+                },
+                StoredPatch::Single {
+                    level: other_level,
+                    rect: other_rect,
+                    num_fields: other_num_fields,
+                    data: other_data,
+                    mask: other_mask,
+                },
+            ) => {
+                (level, rect, num_fields, mask) == (other_level, other_rect, other_num_fields, other_mask)
+                    && data.content_eq(other_data)
+            }
+            (
+                StoredPatch::Quantized {
+                    level,
+                    rect,
+                    num_fields,
+                    offsets,
+                    steps,
+                    data,
+                    mask,
+                },
+                StoredPatch::Quantized {
+                    level: other_level,
+                    rect: other_rect,
+                    num_fields: other_num_fields,
+                    offsets: other_offsets,
+                    steps: other_steps,
+                    data: other_data,
+                    mask: other_mask,
+                },
+            ) => {
+                (level, rect, num_fields, mask) == (other_level, other_rect, other_num_fields, other_mask)
+                    && data == other_data
+                    && offsets.content_eq(other_offsets)
+                    && steps.content_eq(other_steps)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A cache of encoded message payloads, for sends which repeat the same
+/// message on an edge every iteration, e.g. guard zone data from a patch
+/// with fixed inflow values during folded sub-iterations. Each edge keeps
+/// the last payload sent on it and its encoding; if the next payload has
+/// the same content (see [`ContentEq`]), the encoding is reused rather than
+/// serializing the payload again. The iteration and key of the envelope are
+/// encoded on every send. Using the cache is optional: the bytes are
+/// identical to those of [`Envelope::encode`].
+///
+pub struct MessageCache<E, M> {
+    entries: HashMap<E, (M, Vec<u8>)>,
+    num_hits: usize,
+    num_misses: usize,
+}
+
+impl<E: Hash + Eq, M> Default for MessageCache<E, M> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            num_hits: 0,
+            num_misses: 0,
+        }
+    }
+}
+
+impl<E: Hash + Eq, M: Serialize + ContentEq> MessageCache<E, M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encode an envelope for a message sent on the given edge, reusing the
+    /// encoding of the payload sent on that edge last time if its content
+    /// is unchanged. The payload is kept to compare with the next one.
+    ///
+    pub fn encode<K: Serialize>(&mut self, edge: E, iteration: u64, key: &K, payload: M) -> Vec<u8> {
+        // The envelope's fields are encoded in order, so an envelope with an
+        // empty payload ends with the encoding of `()`, a single CBOR null,
+        // which is replaced with the encoded payload.
+        let mut bytes = Envelope::new(iteration, key, ()).encode();
+        assert_eq!(bytes.pop(), Some(0xf6));
+
+        match self.entries.get_mut(&edge) {
+            Some((cached, encoded)) if cached.content_eq(&payload) => {
+                self.num_hits += 1;
+                bytes.extend_from_slice(encoded);
+            }
+            _ => {
+                self.num_misses += 1;
+                let mut encoded = Vec::new();
+                ciborium::ser::into_writer(&payload, &mut encoded).unwrap();
+                bytes.extend_from_slice(&encoded);
+                self.entries.insert(edge, (payload, encoded));
+            }
+        }
+        bytes
+    }
+
+    /// Forget the payload sent on an edge, e.g. when the edge is removed
+    /// from the mesh.
+    ///
+    pub fn remove(&mut self, edge: &E) {
+        self.entries.remove(edge);
+    }
+
+    /// Return the number of sends which reused a cached encoding.
+    ///
+    pub fn num_hits(&self) -> usize {
+        self.num_hits
+    }
+
+    /// Return the number of sends whose payload had to be encoded.
+    ///
+    pub fn num_misses(&self) -> usize {
+        self.num_misses
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::MessageCache;
+    use crate::index_space::range2d;
+    use crate::message::envelope::Envelope;
+    use crate::patch::{Patch, Precision, StoredPatch};
+    use std::ops::Range;
+
+    type Message = ((i64, i64), StoredPatch);
+
+    #[test]
+    fn unchanged_payloads_reuse_their_encoding() {
+        let mut cache = MessageCache::new();
+        let key = (0..4, 4..8);
+        let inflow = |value| -> Message {
+            let patch = Patch::from_scalar_function(0, range2d(0..4, 2..4), |_| value);
+            ((0, 1), StoredPatch::new(patch, Precision::Double))
+        };
+
+        for iteration in 0..3 {
+            let bytes = cache.encode("west", iteration, &key, inflow(1.0));
+            let envelope = Envelope::<(Range<i64>, Range<i64>), Message>::decode(&bytes).unwrap();
+            assert_eq!(bytes, Envelope::new(iteration, &key, &inflow(1.0)).encode());
+            assert_eq!((envelope.iteration, envelope.key), (iteration, key.clone()));
+        }
+        assert_eq!((cache.num_hits(), cache.num_misses()), (2, 1));
+
+        let bytes = cache.encode("west", 3, &key, inflow(2.0));
+        assert_eq!(bytes, Envelope::new(3, &key, &inflow(2.0)).encode());
+        cache.encode("east", 3, &key, inflow(2.0));
+        assert_eq!((cache.num_hits(), cache.num_misses()), (2, 3));
+
+        let bytes = cache.encode("east", 4, &key, inflow(-0.0));
+        assert_eq!(bytes, Envelope::new(4, &key, &inflow(-0.0)).encode());
+        cache.encode("east", 5, &key, inflow(0.0));
+        assert_eq!((cache.num_hits(), cache.num_misses()), (2, 5));
+    }
+}
@@ -1,4 +1,6 @@
 use super::util;
+use std::convert::TryInto;
+use std::time::Duration;
 
 /// Interface for a group of processes that can exchange messages over a
 /// network. The underlying transport can in principle be TCP, UDP, or a
@@ -22,6 +24,38 @@ pub trait Communicator {
     /// method is allowed to block until a message is ready to be received
     fn recv(&self) -> Vec<u8>;
 
+    /// Receive a message from any of the peers, waiting at most the given
+    /// duration for one to arrive, and return `None` if none did. The
+    /// default implementation blocks in [`Communicator::recv`], so transports
+    /// which can time out should override it.
+    ///
+    fn recv_timeout(&self, timeout: Duration) -> Option<Vec<u8>> {
+        let _ = timeout;
+        Some(self.recv())
+    }
+
+    /// Inform the transport that this rank has moved on to the given
+    /// iteration: it has received all of its messages from earlier
+    /// iterations, and the messages it sends with [`Communicator::send`]
+    /// from now on belong to this iteration. Transports which keep sent
+    /// messages for re-delivery use it to decide which of them a peer still
+    /// needs. The default implementation does nothing.
+    ///
+    fn set_iteration(&self, iteration: u64) {
+        let _ = iteration;
+    }
+
+    /// Send a message to a peer which belongs to the given iteration, rather
+    /// than the one last given to [`Communicator::set_iteration`].
+    /// Transports which keep sent messages for re-delivery keep it until
+    /// the peer has moved past that iteration. The default implementation
+    /// calls [`Communicator::send`].
+    ///
+    fn send_at_iteration(&self, rank: usize, iteration: u64, message: Vec<u8>) {
+        let _ = iteration;
+        self.send(rank, message)
+    }
+
     /// Implements a binomial tree broadcast from the root node. The message
     /// buffer must be `Some` if this is the root node, and it must be `None`
     /// otherwise.
@@ -38,7 +72,7 @@ pub trait Communicator {
             let one = 1 << level;
             let two = 1 << (level + 1);
 
-            if r % two == 0 && r + one <= p {
+            if r % two == 0 && r + one < p {
                 self.send(r + one, value.clone())
             }
         }
@@ -46,7 +80,13 @@ pub trait Communicator {
     }
 
     /// Implements a binomial tree reduce. All ranks return `None` except for
-    /// the root.
+    /// the root. Values are combined pairwise in a fixed order: at each level
+    /// of the tree, the value from the lower ranks is the first argument to
+    /// `f`, and the value from the higher ranks is the second. The result on
+    /// the root therefore depends only on the number of ranks, and not on the
+    /// order messages arrive in, so floating-point sums are reproducible from
+    /// run to run. The operator need not be commutative, but it must be
+    /// associative for the result to equal a sequential fold in rank order.
     ///
     fn reduce<F>(&self, f: F, mut value: Vec<u8>) -> Option<Vec<u8>>
     where
@@ -54,23 +94,26 @@ pub trait Communicator {
     {
         let r = self.rank();
         let p = self.size();
+        let mut early = Vec::new();
 
-        for level in (0..util::ceil_log2(p)).rev() {
+        for level in 0..util::ceil_log2(p) {
             let one = 1 << level;
             let two = 1 << (level + 1);
 
-            if r % two == 0 {
-                value = f(value, self.recv())
-            } else {
-                self.send(r - one, value);
+            if r % two != 0 {
+                self.send(r - one, tagged(r, value));
                 return None;
             }
+            if r + one < p {
+                value = f(value, recv_from(self, r + one, &mut early))
+            }
         }
         Some(value)
     }
 
-    /// Implements an all-reduce (symmetric fold) operation over a commutative
-    /// binary operator.
+    /// Implements an all-reduce (symmetric fold) operation, with the same
+    /// deterministic ordering as [`Communicator::reduce`]. Every rank returns
+    /// the same result.
     ///
     fn all_reduce<F>(&self, f: F, value: Vec<u8>) -> Vec<u8>
     where
@@ -78,4 +121,156 @@ pub trait Communicator {
     {
         self.broadcast(self.reduce(f, value))
     }
+
+    /// Like [`Communicator::all_reduce`], but for any serializable value. The
+    /// values are encoded as CBOR. For example, a global sum which is
+    /// reproducible across runs with the same number of ranks is
+    /// `comm.all_reduce_values(|a: f64, b| a + b, &local_sum)`.
+    ///
+    fn all_reduce_values<T, F>(&self, f: F, value: &T) -> T
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        F: Fn(T, T) -> T,
+    {
+        let encode = |value: &T| {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(value, &mut bytes).unwrap();
+            bytes
+        };
+        let decode = |bytes: &[u8]| -> T { ciborium::de::from_reader(bytes).unwrap() };
+        let result = self.all_reduce(|a, b| encode(&f(decode(&a), decode(&b))), encode(value));
+        decode(&result)
+    }
+
+    /// Collect a buffer from every rank on rank 0. The root returns the
+    /// buffers in rank order, and the other ranks return `None`. Each rank
+    /// sends its buffer directly to the root, which is appropriate for small
+    /// payloads like per-rank statistics.
+    ///
+    fn gather(&self, value: Vec<u8>) -> Option<Vec<Vec<u8>>> {
+        let r = self.rank();
+        let p = self.size();
+
+        if r != 0 {
+            self.send(0, tagged(r, value));
+            return None;
+        }
+        let mut values = vec![None; p];
+        values[0] = Some(value);
+
+        for _ in 1..p {
+            let (source, value) = untagged(self.recv());
+            values[source] = Some(value);
+        }
+        Some(values.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Like [`Communicator::gather`], but for any serializable value. The
+    /// values are encoded as CBOR.
+    ///
+    fn gather_values<T>(&self, value: &T) -> Option<Vec<T>>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+
+        self.gather(bytes).map(|values| {
+            values
+                .iter()
+                .map(|bytes| ciborium::de::from_reader(&bytes[..]).unwrap())
+                .collect()
+        })
+    }
+}
+
+/// Prefix a message with the rank of its sender.
+fn tagged(rank: usize, value: Vec<u8>) -> Vec<u8> {
+    let mut message = rank.to_le_bytes().to_vec();
+    message.extend(value);
+    message
+}
+
+/// Split a message prefixed by [`tagged`] into the sender's rank and the
+/// value.
+fn untagged(mut message: Vec<u8>) -> (usize, Vec<u8>) {
+    let value = message.split_off(std::mem::size_of::<usize>());
+    (usize::from_le_bytes(message[..].try_into().unwrap()), value)
+}
+
+/// Receive the tagged message from the given rank. Messages from other ranks
+/// which arrive first are kept in `early` until they are asked for.
+fn recv_from<C: Communicator + ?Sized>(comm: &C, source: usize, early: &mut Vec<(usize, Vec<u8>)>) -> Vec<u8> {
+    if let Some(n) = early.iter().position(|(rank, _)| *rank == source) {
+        return early.swap_remove(n).1;
+    }
+    loop {
+        let (rank, value) = untagged(comm.recv());
+
+        if rank == source {
+            return value;
+        }
+        early.push((rank, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::Communicator;
+    use crate::message::local::LocalCommunicator;
+    use std::thread;
+
+    #[test]
+    fn broadcast_reaches_every_rank_of_an_odd_sized_group() {
+        let handles: Vec<_> = LocalCommunicator::group(3)
+            .into_iter()
+            .map(|comm| thread::spawn(move || comm.broadcast(if comm.rank() == 0 { Some(vec![7]) } else { None })))
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(results, [vec![7], vec![7], vec![7]]);
+    }
+
+    #[test]
+    fn gathered_values_arrive_at_root_in_rank_order() {
+        let handles: Vec<_> = LocalCommunicator::group(3)
+            .into_iter()
+            .map(|comm| thread::spawn(move || comm.gather_values(&(comm.rank(), vec![0.5; comm.rank()]))))
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert!(results[1].is_none() && results[2].is_none());
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &[(0, vec![]), (1, vec![0.5]), (2, vec![0.5, 0.5])]
+        );
+    }
+
+    #[test]
+    fn reductions_combine_values_in_a_fixed_order_on_any_number_of_ranks() {
+        for size in 1..=6 {
+            let handles: Vec<_> = LocalCommunicator::group(size)
+                .into_iter()
+                .map(|comm| thread::spawn(move || comm.all_reduce_values(|a: Vec<usize>, b| [a, b].concat(), &vec![comm.rank()])))
+                .collect();
+            let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+            assert!(results.iter().all(|result| *result == (0..size).collect::<Vec<_>>()));
+        }
+    }
+
+    #[test]
+    fn floating_point_sums_follow_the_tree() {
+        let values = [1e16, 1.0, -1e16, 1.0, 3.0];
+        let handles: Vec<_> = LocalCommunicator::group(5)
+            .into_iter()
+            .map(|comm| thread::spawn(move || comm.all_reduce_values(|a: f64, b| a + b, &values[comm.rank()])))
+            .collect();
+        let expected = ((values[0] + values[1]) + (values[2] + values[3])) + values[4];
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().to_bits(), expected.to_bits());
+        }
+    }
 }
@@ -0,0 +1,301 @@
+use super::comm::Communicator;
+use crate::error::{Error, Result};
+use crate::parameters::Parameters;
+use crate::patch::Patch;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+
+/// A request to pause a run at its next pause point. The switch is read on
+/// rank 0, and can be flipped from any thread, e.g. a thread reading
+/// commands from a terminal or a socket.
+///
+#[derive(Debug, Default)]
+pub struct PauseSwitch {
+    requested: AtomicBool,
+}
+
+impl PauseSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request a pause at the next pause point.
+    ///
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst)
+    }
+
+    /// Return whether a pause has been requested and not yet taken.
+    ///
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// A point between iterations where a run may be paused for inspection.
+/// This must be called by every rank, after all of the messages for the
+/// iteration have been received, since the control messages are exchanged
+/// with the collective operations of the communicator.
+///
+/// Rank 0 decides whether to pause, from its [`PauseSwitch`], and broadcasts
+/// the decision. If the run is paused, every rank calls `inspect` with its
+/// patches and parameters. The parameter updates returned by `inspect` on
+/// rank 0 are broadcast and applied on every rank with
+/// [`Parameters::update`] before the run resumes; those returned on the
+/// other ranks are ignored. Returns whether the run was paused. If the
+/// updates are invalid, every rank returns the same error and the
+/// parameters are unchanged.
+///
+pub fn pause_point<C, F>(
+    comm: &C,
+    switch: &PauseSwitch,
+    patches: &[Patch],
+    parameters: &mut Parameters,
+    mut inspect: F,
+) -> Result<bool>
+where
+    C: Communicator,
+    F: FnMut(&[Patch], &Parameters) -> Parameters,
+{
+    let is_root = comm.rank() == 0;
+    let pause = comm.broadcast(is_root.then(|| vec![switch.take() as u8]));
+
+    if pause[0] == 0 {
+        return Ok(false);
+    }
+    let updates = inspect(patches, parameters);
+    let updates = comm.broadcast(is_root.then(|| {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&updates, &mut bytes).unwrap();
+        bytes
+    }));
+    let updates: Parameters = ciborium::de::from_reader(&updates[..])
+        .map_err(|e| Error::Parameters(format!("could not decode parameter updates: {:?}", e)))?;
+
+    parameters.update(&updates)?;
+    Ok(true)
+}
+
+/// A source of parameter updates for steering a run, polled on rank 0 at
+/// each [`steering_point`].
+///
+pub trait SteeringSource {
+    /// Return the updates which have arrived since the last poll, typed
+    /// after the current parameters. An empty registry means there are none.
+    fn poll(&mut self, current: &Parameters) -> Result<Parameters>;
+}
+
+/// Steering updates read from a local file, in the format accepted by
+/// [`Parameters::parse_updates`]. The file is removed once it has been
+/// read, so each set of updates is applied once; to steer the run, write
+/// the file next to it and move it into place.
+///
+#[derive(Clone, Debug)]
+pub struct SteeringFile {
+    path: PathBuf,
+}
+
+impl SteeringFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl SteeringSource for SteeringFile {
+    fn poll(&mut self, current: &Parameters) -> Result<Parameters> {
+        if !self.path.exists() {
+            return Ok(Parameters::new());
+        }
+        let text = fs::read_to_string(&self.path)?;
+        fs::remove_file(&self.path)?;
+        current.parse_updates(&text)
+    }
+}
+
+/// Steering updates sent from another thread, e.g. one reading commands
+/// from a socket.
+///
+impl SteeringSource for Receiver<Parameters> {
+    fn poll(&mut self, _: &Parameters) -> Result<Parameters> {
+        let mut updates = Parameters::new();
+
+        for received in self.try_iter() {
+            updates.extend(received)
+        }
+        Ok(updates)
+    }
+}
+
+/// A point between iterations where parameter updates may be applied to a
+/// running simulation, e.g. to change an output cadence or a source term
+/// coefficient. Like [`pause_point`], this must be called by every rank
+/// after all of the messages for the iteration have been received.
+///
+/// Rank 0 polls its steering source and broadcasts the updates; the source
+/// is not used on the other ranks. If there are any, every rank applies them
+/// with [`Parameters::update`] and then calls `apply` with the updates and
+/// the updated parameters, so the application can act on the changes.
+/// Returns whether any updates were applied. If the source fails on rank 0,
+/// a warning is printed and the run continues unchanged; if the updates are
+/// invalid, every rank returns the same error and the parameters are
+/// unchanged.
+///
+pub fn steering_point<C, S, F>(comm: &C, source: &mut S, parameters: &mut Parameters, mut apply: F) -> Result<bool>
+where
+    C: Communicator,
+    S: SteeringSource,
+    F: FnMut(&Parameters, &Parameters),
+{
+    let is_root = comm.rank() == 0;
+    let updates = comm.broadcast(is_root.then(|| {
+        let updates = source.poll(parameters).unwrap_or_else(|e| {
+            eprintln!("warning: ignoring steering updates: {}", e);
+            Parameters::new()
+        });
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&updates, &mut bytes).unwrap();
+        bytes
+    }));
+    let updates: Parameters = ciborium::de::from_reader(&updates[..])
+        .map_err(|e| Error::Parameters(format!("could not decode parameter updates: {:?}", e)))?;
+
+    if updates.is_empty() {
+        return Ok(false);
+    }
+    parameters.update(&updates)?;
+    apply(&updates, parameters);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{pause_point, steering_point, PauseSwitch, SteeringFile};
+    use crate::index_space::range2d;
+    use crate::message::comm::Communicator;
+    use crate::message::local::LocalCommunicator;
+    use crate::parameters::Parameters;
+    use crate::patch::Patch;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn paused_runs_are_inspected_and_updated_on_every_rank() {
+        let switch = Arc::new(PauseSwitch::new());
+        let handles: Vec<_> = LocalCommunicator::group(3)
+            .into_iter()
+            .map(|comm| {
+                let switch = switch.clone();
+                thread::spawn(move || {
+                    let patches = vec![Patch::from_scalar_function(0, range2d(0..2, 0..2), |_| comm.rank() as f64)];
+                    let mut parameters = Parameters::new().fixed("num_guard", 2).mutable("cfl", 0.4);
+                    let mut inspected = Vec::new();
+                    let mut paused = Vec::new();
+
+                    for step in 0..3 {
+                        if step == 1 && comm.rank() == 0 {
+                            switch.request()
+                        }
+                        paused.push(
+                            pause_point(&comm, &switch, &patches, &mut parameters, |patches, parameters| {
+                                inspected.push((patches[0].data()[0], parameters.get_float("cfl").unwrap()));
+                                Parameters::new().mutable("cfl", 0.1 * (comm.rank() + 2) as f64)
+                            })
+                            .unwrap(),
+                        );
+                    }
+                    (paused, inspected, parameters.get_float("cfl").unwrap())
+                })
+            })
+            .collect();
+
+        for (rank, handle) in handles.into_iter().enumerate() {
+            let (paused, inspected, cfl) = handle.join().unwrap();
+            assert_eq!(paused, [false, true, false]);
+            assert_eq!(inspected, [(rank as f64, 0.4)]);
+            assert_eq!(cfl, 0.2);
+        }
+        assert!(!switch.is_requested());
+    }
+
+    #[test]
+    fn invalid_updates_fail_on_every_rank() {
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(|comm| {
+                thread::spawn(move || {
+                    let switch = PauseSwitch::new();
+                    switch.request();
+                    let mut parameters = Parameters::new().fixed("num_guard", 2);
+                    let result = pause_point(&comm, &switch, &[], &mut parameters, |_, _| {
+                        Parameters::new().mutable("num_guard", 3)
+                    });
+                    (result.is_err(), parameters.get_int("num_guard").unwrap())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (true, 2));
+        }
+    }
+
+    #[test]
+    fn steering_updates_from_a_file_reach_every_rank_once() {
+        let dir = std::env::temp_dir().join(format!("gridiron-steering-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("steer.txt");
+
+        let handles: Vec<_> = LocalCommunicator::group(3)
+            .into_iter()
+            .map(|comm| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let mut source = SteeringFile::new(&path);
+                    let mut parameters = Parameters::new().fixed("num_guard", 2).mutable("output_interval", 0.5);
+                    let mut applied = Vec::new();
+
+                    for step in 0..3 {
+                        if step == 1 && comm.rank() == 0 {
+                            fs::write(&path, "output_interval = 0.25\n").unwrap();
+                        }
+                        let steered = steering_point(&comm, &mut source, &mut parameters, |updates, parameters| {
+                            applied.push((updates.get_float("output_interval").unwrap(), parameters.get_int("num_guard").unwrap()))
+                        })
+                        .unwrap();
+                        assert_eq!(steered, step == 1);
+                    }
+                    (applied, parameters.get_float("output_interval").unwrap())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (vec![(0.25, 2)], 0.25));
+        }
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn steering_updates_can_come_from_a_channel() {
+        let comm = LocalCommunicator::group(1).pop().unwrap();
+        let (sender, mut receiver) = std::sync::mpsc::channel();
+        let mut parameters = Parameters::new().mutable("source_strength", 1.0);
+
+        sender.send(Parameters::new().mutable("source_strength", 2.0)).unwrap();
+        assert!(steering_point(&comm, &mut receiver, &mut parameters, |_, _| {}).unwrap());
+        assert!(!steering_point(&comm, &mut receiver, &mut parameters, |_, _| {}).unwrap());
+        assert_eq!(parameters.get_float("source_strength").unwrap(), 2.0);
+
+        sender.send(Parameters::new().mutable("num_guard", 3)).unwrap();
+        assert!(steering_point(&comm, &mut receiver, &mut parameters, |_, _| {}).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::{Error, Result};
+use crate::thread_pool::ThreadPool;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io;
+
+/// The wire format of an automaton message sent to another rank: the
+/// iteration it belongs to, the key of the task it's addressed to, and the
+/// message itself. Envelopes are encoded as CBOR, and are carried as the
+/// payload of any [`super::comm::Communicator`], so every transport and
+/// executor which routes task messages between ranks agrees on one layout.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<K, M> {
+    pub iteration: u64,
+    pub key: K,
+    pub payload: M,
+}
+
+impl<K, M> Envelope<K, M> {
+    /// Create an envelope for a message to the task with the given key.
+    ///
+    pub fn new(iteration: u64, key: K, payload: M) -> Self {
+        Self { iteration, key, payload }
+    }
+}
+
+impl<K: Serialize, M: Serialize> Envelope<K, M> {
+    /// Encode the envelope to bytes, to be sent with a communicator.
+    ///
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// Encode a batch of envelopes on the communication workers of the pool
+/// (see [`ThreadPool::spawn_comm`]), one job per envelope, so that the
+/// serialization does not hold up the compute workers. Returns the encoded
+/// bytes in the order the envelopes were given.
+///
+pub fn encode_on<K, M>(pool: &ThreadPool, envelopes: Vec<Envelope<K, M>>) -> Vec<Vec<u8>>
+where
+    K: Serialize + Send + 'static,
+    M: Serialize + Send + 'static,
+{
+    let num_envelopes = envelopes.len();
+    let (sink, source) = crossbeam_channel::unbounded();
+
+    for (n, envelope) in envelopes.into_iter().enumerate() {
+        let sink = sink.clone();
+        pool.spawn_comm(move || sink.send((n, envelope.encode())).unwrap());
+    }
+    drop(sink);
+
+    let mut encoded: Vec<_> = source.iter().collect();
+    assert_eq!(encoded.len(), num_envelopes, "an envelope failed to encode");
+    encoded.sort_by_key(|(n, _)| *n);
+    encoded.into_iter().map(|(_, bytes)| bytes).collect()
+}
+
+impl<K: DeserializeOwned, M: DeserializeOwned> Envelope<K, M> {
+    /// Decode an envelope received from a peer. A transport error is
+    /// returned if the bytes are not an envelope of the expected type.
+    ///
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        ciborium::de::from_reader(bytes).map_err(|e| Error::Transport {
+            peer: None,
+            source: io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{encode_on, Envelope};
+    use crate::thread_pool::ThreadPool;
+
+    #[test]
+    fn envelopes_survive_encoding() {
+        let envelope = Envelope::new(3, (0..4, 2..6), vec![1.0, 2.0]);
+        let bytes = envelope.encode();
+        assert_eq!(Envelope::decode(&bytes).unwrap(), envelope);
+        assert!(Envelope::<String, f64>::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn envelopes_are_encoded_in_order_on_comm_workers() {
+        let pool = ThreadPool::unpinned(1).with_comm_threads(2);
+        let envelopes: Vec<_> = (0..5).map(|n| Envelope::new(n, n as usize, vec![n as f64])).collect();
+        let expected: Vec<_> = envelopes.iter().map(Envelope::encode).collect();
+        assert_eq!(encode_on(&pool, envelopes), expected);
+        assert_eq!(pool.placement().jobs_per_worker, [0]);
+    }
+}
@@ -0,0 +1,55 @@
+use super::comm::Communicator;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::time::Duration;
+
+/// An in-process communicator, where each rank is expected to live on its
+/// own thread. Messages are passed over channels rather than a network
+/// transport, which makes this useful for tests and for running multi-rank
+/// configurations inside a single process.
+///
+pub struct LocalCommunicator {
+    rank: usize,
+    peers: Vec<Sender<Vec<u8>>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl LocalCommunicator {
+    /// Create a group of communicators which can exchange messages with one
+    /// another. The communicator at index `n` has rank `n`.
+    ///
+    pub fn group(size: usize) -> Vec<Self> {
+        let (peers, receivers): (Vec<_>, Vec<_>) = (0..size).map(|_| unbounded()).unzip();
+
+        receivers
+            .into_iter()
+            .enumerate()
+            .map(|(rank, receiver)| Self {
+                rank,
+                peers: peers.clone(),
+                receiver,
+            })
+            .collect()
+    }
+}
+
+impl Communicator for LocalCommunicator {
+    fn rank(&self) -> usize {
+        self.rank
+    }
+
+    fn size(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn send(&self, rank: usize, message: Vec<u8>) {
+        self.peers[rank].send(message).unwrap()
+    }
+
+    fn recv(&self) -> Vec<u8> {
+        self.receiver.recv().unwrap()
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Vec<u8>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
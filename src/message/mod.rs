@@ -1,10 +1,44 @@
 //! This module exports a minimal message-passing API, which is encapsulated
 //! by a `Communicator` trait. Implementors only need to write `send` and
 //! `recv` operations for a given transport layer (a pure-Rust TCP example is
-//! included). The trait then provides default implementations for broadcast,
-//! reduce, and reduce-all operations.
+//! included, as well as an in-process communicator for tests). The trait then
+//! provides default implementations for broadcast, reduce, reduce-all, and
+//! gather operations.
+//!
+//! Task messages sent between ranks are wrapped in an `Envelope` naming
+//! their iteration and recipient. Envelopes can be encoded on the
+//! communication workers of a thread pool, and a `MessageCache` can reuse
+//! the encoding of a payload which is sent unchanged on an edge every
+//! iteration.
+//!
+//! The `OrderedCommunicator` adapter tags messages with an iteration number,
+//! so that messages from peers which run ahead are held back until they are
+//! needed, and drops messages which are delivered twice or malformed. It
+//! also provides a barrier, which waits until every rank has finished an
+//! iteration. Before a checkpoint, it can confirm that no messages are in
+//! flight (quiesce), and a restarted run resumes it at the saved iteration.
+//! Given a `FanIn`, which counts the messages a rank expects on each edge of
+//! the task graph, it detects the end of an iteration, and names the missing
+//! edges if a message doesn't arrive in time.
+//!
+//! The `ReplayBuffer` keeps recently sent messages so the TCP communicator
+//! can re-deliver them to a peer which reconnects mid-run. Instead of using
+//! a static peer list, TCP communicators can also discover each other
+//! through a small rendezvous service.
+//!
+//! Runs can be paused between iterations for inspection, with the decision
+//! to pause coordinated from rank 0 by a `pause_point`. Similarly, a
+//! `steering_point` broadcasts parameter updates read on rank 0 from a file
+//! or a channel.
 //!
 
+pub mod backoff;
+pub mod cache;
 pub mod comm;
+pub mod control;
+pub mod envelope;
+pub mod local;
+pub mod ordered;
+pub mod replay;
 pub mod tcp;
 pub mod util;
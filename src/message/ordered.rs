@@ -0,0 +1,892 @@
+use super::comm::Communicator;
+use super::envelope::Envelope;
+use crate::adjacency_list::AdjacencyList;
+use crate::error::{Error, Result};
+use core::hash::Hash;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::fmt::Debug;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The number of past iterations for which an [`OrderedCommunicator`]
+/// remembers the messages it has received, to detect duplicates. A
+/// duplicate of a message older than this is treated as a stale message.
+pub const DEDUP_WINDOW: u64 = 4;
+
+/// A communicator adapter which delivers messages in iteration order.
+/// Outgoing messages are stamped with an iteration number, and `recv` only
+/// returns messages stamped with the current iteration. Messages
+/// which arrive early (from peers that have already moved on to a later
+/// iteration) are buffered until this rank reaches that iteration.
+///
+/// Messages are also stamped with the sender's rank and a sequence number,
+/// so that a message delivered twice (e.g. re-delivered by a transport
+/// after a reconnect) is detected and dropped, rather than being received
+/// twice by a task. Duplicates are recognized for messages from the current
+/// iteration, buffered iterations, and the last [`DEDUP_WINDOW`]
+/// iterations. Messages from before the iteration this rank started at,
+/// which a peer may re-deliver after this rank restarts, are dropped too.
+///
+/// The wrapped communicator is told the iteration as it advances (see
+/// [`Communicator::set_iteration`]), so a transport which re-delivers
+/// messages knows which ones this rank has finished with.
+///
+/// __Threading model__: `send` may be called concurrently from any number of
+/// threads. `recv` and `next_iteration` are expected to be called from a
+/// single receiving thread (the one driving the execution); calling `recv`
+/// concurrently from several threads is memory safe, but each message is
+/// delivered to only one of them. The buffer of early messages is guarded by
+/// a single lock, which is never held while blocking on the underlying
+/// transport, so a thread blocked in `recv` cannot stall senders or the
+/// iteration counter.
+///
+pub struct OrderedCommunicator<C: Communicator> {
+    comm: C,
+    start: u64,
+    iteration: AtomicU64,
+    buffer: Mutex<HashMap<u64, VecDeque<Received>>>,
+    sequence: Vec<AtomicU64>,
+    seen: Mutex<HashSet<Header>>,
+    num_duplicates: AtomicUsize,
+    num_malformed: AtomicUsize,
+    completions: Mutex<HashMap<u64, usize>>,
+}
+
+/// A message for the current iteration, and its stamp.
+type Received = (Header, Vec<u8>);
+
+/// The stamp on each message: the iteration, the sender's rank, a
+/// sequence number which is unique among the messages from the sender to
+/// the receiver, and the kind of message.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Header {
+    iteration: u64,
+    sender: usize,
+    sequence: u64,
+    kind: Kind,
+}
+
+/// Whether a message is from the application, or one of the control
+/// messages exchanged by the communicator itself. Control messages are
+/// recognized by their stamp, so any payload can be sent by the
+/// application.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    Message,
+    Barrier,
+    QuiesceMarker,
+    QuiesceCount,
+}
+
+impl Kind {
+    fn to_word(self) -> u64 {
+        match self {
+            Kind::Message => 0,
+            Kind::Barrier => 1,
+            Kind::QuiesceMarker => 2,
+            Kind::QuiesceCount => 3,
+        }
+    }
+
+    fn from_word(word: u64) -> Option<Self> {
+        match word {
+            0 => Some(Kind::Message),
+            1 => Some(Kind::Barrier),
+            2 => Some(Kind::QuiesceMarker),
+            3 => Some(Kind::QuiesceCount),
+            _ => None,
+        }
+    }
+}
+
+impl<C: Communicator> OrderedCommunicator<C> {
+    /// Wrap a communicator. The iteration number starts at zero.
+    ///
+    pub fn new(comm: C) -> Self {
+        Self::starting_at(comm, 0)
+    }
+
+    /// Wrap a communicator, starting at the given iteration number, e.g. the
+    /// iteration saved in a checkpoint by a restarted run. Every rank must
+    /// start at the same iteration.
+    ///
+    pub fn starting_at(comm: C, iteration: u64) -> Self {
+        comm.set_iteration(iteration);
+        Self {
+            sequence: (0..comm.size()).map(|_| AtomicU64::new(0)).collect(),
+            comm,
+            start: iteration,
+            iteration: AtomicU64::new(iteration),
+            buffer: Mutex::new(HashMap::new()),
+            seen: Mutex::new(HashSet::new()),
+            num_duplicates: AtomicUsize::new(0),
+            num_malformed: AtomicUsize::new(0),
+            completions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return the current iteration number.
+    ///
+    pub fn iteration(&self) -> u64 {
+        self.iteration.load(Ordering::SeqCst)
+    }
+
+    /// Advance to the next iteration, and return the new iteration number.
+    /// Messages for the new iteration which were buffered will be returned
+    /// by subsequent calls to `recv`.
+    ///
+    pub fn next_iteration(&self) -> u64 {
+        let iteration = self.iteration.fetch_add(1, Ordering::SeqCst) + 1;
+        self.comm.set_iteration(iteration);
+        self.seen
+            .lock()
+            .unwrap()
+            .retain(|header| header.iteration + DEDUP_WINDOW >= iteration);
+        iteration
+    }
+
+    /// Send a message stamped with the given iteration number. Executors
+    /// should prefer this method over `Communicator::send`, which reads the
+    /// current iteration number at the time of the call: if the driver
+    /// advances the iteration while messages for the previous step are still
+    /// being produced (e.g. on worker threads), those messages would be
+    /// stamped with the wrong iteration. Passing the iteration explicitly
+    /// makes message ordering independent of when the increment happens.
+    ///
+    pub fn send_at(&self, rank: usize, iteration: u64, message: Vec<u8>) {
+        self.send_kind(rank, iteration, Kind::Message, message)
+    }
+
+    /// Return the number of messages buffered for future iterations.
+    ///
+    pub fn num_buffered(&self) -> usize {
+        self.buffer.lock().unwrap().values().map(VecDeque::len).sum()
+    }
+
+    /// Return the number of duplicate messages which have been dropped.
+    ///
+    pub fn num_duplicates(&self) -> usize {
+        self.num_duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of messages which have been dropped because they
+    /// were too short to carry a stamp, or their stamp has an unknown kind.
+    ///
+    pub fn num_malformed(&self) -> usize {
+        self.num_malformed.load(Ordering::Relaxed)
+    }
+
+    /// Confirm that the group is quiet at an iteration boundary, so that its
+    /// state is fully described by the iteration number, e.g. before writing
+    /// a checkpoint. This is a collective operation: every rank must call it
+    /// after advancing to the same iteration, and before sending any
+    /// messages for that iteration. Returns the iteration number if no rank
+    /// holds stray messages (for this iteration or a later one), and a
+    /// transport error on every rank otherwise, since those messages would
+    /// be lost on restart.
+    ///
+    /// Every rank sends a marker to every other rank. Since the transport
+    /// preserves the order of messages between a pair of ranks, once a rank
+    /// has every peer's marker it has also received any stray message sent
+    /// to it before the markers. The ranks then exchange their counts of
+    /// stray messages, so they all reach the same verdict.
+    ///
+    pub fn quiesce(&self) -> Result<u64> {
+        let iteration = self.iteration();
+        let num_peers = self.size() - 1;
+        let peers = (0..self.size()).filter(|&rank| rank != self.rank());
+        let mut counts = Vec::new();
+        let mut num_markers = 0;
+        let mut num_strays = 0;
+
+        for peer in peers.clone() {
+            self.send_kind(peer, iteration, Kind::QuiesceMarker, Vec::new())
+        }
+        while num_markers < num_peers {
+            match self.next(iteration) {
+                (Header { kind: Kind::QuiesceMarker, .. }, _) => num_markers += 1,
+                (Header { kind: Kind::QuiesceCount, .. }, count) => counts.push(quiesce_count(&count)),
+                _ => num_strays += 1,
+            }
+        }
+        let local = num_strays + self.num_buffered() as u64;
+
+        for peer in peers {
+            self.send_kind(peer, iteration, Kind::QuiesceCount, local.to_le_bytes().to_vec())
+        }
+        while counts.len() < num_peers {
+            match self.next(iteration) {
+                (Header { kind: Kind::QuiesceCount, .. }, count) => counts.push(quiesce_count(&count)),
+                _ => panic!("received a message on iteration {} while quiescing", iteration),
+            }
+        }
+        let total = local + counts.iter().sum::<u64>();
+
+        if total != 0 {
+            return Err(Error::Transport {
+                peer: None,
+                source: io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} stray messages were found when quiescing on iteration {}", total, iteration),
+                ),
+            });
+        }
+        Ok(iteration)
+    }
+
+    /// Wait until every rank has finished the given iteration, e.g. before
+    /// writing output which must reflect the whole group's state. This is a
+    /// collective operation: every rank must call it on the given iteration,
+    /// after it has received all of its messages for that iteration, and
+    /// before advancing. Each rank sends a completion message to every other
+    /// rank, and returns once it has counted one from each of them.
+    /// Completion messages are counted per iteration as they arrive, and are
+    /// never returned by `recv`, so a rank which is still receiving its
+    /// messages for the iteration is not confused by peers which have
+    /// already reached the barrier. Messages that peers send for later
+    /// iterations in the meantime are buffered as usual.
+    ///
+    /// This method panics if the given iteration is not the current one, or
+    /// if a message other than a completion message is received for the
+    /// iteration, since that means some rank had not finished it.
+    ///
+    pub fn barrier(&self, iteration: u64) {
+        assert_eq! {
+            iteration,
+            self.iteration(),
+            "barrier for iteration {} called on iteration {}",
+            iteration,
+            self.iteration()
+        };
+        for peer in (0..self.size()).filter(|&rank| rank != self.rank()) {
+            self.send_kind(peer, iteration, Kind::Barrier, Vec::new())
+        }
+        while self.completions.lock().unwrap().get(&iteration).copied().unwrap_or(0) < self.size() - 1 {
+            assert! {
+                self.take_buffered(iteration).or_else(|| self.poll(iteration)).is_none(),
+                "received a message for iteration {} at its barrier",
+                iteration
+            };
+        }
+        self.completions.lock().unwrap().remove(&iteration);
+    }
+
+    /// Receive this rank's remote messages for the current iteration, as
+    /// counted by the given fan-in, and return them in the order they
+    /// arrived. Each message must be an [`Envelope`] addressed to a task
+    /// with a key of type `K`. The method returns as soon as the last
+    /// expected message is received, so the end of the iteration is detected
+    /// positively rather than by waiting for the transport to go quiet.
+    ///
+    /// If no expected message arrives within `timeout`, a transport error
+    /// with the `TimedOut` kind is returned, naming the iteration and the
+    /// edges whose messages are still missing; the peer is set if they are
+    /// all owned by one rank. A message which the fan-in does not expect
+    /// (from another rank or to another task, or one too many) is an error
+    /// as well, since it means the ranks disagree about the edge list.
+    ///
+    pub fn recv_expected<K>(&self, fan_in: &FanIn<K>, timeout: Duration) -> Result<Vec<Vec<u8>>>
+    where
+        K: DeserializeOwned + Hash + Eq + Clone + Debug,
+    {
+        let iteration = self.iteration();
+        let mut remaining = fan_in.counts();
+        let mut messages = Vec::with_capacity(fan_in.len());
+        let mut deadline = Instant::now() + timeout;
+
+        while messages.len() < fan_in.len() {
+            let (header, message) = match self.take_buffered(iteration) {
+                Some(message) => message,
+                None => match self.comm.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Some(bytes) => match self.accept(bytes, iteration) {
+                        Some(message) => message,
+                        None => continue,
+                    },
+                    None => return Err(fan_in.missing(iteration, &remaining, timeout)),
+                },
+            };
+            let key = Envelope::<K, IgnoredAny>::decode(&message)?.key;
+
+            match remaining.get_mut(&(header.sender, key.clone())) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => {
+                    return Err(Error::Transport {
+                        peer: Some(header.sender),
+                        source: io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unexpected message to {:?} on iteration {}", key, iteration),
+                        ),
+                    })
+                }
+            }
+            messages.push(message);
+            deadline = Instant::now() + timeout;
+        }
+        Ok(messages)
+    }
+
+    /// Return the underlying communicator. Any buffered messages are
+    /// dropped.
+    ///
+    pub fn into_inner(self) -> C {
+        self.comm
+    }
+
+    fn send_kind(&self, rank: usize, iteration: u64, kind: Kind, message: Vec<u8>) {
+        let header = Header {
+            iteration,
+            sender: self.comm.rank(),
+            sequence: self.sequence[rank].fetch_add(1, Ordering::Relaxed),
+            kind,
+        };
+        self.comm.send_at_iteration(rank, iteration, stamp(header, message))
+    }
+
+    /// Return the next message for the given iteration, buffered or from
+    /// the underlying communicator, with its stamp.
+    fn next(&self, iteration: u64) -> Received {
+        if let Some(received) = self.take_buffered(iteration) {
+            return received;
+        }
+        loop {
+            if let Some(received) = self.poll(iteration) {
+                return received;
+            }
+        }
+    }
+
+    /// Receive one message from the underlying communicator, and return it
+    /// with its stamp if it's for the given iteration.
+    fn poll(&self, iteration: u64) -> Option<Received> {
+        self.accept(self.comm.recv(), iteration)
+    }
+
+    /// Return a message received from the underlying communicator with its
+    /// stamp, if it's for the given iteration. Duplicates (and messages
+    /// from before the starting iteration) are dropped, completion messages
+    /// are counted, and messages for later iterations are buffered.
+    /// Malformed messages are dropped with a warning.
+    fn accept(&self, bytes: Vec<u8>, iteration: u64) -> Option<Received> {
+        let num_bytes = bytes.len();
+
+        let (header, message) = match unstamp(bytes) {
+            Some(stamped) => stamped,
+            None => {
+                eprintln!("warning: dropping a malformed message of {} bytes", num_bytes);
+                self.num_malformed.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+
+        if header.iteration < self.start || !self.seen.lock().unwrap().insert(header) {
+            self.num_duplicates.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        if header.kind == Kind::Barrier {
+            *self.completions.lock().unwrap().entry(header.iteration).or_default() += 1;
+            return None;
+        }
+        if header.iteration == iteration {
+            return Some((header, message));
+        }
+        assert! {
+            header.iteration > iteration,
+            "received a message for iteration {} on iteration {}",
+            header.iteration,
+            iteration
+        };
+        self.buffer
+            .lock()
+            .unwrap()
+            .entry(header.iteration)
+            .or_default()
+            .push_back((header, message));
+        None
+    }
+
+    fn take_buffered(&self, iteration: u64) -> Option<Received> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let queue = buffer.get_mut(&iteration)?;
+        let message = queue.pop_front();
+
+        if queue.is_empty() {
+            buffer.remove(&iteration);
+        }
+        message
+    }
+}
+
+impl<C: Communicator> Communicator for OrderedCommunicator<C> {
+    fn rank(&self) -> usize {
+        self.comm.rank()
+    }
+
+    fn size(&self) -> usize {
+        self.comm.size()
+    }
+
+    /// Send a message stamped with the current iteration number. See
+    /// `OrderedCommunicator::send_at` to stamp messages explicitly.
+    ///
+    fn send(&self, rank: usize, message: Vec<u8>) {
+        self.send_at(rank, self.iteration(), message)
+    }
+
+    fn send_at_iteration(&self, rank: usize, iteration: u64, message: Vec<u8>) {
+        self.send_at(rank, iteration, message)
+    }
+
+    /// Receive the next message for the current iteration. Messages for
+    /// later iterations are buffered, and duplicate messages are dropped.
+    /// This method panics if a message from an earlier iteration is received
+    /// which is not a duplicate, because that means either a peer or this
+    /// rank has advanced its iteration number incorrectly.
+    ///
+    fn recv(&self) -> Vec<u8> {
+        self.next(self.iteration()).1
+    }
+}
+
+/// The messages a rank expects from its peers on every iteration: one for
+/// each edge of the task graph whose target task is owned by this rank, and
+/// whose source task is owned by another rank. It's used with
+/// [`OrderedCommunicator::recv_expected`] to detect the end of an
+/// iteration, and to name the missing edges if it doesn't end.
+///
+#[derive(Clone, Debug)]
+pub struct FanIn<K> {
+    edges: Vec<(K, K, usize)>,
+}
+
+impl<K: Hash + Eq + Clone + Debug> FanIn<K> {
+    /// Find the remote edges into this rank's tasks. The router maps a
+    /// vertex of the edge list to the rank which owns its task, and the key
+    /// which messages to the task are addressed to.
+    ///
+    pub fn from_edges<V, R>(edges: &AdjacencyList<V>, rank: usize, router: R) -> Self
+    where
+        V: Hash + Eq + Clone,
+        R: Fn(&V) -> (usize, K),
+    {
+        let edges = edges
+            .edges()
+            .filter_map(|(a, b)| {
+                let (source_rank, source) = router(a);
+                let (target_rank, target) = router(b);
+                (target_rank == rank && source_rank != rank).then_some((source, target, source_rank))
+            })
+            .collect();
+        Self { edges }
+    }
+
+    /// Return the number of messages expected on each iteration.
+    ///
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Return true if no messages are expected from other ranks.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    /// Return the number of messages expected from the given rank on each
+    /// iteration.
+    ///
+    pub fn num_from(&self, rank: usize) -> usize {
+        self.edges.iter().filter(|(_, _, r)| *r == rank).count()
+    }
+
+    /// Return the remote edges, as (source key, target key, source rank).
+    ///
+    pub fn edges(&self) -> impl Iterator<Item = &(K, K, usize)> {
+        self.edges.iter()
+    }
+
+    /// Return the number of messages expected from each rank to each task.
+    fn counts(&self) -> HashMap<(usize, K), usize> {
+        let mut counts = HashMap::new();
+
+        for (_, target, rank) in &self.edges {
+            *counts.entry((*rank, target.clone())).or_default() += 1
+        }
+        counts
+    }
+
+    /// Return the timeout error for an iteration on which the given counts
+    /// of messages were still missing. Messages carry only their target
+    /// key, so when several edges from one rank lead to the same task, they
+    /// are all named as candidates.
+    fn missing(&self, iteration: u64, remaining: &HashMap<(usize, K), usize>, timeout: Duration) -> Error {
+        let mut ranks: Vec<_> = remaining.iter().filter(|(_, &n)| n > 0).map(|((rank, _), _)| *rank).collect();
+        ranks.sort_unstable();
+        ranks.dedup();
+
+        let mut descriptions: Vec<_> = self
+            .counts()
+            .into_iter()
+            .filter(|(pair, _)| remaining[pair] > 0)
+            .map(|((rank, target), total)| {
+                let sources: Vec<_> = self
+                    .edges
+                    .iter()
+                    .filter(|(_, t, r)| *r == rank && *t == target)
+                    .map(|(s, _, _)| s)
+                    .collect();
+                format!(
+                    "{} of {} messages to {:?} from rank {} (edges from {:?})",
+                    remaining[&(rank, target.clone())],
+                    total,
+                    target,
+                    rank,
+                    sources
+                )
+            })
+            .collect();
+        descriptions.sort();
+
+        Error::Transport {
+            peer: if ranks.len() == 1 { Some(ranks[0]) } else { None },
+            source: io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "no message for {:?} on iteration {}; missing {}",
+                    timeout,
+                    iteration,
+                    descriptions.join(", ")
+                ),
+            ),
+        }
+    }
+}
+
+const HEADER_SIZE: usize = 32;
+
+/// Decode the count of stray messages sent by a peer which is quiescing.
+fn quiesce_count(message: &[u8]) -> u64 {
+    u64::from_le_bytes(message.try_into().expect("malformed quiesce count"))
+}
+
+fn stamp(header: Header, message: Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(message.len() + HEADER_SIZE);
+    bytes.extend_from_slice(&header.iteration.to_le_bytes());
+    bytes.extend_from_slice(&(header.sender as u64).to_le_bytes());
+    bytes.extend_from_slice(&header.sequence.to_le_bytes());
+    bytes.extend_from_slice(&header.kind.to_word().to_le_bytes());
+    bytes.extend(message);
+    bytes
+}
+
+/// Split a message into its stamp and payload, or return `None` if it's too
+/// short to carry a stamp, or the stamp has an unknown kind.
+fn unstamp(mut bytes: Vec<u8>) -> Option<(Header, Vec<u8>)> {
+    if bytes.len() < HEADER_SIZE {
+        return None;
+    }
+    let word = |n: usize| u64::from_le_bytes(bytes[n * 8..(n + 1) * 8].try_into().unwrap());
+    let header = Header {
+        iteration: word(0),
+        sender: word(1) as usize,
+        sequence: word(2),
+        kind: Kind::from_word(word(3))?,
+    };
+    bytes.drain(..HEADER_SIZE);
+    Some((header, bytes))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{stamp, FanIn, Header, Kind, OrderedCommunicator};
+    use crate::adjacency_list::AdjacencyList;
+    use crate::error::Error;
+    use crate::message::comm::Communicator;
+    use crate::message::envelope::Envelope;
+    use crate::message::local::LocalCommunicator;
+    use std::thread;
+    use std::time::Duration;
+
+    fn pair() -> (OrderedCommunicator<LocalCommunicator>, OrderedCommunicator<LocalCommunicator>) {
+        let mut group = LocalCommunicator::group(2).into_iter().map(OrderedCommunicator::new);
+        (group.next().unwrap(), group.next().unwrap())
+    }
+
+    #[test]
+    fn future_iteration_messages_are_buffered() {
+        let (c0, c1) = pair();
+
+        c1.next_iteration();
+        c1.send(0, vec![1]);
+        c1.next_iteration();
+        c1.send(0, vec![2]);
+        c1.send(0, vec![3]);
+
+        assert_eq!(c0.iteration(), 0);
+        c0.send_at(0, 0, vec![0]);
+        assert_eq!(c0.recv(), vec![0]);
+        assert_eq!(c0.num_buffered(), 3);
+
+        c0.next_iteration();
+        assert_eq!(c0.recv(), vec![1]);
+
+        c0.next_iteration();
+        assert_eq!(c0.recv(), vec![2]);
+        assert_eq!(c0.recv(), vec![3]);
+        assert_eq!(c0.num_buffered(), 0);
+    }
+
+    #[test]
+    fn buffered_messages_keep_their_order() {
+        let (c0, c1) = pair();
+
+        c1.next_iteration();
+        for n in 0..10 {
+            c1.send(0, vec![n]);
+        }
+        c1.send_at(0, 0, vec![100]);
+
+        assert_eq!(c0.recv(), vec![100]);
+        assert_eq!(c0.num_buffered(), 10);
+
+        c0.next_iteration();
+        assert_eq!((0..10).map(|_| c0.recv()[0]).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn explicitly_tagged_messages_ignore_the_counter() {
+        let (c0, c1) = pair();
+
+        // Rank 1 has advanced to iteration 1, but is still sending the
+        // messages it produced on iteration 0.
+        c1.next_iteration();
+        c1.send_at(0, 0, vec![0]);
+        c1.send_at(0, 1, vec![1]);
+
+        assert_eq!(c0.recv(), vec![0]);
+        c0.next_iteration();
+        assert_eq!(c0.recv(), vec![1]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn stale_messages_are_rejected() {
+        let (c0, c1) = pair();
+        c1.send(0, vec![0]);
+        c0.next_iteration();
+        c0.recv();
+    }
+
+    #[test]
+    fn duplicate_deliveries_are_dropped() {
+        let (c0, _c1) = pair();
+        let message = |iteration, sequence, n| {
+            let header = Header {
+                iteration,
+                sender: 1,
+                sequence,
+                kind: Kind::Message,
+            };
+            stamp(header, vec![n])
+        };
+
+        for (iteration, sequence, n) in [(0, 0, 0), (0, 0, 0), (0, 1, 1), (1, 2, 2), (1, 2, 2)] {
+            c0.comm.send(0, message(iteration, sequence, n));
+        }
+        assert_eq!(c0.recv(), vec![0]);
+        assert_eq!(c0.recv(), vec![1]);
+        c0.next_iteration();
+        assert_eq!(c0.recv(), vec![2]);
+        assert_eq!(c0.num_duplicates(), 1);
+
+        // A late re-delivery of a message from an earlier iteration is also
+        // recognized as a duplicate, rather than rejected as stale.
+        c0.comm.send(0, message(0, 0, 0));
+        c0.comm.send(0, message(1, 3, 3));
+        assert_eq!(c0.recv(), vec![3]);
+        assert_eq!(c0.num_duplicates(), 3);
+    }
+
+    #[test]
+    fn malformed_messages_are_dropped() {
+        let (c0, _c1) = pair();
+        let header = Header {
+            iteration: 0,
+            sender: 1,
+            sequence: 0,
+            kind: Kind::Message,
+        };
+        let mut unknown_kind = stamp(header, vec![5]);
+        unknown_kind[24] = 9;
+
+        c0.comm.send(0, vec![1, 2, 3]);
+        c0.comm.send(0, unknown_kind);
+        c0.comm.send(0, stamp(header, vec![4]));
+        assert_eq!(c0.recv(), vec![4]);
+        assert_eq!(c0.num_malformed(), 2);
+    }
+
+    #[test]
+    fn barriers_wait_for_every_rank_to_finish_the_iteration() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = LocalCommunicator::group(4)
+            .into_iter()
+            .map(OrderedCommunicator::new)
+            .map(|comm| {
+                let finished = finished.clone();
+                thread::spawn(move || {
+                    let peer = (comm.rank() + 1) % comm.size();
+                    thread::sleep(std::time::Duration::from_millis(5 * comm.rank() as u64));
+                    comm.send(peer, vec![0]);
+                    comm.recv();
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    comm.barrier(0);
+                    let count = finished.load(Ordering::SeqCst);
+
+                    // Messages sent right after the barrier are buffered by
+                    // ranks which are still waiting in it.
+                    comm.next_iteration();
+                    comm.send(peer, vec![1]);
+                    (count, comm.recv())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (4, vec![1]));
+        }
+    }
+
+    #[test]
+    fn application_messages_are_never_taken_for_barriers() {
+        let (c0, c1) = pair();
+        c1.send(0, b"gridiron.barrier".to_vec());
+        c1.send(0, Vec::new());
+        assert_eq!(c0.recv(), b"gridiron.barrier");
+        assert_eq!(c0.recv(), b"");
+    }
+
+    #[test]
+    #[should_panic]
+    fn barriers_are_only_for_the_current_iteration() {
+        let (c0, _c1) = pair();
+        c0.barrier(1);
+    }
+
+    /// The fan-in of rank 0, for tasks 0 to 3 where the odd tasks live on
+    /// rank 1. Tasks 1 and 3 send to task 0, task 3 to task 2, and task 2
+    /// (which is local) to task 0.
+    fn fan_in() -> FanIn<usize> {
+        let mut edges = AdjacencyList::new();
+        edges.insert(1, 0);
+        edges.insert(3, 0);
+        edges.insert(3, 2);
+        edges.insert(2, 0);
+        FanIn::from_edges(&edges, 0, |&key: &usize| (key % 2, key))
+    }
+
+    #[test]
+    fn expected_messages_end_the_iteration() {
+        let (c0, c1) = pair();
+        let fan_in = fan_in();
+
+        for (iteration, key) in [(0, 2), (1, 0), (0, 0), (0, 0)] {
+            c1.send_at(0, iteration, Envelope::new(iteration, key, ()).encode());
+        }
+        let messages = c0.recv_expected(&fan_in, Duration::from_secs(10)).unwrap();
+        let keys: Vec<_> = messages.iter().map(|m| Envelope::<usize, ()>::decode(m).unwrap().key).collect();
+
+        assert_eq!((fan_in.len(), fan_in.num_from(1)), (3, 3));
+        assert_eq!(keys, [2, 0, 0]);
+        assert_eq!(c0.num_buffered(), 1);
+    }
+
+    #[test]
+    fn missing_messages_time_out_naming_their_edges() {
+        let (c0, c1) = pair();
+        c1.send(0, Envelope::new(0, 0, ()).encode());
+        c1.send(0, Envelope::new(0, 0, ()).encode());
+
+        match c0.recv_expected(&fan_in(), Duration::from_millis(20)) {
+            Err(Error::Transport { peer: Some(1), source }) => {
+                assert_eq!(source.kind(), std::io::ErrorKind::TimedOut);
+                assert!(source.to_string().contains("1 of 1 messages to 2 from rank 1 (edges from [3])"));
+            }
+            _ => panic!("expected a timeout"),
+        }
+        c1.next_iteration();
+        c1.send(0, Envelope::new(1, 1, ()).encode());
+        c0.next_iteration();
+        assert!(c0.recv_expected(&fan_in(), Duration::from_millis(20)).is_err());
+    }
+
+    #[test]
+    fn quiet_groups_resume_at_the_saved_iteration() {
+        let handles: Vec<_> = LocalCommunicator::group(3)
+            .into_iter()
+            .map(|comm| {
+                thread::spawn(move || {
+                    let comm = OrderedCommunicator::starting_at(comm, 7);
+                    let peer = (comm.rank() + 1) % comm.size();
+                    comm.send(peer, vec![comm.rank() as u8]);
+                    comm.recv();
+                    comm.next_iteration();
+                    comm.quiesce().unwrap()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 8);
+        }
+    }
+
+    #[test]
+    fn application_messages_are_never_taken_for_quiesce_markers() {
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(OrderedCommunicator::new)
+            .map(|comm| {
+                thread::spawn(move || {
+                    if comm.rank() == 1 {
+                        comm.send(0, b"gridiron.quiesce\0".to_vec());
+                    }
+                    comm.quiesce().is_err()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn groups_with_buffered_messages_are_not_quiet() {
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(OrderedCommunicator::new)
+            .map(|comm| {
+                thread::spawn(move || {
+                    if comm.rank() == 1 {
+                        comm.send_at(0, 1, vec![1]);
+                    }
+                    comm.quiesce().is_err()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+}
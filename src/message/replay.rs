@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+/// A bounded buffer of sent messages, each tagged with the iteration it was
+/// sent in, which can be re-delivered to a peer that lost them (e.g.
+/// because it restarted). Messages are kept until the peer acknowledges
+/// their iteration. If the buffer is full, the oldest message is evicted,
+/// and can no longer be replayed; [`ReplayBuffer::can_replay_from`] tells
+/// whether a peer which lost its messages can still be caught up.
+///
+pub struct ReplayBuffer {
+    capacity: usize,
+    entries: VecDeque<(u64, Vec<u8>)>,
+    latest_evicted: Option<u64>,
+}
+
+impl ReplayBuffer {
+    /// Create an empty buffer which holds at most `capacity` messages.
+    ///
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+            latest_evicted: None,
+        }
+    }
+
+    /// Add a message sent in the given iteration.
+    ///
+    pub fn push(&mut self, iteration: u64, message: Vec<u8>) {
+        self.entries.push_back((iteration, message));
+
+        if self.entries.len() > self.capacity {
+            let (evicted, _) = self.entries.pop_front().unwrap();
+            self.latest_evicted = self.latest_evicted.max(Some(evicted))
+        }
+    }
+
+    /// Discard the messages sent in the given iteration and any earlier
+    /// ones: the peer has confirmed it does not need them re-delivered.
+    ///
+    pub fn acknowledge(&mut self, iteration: u64) {
+        self.entries.retain(|(i, _)| *i > iteration)
+    }
+
+    /// Return an iterator over the unacknowledged messages, oldest first.
+    ///
+    pub fn pending(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.entries.iter().map(|(i, m)| (*i, m.as_slice()))
+    }
+
+    /// Return the number of unacknowledged messages held in the buffer.
+    ///
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Determine whether the buffer is empty.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Determine whether every message a peer needs, once it has reported
+    /// the given iteration, is still in the buffer: none of the evicted
+    /// messages were sent in that iteration or a later one.
+    ///
+    pub fn can_replay_from(&self, iteration: u64) -> bool {
+        !matches!(self.latest_evicted, Some(evicted) if evicted >= iteration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::ReplayBuffer;
+
+    #[test]
+    fn replay_buffer_is_bounded_and_acknowledged_by_iteration() {
+        let mut buffer = ReplayBuffer::new(3);
+        buffer.push(0, vec![0]);
+        buffer.push(1, vec![1]);
+        buffer.push(1, vec![2]);
+        buffer.push(2, vec![3]);
+
+        assert_eq!(buffer.len(), 3);
+        assert!(buffer.can_replay_from(1));
+        assert!(!buffer.can_replay_from(0));
+
+        buffer.acknowledge(1);
+        let pending: Vec<_> = buffer.pending().map(|(i, m)| (i, m.to_vec())).collect();
+        assert_eq!(pending, [(2, vec![3])]);
+    }
+}
@@ -1,70 +1,792 @@
+use super::backoff::ExponentialBackoff;
 use super::comm::Communicator;
+use super::replay::ReplayBuffer;
 use super::util;
+use crate::clock::SystemClock;
+use crate::error::{Error, Result};
+use crate::stats::Metrics;
 use std::io::prelude::*;
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::sync::mpsc;
+use std::convert::TryInto;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-type Sender = mpsc::Sender<(usize, Vec<u8>)>;
-type Receiver = mpsc::Receiver<(usize, Vec<u8>)>;
+/// How long a receiver thread blocks reading from its connection before
+/// checking for a change of iteration to report, or a request to stop.
+const REPORT_INTERVAL: Duration = Duration::from_millis(10);
 
+/// How long the listener waits before accepting again after an error, such
+/// as running out of file descriptors.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The number of unacknowledged messages kept for replay to each peer.
+pub const REPLAY_CAPACITY: usize = 1024;
+
+/// The number of times a sender tries to re-establish a broken connection
+/// before giving up.
+pub const RECONNECT_ATTEMPTS: u32 = 10;
+
+/// The delay before the first reconnect attempt. The delay doubles with
+/// each subsequent attempt.
+const RECONNECT_DELAY: Duration = Duration::from_millis(10);
+
+/// How long a sender waits for a peer to report its iteration after
+/// connecting.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The word a rank reports to its peers in place of an iteration when it
+/// shuts down.
+const CLOSED: u64 = u64::MAX;
+
+/// A communicator which exchanges length-prefixed messages over TCP. Each
+/// rank holds one outgoing connection to each peer it has sent to, which is
+/// opened on the first send and re-used afterwards, so messages from one
+/// rank to another arrive in the order they were sent.
+///
+/// Outgoing messages are put on a per-peer queue, each drained by its own
+/// sender thread, so a slow link to one peer does not delay messages to the
+/// others. Sender threads are started on the first send to each peer. The
+/// number of messages waiting on each queue is available from
+/// [`TcpCommunicator::backlog`].
+///
+/// Each incoming connection is serviced by its own receiver thread, which
+/// blocks reading from the socket, and forwards each complete message to
+/// [`Communicator::recv`]. A connection which fails is dropped, without
+/// affecting the others, and the peer reconnects when it next sends.
+///
+/// To survive a transient restart of a peer, each send queue keeps a
+/// [`ReplayBuffer`] of the messages it has sent, tagged with the iteration
+/// they belong to: the one given to [`Communicator::send_at_iteration`], or
+/// else the one set by [`Communicator::set_iteration`].
+/// [`OrderedCommunicator`](super::ordered::OrderedCommunicator) does both
+/// for the messages it stamps. Each rank reports its iteration to the ranks connected to it,
+/// when they connect and whenever it changes. A rank at iteration `i` has
+/// received every message from earlier iterations, so its peers release the
+/// messages tagged before `i` from their replay buffers. When a connection
+/// breaks, the sender reconnects (retrying with exponential backoff), waits
+/// for the peer to report its iteration, and re-delivers the messages from
+/// that iteration on, in the original order. A broken connection is noticed
+/// the next time a message is sent to the peer. A rank which shuts down
+/// says so to its peers, so they only reconnect if they send to it again.
+///
+/// If a peer can't be reached, or it reconnects needing messages which were
+/// evicted from a full replay buffer, the thread sending to it stops, and
+/// messages sent to the peer are dropped. The error is returned by
+/// [`TcpCommunicator::check`] or [`TcpCommunicator::close`].
+///
 pub struct TcpCommunicator {
     rank: usize,
-    num_peers: usize,
-    listener: TcpListener,
-    send_sink: Option<mpsc::Sender<(usize, Vec<u8>)>>,
-    send_thread: Option<thread::JoinHandle<()>>,
+    peers: Vec<SocketAddr>,
+    address: SocketAddr,
+    iteration: Arc<AtomicU64>,
+    recv_source: mpsc::Receiver<Vec<u8>>,
+    send_queues: Mutex<Vec<Option<SendQueue>>>,
+    recv_thread: Option<thread::JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
 }
 
-impl TcpCommunicator {
-    pub fn new(rank: usize, peers: Vec<SocketAddr>) -> Self {
-        let listener = TcpListener::bind(peers[rank]).unwrap();
-        let num_peers = peers.len();
-        let (send_sink, recv_sink): (Sender, Receiver) = mpsc::channel();
-        let send_thread = thread::spawn(move || {
-            for (rank, message) in recv_sink {
-                let mut stream = TcpStream::connect(peers[rank]).unwrap();
-                stream.write_all(&message.len().to_le_bytes()).unwrap();
-                stream.write_all(&message).unwrap();
+/// An outgoing message queue to one peer, and the thread which drains it.
+///
+struct SendQueue {
+    sink: mpsc::Sender<(u64, Vec<u8>)>,
+    thread: thread::JoinHandle<io::Result<()>>,
+    backlog: Arc<AtomicUsize>,
+}
+
+impl SendQueue {
+    fn new(address: SocketAddr) -> Self {
+        let (sink, source) = mpsc::channel::<(u64, Vec<u8>)>();
+        let backlog = Arc::new(AtomicUsize::new(0));
+        let thread_backlog = backlog.clone();
+        let thread = thread::spawn(move || {
+            let mut replay = ReplayBuffer::new(REPLAY_CAPACITY);
+            let mut link = Link::open(address, &mut replay)?;
+
+            for (iteration, message) in source {
+                link.read_reports(&mut replay);
+
+                let written = !link.closed && write_message(&mut link.stream, &message).is_ok();
+                replay.push(iteration, message);
+
+                if !written {
+                    link = Link::open(address, &mut replay)?
+                }
+                thread_backlog.fetch_sub(1, Ordering::Relaxed);
             }
+            Ok(())
         });
+        Self {
+            sink,
+            thread,
+            backlog,
+        }
+    }
+
+    fn push(&self, iteration: u64, message: Vec<u8>) {
+        self.backlog.fetch_add(1, Ordering::Relaxed);
+
+        if self.sink.send((iteration, message)).is_err() {
+            self.backlog.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Determine whether the sender thread has stopped, which it only does
+    /// before the queue is closed if the peer can't be reached.
+    fn has_failed(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    fn close(self) -> io::Result<()> {
+        drop(self.sink);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the sender thread panicked")))
+    }
+}
+
+/// A connection to a peer, and the iterations the peer has reported on it
+/// which have not been read in full.
+///
+struct Link {
+    stream: TcpStream,
+    reports: Vec<u8>,
+    closed: bool,
+}
+
+impl Link {
+    /// Connect to a peer, wait for it to report its iteration, release the
+    /// messages from earlier iterations, and re-deliver the rest of the
+    /// replay buffer. If the new connection also breaks, this is retried up
+    /// to [`RECONNECT_ATTEMPTS`] times, and the error from the last attempt
+    /// is returned. An error connecting is returned straight away, since
+    /// [`try_connect`] has already retried it, and so is an error saying
+    /// the peer needs messages which were evicted from the replay buffer,
+    /// since reconnecting can't recover them.
+    ///
+    fn open(address: SocketAddr, replay: &mut ReplayBuffer) -> io::Result<Self> {
+        let mut result = Self::handshake(try_connect(address)?, replay);
+
+        for _ in 1..RECONNECT_ATTEMPTS {
+            if !matches!(&result, Err(e) if e.kind() != ErrorKind::Other) {
+                break;
+            }
+            result = Self::handshake(try_connect(address)?, replay)
+        }
+        result
+    }
+
+    fn handshake(mut stream: TcpStream, replay: &mut ReplayBuffer) -> io::Result<Self> {
+        let mut word = [0; 8];
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        stream.read_exact(&mut word)?;
+        stream.set_read_timeout(None)?;
+
+        let reported = u64::from_le_bytes(word);
+
+        if reported != CLOSED && !replay.can_replay_from(reported) {
+            return Err(io::Error::other(format!(
+                "the peer needs messages from iteration {}, some of which were evicted from the replay buffer",
+                reported
+            )));
+        }
+        let mut link = Self {
+            stream,
+            reports: word.to_vec(),
+            closed: false,
+        };
+        link.release(replay);
+
+        if link.closed {
+            return Err(ErrorKind::ConnectionAborted.into());
+        }
+        for (_, message) in replay.pending() {
+            write_message(&mut link.stream, message)?
+        }
+        Ok(link)
+    }
+
+    /// Read the iterations the peer has reported since the last call,
+    /// without blocking, and release the messages it no longer needs from
+    /// the replay buffer. The link is marked closed if the peer has shut
+    /// down, or the connection is gone.
+    ///
+    fn read_reports(&mut self, replay: &mut ReplayBuffer) {
+        if self.closed {
+            return;
+        }
+        let mut chunk = [0; 64];
+
+        if self.stream.set_nonblocking(true).is_err() {
+            self.closed = true;
+            return;
+        }
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(n) => self.reports.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        if self.stream.set_nonblocking(false).is_err() {
+            self.closed = true
+        }
+        self.release(replay)
+    }
+
+    /// Apply the complete reports read so far: release the messages from
+    /// before each reported iteration, or mark the link closed.
+    fn release(&mut self, replay: &mut ReplayBuffer) {
+        let num_words = self.reports.len() / 8;
+
+        for word in self.reports.chunks_exact(8) {
+            match u64::from_le_bytes(word.try_into().unwrap()) {
+                CLOSED => self.closed = true,
+                0 => {}
+                iteration => replay.acknowledge(iteration - 1),
+            }
+        }
+        self.reports.drain(..num_words * 8);
+    }
+}
+
+fn write_message(stream: &mut TcpStream, message: &[u8]) -> std::io::Result<()> {
+    stream.write_all(&message.len().to_le_bytes())?;
+    stream.write_all(message)
+}
+
+fn read_message(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut size = [0; std::mem::size_of::<usize>()];
+    stream.read_exact(&mut size)?;
+    let mut message = vec![0; usize::from_le_bytes(size)];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// Connect to a peer, retrying with exponential backoff. Returns the error
+/// from the last attempt if the peer cannot be reached after
+/// [`RECONNECT_ATTEMPTS`] tries.
+///
+fn try_connect(address: SocketAddr) -> std::io::Result<TcpStream> {
+    ExponentialBackoff::new(RECONNECT_DELAY, RECONNECT_ATTEMPTS).retry(&SystemClock, || TcpStream::connect(address))
+}
+
+impl TcpCommunicator {
+    /// Create a communicator for the given rank, listening on the address
+    /// of that rank in the list of peers. This function returns a transport
+    /// error if the listener cannot be bound.
+    ///
+    pub fn new(rank: usize, peers: Vec<SocketAddr>) -> Result<Self> {
+        let listener = TcpListener::bind(peers[rank]).map_err(|source| Error::Transport { peer: None, source })?;
+        Ok(Self::from_listener(rank, peers, listener))
+    }
+
+    /// Create a communicator without knowing the peer addresses up front.
+    /// This rank listens on the given address (which may have port 0, to
+    /// let the system choose one), registers that address with the
+    /// rendezvous service at `registry` (see [`serve_rendezvous`]), and
+    /// waits for the service to return this rank's number and the address
+    /// table of the whole group. The listening address must be reachable by
+    /// the other ranks, so it should not be a wildcard address. This
+    /// function returns a transport error if the listener cannot be bound,
+    /// or the exchange with the service fails.
+    ///
+    pub fn rendezvous(registry: SocketAddr, listen: SocketAddr) -> Result<Self> {
+        let transport = |source| Error::Transport { peer: None, source };
+        let listener = TcpListener::bind(listen).map_err(transport)?;
+        let address = listener.local_addr().map_err(transport)?;
+
+        let mut stream = try_connect(registry).map_err(transport)?;
+        let mut request = Vec::new();
+        ciborium::ser::into_writer(&address, &mut request).unwrap();
+        write_message(&mut stream, &request).map_err(transport)?;
+
+        let reply = read_message(&mut stream).map_err(transport)?;
+        let (rank, peers): (usize, Vec<SocketAddr>) =
+            ciborium::de::from_reader(&reply[..]).map_err(|e| transport(invalid_data(e)))?;
+
+        Ok(Self::from_listener(rank, peers, listener))
+    }
+
+    fn from_listener(rank: usize, peers: Vec<SocketAddr>, listener: TcpListener) -> Self {
+        let (recv_sink, recv_source) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let send_queues = Mutex::new((0..peers.len()).map(|_| None).collect());
+
+        let iteration = Arc::new(AtomicU64::new(0));
+
+        let recv_stop = stop.clone();
+        let recv_iteration = iteration.clone();
+        let address = listener.local_addr().unwrap_or(peers[rank]);
+        let recv_thread = thread::spawn(move || accept_incoming(listener, recv_sink, recv_iteration, recv_stop));
+
         Self {
             rank,
-            num_peers,
-            listener,
-            send_sink: Some(send_sink),
-            send_thread: Some(send_thread),
+            peers,
+            address,
+            iteration,
+            recv_source,
+            send_queues,
+            recv_thread: Some(recv_thread),
+            stop,
+        }
+    }
+
+    /// Return an error if a peer could not be reached, after retrying, so
+    /// that messages sent to it were dropped. The failed send queue is
+    /// discarded, so a later send to that peer starts over with a new
+    /// connection.
+    ///
+    pub fn check(&self) -> Result<()> {
+        for (rank, queue) in self.send_queues.lock().unwrap().iter_mut().enumerate() {
+            if matches!(queue, Some(queue) if queue.has_failed()) {
+                let queue = queue.take().unwrap();
+                queue.close().map_err(|source| Error::Transport { peer: Some(rank), source })?
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the queued messages, close the connections, and stop the
+    /// receiver thread. This returns the first error from sending to any of
+    /// the peers. Dropping the communicator does the same, but ignores the
+    /// errors.
+    ///
+    pub fn close(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        let mut result = Ok(());
+
+        for (rank, queue) in self.send_queues.get_mut().unwrap().iter_mut().enumerate() {
+            if let Some(queue) = queue.take() {
+                result = result.and(queue.close().map_err(|source| Error::Transport { peer: Some(rank), source }))
+            }
+        }
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.recv_thread.take() {
+            // Wake the listener, which is blocked waiting for a connection.
+            TcpStream::connect(connectable(self.address)).ok();
+
+            if thread.join().is_err() {
+                let source = io::Error::other("the receiver thread panicked");
+                result = result.and(Err(Error::Transport { peer: None, source }))
+            }
+        }
+        result
+    }
+
+    /// Return the number of messages queued for the given peer which have
+    /// not yet been written to its socket.
+    ///
+    pub fn backlog(&self, rank: usize) -> usize {
+        self.send_queues.lock().unwrap()[rank]
+            .as_ref()
+            .map_or(0, |queue| queue.backlog.load(Ordering::Relaxed))
+    }
+
+    /// Record the current backlog of each peer which has been sent to, as
+    /// the metric `tcp.backlog.<rank>`.
+    ///
+    pub fn record_backlog(&self, metrics: &Metrics) {
+        for (rank, queue) in self.send_queues.lock().unwrap().iter().enumerate() {
+            if let Some(queue) = queue {
+                let backlog = queue.backlog.load(Ordering::Relaxed);
+                metrics.record(&format!("tcp.backlog.{}", rank), backlog as f64)
+            }
         }
     }
 }
 
+/// Run a rendezvous service for a group of `num_ranks` communicators created
+/// with [`TcpCommunicator::rendezvous`]. The service accepts one
+/// registration from each rank, assigns ranks in the order the
+/// registrations arrive, and then sends every rank its number and the table
+/// of all the addresses. It returns once the table has been sent to every
+/// rank. The service can be run on its own, or on a thread of one of the
+/// peers.
+///
+pub fn serve_rendezvous(listener: TcpListener, num_ranks: usize) -> Result<()> {
+    let transport = |source| Error::Transport { peer: None, source };
+    let mut streams = Vec::with_capacity(num_ranks);
+    let mut peers = Vec::with_capacity(num_ranks);
+
+    while streams.len() < num_ranks {
+        let (mut stream, _) = listener.accept().map_err(transport)?;
+        let request = read_message(&mut stream).map_err(transport)?;
+        let address: SocketAddr = ciborium::de::from_reader(&request[..]).map_err(|e| transport(invalid_data(e)))?;
+        peers.push(address);
+        streams.push(stream);
+    }
+    for (rank, mut stream) in streams.into_iter().enumerate() {
+        let mut reply = Vec::new();
+        ciborium::ser::into_writer(&(rank, &peers), &mut reply).unwrap();
+        write_message(&mut stream, &reply).map_err(|source| Error::Transport { peer: Some(rank), source })?;
+    }
+    Ok(())
+}
+
+fn invalid_data<E: std::fmt::Debug>(error: E) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, format!("{:?}", error))
+}
+
+/// An accepted connection, the bytes read from it which do not yet form
+/// a complete message, and the reports of this rank's iteration to the
+/// sender which have not been written in full.
+///
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    reports: Vec<u8>,
+    reported: Option<u64>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_read_timeout(Some(REPORT_INTERVAL))?;
+        stream.set_write_timeout(Some(REPORT_INTERVAL))?;
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            reports: Vec::new(),
+            reported: None,
+        })
+    }
+
+    /// Read the bytes which arrive within the report interval. Returns
+    /// false if the peer has closed the connection, or it has failed.
+    fn fill(&mut self) -> bool {
+        let mut chunk = [0; 8192];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => false,
+            Ok(n) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                true
+            }
+            Err(e) => matches!(
+                e.kind(),
+                ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+            ),
+        }
+    }
+
+    /// Report the given iteration to the sender, if it hasn't been already.
+    /// A report which can't be written within the report interval is
+    /// retried on the next call.
+    fn report(&mut self, iteration: u64) {
+        if self.reports.is_empty() && self.reported != Some(iteration) {
+            self.reports.extend_from_slice(&iteration.to_le_bytes());
+            self.reported = Some(iteration)
+        }
+        if !self.reports.is_empty() {
+            if let Ok(n) = self.stream.write(&self.reports) {
+                self.reports.drain(..n);
+            }
+        }
+    }
+
+    /// Tell the sender that this rank is shutting down, if the report can
+    /// be written within the report interval.
+    fn report_closed(&mut self) {
+        self.reports.extend_from_slice(&CLOSED.to_le_bytes());
+        self.stream.write_all(&self.reports).ok();
+    }
+
+    /// Remove and return the next complete message from the buffer, if
+    /// there is one.
+    fn take_message(&mut self) -> Option<Vec<u8>> {
+        let header = core::mem::size_of::<usize>();
+        if self.buffer.len() < header {
+            return None;
+        }
+        let size = util::read_usize(&mut &self.buffer[..header]);
+        if self.buffer.len() < header + size {
+            return None;
+        }
+        let message = self.buffer[header..header + size].to_vec();
+        self.buffer.drain(..header + size);
+        Some(message)
+    }
+}
+
+/// The listener thread's loop: accept new connections, and start a
+/// receiver thread for each one, until the stop flag is raised. Errors from
+/// accepting, which may be transient, are logged and the listener carries
+/// on. Returns once all of the receiver threads have finished.
+///
+fn accept_incoming(
+    listener: TcpListener,
+    sink: mpsc::Sender<Vec<u8>>,
+    iteration: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut receivers: Vec<thread::JoinHandle<()>> = Vec::new();
+
+    for incoming in listener.incoming() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        match incoming.and_then(Connection::new) {
+            Ok(connection) => {
+                let (sink, iteration, stop) = (sink.clone(), iteration.clone(), stop.clone());
+                receivers.push(thread::spawn(move || receive(connection, sink, &iteration, &stop)))
+            }
+            Err(e) => {
+                eprintln!("warning: could not accept a connection: {}", e);
+                thread::sleep(ACCEPT_RETRY_DELAY)
+            }
+        }
+        receivers.retain(|receiver| !receiver.is_finished());
+    }
+    for receiver in receivers {
+        receiver.join().ok();
+    }
+}
+
+/// A receiver thread's loop: report this rank's iteration to the sender,
+/// and forward complete messages, until the connection closes or fails, or
+/// the stop flag is raised. The sender is then told if this rank is
+/// shutting down.
+///
+fn receive(mut connection: Connection, sink: mpsc::Sender<Vec<u8>>, iteration: &AtomicU64, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        connection.report(iteration.load(Ordering::Relaxed));
+
+        if !connection.fill() {
+            return;
+        }
+        while let Some(message) = connection.take_message() {
+            sink.send(message).ok();
+        }
+    }
+    connection.report_closed()
+}
+
+/// Return an address which a connection to the given listening address can
+/// be made on, substituting the loopback address for an unspecified one.
+///
+fn connectable(mut address: SocketAddr) -> SocketAddr {
+    if address.ip().is_unspecified() {
+        match address {
+            SocketAddr::V4(_) => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(_) => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+        }
+    }
+    address
+}
+
 impl Communicator for TcpCommunicator {
     fn rank(&self) -> usize {
         self.rank
     }
 
     fn size(&self) -> usize {
-        self.num_peers
+        self.peers.len()
     }
 
     fn send(&self, rank: usize, message: Vec<u8>) {
-        self.send_sink
-            .as_ref()
-            .unwrap()
-            .send((rank, message))
-            .unwrap()
+        self.send_at_iteration(rank, self.iteration.load(Ordering::Relaxed), message)
+    }
+
+    fn send_at_iteration(&self, rank: usize, iteration: u64, message: Vec<u8>) {
+        let mut queues = self.send_queues.lock().unwrap();
+        queues[rank]
+            .get_or_insert_with(|| SendQueue::new(self.peers[rank]))
+            .push(iteration, message)
+    }
+
+    fn set_iteration(&self, iteration: u64) {
+        self.iteration.store(iteration, Ordering::Relaxed)
     }
 
     fn recv(&self) -> Vec<u8> {
-        let (mut stream, _) = self.listener.accept().unwrap();
-        let size = util::read_usize(&mut stream);
-        util::read_bytes_vec(&mut stream, size)
+        self.recv_source.recv().unwrap()
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<Vec<u8>> {
+        self.recv_source.recv_timeout(timeout).ok()
     }
 }
 
 impl Drop for TcpCommunicator {
     fn drop(&mut self) {
-        self.send_sink.take().unwrap();
-        self.send_thread.take().unwrap().join().unwrap();
+        self.shutdown().ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{serve_rendezvous, TcpCommunicator};
+    use crate::message::comm::Communicator;
+    use crate::message::ordered::OrderedCommunicator;
+    use crate::stats::Metrics;
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn free_addresses(count: usize) -> Vec<SocketAddr> {
+        let listeners: Vec<_> = (0..count)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        listeners.iter().map(|l| l.local_addr().unwrap()).collect()
+    }
+
+    #[test]
+    fn tcp_messages_arrive_in_order_from_each_peer() {
+        let peers = free_addresses(3);
+        let barrier = Arc::new(Barrier::new(3));
+        let handles: Vec<_> = (0..3)
+            .map(|rank| {
+                let peers = peers.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let comm = TcpCommunicator::new(rank, peers).unwrap();
+                    barrier.wait();
+                    for n in 0..50u8 {
+                        for dest in (0..3).filter(|&d| d != rank) {
+                            comm.send(dest, vec![rank as u8, n])
+                        }
+                    }
+                    let received: Vec<_> = (0..100).map(|_| comm.recv()).collect();
+                    barrier.wait();
+
+                    let metrics = Metrics::new();
+                    comm.record_backlog(&metrics);
+                    assert_eq!(metrics.snapshot().len(), 2);
+                    received
+                })
+            })
+            .collect();
+
+        for (rank, handle) in handles.into_iter().enumerate() {
+            let received = handle.join().unwrap();
+            for source in (0..3).filter(|&s| s != rank) {
+                let sequence: Vec<_> = received
+                    .iter()
+                    .filter(|m| m[0] as usize == source)
+                    .map(|m| m[1])
+                    .collect();
+                assert_eq!(sequence, (0..50u8).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn a_broken_connection_does_not_stop_the_receiver() {
+        let peers = free_addresses(2);
+        let receiver = TcpCommunicator::new(0, peers.clone()).unwrap();
+
+        let mut stream = TcpStream::connect(peers[0]).unwrap();
+        stream.write_all(&[100, 0, 0]).unwrap();
+        drop(stream);
+
+        let sender = TcpCommunicator::new(1, peers).unwrap();
+        sender.send(0, vec![1, 2, 3]);
+        assert_eq!(receiver.recv(), vec![1, 2, 3]);
+        sender.close().unwrap();
+        receiver.close().unwrap();
+    }
+
+    /// Send three messages to the other rank, stamped with the current
+    /// iteration, if `send` is true, and then receive `count` messages.
+    fn exchange(comm: &OrderedCommunicator<TcpCommunicator>, send: bool, count: usize) -> Vec<Vec<u8>> {
+        let (rank, iteration) = (comm.rank() as u8, comm.iteration() as u8);
+
+        if send {
+            for n in 0..3 {
+                comm.send(1 - comm.rank(), vec![rank, iteration, n])
+            }
+        }
+        (0..count).map(|_| comm.recv()).collect()
+    }
+
+    fn expected(rank: u8, iterations: std::ops::Range<u8>) -> Vec<Vec<u8>> {
+        iterations.flat_map(|i| (0..3).map(move |n| vec![rank, i, n])).collect()
+    }
+
+    #[test]
+    fn restarted_peer_receives_each_message_once() {
+        let peers = free_addresses(2);
+        let rank_peers = peers.clone();
+
+        let rank0 = thread::spawn(move || {
+            let comm = OrderedCommunicator::new(TcpCommunicator::new(0, rank_peers).unwrap());
+            let mut received = Vec::new();
+
+            for _ in 0..6 {
+                received.extend(exchange(&comm, true, 3));
+                comm.next_iteration();
+            }
+            comm.into_inner().close().unwrap();
+            received
+        });
+
+        // Rank 1 finishes three iterations, takes one message of the fourth,
+        // and stops. It then restarts at the fourth iteration, so it needs
+        // that iteration's messages again, but not the earlier ones.
+        let comm = OrderedCommunicator::new(TcpCommunicator::new(1, peers.clone()).unwrap());
+        let mut before = Vec::new();
+
+        for _ in 0..3 {
+            before.extend(exchange(&comm, true, 3));
+            comm.next_iteration();
+        }
+        before.extend(exchange(&comm, false, 1));
+        drop(comm);
+
+        let comm = OrderedCommunicator::starting_at(TcpCommunicator::new(1, peers).unwrap(), 3);
+        let mut after = Vec::new();
+
+        for _ in 3..6 {
+            after.extend(exchange(&comm, true, 3));
+            comm.next_iteration();
+        }
+        assert_eq!(before, [expected(0, 0..3), vec![vec![0, 3, 0]]].concat());
+        assert_eq!(after, expected(0, 3..6));
+        assert_eq!(rank0.join().unwrap(), expected(1, 0..6));
+    }
+
+    #[test]
+    fn unreachable_peers_are_reported() {
+        let peers = free_addresses(2);
+        let comm = TcpCommunicator::new(0, peers).unwrap();
+        comm.send(1, vec![0]);
+        assert!(comm.close().is_err());
+    }
+
+    #[test]
+    fn ranks_discover_each_other_through_rendezvous() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let registry = listener.local_addr().unwrap();
+        let service = thread::spawn(move || serve_rendezvous(listener, 3));
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                thread::spawn(move || {
+                    let comm = TcpCommunicator::rendezvous(registry, "127.0.0.1:0".parse().unwrap()).unwrap();
+                    comm.send((comm.rank() + 1) % comm.size(), vec![comm.rank() as u8]);
+                    (comm.rank(), comm.recv())
+                })
+            })
+            .collect();
+        let mut results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        service.join().unwrap().unwrap();
+        results.sort();
+
+        assert_eq!(results, [(0, vec![2]), (1, vec![0]), (2, vec![1])]);
     }
 }
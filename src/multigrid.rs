@@ -0,0 +1,231 @@
+//! The mesh-topology side of geometric multigrid: computing residuals,
+//! restricting residuals to coarser levels, and prolonging corrections to
+//! finer levels. No specific elliptic operator is assumed; the operator is
+//! supplied as a closure acting on a patch extended with guard zones.
+//!
+//! The functions operating on a whole mesh take a patch map keyed by the
+//! high-resolution rectangle of each patch, which may contain patches on
+//! several levels. Level `n + 1` is coarser than level `n` by a factor of
+//! two on each axis. Where a patch on level `n` is adjacent to a region not
+//! covered by other level `n` patches (a level boundary), its guard zones are
+//! filled from the level `n + 1` patches by piecewise-constant injection.
+
+use crate::index_space::IndexSpace;
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+
+/// Compute the residual `r = f - A(u)` on the index space of the right-hand
+/// side patch `f`. The solution patch `u` must be extended with as many
+/// guard zones as the operator stencil needs. The operator writes `A(u)` at
+/// the given index to the output slice.
+///
+pub fn residual<A>(u: &Patch, rhs: &Patch, operator: A) -> Patch
+where
+    A: Fn(&Patch, (i64, i64), &mut [f64]),
+{
+    assert_eq!(u.level(), rhs.level());
+    assert_eq!(u.num_fields(), rhs.num_fields());
+
+    let mut au = vec![0.0; rhs.num_fields()];
+    let mut result = rhs.clone();
+
+    for index in rhs.index_space().iter() {
+        operator(u, index, &mut au);
+        for (r, a) in result.get_slice_mut(index).iter_mut().zip(&au) {
+            *r -= a
+        }
+    }
+    result
+}
+
+/// Restrict a patch to the next coarser level, by averaging each 2x2 block of
+/// zones. The patch index space must be aligned with the coarser level.
+///
+pub fn restrict(fine: &Patch) -> Patch {
+    let space = fine.index_space().coarsen_by(2);
+    let num_fields = fine.num_fields();
+    Patch::from_slice_function(fine.level() + 1, space, num_fields, |index, slice| {
+        for (field, x) in slice.iter_mut().enumerate() {
+            *x = fine.sample_unchecked(fine.level() + 1, index, field)
+        }
+    })
+}
+
+/// Prolong a patch to the next finer level, over the given index space
+/// (measured at the finer level), by piecewise-constant injection. The space
+/// must be covered by the coarse patch.
+///
+pub fn prolong(coarse: &Patch, space: IndexSpace) -> Patch {
+    assert!(coarse.level() > 0, "cannot prolong a patch on the finest level");
+    let level = coarse.level() - 1;
+    Patch::from_slice_function(level, space, coarse.num_fields(), |(i, j), slice| {
+        slice.copy_from_slice(coarse.get_slice((i.div_euclid(2), j.div_euclid(2))))
+    })
+}
+
+/// Replace the data on each patch at level `level + 1` with the restriction
+/// of the level `level` data, wherever the finer level covers it. Zones not
+/// covered by the finer level are unchanged.
+///
+pub fn restrict_level(patches: &mut RectangleMap<i64, Patch>, level: u32) {
+    let mut updates = Vec::new();
+
+    for (rect, coarse) in patches.iter() {
+        if coarse.level() == level + 1 {
+            for (_, fine) in patches.query_rect(IndexSpace::from(rect)) {
+                if fine.level() == level {
+                    let overlap = fine.index_space().coarsen_by(2).intersect(coarse.index_space());
+                    let restricted = restrict(fine).extract(overlap);
+                    updates.push(((rect.0.clone(), rect.1.clone()), restricted))
+                }
+            }
+        }
+    }
+    for (rect, restricted) in updates {
+        let coarse = patches.get_mut((&rect.0, &rect.1)).unwrap();
+        for index in restricted.index_space().iter() {
+            coarse.get_slice_mut(index).copy_from_slice(restricted.get_slice(index))
+        }
+    }
+}
+
+/// Add the correction stored on each patch at level `level + 1` to the
+/// patches at level `level` which it covers, by piecewise-constant
+/// prolongation. The corrections map contains only coarse-level patches;
+/// the solution map is modified in place.
+///
+pub fn prolong_level(
+    solution: &mut RectangleMap<i64, Patch>,
+    corrections: &RectangleMap<i64, Patch>,
+    level: u32,
+) {
+    for (_, fine) in solution.iter_mut() {
+        if fine.level() != level {
+            continue;
+        }
+        for (_, coarse) in corrections.query_rect(fine.high_resolution_space()) {
+            if coarse.level() == level + 1 {
+                let overlap = coarse.index_space().refine_by(2).intersect(fine.index_space());
+                let correction = prolong(coarse, overlap);
+                for index in correction.index_space().iter() {
+                    for (u, du) in fine.get_slice_mut(index).iter_mut().zip(correction.get_slice(index)) {
+                        *u += du
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Fill the guard zones of a patch, i.e. the zones outside the valid index
+/// space. Each guard zone is taken from a patch on the same level if one
+/// covers it, otherwise it's injected from a patch one level coarser
+/// (across a level boundary), and otherwise (outside the domain) it's set by
+/// the boundary value closure. Unlike [`crate::meshing::extend_patch_mut`],
+/// this function also fills the patch corners.
+///
+pub fn fill_guard_zones<G>(
+    patch: &mut Patch,
+    valid_index_space: &IndexSpace,
+    boundary_value: G,
+    patches: &RectangleMap<i64, Patch>,
+) where
+    G: Fn((i64, i64), &mut [f64]),
+{
+    let level = patch.level();
+    let factor = 1 << level;
+
+    for (i, j) in patch.index_space().iter() {
+        if valid_index_space.contains((i, j)) {
+            continue;
+        }
+        let high_res = (i * factor, j * factor);
+        let source = patches
+            .query_point(high_res)
+            .map(|(_, p)| p)
+            .filter(|p| p.level() == level || p.level() == level + 1)
+            .min_by_key(|p| p.level());
+        let slice = patch.get_slice_mut((i, j));
+
+        match source {
+            Some(p) if p.level() == level => slice.copy_from_slice(p.get_slice((i, j))),
+            Some(p) => slice.copy_from_slice(p.get_slice((i.div_euclid(2), j.div_euclid(2)))),
+            None => boundary_value((i, j), slice),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{fill_guard_zones, prolong, prolong_level, residual, restrict, restrict_level};
+    use crate::index_space::IndexSpace;
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+
+    fn two_level_map() -> RectangleMap<i64, Patch> {
+        let coarse = Patch::from_scalar_function(1, (0..8, 0..8), |_| 0.0);
+        let fine = Patch::from_scalar_function(0, (4..8, 4..12), |(i, j)| (i + j) as f64);
+        vec![coarse, fine]
+            .into_iter()
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect()
+    }
+
+    #[test]
+    fn residual_of_discrete_laplacian_works() {
+        let u = Patch::from_scalar_function(0, (-1..9, -1..9), |(i, j)| (i * i + j * j) as f64);
+        let f = Patch::from_scalar_function(0, (0..8, 0..8), |_| 5.0);
+        let r = residual(&u, &f, |u, (i, j), au| {
+            let v = |i, j| u.get_slice((i, j))[0];
+            au[0] = v(i + 1, j) + v(i - 1, j) + v(i, j + 1) + v(i, j - 1) - 4.0 * v(i, j);
+        });
+        assert!(r.data().iter().all(|&x| x == 1.0));
+    }
+
+    #[test]
+    fn restriction_and_prolongation_work() {
+        let fine = Patch::from_scalar_function(0, (0..4, 2..6), |(i, j)| (i + j) as f64);
+        let coarse = restrict(&fine);
+        assert_eq!(coarse.level(), 1);
+        assert_eq!(coarse.index_space().start(), (0, 1));
+        assert_eq!(coarse.get_slice((1, 2))[0], 7.0);
+
+        let back = prolong(&coarse, IndexSpace::new(0..4, 2..6));
+        assert_eq!(back.get_slice((3, 5))[0], 7.0);
+        assert_eq!(restrict(&back).data(), coarse.data());
+    }
+
+    #[test]
+    fn level_operations_respect_coverage() {
+        let mut patches = two_level_map();
+        restrict_level(&mut patches, 0);
+        let coarse = patches.get((&(0..16), &(0..16))).unwrap();
+        assert_eq!(coarse.get_slice((2, 2))[0], 9.0);
+        assert_eq!(coarse.get_slice((1, 2))[0], 0.0);
+
+        let corrections: RectangleMap<_, _> = vec![Patch::from_scalar_function(1, (0..8, 0..8), |_| 1.0)]
+            .into_iter()
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+        let mut solution = two_level_map();
+        prolong_level(&mut solution, &corrections, 0);
+        let fine = solution.get((&(4..8), &(4..12))).unwrap();
+        assert_eq!(fine.get_slice((4, 4))[0], 9.0);
+        assert_eq!(solution.get((&(0..16), &(0..16))).unwrap().get_slice((0, 0))[0], 0.0);
+    }
+
+    #[test]
+    fn guard_zones_are_filled_across_level_boundaries() {
+        let mut patches = two_level_map();
+        patches.get_mut((&(0..16), &(0..16))).unwrap().map_index_mut(|(i, j), x| x[0] = (10 * i + j) as f64);
+
+        let valid = IndexSpace::new(4..8, 4..12);
+        let mut extended = Patch::zeros(0, 1, valid.extend_all(1));
+        fill_guard_zones(&mut extended, &valid, |_, x| x[0] = -1.0, &patches);
+
+        assert_eq!(extended.get_slice((3, 5))[0], 12.0);
+        assert_eq!(extended.get_slice((8, 12))[0], 46.0);
+        assert_eq!(extended.get_slice((3, 3))[0], 11.0);
+    }
+}
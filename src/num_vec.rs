@@ -1,4 +1,6 @@
-use core::ops::{Add, Sub, Mul, Div, Index};
+#[cfg(feature = "mesh")]
+use core::{fmt, marker::PhantomData};
+use core::ops::{Add, Sub, Mul, Div, Neg, Index, IndexMut};
 
 
 
@@ -7,7 +9,7 @@ use core::ops::{Add, Sub, Mul, Div, Index};
  * A statically-sized numeric vector over a generic scalar data type T, which
  * supports arithmetic operations also supported by T.
  */
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector<T, const DIM: usize> {
     data: [T; DIM]
 }
@@ -15,6 +17,97 @@ pub struct Vector<T, const DIM: usize> {
 
 
 
+// ============================================================================
+impl<T, const DIM: usize> Vector<T, DIM> {
+
+    /// Return the components as an array.
+    pub fn as_array(&self) -> &[T; DIM] {
+        &self.data
+    }
+
+    /// Return an iterator over the components.
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    /// Return an iterator over mutable references to the components.
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, T> {
+        self.data.iter_mut()
+    }
+
+    /// Apply a function to each component, and return the vector of
+    /// results.
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Vector<U, DIM> {
+        Vector { data: self.data.map(f) }
+    }
+}
+
+impl<T, const DIM: usize> Vector<T, DIM>
+where
+    T: Copy + Default + Add<Output = T> + Mul<Output = T>
+{
+    /// Return the dot product of two vectors.
+    pub fn dot(&self, other: &Self) -> T {
+        self.iter().zip(other.iter()).fold(T::default(), |a, (&x, &y)| a + x * y)
+    }
+}
+
+impl<const DIM: usize> Vector<f64, DIM> {
+
+    /// Return the squared Euclidean norm of this vector.
+    pub fn norm_squared(&self) -> f64 {
+        self.dot(self)
+    }
+
+    /// Return the Euclidean norm of this vector.
+    pub fn norm(&self) -> f64 {
+        self.norm_squared().sqrt()
+    }
+}
+
+
+
+
+// ============================================================================
+impl<T: Copy + Default, const DIM: usize> Default for Vector<T, DIM> {
+    fn default() -> Self {
+        Self { data: [T::default(); DIM] }
+    }
+}
+
+impl<T, const DIM: usize> From<[T; DIM]> for Vector<T, DIM> {
+    fn from(data: [T; DIM]) -> Self {
+        Self { data }
+    }
+}
+
+impl<T, const DIM: usize> From<Vector<T, DIM>> for [T; DIM] {
+    fn from(v: Vector<T, DIM>) -> Self {
+        v.data
+    }
+}
+
+impl<T, const DIM: usize> IntoIterator for Vector<T, DIM> {
+    type Item = T;
+    type IntoIter = core::array::IntoIter<T, DIM>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self.data)
+    }
+}
+
+impl<'a, T, const DIM: usize> IntoIterator for &'a Vector<T, DIM> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+
+
+
 // ============================================================================
 impl<T, U, V, const DIM: usize> Add<Vector<U, DIM>> for Vector<T, DIM>
 where
@@ -91,6 +184,20 @@ where
 
 
 
+impl<T, V, const DIM: usize> Neg for Vector<T, DIM>
+where
+    T: Neg<Output = V>,
+{
+    type Output = Vector<V, DIM>;
+
+    fn neg(self) -> Self::Output {
+        self.map(|x| -x)
+    }
+}
+
+
+
+
 // ============================================================================
 impl<T, const DIM: usize> Index<usize> for Vector<T, DIM> {
     type Output = T;
@@ -100,6 +207,63 @@ impl<T, const DIM: usize> Index<usize> for Vector<T, DIM> {
     }
 }
 
+impl<T, const DIM: usize> IndexMut<usize> for Vector<T, DIM> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.data[index]
+    }
+}
+
+
+
+
+// ============================================================================
+#[cfg(feature = "mesh")]
+impl<T: serde::Serialize, const DIM: usize> serde::Serialize for Vector<T, DIM> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(DIM)?;
+        for x in &self.data {
+            tuple.serialize_element(x)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "mesh")]
+impl<'de, T, const DIM: usize> serde::Deserialize<'de> for Vector<T, DIM>
+where
+    T: serde::Deserialize<'de> + Copy + Default
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(DIM, VectorVisitor(PhantomData))
+    }
+}
+
+#[cfg(feature = "mesh")]
+struct VectorVisitor<T, const DIM: usize>(PhantomData<T>);
+
+#[cfg(feature = "mesh")]
+impl<'de, T, const DIM: usize> serde::de::Visitor<'de> for VectorVisitor<T, DIM>
+where
+    T: serde::Deserialize<'de> + Copy + Default
+{
+    type Value = Vector<T, DIM>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of {} elements", DIM)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut data = [T::default(); DIM];
+
+        for (i, x) in data.iter_mut().enumerate() {
+            *x = seq.next_element()?.ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+        }
+        Ok(Vector { data })
+    }
+}
+
 
 
 
@@ -139,3 +303,33 @@ impl<T, const DIM: usize> Index<usize> for Vector<T, DIM> {
     //     })
     // }
 // }
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod test {
+    use super::Vector;
+
+    #[test]
+    fn vector_math_and_conversions() {
+        let a = Vector::from([3.0, 4.0]);
+        let b = Vector::from([1.0, -1.0]);
+        assert_eq!(a.dot(&b), -1.0);
+        assert_eq!(a.norm(), 5.0);
+        assert_eq!(<[f64; 2]>::from(-(a + b) * 2.0), [-8.0, -6.0]);
+        assert_eq!(a.iter().sum::<f64>(), 7.0);
+        assert_eq!(Vector::<i32, 3>::default().into_iter().count(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn vector_serde_round_trip() {
+        let a = Vector::from([1.5, 2.5, -3.0]);
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&a, &mut bytes).unwrap();
+        let b: Vector<f64, 3> = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,360 @@
+//! A registry of named simulation parameters, such as the gas equation of
+//! state, the number of guard zones, or the boundary conditions. The
+//! registry is written into checkpoints, so that a restarted run can verify
+//! that it was configured the same way as the run which wrote the
+//! checkpoint.
+
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The value of a parameter.
+///
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// Whether a parameter may be changed when a run is restarted from a
+/// checkpoint.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Restart {
+    /// The parameter must have the same value on restart. These are the
+    /// parameters which the checkpoint data depends on.
+    Fixed,
+
+    /// The parameter may be changed on restart, e.g. an output interval.
+    Mutable,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    value: Value,
+    restart: Restart,
+}
+
+/// A collection of named, typed parameters. Names are conventionally
+/// qualified by the component that uses them, e.g. `euler2d_pcm.num_guard`.
+///
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Parameters {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Text(_) => "text",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(x) => write!(fmt, "{}", x),
+            Value::Int(x) => write!(fmt, "{}", x),
+            Value::Float(x) => write!(fmt, "{:e}", x),
+            Value::Text(x) => write!(fmt, "{:?}", x),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(x: bool) -> Self {
+        Value::Bool(x)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(x: i64) -> Self {
+        Value::Int(x)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(x: &str) -> Self {
+        Value::Text(x.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(x: String) -> Self {
+        Value::Text(x)
+    }
+}
+
+impl Parameters {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a parameter which must not change on restart, replacing any
+    /// parameter with the same name.
+    ///
+    pub fn fixed<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.insert(name, value, Restart::Fixed);
+        self
+    }
+
+    /// Add a parameter which may change on restart, replacing any parameter
+    /// with the same name.
+    ///
+    pub fn mutable<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.insert(name, value, Restart::Mutable);
+        self
+    }
+
+    /// Add a parameter, replacing any parameter with the same name.
+    pub fn insert<V: Into<Value>>(&mut self, name: &str, value: V, restart: Restart) {
+        let value = value.into();
+        self.entries.insert(name.to_string(), Entry { value, restart });
+    }
+
+    /// Add all of the parameters in another registry to this one.
+    pub fn extend(&mut self, other: Parameters) {
+        self.entries.extend(other.entries)
+    }
+
+    /// Return whether the registry has no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return the value of a parameter, if it exists.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.entries.get(name).map(|e| &e.value)
+    }
+
+    /// Return a boolean parameter, or an error if it is missing or has
+    /// another type.
+    ///
+    pub fn get_bool(&self, name: &str) -> Result<bool> {
+        match self.require(name, "bool")? {
+            Value::Bool(x) => Ok(*x),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return an integer parameter, or an error if it is missing or has
+    /// another type.
+    ///
+    pub fn get_int(&self, name: &str) -> Result<i64> {
+        match self.require(name, "int")? {
+            Value::Int(x) => Ok(*x),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return a floating point parameter, or an error if it is missing or
+    /// has another type.
+    ///
+    pub fn get_float(&self, name: &str) -> Result<f64> {
+        match self.require(name, "float")? {
+            Value::Float(x) => Ok(*x),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return a text parameter, or an error if it is missing or has another
+    /// type.
+    ///
+    pub fn get_text(&self, name: &str) -> Result<&str> {
+        match self.require(name, "text")? {
+            Value::Text(x) => Ok(x),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return an iterator over the parameter names and values, in order of
+    /// name.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> + '_ {
+        self.entries.iter().map(|(k, e)| (k.as_str(), &e.value))
+    }
+
+    /// Check that this registry (the configuration of a restarted run) is
+    /// compatible with the registry saved in a checkpoint. Every fixed
+    /// parameter in either registry must be present in both, with the same
+    /// value. Mutable parameters may be added, removed, or changed. All the
+    /// incompatible parameters are listed in the returned error.
+    ///
+    pub fn check_restart(&self, saved: &Parameters) -> Result<()> {
+        let mut problems = Vec::new();
+
+        for (name, entry) in &saved.entries {
+            match self.entries.get(name) {
+                Some(current)
+                    if (entry.restart == Restart::Fixed || current.restart == Restart::Fixed)
+                        && current.value != entry.value =>
+                {
+                    problems.push(format!("{} was {} but is now {}", name, entry.value, current.value))
+                }
+                None if entry.restart == Restart::Fixed => {
+                    problems.push(format!("{} = {} is missing", name, entry.value))
+                }
+                _ => {}
+            }
+        }
+        for (name, entry) in &self.entries {
+            if entry.restart == Restart::Fixed && !saved.entries.contains_key(name) {
+                problems.push(format!("{} = {} was not in the checkpoint", name, entry.value))
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Parameters(format!(
+                "incompatible with checkpoint: {}",
+                problems.join("; ")
+            )))
+        }
+    }
+
+    /// Change the values of existing mutable parameters during a run, e.g.
+    /// a time step safety factor. Every updated parameter must exist, be
+    /// mutable, and keep its type; otherwise nothing is changed, and all the
+    /// invalid updates are listed in the returned error.
+    ///
+    pub fn update(&mut self, updates: &Parameters) -> Result<()> {
+        let mut problems = Vec::new();
+
+        for (name, update) in &updates.entries {
+            match self.entries.get(name) {
+                None => problems.push(format!("{} does not exist", name)),
+                Some(entry) if entry.restart == Restart::Fixed => problems.push(format!("{} is fixed", name)),
+                Some(entry) if entry.value.type_name() != update.value.type_name() => problems.push(format!(
+                    "{} has type {} but was given a {}",
+                    name,
+                    entry.value.type_name(),
+                    update.value.type_name()
+                )),
+                Some(_) => {}
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(Error::Parameters(format!("invalid update: {}", problems.join("; "))));
+        }
+        for (name, update) in &updates.entries {
+            self.entries.get_mut(name).unwrap().value = update.value.clone();
+        }
+        Ok(())
+    }
+
+    /// Parse updates to this registry from lines of text of the form
+    /// `name = value`. Blank lines and lines starting with `#` are skipped.
+    /// Each value is parsed as the type of the existing parameter with that
+    /// name; values for parameters which do not exist, or which cannot be
+    /// parsed as the right type, are kept as text so that [`Self::update`]
+    /// rejects them.
+    ///
+    pub fn parse_updates(&self, text: &str) -> Result<Parameters> {
+        let mut updates = Parameters::new();
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| Error::Parameters(format!("expected name = value, got '{}'", line)))?;
+            let (name, value) = (name.trim(), value.trim());
+            let text = || Value::Text(value.trim_matches('"').to_string());
+            let value = match self.get(name) {
+                Some(Value::Bool(_)) => value.parse().map(Value::Bool).unwrap_or_else(|_| text()),
+                Some(Value::Int(_)) => value.parse().map(Value::Int).unwrap_or_else(|_| text()),
+                Some(Value::Float(_)) => value.parse().map(Value::Float).unwrap_or_else(|_| text()),
+                Some(Value::Text(_)) | None => text(),
+            };
+            updates.insert(name, value, Restart::Mutable);
+        }
+        Ok(updates)
+    }
+
+    fn require(&self, name: &str, type_name: &str) -> Result<&Value> {
+        match self.get(name) {
+            None => Err(Error::Parameters(format!("{} is missing", name))),
+            Some(value) if value.type_name() != type_name => Err(Error::Parameters(format!(
+                "{} has type {} but {} was expected",
+                name,
+                value.type_name(),
+                type_name
+            ))),
+            Some(value) => Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::Parameters;
+
+    fn parameters() -> Parameters {
+        Parameters::new()
+            .fixed("gamma_law_index", 5.0 / 3.0)
+            .fixed("num_guard", 2_i64)
+            .fixed("riemann_solver", "hlle")
+            .mutable("checkpoint_interval", 0.1)
+    }
+
+    #[test]
+    fn parameters_are_typed() {
+        let p = parameters();
+        assert_eq!(p.get_int("num_guard").unwrap(), 2);
+        assert_eq!(p.get_text("riemann_solver").unwrap(), "hlle");
+        assert!(p.get_float("num_guard").is_err());
+        assert!(p.get_bool("dimensions").is_err());
+    }
+
+    #[test]
+    fn only_fixed_parameters_must_match_on_restart() {
+        let saved = parameters();
+
+        assert!(parameters().mutable("checkpoint_interval", 0.5).check_restart(&saved).is_ok());
+        assert!(parameters().fixed("num_guard", 3_i64).check_restart(&saved).is_err());
+        assert!(parameters().fixed("num_guard", 2.0).check_restart(&saved).is_err());
+        assert!(parameters().fixed("limiter", "minmod").check_restart(&saved).is_err());
+        assert!(Parameters::new().check_restart(&saved).is_err());
+    }
+
+    #[test]
+    fn only_existing_mutable_parameters_can_be_updated() {
+        let mut p = parameters();
+        let original = p.clone();
+
+        assert!(p.update(&Parameters::new().mutable("checkpoint_interval", 0.5).mutable("num_guard", 3_i64)).is_err());
+        assert!(p.update(&Parameters::new().mutable("checkpoint_interval", 1_i64)).is_err());
+        assert!(p.update(&Parameters::new().mutable("missing", 1.0)).is_err());
+        assert_eq!(p.iter().collect::<Vec<_>>(), original.iter().collect::<Vec<_>>());
+
+        p.update(&Parameters::new().mutable("checkpoint_interval", 0.5)).unwrap();
+        assert_eq!(p.get_float("checkpoint_interval").unwrap(), 0.5);
+        assert!(p.check_restart(&original).is_ok());
+    }
+
+    #[test]
+    fn updates_are_parsed_as_the_type_of_the_existing_parameter() {
+        let mut p = parameters();
+        let updates = p.parse_updates("# steering\n\ncheckpoint_interval = 2\n").unwrap();
+
+        assert_eq!(updates.get_float("checkpoint_interval").unwrap(), 2.0);
+        assert!(p.update(&updates).is_ok());
+        assert!(p.update(&p.parse_updates("checkpoint_interval = soon").unwrap()).is_err());
+        assert!(p.update(&p.parse_updates("missing = 1").unwrap()).is_err());
+        assert!(p.parse_updates("checkpoint_interval").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Scaffolding for tracer particles. Particles are partitioned by patch: each
+//! patch owns the particles whose positions fall inside its high-resolution
+//! rectangle. Particles which leave a patch during an update are migrated to
+//! the neighboring patch which contains them, by passing messages between
+//! [`ParticleUpdate`] tasks in the same way guard zones are exchanged. The
+//! physics of the particle update is supplied by the application, through
+//! the [`Pusher`] trait.
+
+use crate::adjacency_list::AdjacencyList;
+use crate::automaton::{Automaton, Status};
+use crate::index_space::IndexSpace;
+use crate::meshing::Mesh;
+use crate::rect_map::Rectangle;
+use std::sync::Arc;
+
+/// A tracer particle.
+///
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Particle {
+    /// An identifier for the particle, which is preserved when the particle
+    /// migrates between patches.
+    pub id: u64,
+
+    /// The particle position, in physical coordinates.
+    pub position: (f64, f64),
+
+    /// The particle velocity.
+    pub velocity: (f64, f64),
+}
+
+/// Hooks for the user-supplied parts of a particle update. Each time step,
+/// the particle is given a kick (an update to its velocity) and then a drift
+/// (an update to its position).
+///
+pub trait Pusher {
+    /// Update the particle velocity. The default implementation does
+    /// nothing, which is appropriate for ballistic particles.
+    fn kick(&self, _particle: &mut Particle, _dt: f64) {}
+
+    /// Update the particle position. The default implementation moves the
+    /// particle along its velocity vector.
+    fn drift(&self, particle: &mut Particle, dt: f64) {
+        particle.position.0 += particle.velocity.0 * dt;
+        particle.position.1 += particle.velocity.1 * dt;
+    }
+}
+
+/// A task which advances the particles owned by a single patch. The
+/// particles which leave the patch in one update are sent to the neighboring
+/// patches at the start of the next update. Particles which move outside of
+/// every neighboring patch (e.g. through an outflow boundary) are dropped.
+///
+/// Particles are expected to move no further than a patch neighbor in a
+/// single time step.
+///
+pub struct ParticleUpdate<P> {
+    rect: Rectangle<i64>,
+    mesh: Mesh,
+    particles: Vec<Particle>,
+    emigrants: Vec<Particle>,
+    neighbors: Vec<Rectangle<i64>>,
+    incoming_count: usize,
+    received_count: usize,
+    time_step_size: f64,
+    pusher: Arc<P>,
+}
+
+impl<P: Pusher> ParticleUpdate<P> {
+    /// Create a particle task for the patch at the given level, which covers
+    /// the given high-resolution rectangle. Particles outside the rectangle
+    /// are migrated to neighbors on the first update. The edge list is the
+    /// same as the one used for the hydrodynamics update.
+    ///
+    pub fn new(
+        rect: Rectangle<i64>,
+        level: u32,
+        mesh: Mesh,
+        particles: Vec<Particle>,
+        time_step_size: f64,
+        pusher: Arc<P>,
+        edge_list: &AdjacencyList<(Rectangle<i64>, u32)>,
+    ) -> Self {
+        let key = (rect.clone(), level);
+        let neighbors = edge_list.outgoing_edges(&key).map(|(r, _)| r.clone()).collect();
+        let incoming_count = edge_list.incoming_edges(&key).count();
+        let mut task = Self {
+            rect,
+            mesh,
+            particles: Vec::new(),
+            emigrants: Vec::new(),
+            neighbors,
+            incoming_count,
+            received_count: 0,
+            time_step_size,
+            pusher,
+        };
+        task.settle(particles);
+        task
+    }
+
+    /// Return the particles currently owned by this task.
+    ///
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    fn contains(&self, rect: &Rectangle<i64>, particle: &Particle) -> bool {
+        IndexSpace::from(rect.clone()).contains(self.mesh.index_at(particle.position, 0))
+    }
+
+    fn settle(&mut self, particles: Vec<Particle>) {
+        for particle in particles {
+            if self.contains(&self.rect, &particle) {
+                self.particles.push(particle)
+            } else {
+                self.emigrants.push(particle)
+            }
+        }
+    }
+}
+
+impl<P: Pusher> Automaton for ParticleUpdate<P> {
+    type Key = Rectangle<i64>;
+    type Message = Vec<Particle>;
+    type Value = Self;
+
+    fn key(&self) -> Self::Key {
+        self.rect.clone()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.neighbors
+            .iter()
+            .map(|rect| {
+                let particles = self
+                    .emigrants
+                    .iter()
+                    .filter(|p| self.contains(rect, p))
+                    .cloned()
+                    .collect();
+                (rect.clone(), particles)
+            })
+            .collect()
+    }
+
+    fn receive(&mut self, particles: Self::Message) -> Status {
+        self.particles.extend(particles);
+        self.received_count += 1;
+        Status::eligible_if(self.received_count == self.incoming_count)
+    }
+
+    fn value(mut self) -> Self::Value {
+        let dt = self.time_step_size;
+        let mut particles = std::mem::take(&mut self.particles);
+
+        for particle in &mut particles {
+            self.pusher.kick(particle, dt);
+            self.pusher.drift(particle, dt);
+        }
+        self.emigrants.clear();
+        self.received_count = 0;
+        self.settle(particles);
+        self
+    }
+
+    fn message_size(particles: &Self::Message) -> usize {
+        particles.len() * core::mem::size_of::<Particle>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Particle, ParticleUpdate, Pusher};
+    use crate::automaton::execute;
+    use crate::meshing::{GraphTopology, Mesh};
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+    use std::sync::Arc;
+
+    struct Ballistic;
+
+    impl Pusher for Ballistic {}
+
+    #[test]
+    fn particles_migrate_between_patches() {
+        let mesh = Mesh {
+            area: (0.0..2.0, 0.0..1.0),
+            size: (20, 10),
+        };
+        let patches: RectangleMap<_, _> = vec![
+            Patch::zeros(0, 1, (0..10, 0..10)),
+            Patch::zeros(0, 1, (10..20, 0..10)),
+        ]
+        .into_iter()
+        .map(|p| (p.high_resolution_rect(), p))
+        .collect();
+
+        let edge_list = patches.adjacency_list(1);
+        let particle = Particle {
+            id: 7,
+            position: (0.95, 0.5),
+            velocity: (1.0, 0.0),
+        };
+        let mut tasks: Vec<_> = patches
+            .keys()
+            .map(|(di, dj)| {
+                let rect = (di.clone(), dj.clone());
+                let particles = if di.start == 0 { vec![particle.clone()] } else { vec![] };
+                ParticleUpdate::new(rect, 0, mesh.clone(), particles, 0.1, Arc::new(Ballistic), &edge_list)
+            })
+            .collect();
+
+        for _ in 0..2 {
+            tasks = execute(tasks).collect();
+        }
+        tasks.sort_by_key(|t| t.rect.0.start);
+
+        assert!(tasks[0].particles().is_empty());
+        assert_eq!(tasks[1].particles().len(), 1);
+        assert_eq!(tasks[1].particles()[0].id, 7);
+        assert!((tasks[1].particles()[0].position.0 - 1.15).abs() < 1e-12);
+    }
+}
@@ -1,6 +1,21 @@
-use crate::index_space::IndexSpace;
+use crate::error::{Error, Result};
+use crate::index_space::{IndexSpace, MemoryRegion};
+use crate::meshing::WrappedRegion;
+use crate::num_vec::Vector;
+use crate::parameters::Parameters;
 use crate::rect_map::Rectangle;
 use std::cmp::Ordering::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A signaling NaN with a distinctive payload, written into zones which must
+/// be overwritten before they are read (see [`Patch::poison`]). Arithmetic on
+/// it yields an ordinary NaN, so a poisoned value which leaks into a result
+/// is still visible, but only the untouched value has exactly these bits.
+///
+pub const POISON: f64 = f64::from_bits(0x7ff0_0000_dead_beef);
 
 /// Identifies the part of the mesh where patch data resides. An
 /// `n`-dimensional cartesian array has `n` of these parameters, one per axis.
@@ -31,7 +46,7 @@ pub enum MeshLocation {
     Node,
 }
 
-#[derive(Clone, serde::Serialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 
 /// A patch is a mapping from a rectangular subset of a high-resolution index
 /// space (HRIS), to associated field values. The mapping is backed by an
@@ -58,6 +73,11 @@ pub struct Patch {
 
     /// The backing array of data on this patch.
     data: Vec<f64>,
+
+    /// Flags marking solid zones (internal obstacles), in the same row-major
+    /// order as the data. `None` if the patch has no solid zones.
+    #[serde(default)]
+    mask: Option<Vec<bool>>,
 }
 
 impl Patch {
@@ -69,6 +89,7 @@ impl Patch {
             rect: (0..0, 0..0),
             num_fields: 0,
             data: Vec::new(),
+            mask: None,
         }
     }
 
@@ -82,6 +103,7 @@ impl Patch {
             level,
             num_fields,
             data,
+            mask: None,
         }
     }
 
@@ -125,11 +147,13 @@ impl Patch {
             data,
             rect: space.into(),
             num_fields,
+            mask: None,
         }
     }
 
     pub fn extract_from(source: &Patch, selection: IndexSpace) -> Self {
-        Self::from_slice_function(
+        let mask = source.mask_subset(&selection);
+        let patch = Self::from_slice_function(
             source.level,
             selection,
             source.num_fields,
@@ -138,7 +162,49 @@ impl Patch {
                     slice.clone_from_slice(source.get_slice(index))
                 }
             },
-        )
+        );
+        Self { mask, ..patch }
+    }
+
+    /// Mark the zones for which the given function returns true as solid.
+    /// The update schemes do not update solid zones, and treat the faces
+    /// between solid and fluid zones as reflecting walls. Any existing mask
+    /// is replaced.
+    pub fn with_mask<F>(mut self, solid: F) -> Self
+    where
+        F: Fn((i64, i64)) -> bool,
+    {
+        self.mask = Some(self.index_space().iter().map(solid).collect());
+        self
+    }
+
+    /// Return the solid zone flags of this patch, in row-major order, or
+    /// `None` if the patch has no mask.
+    pub fn mask(&self) -> Option<&[bool]> {
+        self.mask.as_deref()
+    }
+
+    /// Return true if the zone at the given index is solid. Patches without
+    /// a mask have no solid zones, and indexes outside the patch are never
+    /// solid.
+    pub fn is_solid(&self, index: (i64, i64)) -> bool {
+        match &self.mask {
+            Some(mask) if self.index_space().contains(index) => mask[self.index_space().row_major_offset(index)],
+            _ => false,
+        }
+    }
+
+    /// Mark the zone at the given index as solid or fluid. A mask is only
+    /// allocated when a zone is first made solid. This method panics if the
+    /// index is out of bounds.
+    pub fn set_solid(&mut self, index: (i64, i64), solid: bool) {
+        let space = self.index_space();
+        assert!(space.contains(index), "index ({} {}) is out of bounds", index.0, index.1);
+
+        if solid || self.mask.is_some() {
+            let mask = self.mask.get_or_insert_with(|| vec![false; space.len()]);
+            mask[space.row_major_offset(index)] = solid
+        }
     }
 
     pub fn level(&self) -> u32 {
@@ -157,6 +223,17 @@ impl Patch {
         self.data.chunks_exact_mut(self.num_fields)
     }
 
+    /// Like [`Patch::iter_data_mut`], but yielding fixed-length arrays. This
+    /// function panics if the patch does not have `NUM_FIELDS` fields.
+    pub fn iter_data_mut_fixed<const NUM_FIELDS: usize>(
+        &mut self,
+    ) -> impl Iterator<Item = &mut [f64; NUM_FIELDS]> {
+        assert!(self.num_fields == NUM_FIELDS);
+        self.data
+            .chunks_exact_mut(NUM_FIELDS)
+            .map(|x| x.try_into().unwrap())
+    }
+
     pub fn select(&self, subspace: IndexSpace) -> impl Iterator<Item = &'_ [f64]> {
         subspace.memory_region_in(self.index_space()).iter_slice(&self.data, self.num_fields)
     }
@@ -165,6 +242,128 @@ impl Patch {
         subspace.memory_region_in(self.index_space()).iter_slice_mut(&mut self.data, self.num_fields)
     }
 
+    /// Overwrite every field in the given subspace with [`POISON`], to
+    /// detect reads of zones that were never filled in.
+    pub fn poison(&mut self, subspace: IndexSpace) {
+        for zone in self.select_mut(subspace) {
+            zone.fill(POISON)
+        }
+    }
+
+    /// Return the first index in the given subspace, in row-major order,
+    /// where a field still holds [`POISON`].
+    pub fn find_poisoned(&self, subspace: IndexSpace) -> Option<(i64, i64)> {
+        subspace
+            .iter()
+            .zip(self.select(subspace.clone()))
+            .find(|(_, zone)| zone.iter().any(|x| x.to_bits() == POISON.to_bits()))
+            .map(|(index, _)| index)
+    }
+
+    /// Return mutable iterators over two disjoint subspaces of this patch.
+    /// This is like two calls to [`Patch::select_mut`] whose results can be
+    /// held at the same time, e.g. to write the rim of a patch while reading
+    /// its interior. This method panics if the subspaces overlap, or if
+    /// either is not contained in the patch.
+    pub fn select_disjoint_mut(
+        &mut self,
+        a: IndexSpace,
+        b: IndexSpace,
+    ) -> (impl Iterator<Item = &'_ mut [f64]>, impl Iterator<Item = &'_ mut [f64]>) {
+        let space = self.index_space();
+        assert!(
+            space.contains_space(&a) && space.contains_space(&b),
+            "subspaces {:?} and {:?} are not both contained in the patch {:?}",
+            a,
+            b,
+            space
+        );
+        assert!(a.intersect(b.clone()).is_empty(), "subspaces {:?} and {:?} overlap", a, b);
+
+        let nf = self.num_fields;
+        let (i0, j0) = space.start();
+        let row_len = space.dim().1 * nf;
+        let segment = |s: &IndexSpace, i: i64| {
+            (!s.is_empty() && (s.start().0..s.end().0).contains(&i))
+                .then(|| ((s.start().1 - j0) as usize * nf, (s.end().1 - j0) as usize * nf))
+        };
+        let mut rows_a = Vec::new();
+        let mut rows_b = Vec::new();
+
+        if row_len > 0 {
+            for (i, row) in (i0..).zip(self.data.chunks_exact_mut(row_len)) {
+                match (segment(&a, i), segment(&b, i)) {
+                    (None, None) => {}
+                    (Some((s, e)), None) => rows_a.push(&mut row[s..e]),
+                    (None, Some((s, e))) => rows_b.push(&mut row[s..e]),
+                    (Some((sa, ea)), Some((sb, eb))) if ea <= sb => {
+                        let (l, r) = row.split_at_mut(sb);
+                        rows_a.push(&mut l[sa..ea]);
+                        rows_b.push(&mut r[..eb - sb]);
+                    }
+                    (Some((sa, ea)), Some((sb, eb))) => {
+                        let (l, r) = row.split_at_mut(sa);
+                        rows_b.push(&mut l[sb..eb]);
+                        rows_a.push(&mut r[..ea - sa]);
+                    }
+                }
+            }
+        }
+        (
+            rows_a.into_iter().flat_map(move |row| row.chunks_exact_mut(nf)),
+            rows_b.into_iter().flat_map(move |row| row.chunks_exact_mut(nf)),
+        )
+    }
+
+    /// Return the contiguous data for row `i` of this patch, i.e. the values
+    /// at indexes `(i, j)` for each `j` in the index space, with the fields
+    /// of each zone adjacent. This method panics if the row is out of
+    /// bounds.
+    pub fn row(&self, i: i64) -> &[f64] {
+        let space = self.index_space();
+        assert!(space.to_rect_ref().0.contains(&i), "row {} is out of bounds", i);
+        let n = space.dim().1 * self.num_fields;
+        let s = (i - space.start().0) as usize * n;
+        &self.data[s..s + n]
+    }
+
+    /// Like [`Patch::row`], but returning a mutable slice.
+    pub fn row_mut(&mut self, i: i64) -> &mut [f64] {
+        let space = self.index_space();
+        assert!(space.to_rect_ref().0.contains(&i), "row {} is out of bounds", i);
+        let n = space.dim().1 * self.num_fields;
+        let s = (i - space.start().0) as usize * n;
+        &mut self.data[s..s + n]
+    }
+
+    /// Return an iterator over the rows of this patch, in order of
+    /// increasing `i`. Kernels can use this to write explicit `(i, j)` loops
+    /// without computing an offset for each zone.
+    pub fn rows(&self) -> impl Iterator<Item = &'_ [f64]> {
+        self.select_rows(self.index_space())
+    }
+
+    /// Like [`Patch::rows`], but yielding mutable row slices.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &'_ mut [f64]> {
+        self.select_rows_mut(self.index_space())
+    }
+
+    /// Return an iterator over the rows of a subspace of this patch. Each
+    /// row is the contiguous part of a patch row within the subspace.
+    pub fn select_rows(&self, subspace: IndexSpace) -> impl Iterator<Item = &'_ [f64]> {
+        subspace.memory_region_in(self.index_space()).iter_rows(&self.data, self.num_fields)
+    }
+
+    /// Like [`Patch::select_rows`], but yielding mutable row slices.
+    pub fn select_rows_mut(&mut self, subspace: IndexSpace) -> impl Iterator<Item = &'_ mut [f64]> {
+        subspace.memory_region_in(self.index_space()).iter_rows_mut(&mut self.data, self.num_fields)
+    }
+
+    /// Return the number of bytes held by the patch data and mask.
+    pub fn size_in_bytes(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f64>() + self.mask.as_ref().map_or(0, Vec::len)
+    }
+
     /// Return this patch's rectangle.
     pub fn local_rect(&self) -> &Rectangle<i64> {
         &self.rect
@@ -186,11 +385,24 @@ impl Patch {
     }
 
     /// Sample the field at the given level and index. The index measures
-    /// ticks at the target sampling level, not the HRIS.
+    /// ticks at the target sampling level, not the HRIS. The index and field
+    /// are checked in debug builds, or if the `checks` feature is enabled.
     pub fn sample(&self, level: u32, index: (i64, i64), field: usize) -> f64 {
+        self.sample_with(level, index, field, crate::CHECKS)
+    }
+
+    /// Like [`Patch::sample`], but never checks the index and field, for use
+    /// where they have already been validated.
+    pub(crate) fn sample_unchecked(&self, level: u32, index: (i64, i64), field: usize) -> f64 {
+        self.sample_with(level, index, field, false)
+    }
+
+    fn sample_with(&self, level: u32, index: (i64, i64), field: usize, check: bool) -> f64 {
         match level.cmp(&self.level) {
             Equal => {
-                self.validate_index(index, field);
+                if check {
+                    self.validate_index(index, field);
+                }
 
                 let (i0, j0) = self.index_space().start();
                 let i = (index.0 - i0) as usize;
@@ -199,25 +411,25 @@ impl Patch {
                 let (_m, n) = self.index_space().dim();
                 self.data[(i * n + j) * self.num_fields + field]
             }
-            Less => self.sample(level + 1, (index.0 / 2, index.1 / 2), field),
+            Less => self.sample_with(level + 1, (index.0 / 2, index.1 / 2), field, check),
             Greater => {
-                let y00 = self.sample(level - 1, (index.0 * 2, index.1 * 2), field);
-                let y01 = self.sample(level - 1, (index.0 * 2, index.1 * 2 + 1), field);
-                let y10 = self.sample(level - 1, (index.0 * 2 + 1, index.1 * 2), field);
-                let y11 = self.sample(level - 1, (index.0 * 2 + 1, index.1 * 2 + 1), field);
+                let y00 = self.sample_with(level - 1, (index.0 * 2, index.1 * 2), field, check);
+                let y01 = self.sample_with(level - 1, (index.0 * 2, index.1 * 2 + 1), field, check);
+                let y10 = self.sample_with(level - 1, (index.0 * 2 + 1, index.1 * 2), field, check);
+                let y11 = self.sample_with(level - 1, (index.0 * 2 + 1, index.1 * 2 + 1), field, check);
                 0.25 * (y00 + y01 + y10 + y11)
             }
         }
     }
 
     /// Sample all the fields in this patch at the given index and return the
-    /// result as a fixed-length array. The array size must be less than or
+    /// result as a fixed-length vector. The vector size must be less than or
     /// equal to the number of fields.
     pub fn sample_vector<const NUM_FIELDS: usize>(
         &self,
         level: u32,
         index: (i64, i64),
-    ) -> [f64; NUM_FIELDS] {
+    ) -> Vector<f64, NUM_FIELDS> {
         assert! {
             NUM_FIELDS <= self.num_fields,
             "attempt to sample {} fields from a patch with {} fields",
@@ -225,8 +437,10 @@ impl Patch {
             self.num_fields
         };
 
-        let mut result = [0.0; NUM_FIELDS];
-        self.sample_slice(level, index, &mut result);
+        let mut result = Vector::default();
+        for (field, x) in result.iter_mut().enumerate() {
+            *x = self.sample(level, index, field)
+        }
         result
     }
 
@@ -262,9 +476,87 @@ impl Patch {
             "the index space is out of bounds"
         }
 
-        Self::from_slice_function(self.level, subset, self.num_fields, |index, slice| {
-            slice.clone_from_slice(self.get_slice(index))
-        })
+        let mut data = Vec::with_capacity(subset.len() * self.num_fields);
+
+        for row in self.select_rows(subset.clone()) {
+            data.extend_from_slice(row)
+        }
+        Self {
+            level: self.level,
+            mask: self.mask_subset(&subset),
+            rect: subset.into(),
+            num_fields: self.num_fields,
+            data,
+        }
+    }
+
+    /// Extract the data in a precomputed [`Selection`] of this patch. This
+    /// copies whole rows, and does no geometry calculations, so it is cheaper
+    /// than [`Patch::extract`] when the same subset is extracted repeatedly.
+    pub fn extract_selection(&self, selection: &Selection) -> Self {
+        if crate::CHECKS {
+            assert!(selection.parent == self.rect, "the selection was made for another patch");
+        }
+        let mut data = Vec::with_capacity(selection.space.len() * self.num_fields);
+
+        for row in selection.region.iter_rows(&self.data, self.num_fields) {
+            data.extend_from_slice(row)
+        }
+        Self {
+            level: self.level,
+            rect: selection.space.clone().into(),
+            num_fields: self.num_fields,
+            data,
+            mask: self.mask_subset(&selection.space),
+        }
+    }
+
+    /// Return a copy of one field of this patch, as a single-field patch.
+    /// This method panics if the field is out of range.
+    pub fn field(&self, field: usize) -> Self {
+        self.fields(field..field + 1)
+    }
+
+    /// Return a copy of a contiguous range of fields of this patch, as a
+    /// patch with that many fields. This method panics if the range is out
+    /// of bounds.
+    pub fn fields(&self, fields: Range<usize>) -> Self {
+        assert! {
+            fields.start <= fields.end && fields.end <= self.num_fields,
+            "fields {}..{} out of range on patch with {} fields",
+            fields.start,
+            fields.end,
+            self.num_fields
+        };
+        let mut data = Vec::with_capacity(self.index_space().len() * fields.len());
+
+        for zone in self.data.chunks_exact(self.num_fields) {
+            data.extend_from_slice(&zone[fields.clone()])
+        }
+        Self {
+            level: self.level,
+            rect: self.rect.clone(),
+            num_fields: fields.len(),
+            data,
+            mask: self.mask.clone(),
+        }
+    }
+
+    /// Copy one field of this patch into the given buffer, in row-major
+    /// order, without allocating. This method panics if the field is out of
+    /// range, or if the buffer length is not the number of zones.
+    pub fn copy_field_into(&self, field: usize, buffer: &mut [f64]) {
+        assert! {
+            field < self.num_fields,
+            "field index {} out of range on patch with {} fields",
+            field,
+            self.num_fields
+        };
+        assert_eq!(buffer.len(), self.index_space().len(), "buffer length must equal the number of zones");
+
+        for (x, zone) in buffer.iter_mut().zip(self.data.chunks_exact(self.num_fields)) {
+            *x = zone[field]
+        }
     }
 
     pub fn map_index_mut<F>(&mut self, f: F)
@@ -303,6 +595,18 @@ impl Patch {
             .for_each(|x| f(x.0, x.1))
     }
 
+    /// Like [`Patch::map_into`], but with the number of fields fixed at
+    /// compile time, which allows the compiler to unroll per-field loops in
+    /// the closure. This function panics if the patches do not have
+    /// `NUM_FIELDS` fields.
+    pub fn map_into_fixed<F, const NUM_FIELDS: usize>(&self, target: &mut Self, f: F)
+    where
+        F: Fn(&[f64; NUM_FIELDS], &mut [f64; NUM_FIELDS]),
+    {
+        assert!(self.num_fields == NUM_FIELDS);
+        self.map_into(target, |a, b| f(a.try_into().unwrap(), b.try_into().unwrap()))
+    }
+
     pub fn map<F>(&self, f: F) -> Self
     where
         F: Fn(&[f64], &mut [f64]),
@@ -317,9 +621,196 @@ impl Patch {
             rect: self.rect.clone(),
             num_fields: self.num_fields,
             data,
+            mask: self.mask.clone(),
+        }
+    }
+
+    /// Return a patch whose data is linearly interpolated between this patch
+    /// (at `fraction = 0`) and a later state of the same patch (at `fraction
+    /// = 1`). This is used to approximate guard zone data from a neighbor
+    /// that is mid-way through a longer time step. The two patches must have
+    /// the same level, index space, and number of fields.
+    pub fn interpolate_in_time(&self, later: &Self, fraction: f64) -> Self {
+        assert!(self.level == later.level);
+        assert!(self.rect == later.rect);
+        assert!(self.num_fields == later.num_fields);
+
+        Self {
+            level: self.level,
+            rect: self.rect.clone(),
+            num_fields: self.num_fields,
+            data: self
+                .data
+                .iter()
+                .zip(&later.data)
+                .map(|(a, b)| a + (b - a) * fraction)
+                .collect(),
+            mask: self.mask.clone(),
+        }
+    }
+
+    /// Return a copy of this patch, translated by the given offset on the
+    /// high-resolution index space. This is used to place data received from
+    /// across a periodic boundary at the receiver's indexes. The offset must
+    /// be a multiple of the patch's refinement factor.
+    pub fn translate(&self, offset: (i64, i64)) -> Self {
+        let factor = 1 << self.level;
+        assert! {
+            offset.0 % factor == 0 && offset.1 % factor == 0,
+            "offset ({} {}) is not aligned with patch at level {}",
+            offset.0,
+            offset.1,
+            self.level
+        };
+        Self {
+            level: self.level,
+            rect: self.index_space().translate_by((offset.0 / factor, offset.1 / factor)).into(),
+            num_fields: self.num_fields,
+            data: self.data.clone(),
+            mask: self.mask.clone(),
+        }
+    }
+
+    /// Extract the parts of this patch which fall in a region wrapped around
+    /// a periodic domain (see [`crate::meshing::Periodicity::wrap`]), each
+    /// translated to its place in the region. The patch is given in the
+    /// domain's coordinates, and the region's pieces must be aligned with
+    /// its level. The returned patches can be used to fill guard zones
+    /// across a periodic boundary, e.g. with
+    /// [`crate::meshing::extend_patch_mut`], without handling the
+    /// translations separately.
+    pub fn extract_wrapped(&self, region: &WrappedRegion) -> Vec<Self> {
+        let factor = 1 << self.level;
+        region
+            .overlaps(&self.high_resolution_space())
+            .map(|(overlap, t)| self.extract(overlap.coarsen_by(factor)).translate(t))
+            .collect()
+    }
+
+    /// Return a copy of this patch with the `i` and `j` axes exchanged: the
+    /// value at index `(i, j)` in this patch is at `(j, i)` in the returned
+    /// one. Since data is stored in row-major order, this reorders the
+    /// backing storage so that a sweep along the `i` axis of this patch can
+    /// be done as a contiguous sweep along the `j` axis of the transpose.
+    pub fn transpose(&self) -> Self {
+        let space = self.index_space();
+        let (ni, nj) = space.dim();
+        let nq = self.num_fields;
+        let mut data = vec![0.0; self.data.len()];
+
+        for i in 0..ni {
+            for j in 0..nj {
+                let source = (i * nj + j) * nq;
+                let target = (j * ni + i) * nq;
+                data[target..target + nq].copy_from_slice(&self.data[source..source + nq]);
+            }
+        }
+        Self {
+            level: self.level,
+            rect: space.swap_axes().into(),
+            num_fields: nq,
+            data,
+            mask: self.mask.as_ref().map(|_| space.swap_axes().iter().map(|(j, i)| self.is_solid((i, j))).collect()),
+        }
+    }
+
+    /// Return a copy of this patch at a resolution coarser by the given factor,
+    /// on the same level. Each zone of the returned patch is the average of
+    /// the `factor x factor` zones it covers, so that totals are conserved,
+    /// and it is solid if any of them is solid. A mesh error is returned if
+    /// the factor does not divide the patch index space.
+    pub fn try_coarsen_by(&self, factor: u32) -> Result<Self> {
+        let space = self.index_space().try_coarsen_by(factor)?;
+        let f = factor as i64;
+        let weight = 1.0 / (f * f) as f64;
+        let fine_zones = |(i, j): (i64, i64)| IndexSpace::new(i * f..(i + 1) * f, j * f..(j + 1) * f);
+
+        let coarse = Self::from_slice_function(self.level, space, self.num_fields, |index, slice| {
+            for fine in fine_zones(index).iter() {
+                for (x, y) in slice.iter_mut().zip(self.get_slice(fine)) {
+                    *x += y * weight
+                }
+            }
+        });
+        Ok(match self.mask {
+            Some(_) => coarse.with_mask(|index| fine_zones(index).iter().any(|fine| self.is_solid(fine))),
+            None => coarse,
+        })
+    }
+
+    /// Return a copy of this patch at a resolution finer by the given factor,
+    /// on the same level. Data and solid zone flags are injected piecewise
+    /// constant, so that totals are conserved.
+    pub fn refine_by(&self, factor: u32) -> Self {
+        let f = factor as i64;
+        let coarse_zone = |(i, j): (i64, i64)| (i.div_euclid(f), j.div_euclid(f));
+        let space = self.index_space().refine_by(factor);
+        let fine = Self::from_slice_function(self.level, space, self.num_fields, |index, slice| {
+            slice.copy_from_slice(self.get_slice(coarse_zone(index)))
+        });
+        match self.mask {
+            Some(_) => fine.with_mask(|index| self.is_solid(coarse_zone(index))),
+            None => fine,
         }
     }
 
+    /// Generate a single-field patch from rows of values, for writing tests.
+    /// Row `n` holds the values at `i = start.0 + n`, and its `m`-th value is
+    /// at `j = start.1 + m`, the same order as [`Patch::rows`]. The rows must
+    /// all have the same length.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn from_rows(level: u32, start: (i64, i64), rows: &[&[f64]]) -> Self {
+        let n = rows.first().map_or(0, |row| row.len());
+        assert!(rows.iter().all(|row| row.len() == n), "rows must all have the same length");
+
+        let space = IndexSpace::new(start.0..start.0 + rows.len() as i64, start.1..start.1 + n as i64);
+        Self::from_scalar_function(level, space, |(i, j)| rows[(i - start.0) as usize][(j - start.1) as usize])
+    }
+
+    /// Compare two patches, and return a description of how they differ, or
+    /// `None` if they have the same level, index space, and number of fields,
+    /// and all their values agree to within the tolerance (NaN agrees only
+    /// with NaN). At most 10 mismatching values are listed. This is used by
+    /// [`assert_patch_eq`].
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn mismatch_report(&self, other: &Self, tolerance: f64) -> Option<String> {
+        if self.level != other.level || self.rect != other.rect || self.num_fields != other.num_fields {
+            return Some(format!(
+                "patches differ in layout: level {} {:?} with {} fields vs. level {} {:?} with {} fields",
+                self.level, self.rect, self.num_fields, other.level, other.rect, other.num_fields
+            ));
+        }
+        let mismatches: Vec<_> = self
+            .index_space()
+            .iter()
+            .flat_map(|index| (0..self.num_fields).map(move |field| (index, field)))
+            .zip(self.data.iter().zip(&other.data))
+            .filter(|(_, (a, b))| (*a - *b).abs() > tolerance || a.is_nan() != b.is_nan())
+            .collect();
+
+        if mismatches.is_empty() {
+            return None;
+        }
+        let lines: Vec<_> = mismatches
+            .iter()
+            .take(10)
+            .map(|((index, field), (a, b))| format!("  {:?} field {}: {} vs. {}", index, field, a, b))
+            .collect();
+        Some(format!(
+            "{} values differ by more than {}:\n{}{}",
+            mismatches.len(),
+            tolerance,
+            lines.join("\n"),
+            if mismatches.len() > lines.len() { "\n  ..." } else { "" }
+        ))
+    }
+
+    /// Return the mask flags for a subset of this patch, or `None` if this
+    /// patch has no mask.
+    fn mask_subset(&self, subset: &IndexSpace) -> Option<Vec<bool>> {
+        self.mask.as_ref().map(|_| subset.iter().map(|index| self.is_solid(index)).collect())
+    }
+
     fn validate_index(&self, index: (i64, i64), field: usize) {
         let space = self.index_space();
 
@@ -349,11 +840,276 @@ impl Default for Patch {
     }
 }
 
+/// A subset of a patch's index space, together with its memory region in
+/// the patch data. Selections can be computed once for a fixed patch layout,
+/// and then used to extract data from the patch with
+/// [`Patch::extract_selection`].
+#[derive(Clone, Debug)]
+pub struct Selection {
+    parent: Rectangle<i64>,
+    space: IndexSpace,
+    region: MemoryRegion,
+}
+
+impl Selection {
+    /// Select the given subset of a patch index space. This method panics if
+    /// the subset is out of bounds.
+    pub fn new(parent: &IndexSpace, subset: IndexSpace) -> Self {
+        assert! {
+            parent.contains_space(&subset),
+            "the index space is out of bounds"
+        }
+        Self {
+            parent: parent.clone().into(),
+            region: subset.memory_region_in(parent.clone()),
+            space: subset,
+        }
+    }
+
+    /// Return the selected index space.
+    pub fn index_space(&self) -> &IndexSpace {
+        &self.space
+    }
+}
+
+/// The floating point precision in which patch data is stored between
+/// computations, or sent in messages. Computations are always done in `f64`.
+/// The lossy options are off by default; only use them for data which
+/// tolerates the loss, like the guard zones of some explicit schemes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    Double,
+    Single,
+
+    /// 16-bit integers, with an offset and a step size for each field, so
+    /// values are rounded to 1/65534 of the field's range over the patch.
+    /// Non-finite values are stored as NaN.
+    Quantized,
+}
+
+impl Precision {
+    /// The name of the run parameter which selects the precision of guard
+    /// zone messages.
+    pub const PARAMETER: &'static str = "message.precision";
+
+    /// Return the message precision for a run, from its parameters. The
+    /// precision is double if the parameter is not present. Every rank
+    /// should use the same parameters, although stored patches are
+    /// self-describing, so a patch can be decoded whatever its precision.
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self> {
+        match parameters.get(Self::PARAMETER) {
+            None => Ok(Self::Double),
+            Some(_) => parameters.get_text(Self::PARAMETER)?.parse(),
+        }
+    }
+
+    /// Return the run parameter for this message precision.
+    pub fn parameters(self) -> Parameters {
+        Parameters::new().mutable(Self::PARAMETER, self.to_string())
+    }
+}
+
+impl FromStr for Precision {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "double" => Ok(Self::Double),
+            "single" => Ok(Self::Single),
+            "quantized" => Ok(Self::Quantized),
+            _ => Err(Error::Parameters(format!(
+                "unknown precision '{}' (options are double|single|quantized)",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Double => write!(fmt, "double"),
+            Self::Single => write!(fmt, "single"),
+            Self::Quantized => write!(fmt, "quantized"),
+        }
+    }
+}
+
+/// The largest quantized value; `u16::MAX` is reserved for NaN.
+const QUANTIZED_MAX: u16 = u16::MAX - 1;
+
+/// Patch data held in a chosen [`Precision`]. Memory-bound problems which
+/// accept single precision can store their patches (or send them in
+/// messages) in this form, halving the memory traffic and message sizes, and
+/// convert them back to a [`Patch`] at the boundary of the hydro kernel.
+/// Storing a patch in double precision is free, and lossless.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub enum StoredPatch {
+    Double(Patch),
+    Single {
+        level: u32,
+        rect: Rectangle<i64>,
+        num_fields: usize,
+        data: Vec<f32>,
+        #[serde(default)]
+        mask: Option<Vec<bool>>,
+    },
+    Quantized {
+        level: u32,
+        rect: Rectangle<i64>,
+        num_fields: usize,
+        offsets: Vec<f64>,
+        steps: Vec<f64>,
+        data: Vec<u16>,
+        #[serde(default)]
+        mask: Option<Vec<bool>>,
+    },
+}
+
+impl StoredPatch {
+    /// Store a patch in the given precision.
+    pub fn new(patch: Patch, precision: Precision) -> Self {
+        match precision {
+            Precision::Double => Self::Double(patch),
+            Precision::Single => Self::Single {
+                level: patch.level,
+                rect: patch.rect,
+                num_fields: patch.num_fields,
+                data: patch.data.iter().map(|&x| x as f32).collect(),
+                mask: patch.mask,
+            },
+            Precision::Quantized => {
+                let nf = patch.num_fields;
+                let mut offsets = vec![f64::INFINITY; nf];
+                let mut upper = vec![f64::NEG_INFINITY; nf];
+
+                // A patch with no fields has no data, but chunks must not
+                // be empty.
+                for zone in patch.data.chunks_exact(nf.max(1)) {
+                    for ((x, lo), hi) in zone.iter().zip(&mut offsets).zip(&mut upper) {
+                        if x.is_finite() {
+                            *lo = lo.min(*x);
+                            *hi = hi.max(*x);
+                        }
+                    }
+                }
+                let steps: Vec<_> = offsets
+                    .iter()
+                    .zip(&upper)
+                    .map(|(lo, hi)| if hi > lo { (hi - lo) / QUANTIZED_MAX as f64 } else { 0.0 })
+                    .collect();
+                let data = patch
+                    .data
+                    .chunks_exact(nf.max(1))
+                    .flat_map(|zone| zone.iter().zip(&offsets).zip(&steps))
+                    .map(|((x, lo), step)| match (x.is_finite(), *step > 0.0) {
+                        (false, _) => u16::MAX,
+                        (true, false) => 0,
+                        (true, true) => ((x - lo) / step).round() as u16,
+                    })
+                    .collect();
+
+                Self::Quantized {
+                    level: patch.level,
+                    rect: patch.rect,
+                    num_fields: nf,
+                    offsets,
+                    steps,
+                    data,
+                    mask: patch.mask,
+                }
+            }
+        }
+    }
+
+    /// Return the precision the data is stored in.
+    pub fn precision(&self) -> Precision {
+        match self {
+            Self::Double(_) => Precision::Double,
+            Self::Single { .. } => Precision::Single,
+            Self::Quantized { .. } => Precision::Quantized,
+        }
+    }
+
+    /// Return the index space covered by the stored patch.
+    pub fn index_space(&self) -> IndexSpace {
+        match self {
+            Self::Double(patch) => patch.index_space(),
+            Self::Single { rect, .. } | Self::Quantized { rect, .. } => IndexSpace::from(rect.clone()),
+        }
+    }
+
+    /// Return the number of bytes held by the stored data.
+    pub fn size_in_bytes(&self) -> usize {
+        match self {
+            Self::Double(patch) => patch.data.len() * std::mem::size_of::<f64>(),
+            Self::Single { data, .. } => data.len() * std::mem::size_of::<f32>(),
+            Self::Quantized { data, offsets, steps, .. } => {
+                data.len() * std::mem::size_of::<u16>() + (offsets.len() + steps.len()) * std::mem::size_of::<f64>()
+            }
+        }
+    }
+
+    /// Convert the stored data back to a double precision patch.
+    pub fn into_patch(self) -> Patch {
+        match self {
+            Self::Double(patch) => patch,
+            Self::Single {
+                level,
+                rect,
+                num_fields,
+                data,
+                mask,
+            } => Patch {
+                level,
+                rect,
+                num_fields,
+                data: data.into_iter().map(f64::from).collect(),
+                mask,
+            },
+            Self::Quantized {
+                level,
+                rect,
+                num_fields,
+                offsets,
+                steps,
+                data,
+                mask,
+            } => Patch {
+                level,
+                rect,
+                num_fields,
+                data: data
+                    .chunks_exact(num_fields.max(1))
+                    .flat_map(|zone| zone.iter().zip(&offsets).zip(&steps))
+                    .map(|((&q, lo), step)| if q == u16::MAX { f64::NAN } else { lo + q as f64 * step })
+                    .collect(),
+                mask,
+            },
+        }
+    }
+}
+
+/// Assert that two patches have the same layout, and that their values agree
+/// to within a tolerance. On failure, the mismatching indexes and fields are
+/// listed (see [`Patch::mismatch_report`]).
+#[cfg(any(test, feature = "test-support"))]
+#[macro_export]
+macro_rules! assert_patch_eq {
+    ($a:expr, $b:expr, $tolerance:expr $(,)?) => {
+        if let Some(report) = $crate::patch::Patch::mismatch_report(&$a, &$b, $tolerance) {
+            panic!("assertion failed: `{} == {}`\n{}", stringify!($a), stringify!($b), report)
+        }
+    };
+}
+
 #[cfg(test)]
 mod test {
 
-    use super::Patch;
+    use super::{Patch, Precision, Selection, StoredPatch, POISON};
     use crate::index_space::{range2d, IndexSpace};
+    use crate::parameters::Parameters;
     use crate::rect_map::{Rectangle, RectangleMap, RectangleRef};
 
     fn finest_patch<'a>(
@@ -428,4 +1184,228 @@ mod test {
 
         assert_eq!(p12.sample(0, (20, 20), 0), p21.sample(0, (20, 20), 0));
     }
+
+    #[test]
+    fn patch_can_interpolate_in_time() {
+        let a = Patch::from_scalar_function(0, (0..4, 0..4), |_| 1.0);
+        let b = Patch::from_scalar_function(0, (0..4, 0..4), |_| 3.0);
+        assert!(a.interpolate_in_time(&b, 0.25).data().iter().all(|&x| x == 1.5));
+    }
+
+    #[test]
+    fn patch_can_be_transposed() {
+        let patch = Patch::from_slice_function(0, (2..5, 10..14), 2, |(i, j), p| {
+            p[0] = i as f64;
+            p[1] = j as f64;
+        });
+        let transpose = patch.transpose();
+        assert_eq!(transpose.index_space().start(), (10, 2));
+        assert_eq!(transpose.index_space().dim(), (4, 3));
+        assert_eq!(transpose.get_slice((12, 3)), [3.0, 12.0]);
+        assert_eq!(transpose.transpose().data(), patch.data());
+    }
+
+    #[test]
+    fn fixed_field_count_map_agrees_with_dynamic() {
+        let source = Patch::from_vector_function(0, (0..4, 0..4), |(i, j)| [i as f64, j as f64]);
+        let mut a = Patch::zeros(0, 2, (2..6, 0..4));
+        let mut b = a.clone();
+        source.map_into(&mut a, |x, y| y.copy_from_slice(&[x[1], x[0]]));
+        source.map_into_fixed::<_, 2>(&mut b, |x, y| *y = [x[1], x[0]]);
+        assert_eq!(a.data(), b.data());
+        assert_eq!(b.get_slice((3, 1)), [1.0, 3.0]);
+    }
+
+    #[test]
+    fn single_precision_storage_halves_size_and_round_trips() {
+        let patch = Patch::from_scalar_function(1, (0..4, 2..6), |(i, j)| i as f64 + 0.1 * j as f64);
+        let double = StoredPatch::new(patch.clone(), Precision::Double);
+        let single = StoredPatch::new(patch.clone(), Precision::Single);
+
+        assert_eq!(single.size_in_bytes() * 2, double.size_in_bytes());
+        assert_eq!(single.index_space().start(), (0, 2));
+        assert_eq!(double.into_patch().data(), patch.data());
+
+        let restored = single.into_patch();
+        assert_eq!(restored.level(), 1);
+        for (a, b) in restored.data().iter().zip(patch.data()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn quantized_storage_is_within_one_step_per_field() {
+        let patch = Patch::from_slice_function(0, (0..8, 0..8), 3, |(i, j), p| {
+            p[0] = 1.0 + 1e-3 * (i * 8 + j) as f64;
+            p[1] = -50.0 * i as f64;
+            p[2] = if (i, j) == (3, 3) { f64::NAN } else { 7.0 };
+        });
+        let stored = StoredPatch::new(patch.clone(), Precision::Quantized);
+        let double = StoredPatch::new(patch.clone(), Precision::Double);
+
+        assert_eq!(stored.precision(), Precision::Quantized);
+        assert!(stored.size_in_bytes() * 3 < double.size_in_bytes());
+
+        let restored = stored.into_patch();
+
+        for (a, b) in restored.data().chunks(3).zip(patch.data().chunks(3)) {
+            assert!((a[0] - b[0]).abs() <= 0.5 * 0.063 / 65534.0 + 1e-15);
+            assert!((a[1] - b[1]).abs() <= 0.5 * 350.0 / 65534.0 + 1e-12);
+            assert!(a[2] == b[2] || (a[2].is_nan() && b[2].is_nan()));
+        }
+
+        let empty = StoredPatch::new(Patch::zeros(0, 0, (0..4, 0..4)), Precision::Quantized).into_patch();
+        assert_eq!((empty.num_fields(), empty.data().len()), (0, 0));
+    }
+
+    #[test]
+    fn message_precision_is_a_run_parameter() {
+        for precision in [Precision::Double, Precision::Single, Precision::Quantized] {
+            assert_eq!(Precision::from_parameters(&precision.parameters()).unwrap(), precision);
+        }
+        assert_eq!(Precision::from_parameters(&Parameters::new()).unwrap(), Precision::Double);
+        assert!("half".parse::<Precision>().is_err());
+    }
+
+    #[test]
+    fn patch_rows_are_contiguous_slices() {
+        let mut patch = Patch::from_slice_function(0, (2..5, 10..14), 2, |(i, j), p| {
+            p[0] = i as f64;
+            p[1] = j as f64;
+        });
+        assert_eq!(patch.rows().count(), 3);
+        assert_eq!(patch.row(3), [3.0, 10.0, 3.0, 11.0, 3.0, 12.0, 3.0, 13.0]);
+
+        let rows: Vec<_> = patch.select_rows(IndexSpace::new(3..5, 11..13)).collect();
+        assert_eq!(rows, [[3.0, 11.0, 3.0, 12.0], [4.0, 11.0, 4.0, 12.0]]);
+
+        for row in patch.select_rows_mut(IndexSpace::new(4..5, 12..14)) {
+            row.fill(0.0)
+        }
+        assert_eq!(patch.get_slice((4, 11)), [4.0, 11.0]);
+        assert_eq!(patch.get_slice((4, 13)), [0.0, 0.0]);
+        patch.row_mut(2)[1] = -1.0;
+        assert_eq!(patch.get_slice((2, 10)), [2.0, -1.0]);
+    }
+
+    #[test]
+    fn disjoint_subspaces_can_be_selected_mutably_together() {
+        let mut patch = Patch::from_slice_function(0, (0..4, 0..6), 2, |(i, j), p| {
+            p[0] = i as f64;
+            p[1] = j as f64;
+        });
+        let interior = IndexSpace::new(1..3, 1..5);
+        let left = IndexSpace::new(0..4, 0..1);
+        let (a, b) = patch.select_disjoint_mut(interior.clone(), left.clone());
+        let (a, b): (Vec<_>, Vec<_>) = (a.collect(), b.collect());
+        assert_eq!(a.len(), interior.len());
+        assert_eq!(b.len(), left.len());
+
+        for (x, y) in a.into_iter().zip(b) {
+            x[0] = y[0] + 10.0;
+            y[1] = -1.0;
+        }
+        let values: Vec<_> = patch.select(interior).map(|p| p[0]).collect();
+        assert_eq!(values, [10.0, 11.0, 12.0, 13.0, 2.0, 2.0, 2.0, 2.0]);
+        assert!(patch.select(left).all(|p| p[1] == -1.0));
+
+        let (a, b) = patch.select_disjoint_mut(IndexSpace::new(2..3, 3..6), IndexSpace::new(2..3, 0..3));
+        assert_eq!((a.count(), b.count()), (3, 3));
+        let (a, b) = patch.select_disjoint_mut(IndexSpace::new(2..2, 0..6), patch.index_space());
+        assert_eq!((a.count(), b.count()), (0, 24));
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlapping_subspaces_cannot_be_selected_mutably_together() {
+        let mut patch = Patch::zeros(0, 1, IndexSpace::new(0..4, 0..4));
+        let _ = patch.select_disjoint_mut(IndexSpace::new(0..2, 0..2), IndexSpace::new(1..3, 1..3));
+    }
+
+    #[test]
+    fn extracting_a_selection_matches_extract() {
+        let patch = Patch::from_slice_function(1, (2..6, 10..15), 2, |(i, j), p| {
+            p[0] = i as f64;
+            p[1] = j as f64;
+        });
+        let subset = IndexSpace::new(3..5, 11..13);
+        let selection = Selection::new(&patch.index_space(), subset.clone());
+        let a = patch.extract(subset);
+        let b = patch.extract_selection(&selection);
+        assert_eq!(a.data(), b.data());
+        assert_eq!(a.local_rect(), b.local_rect());
+        assert_eq!(b.level(), 1);
+    }
+
+    #[test]
+    fn masks_follow_patch_data() {
+        let patch = Patch::from_scalar_function(0, (0..4, 0..6), |(i, j)| (i * 10 + j) as f64)
+            .with_mask(|(i, j)| i == 1 && j >= 3);
+        let subset = IndexSpace::new(1..3, 2..5);
+
+        assert!(patch.is_solid((1, 4)) && !patch.is_solid((2, 4)) && !patch.is_solid((1, 6)));
+        assert_eq!(patch.extract(subset.clone()).mask(), Some(&[false, true, true, false, false, false][..]));
+        let selection = Selection::new(&patch.index_space(), subset.clone());
+        assert_eq!(patch.extract_selection(&selection).mask(), patch.extract(subset).mask());
+        assert!(patch.translate((2, 0)).is_solid((3, 3)));
+        assert!(patch.transpose().is_solid((5, 1)) && !patch.transpose().is_solid((1, 5)));
+        assert!(StoredPatch::new(patch.clone(), Precision::Single).into_patch().is_solid((1, 3)));
+
+        let mut plain = Patch::zeros(0, 1, (0..2, 0..2));
+        plain.set_solid((0, 0), false);
+        assert!(plain.mask().is_none());
+        plain.set_solid((1, 0), true);
+        assert_eq!(plain.mask(), Some(&[false, false, true, false][..]));
+    }
+
+    #[test]
+    fn fields_can_be_sliced_into_their_own_patches() {
+        let patch = Patch::from_vector_function(1, (0..3, 2..4), |(i, j)| [i as f64, j as f64, (i * j) as f64]);
+        let density = patch.field(2);
+        let velocity = patch.fields(0..2);
+
+        assert_eq!(density.num_fields(), 1);
+        assert_eq!(density.level(), 1);
+        assert_eq!(density.get_slice((2, 3)), [6.0]);
+        assert_eq!(velocity.num_fields(), 2);
+        assert_eq!(velocity.get_slice((2, 3)), [2.0, 3.0]);
+
+        let mut buffer = vec![0.0; 6];
+        patch.copy_field_into(1, &mut buffer);
+        assert_eq!(buffer, [2.0, 3.0, 2.0, 3.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn patches_can_be_written_as_rows_and_compared() {
+        let patch = Patch::from_rows(0, (2, -1), &[&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]]);
+        assert_eq!(patch.local_rect(), &(2..4, -1..2));
+        assert_eq!(patch.row(3), &[4.0, 5.0, 6.0]);
+        assert_eq!(patch.sample(0, (2, 1), 0), 3.0);
+
+        let coarse = Patch::from_rows(0, (0, 0), &[&[1.0, 2.0], &[3.0, 4.0]]).try_coarsen_by(2).unwrap();
+        assert_patch_eq!(coarse, Patch::from_rows(0, (0, 0), &[&[2.5]]), 1e-12);
+
+        let other = Patch::from_rows(0, (2, -1), &[&[1.0, 2.0, 3.0], &[4.0, 5.5, f64::NAN]]);
+        let report = patch.mismatch_report(&other, 0.1).unwrap();
+        assert!(report.starts_with("2 values differ"));
+        assert!(report.contains("(3, 0) field 0: 5 vs. 5.5"));
+        assert!(report.contains("(3, 1) field 0: 6 vs. NaN"));
+        assert!(patch.mismatch_report(&other, 1.0).is_some());
+        assert!(patch.mismatch_report(&patch.translate((1, 0)), 1.0).unwrap().contains("layout"));
+    }
+
+    #[test]
+    fn poisoned_zones_are_found_until_overwritten() {
+        let mut patch = Patch::zeros(0, 2, range2d(0..4, 0..4));
+        patch.poison(range2d(1..3, 2..4));
+        assert!(POISON.is_nan());
+        assert_ne!((POISON + 1.0).to_bits(), POISON.to_bits());
+        assert_eq!(patch.find_poisoned(range2d(0..4, 0..4)), Some((1, 2)));
+
+        patch.get_slice_mut((1, 2))[0] = 1.0;
+        assert_eq!(patch.find_poisoned(range2d(0..4, 0..4)), Some((1, 2)));
+        patch.get_slice_mut((1, 2))[1] = 1.0;
+        assert_eq!(patch.find_poisoned(range2d(0..4, 0..4)), Some((1, 3)));
+        assert_eq!(patch.find_poisoned(range2d(0..1, 0..4)), None);
+    }
 }
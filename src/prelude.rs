@@ -0,0 +1,31 @@
+//! The stable core of the library, for glob import:
+//!
+//! ```ignore
+//! use gridiron::prelude::*;
+//! ```
+//!
+//! Items are only added to the prelude once their signatures have settled,
+//! and are not removed or changed in a breaking way without a major version
+//! bump. Applications which import from here, rather than from the modules
+//! where the items are defined, are not affected when those modules are
+//! reorganized. Each item is available when the feature which provides it
+//! is enabled.
+
+pub use crate::error::Error;
+pub use crate::index_space::{range2d, Axis, IndexSpace};
+pub use crate::rect_map::{Rectangle, RectangleMap};
+
+#[cfg(feature = "mesh")]
+pub use crate::adjacency_list::AdjacencyList;
+#[cfg(feature = "mesh")]
+pub use crate::meshing::{GraphTopology, Mesh, PatchKey, PatchQuery};
+#[cfg(feature = "mesh")]
+pub use crate::patch::Patch;
+
+#[cfg(feature = "exec")]
+pub use crate::automaton::{execute, execute_par, execute_par_stupid, Automaton, Status};
+#[cfg(feature = "exec")]
+pub use crate::thread_pool::ThreadPool;
+
+#[cfg(feature = "net")]
+pub use crate::message::comm::Communicator;
@@ -11,6 +11,13 @@ pub type RectangleRef<'a, T> = (&'a Range<T>, &'a Range<T>);
 /// An associative map where the keys are `Rectangle` objects. Supports point,
 /// rectangle, generic 2d range-based queries to iterate over key-value pairs.
 ///
+/// Iteration over the whole map (`iter`, `iter_mut`, `keys`, and
+/// `into_iter`) is in lexicographic order of the keys: by the start and then
+/// the end of the first range, followed by the second. The order does not
+/// depend on the order of insertion or the shape of the tree, so it is the
+/// same on every rank holding the same rectangles. Query results are
+/// yielded in tree order; use the `_sorted` variants where order matters.
+///
 #[derive(Clone)]
 pub struct RectangleMap<T: Ord + Copy, V> {
     map: IntervalMap<T, IntervalMap<T, V>>,
@@ -78,6 +85,25 @@ impl<T: Ord + Copy, V> RectangleMap<T, V> {
         }
     }
 
+    /// Combine this map with another one, in time linear in the total number
+    /// of entries. Where both maps have the same rectangle, the value from
+    /// `other` is kept. The result is balanced.
+    pub fn merge(self, other: Self) -> Self {
+        self.merge_by(other, |_, b| b)
+    }
+
+    /// Like [`RectangleMap::merge`], but values with the same rectangle are
+    /// combined by the given function, which receives this map's value
+    /// first.
+    pub fn merge_by<F>(self, other: Self, mut combine: F) -> Self
+    where
+        F: FnMut(V, V) -> V,
+    {
+        Self {
+            map: self.map.merge_by(other.map, |a, b| a.merge_by(b, &mut combine)),
+        }
+    }
+
     #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = (Rectangle<T>, V)> {
         self.map
@@ -132,6 +158,65 @@ impl<T: Ord + Copy, V> RectangleMap<T, V> {
             .map(move |(di, l)| l.query_range(s.clone()).map(move |(dj, m)| ((di, dj), m)))
             .flatten()
     }
+
+    /// Like [`RectangleMap::query_point`], but the results are sorted by key
+    /// rather than yielded in tree order. The results are gathered into a
+    /// buffer first, so the returned iterator knows its exact length.
+    pub fn query_point_sorted(&self, point: (T, T)) -> impl ExactSizeIterator<Item = (RectangleRef<'_, T>, &V)> {
+        sorted_by_key(self.query_point(point))
+    }
+
+    /// Like [`RectangleMap::query_rect`], but the results are sorted by key
+    /// rather than yielded in tree order. The results are gathered into a
+    /// buffer first, so the returned iterator knows its exact length.
+    pub fn query_rect_sorted<I>(&self, space: I) -> impl ExactSizeIterator<Item = (RectangleRef<'_, T>, &V)>
+    where
+        I: Into<Rectangle<T>>,
+    {
+        sorted_by_key(self.query_rect(space))
+    }
+
+    /// Return the number of rectangles overlapping the given one, without
+    /// materializing the query results.
+    pub fn count_overlapping<I>(&self, space: I) -> usize
+    where
+        I: Into<Rectangle<T>>,
+    {
+        let (r, s) = space.into();
+        self.map
+            .query_range(r)
+            .map(|(_, l)| l.query_range(s.clone()).count())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+impl<T: Ord + Copy, V> RectangleMap<T, V> {
+    /// Panic unless the tree for the first axis, and each of the trees for
+    /// the second axis, is ordered and has correct maximum endpoints, and no
+    /// tree for the second axis is empty.
+    fn validate(&self) {
+        self.map.validate_max();
+        self.map.validate_order();
+
+        for (_, l) in self.map.iter() {
+            assert!(!l.is_empty());
+            l.validate_max();
+            l.validate_order();
+        }
+    }
+}
+
+/// Collect key-value pairs into a buffer sorted by key. Keys are ordered by
+/// the start and then the end of the first range, followed by the second.
+fn sorted_by_key<'a, T, V, I>(results: I) -> std::vec::IntoIter<(RectangleRef<'a, T>, V)>
+where
+    T: 'a + Ord + Copy,
+    I: Iterator<Item = (RectangleRef<'a, T>, V)>,
+{
+    let mut results: Vec<_> = results.collect();
+    results.sort_unstable_by_key(|((di, dj), _)| (di.start, di.end, dj.start, dj.end));
+    results.into_iter()
 }
 
 // ============================================================================
@@ -200,7 +285,10 @@ impl<T: Ord + Copy, V> FromIterator<(Rectangle<T>, V)> for RectangleMap<T, V> {
 
 #[cfg(test)]
 mod test {
-    use super::RectangleMap;
+    use super::{Rectangle, RectangleMap, RectangleRef};
+    use crate::aug_node::StupidRandom;
+    use crate::overlap::Overlap;
+    use core::ops::Range;
 
     #[test]
     fn can_query_points() {
@@ -215,4 +303,122 @@ mod test {
         assert_eq!(rect_map.query_point((2, 2)).count(), 1);
         assert_eq!(rect_map.query_point((12, 12)).count(), 1);
     }
+
+    #[test]
+    fn sorted_queries_and_counts_agree_with_tree_queries() {
+        let rect_map: RectangleMap<_, _> = (0..20)
+            .map(|n| (((n * 7) % 20..(n * 7) % 20 + 4, n % 5..n % 5 + 3), n))
+            .collect();
+
+        let sorted: Vec<_> = rect_map.query_rect_sorted((3..9, 2..4)).collect();
+        assert_eq!(sorted.len(), rect_map.query_rect((3..9, 2..4)).count());
+        assert_eq!(rect_map.count_overlapping((3..9, 2..4)), sorted.len());
+        assert!(sorted.windows(2).all(|w| (w[0].0 .0.start, w[0].0 .1.start) <= (w[1].0 .0.start, w[1].0 .1.start)));
+        assert_eq!(rect_map.query_point_sorted((5, 3)).len(), rect_map.query_point((5, 3)).count());
+    }
+
+    fn sort_key(rect: (&Range<i64>, &Range<i64>)) -> (i64, i64, i64, i64) {
+        (rect.0.start, rect.0.end, rect.1.start, rect.1.end)
+    }
+
+    #[test]
+    fn iteration_is_sorted_by_key() {
+        let mut rect_map: RectangleMap<_, _> = (0..50)
+            .map(|n| (((n * 17) % 13..(n * 17) % 13 + 1 + n % 3, (n * 7) % 5..(n * 7) % 5 + 2), n))
+            .collect();
+        rect_map.insert((3..4, 0..1), 100);
+
+        let keys: Vec<_> = rect_map.keys().map(sort_key).collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(rect_map.iter().map(|(r, _)| sort_key(r)).collect::<Vec<_>>(), keys);
+        assert_eq!(rect_map.iter_mut().map(|(r, _)| sort_key(r)).collect::<Vec<_>>(), keys);
+        assert_eq!(rect_map.into_iter().map(|(r, _)| sort_key((&r.0, &r.1))).collect::<Vec<_>>(), keys);
+    }
+
+    #[test]
+    fn merged_maps_are_sorted_and_prefer_the_second_value() {
+        let a: RectangleMap<_, _> = (0..10).map(|n| ((n..n + 2, 0..1), n)).collect();
+        let b: RectangleMap<_, _> = (5..15).map(|n| ((n..n + 2, 0..1), 100 + n)).collect();
+        let merged = a.clone().merge(b.clone());
+
+        assert_eq!(merged.len(), 15);
+        assert_eq!(merged.get((&(3..5), &(0..1))), Some(&3));
+        assert_eq!(merged.get((&(7..9), &(0..1))), Some(&107));
+        assert!(merged.keys().map(sort_key).collect::<Vec<_>>().windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(a.merge_by(b, |x, y| x + y).get((&(7..9), &(0..1))), Some(&114));
+    }
+
+    fn owned<'a, I: Iterator<Item = (RectangleRef<'a, i64>, &'a usize)>>(iter: I) -> Vec<(Rectangle<i64>, usize)> {
+        let mut result: Vec<_> = iter.map(|((di, dj), v)| ((di.clone(), dj.clone()), *v)).collect();
+        result.sort_by_key(|((di, dj), _)| (di.start, di.end, dj.start, dj.end));
+        result
+    }
+
+    fn filtered<F: Fn(&Rectangle<i64>) -> bool>(model: &[(Rectangle<i64>, usize)], f: F) -> Vec<(Rectangle<i64>, usize)> {
+        owned(model.iter().filter(|(k, _)| f(k)).map(|((di, dj), v)| ((di, dj), v)))
+    }
+
+    fn check_queries(map: &RectangleMap<i64, usize>, model: &[(Rectangle<i64>, usize)], rng: &mut StupidRandom) {
+        assert_eq!(owned(map.iter()), filtered(model, |_| true));
+        assert_eq!(map.iter().map(|((di, dj), _)| (di.clone(), dj.clone())).collect::<Vec<_>>(), filtered(model, |_| true).into_iter().map(|(k, _)| k).collect::<Vec<_>>());
+
+        for _ in 0..10 {
+            let p = (rng.below(30) as i64 - 5, rng.below(30) as i64 - 5);
+            let r = (rng.range(24, 8), rng.range(24, 8));
+            let by_point = owned(map.query_point(p));
+            assert_eq!(by_point, filtered(model, |(di, dj)| di.contains(&p.0) && dj.contains(&p.1)));
+            assert_eq!(by_point, owned(map.query_bounds(p.0..p.0 + 1, p.1..p.1 + 1)));
+            assert_eq!(owned(map.query_point_sorted(p)), by_point);
+
+            let by_rect = owned(map.query_rect(r.clone()));
+            assert_eq!(by_rect, filtered(model, |(di, dj)| di.overlaps(&r.0) && dj.overlaps(&r.1)));
+            assert_eq!(owned(map.query_rect_sorted(r.clone())), by_rect);
+            assert_eq!(map.count_overlapping(r.clone()), by_rect.len());
+            assert_eq!(owned(map.query_bounds(..r.0.end, r.1.start..)), filtered(model, |(di, dj)| di.overlaps(&(..r.0.end)) && dj.overlaps(&(r.1.start..))));
+        }
+    }
+
+    #[test]
+    fn map_agrees_with_a_naive_model_under_random_operations() {
+        for seed in 0..8 {
+            let mut rng = StupidRandom::new(seed);
+            let mut map = RectangleMap::new();
+            let mut model: Vec<(Rectangle<i64>, usize)> = Vec::new();
+
+            for op in 0..2000 {
+                match rng.below(4) {
+                    0 | 1 => {
+                        let key = (rng.range(16, 4), rng.range(16, 4));
+                        map.insert(key.clone(), op);
+                        match model.iter_mut().find(|(k, _)| *k == key) {
+                            Some(entry) => entry.1 = op,
+                            None => model.push((key, op)),
+                        }
+                    }
+                    2 => {
+                        let key = (rng.range(16, 4), rng.range(16, 4));
+                        map.remove((&key.0, &key.1));
+                        model.retain(|(k, _)| *k != key);
+                    }
+                    _ => {
+                        if !model.is_empty() {
+                            let (key, value) = model.swap_remove(rng.below(model.len() as u64) as usize);
+                            assert_eq!(map.get((&key.0, &key.1)), Some(&value));
+                            map.remove((&key.0, &key.1));
+                            assert!(!map.contains((&key.0, &key.1)));
+                        }
+                    }
+                }
+                assert_eq!(map.len(), model.len());
+                map.validate();
+
+                if op % 20 == 0 {
+                    check_queries(&map, &model, &mut rng);
+                }
+            }
+            let map = map.into_balanced();
+            map.validate();
+            check_queries(&map, &model, &mut rng);
+        }
+    }
 }
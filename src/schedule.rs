@@ -0,0 +1,177 @@
+use crate::error::{Error, Result};
+use core::hash::Hash;
+use std::collections::HashMap;
+
+/// The highest rung a task may be placed on. A frame is divided into at most
+/// `2^MAX_RUNG` substeps.
+///
+pub const MAX_RUNG: u32 = 32;
+
+/// A schedule for local time stepping, where each task advances with its
+/// own time step size and all tasks synchronize at frame boundaries. Each
+/// task is assigned to a _rung_: a task on rung `r` takes `2^r` steps of size
+/// `frame_dt / 2^r` per frame. The frame is divided into `2^max_rung`
+/// substeps, and a task on rung `r` is _active_ (begins a step) on every
+/// `2^(max_rung - r)`-th substep. Restricting step sizes to power-of-two
+/// fractions of the frame means that every task ends a step exactly at the
+/// start of the next frame.
+///
+/// When an active task needs guard zone data from an inactive neighbor, the
+/// neighbor's data is not available at the active task's time. It can be
+/// approximated by interpolating between the neighbor's states at the start
+/// and end of its current step, using [`LocalTimeSchedule::time_fraction`]
+/// and [`crate::patch::Patch::interpolate_in_time`].
+///
+/// This type only computes the schedule; it does not execute it. The caller
+/// is responsible for advancing the active tasks on each substep with their
+/// own step sizes, and for interpolating the guard data they receive from
+/// inactive neighbors.
+///
+pub struct LocalTimeSchedule<K> {
+    frame_dt: f64,
+    rungs: HashMap<K, u32>,
+    max_rung: u32,
+}
+
+impl<K: Hash + Eq> LocalTimeSchedule<K> {
+    /// Create a schedule from the desired (e.g. CFL-limited) time step size
+    /// of each task. Each task is placed on the lowest rung whose step size
+    /// does not exceed its desired step size. An error is returned if the
+    /// frame or any task's step size is not positive and finite, or if a
+    /// task would need a rung above [`MAX_RUNG`].
+    ///
+    pub fn new<I>(frame_dt: f64, time_step_sizes: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, f64)>,
+    {
+        if !frame_dt.is_finite() || frame_dt <= 0.0 {
+            return Err(Error::Parameters(format!("frame duration {} must be positive", frame_dt)));
+        }
+        let rungs = time_step_sizes
+            .into_iter()
+            .map(|(key, dt)| Ok((key, Self::rung_for(frame_dt, dt)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let max_rung = rungs.values().copied().max().unwrap_or(0);
+
+        Ok(Self {
+            frame_dt,
+            rungs,
+            max_rung,
+        })
+    }
+
+    fn rung_for(frame_dt: f64, dt: f64) -> Result<u32> {
+        if dt.is_nan() || dt <= 0.0 {
+            return Err(Error::Parameters(format!("time step size {} must be positive", dt)));
+        }
+        let mut rung = 0;
+
+        while frame_dt / (1u64 << rung) as f64 > dt {
+            if rung == MAX_RUNG {
+                return Err(Error::Parameters(format!(
+                    "time step size {} needs more than {} rungs in a frame of {}",
+                    dt, MAX_RUNG, frame_dt
+                )));
+            }
+            rung += 1
+        }
+        Ok(rung)
+    }
+
+    /// Return the number of substeps in each frame.
+    ///
+    pub fn num_substeps(&self) -> u64 {
+        1 << self.max_rung
+    }
+
+    /// Return the time elapsed since the start of the frame at the given
+    /// substep.
+    ///
+    pub fn time_at(&self, substep: u64) -> f64 {
+        self.frame_dt * substep as f64 / self.num_substeps() as f64
+    }
+
+    /// Return the rung of the given task.
+    ///
+    pub fn rung(&self, key: &K) -> u32 {
+        self.rungs[key]
+    }
+
+    /// Return the time step size of the given task.
+    ///
+    pub fn time_step_size(&self, key: &K) -> f64 {
+        self.frame_dt / (1u64 << self.rung(key)) as f64
+    }
+
+    /// Determine whether the given task begins a step on the given substep.
+    ///
+    pub fn is_active(&self, key: &K, substep: u64) -> bool {
+        substep.is_multiple_of(1 << (self.max_rung - self.rung(key)))
+    }
+
+    /// Return an iterator over the tasks which begin a step on the given
+    /// substep.
+    ///
+    pub fn active(&self, substep: u64) -> impl Iterator<Item = &K> + '_ {
+        self.rungs
+            .keys()
+            .filter(move |key| self.is_active(key, substep))
+    }
+
+    /// Return the fraction of the given task's current step which has
+    /// elapsed at the given substep. This is zero if the task is active on
+    /// that substep.
+    ///
+    pub fn time_fraction(&self, key: &K, substep: u64) -> f64 {
+        let period = 1 << (self.max_rung - self.rung(key));
+        (substep % period) as f64 / period as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{LocalTimeSchedule, MAX_RUNG};
+
+    #[test]
+    fn schedule_assigns_rungs() {
+        let schedule = LocalTimeSchedule::new(1.0, vec![('a', 1.0), ('b', 0.3), ('c', 0.5)]).unwrap();
+        assert_eq!(schedule.rung(&'a'), 0);
+        assert_eq!(schedule.rung(&'b'), 2);
+        assert_eq!(schedule.rung(&'c'), 1);
+        assert_eq!(schedule.num_substeps(), 4);
+        assert_eq!(schedule.time_step_size(&'b'), 0.25);
+        assert_eq!(schedule.time_at(3), 0.75);
+
+        let mut active: Vec<_> = schedule.active(2).copied().collect();
+        active.sort_unstable();
+        assert_eq!(active, ['b', 'c']);
+        assert_eq!(schedule.active(1).count(), 1);
+        assert_eq!(schedule.time_fraction(&'a', 3), 0.75);
+        assert_eq!(schedule.time_fraction(&'c', 3), 0.5);
+    }
+
+    #[test]
+    fn every_task_takes_a_whole_frame() {
+        let schedule = LocalTimeSchedule::new(2.0, (0..10).map(|n| (n, 2.0 / (n + 1) as f64))).unwrap();
+
+        for key in 0..10 {
+            let steps = (0..schedule.num_substeps())
+                .filter(|&s| schedule.is_active(&key, s))
+                .count() as f64;
+            assert_eq!(steps * schedule.time_step_size(&key), 2.0);
+        }
+    }
+
+    #[test]
+    fn tiny_or_invalid_time_steps_are_rejected() {
+        let deepest = LocalTimeSchedule::new(1.0, vec![('a', 0.5f64.powi(MAX_RUNG as i32))]).unwrap();
+        assert_eq!(deepest.rung(&'a'), MAX_RUNG);
+        assert_eq!(deepest.time_step_size(&'a'), 0.5f64.powi(MAX_RUNG as i32));
+
+        assert!(LocalTimeSchedule::new(1.0, vec![('a', 1e-12)]).is_err());
+        assert!(LocalTimeSchedule::new(1.0, vec![('a', 0.0)]).is_err());
+        assert!(LocalTimeSchedule::new(1.0, vec![('a', f64::NAN)]).is_err());
+        assert!(LocalTimeSchedule::new(0.0, vec![('a', 1.0)]).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+//! Auxiliary per-patch state kept alongside the mesh, such as random number
+//! seeds or scratch space for a chemistry network, without adding fields to
+//! [`Patch`](crate::patch::Patch). A [`SideCar`] maps patch keys to values
+//! of any type. When the mesh is regridded, the values are carried over to
+//! the new patches by the hooks of a [`Lifecycle`], and in a multi-rank run
+//! they are sent to the ranks which own the new patches.
+
+use crate::index_space::IndexSpace;
+use crate::meshing::PatchKey;
+#[cfg(feature = "net")]
+use crate::meshing::PatchOwners;
+#[cfg(feature = "net")]
+use crate::message::comm::Communicator;
+use crate::rect_map::RectangleMap;
+use std::collections::HashMap;
+
+/// How the values in a [`SideCar`] follow the patches when the mesh is
+/// regridded. Each new patch is given a value by exactly one of these hooks,
+/// unless its key is unchanged, in which case its value is kept as is.
+///
+pub trait Lifecycle<T> {
+    /// Make the value for a new patch which overlaps no old patch.
+    fn create(&mut self, key: &PatchKey) -> T;
+
+    /// Make the value for a new patch which overlaps exactly one old patch,
+    /// e.g. one of the pieces of a patch which was split, or a refined patch
+    /// inside a coarser one.
+    fn split(&mut self, from: (&PatchKey, &T), key: &PatchKey) -> T;
+
+    /// Make the value for a new patch which overlaps several old patches,
+    /// e.g. patches which were merged. The old patches are sorted by key.
+    fn merge(&mut self, from: Vec<(&PatchKey, &T)>, key: &PatchKey) -> T;
+}
+
+/// A typed map from patch keys to auxiliary per-patch values.
+///
+#[derive(Clone, Debug)]
+pub struct SideCar<T> {
+    values: HashMap<PatchKey, T>,
+}
+
+impl<T> Default for SideCar<T> {
+    fn default() -> Self {
+        Self { values: HashMap::new() }
+    }
+}
+
+impl<T> SideCar<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach a value to a patch, returning the value it replaces, if any.
+    ///
+    pub fn insert(&mut self, key: PatchKey, value: T) -> Option<T> {
+        self.values.insert(key, value)
+    }
+
+    pub fn get(&self, key: &PatchKey) -> Option<&T> {
+        self.values.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &PatchKey) -> Option<&mut T> {
+        self.values.get_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &PatchKey) -> Option<T> {
+        self.values.remove(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Iterate over the patch keys and their values, in no particular order.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&PatchKey, &T)> + '_ {
+        self.values.iter()
+    }
+
+    /// Replace the patches in the map with the given ones, which cover a
+    /// regridded mesh. A new patch whose key is unchanged keeps its value.
+    /// Otherwise the old patches it overlaps on its own level are passed to
+    /// [`Lifecycle::split`] or [`Lifecycle::merge`]; if there are none, the
+    /// old patches it overlaps on other levels are used instead (e.g. the
+    /// parent of a newly refined patch), and if there are none of those
+    /// either, its value comes from [`Lifecycle::create`]. Values of old
+    /// patches which are not kept are dropped.
+    ///
+    pub fn regrid<I, H>(&mut self, keys: I, hooks: &mut H)
+    where
+        I: IntoIterator<Item = PatchKey>,
+        H: Lifecycle<T>,
+    {
+        let mut old: HashMap<_, _> = self.values.drain().collect();
+        let mut index: RectangleMap<i64, Vec<u32>> = RectangleMap::new();
+        let mut keys: Vec<_> = keys.into_iter().collect();
+
+        for (rect, level) in old.keys() {
+            index.require(rect.clone()).push(*level)
+        }
+        // Unchanged patches are moved over first, so that new patches which
+        // overlap them can still find their values.
+        keys.sort_by_key(|key| !old.contains_key(key));
+
+        let mut new = HashMap::new();
+
+        for key in keys {
+            if let Some(value) = old.remove(&key) {
+                new.insert(key, value);
+                continue;
+            }
+            let (rect, level) = &key;
+            let space = IndexSpace::from(rect.clone());
+            let mut overlaps: Vec<PatchKey> = index
+                .query_rect(rect.clone())
+                .filter(|(r, _)| !space.intersect(IndexSpace::from(*r)).is_empty())
+                .flat_map(|(r, levels)| levels.iter().map(move |&l| ((r.0.clone(), r.1.clone()), l)))
+                .collect();
+
+            if overlaps.iter().any(|(_, l)| l == level) {
+                overlaps.retain(|(_, l)| l == level);
+            }
+            overlaps.sort_by_key(|((di, dj), l)| (*l, di.start, dj.start));
+
+            let lookup = |k: &PatchKey| old.get(k).or_else(|| new.get(k)).unwrap();
+            let value = match overlaps.len() {
+                0 => hooks.create(&key),
+                1 => hooks.split((&overlaps[0], lookup(&overlaps[0])), &key),
+                _ => hooks.merge(overlaps.iter().map(|k| (k, lookup(k))).collect(), &key),
+            };
+            new.insert(key, value);
+        }
+        self.values = new;
+    }
+}
+
+#[cfg(feature = "net")]
+impl<T> SideCar<T>
+where
+    T: Clone + serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Like [`SideCar::regrid`], but for a multi-rank run, where the old
+    /// patches are spread over the ranks. This is a collective operation:
+    /// every rank passes the ownership map of the new patches (see
+    /// [`PatchOwners::gather`]). Each rank first sends the value of each of
+    /// its old patches to every rank which owns a new patch overlapping it,
+    /// and then regrids the patches it owns. A rebalance which only moves
+    /// patches between ranks, without changing their keys, sends each value
+    /// to its new owner without calling any hooks.
+    ///
+    pub fn regrid_across<C, H>(&mut self, comm: &C, owners: &PatchOwners, hooks: &mut H)
+    where
+        C: Communicator,
+        H: Lifecycle<T>,
+    {
+        let rank = comm.rank();
+        let mut index: RectangleMap<i64, Vec<usize>> = RectangleMap::new();
+        let mut outgoing: Vec<Vec<(PatchKey, T)>> = vec![Vec::new(); comm.size()];
+
+        for ((rect, _), owner) in owners.iter() {
+            index.require(rect).push(owner)
+        }
+        for (key, value) in self.values.drain() {
+            let space = IndexSpace::from(key.0.clone());
+            let mut ranks: Vec<_> = index
+                .query_rect(key.0.clone())
+                .filter(|(r, _)| !space.intersect(IndexSpace::from(*r)).is_empty())
+                .flat_map(|(_, owners)| owners.iter().copied())
+                .collect();
+            ranks.sort_unstable();
+            ranks.dedup();
+
+            for owner in ranks {
+                outgoing[owner].push((key.clone(), value.clone()))
+            }
+        }
+        self.values.extend(outgoing[rank].drain(..));
+
+        for (peer, values) in outgoing.iter().enumerate().filter(|(peer, _)| *peer != rank) {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(values, &mut bytes).unwrap();
+            comm.send(peer, bytes)
+        }
+        for _ in 1..comm.size() {
+            let values: Vec<(PatchKey, T)> = ciborium::de::from_reader(&comm.recv()[..]).unwrap();
+            self.values.extend(values)
+        }
+        self.regrid(owners.keys_owned_by(rank), hooks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Lifecycle, SideCar};
+    use crate::meshing::PatchKey;
+
+    /// Values are lists of the seeds of the patches they came from; new
+    /// patches get the seed 100.
+    struct Seeds;
+
+    impl Lifecycle<Vec<u64>> for Seeds {
+        fn create(&mut self, _: &PatchKey) -> Vec<u64> {
+            vec![100]
+        }
+
+        fn split(&mut self, from: (&PatchKey, &Vec<u64>), _: &PatchKey) -> Vec<u64> {
+            from.1.clone()
+        }
+
+        fn merge(&mut self, from: Vec<(&PatchKey, &Vec<u64>)>, _: &PatchKey) -> Vec<u64> {
+            from.into_iter().flat_map(|(_, seeds)| seeds.clone()).collect()
+        }
+    }
+
+    fn key(i: std::ops::Range<i64>, j: std::ops::Range<i64>, level: u32) -> PatchKey {
+        ((i, j), level)
+    }
+
+    #[test]
+    fn values_follow_split_merged_and_refined_patches() {
+        let mut sidecar = SideCar::new();
+        sidecar.insert(key(0..4, 0..4, 1), vec![1]);
+        sidecar.insert(key(4..8, 0..4, 1), vec![2]);
+        sidecar.insert(key(0..8, 4..8, 1), vec![3]);
+
+        let new = [
+            key(0..8, 0..4, 1),
+            key(0..4, 4..8, 1),
+            key(4..8, 4..8, 1),
+            key(4..6, 0..2, 0),
+            key(8..12, 0..4, 1),
+        ];
+        sidecar.regrid(new.iter().cloned(), &mut Seeds);
+
+        assert_eq!(sidecar.len(), 5);
+        assert_eq!(sidecar.get(&new[0]), Some(&vec![1, 2]));
+        assert_eq!(sidecar.get(&new[1]), Some(&vec![3]));
+        assert_eq!(sidecar.get(&new[2]), Some(&vec![3]));
+        assert_eq!(sidecar.get(&new[3]), Some(&vec![2]));
+        assert_eq!(sidecar.get(&new[4]), Some(&vec![100]));
+
+        sidecar.get_mut(&new[4]).unwrap().push(7);
+        sidecar.regrid(vec![new[4].clone()], &mut Seeds);
+        assert_eq!(sidecar.get(&new[4]), Some(&vec![100, 7]));
+        assert_eq!(sidecar.len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn values_follow_patches_to_their_new_owners() {
+        use crate::meshing::PatchOwners;
+        use crate::message::comm::Communicator;
+        use crate::message::local::LocalCommunicator;
+        use std::thread;
+
+        // Rank 0 owns the left half and rank 1 the right half; afterwards
+        // the halves swap owners, and rank 0 also gets a merged patch.
+        let owners: PatchOwners = vec![
+            (key(0..4, 0..8, 0), 1),
+            (key(4..8, 0..8, 0), 0),
+            (key(8..12, 0..8, 0), 0),
+        ]
+        .into_iter()
+        .collect();
+
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(|comm| {
+                let owners = owners.clone();
+                thread::spawn(move || {
+                    let mut sidecar = SideCar::new();
+                    match comm.rank() {
+                        0 => sidecar.insert(key(0..4, 0..8, 0), vec![1]),
+                        _ => sidecar.insert(key(4..8, 0..8, 0), vec![2]),
+                    };
+                    if comm.rank() == 1 {
+                        sidecar.insert(key(8..10, 0..8, 0), vec![3]);
+                        sidecar.insert(key(10..12, 0..8, 0), vec![4]);
+                    }
+                    sidecar.regrid_across(&comm, &owners, &mut Seeds);
+                    let mut values: Vec<_> = sidecar.iter().map(|((r, _), v)| (r.0.start, v.clone())).collect();
+                    values.sort();
+                    values
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(results[0], [(4, vec![2]), (8, vec![3, 4])]);
+        assert_eq!(results[1], [(0, vec![1])]);
+    }
+}
@@ -0,0 +1,205 @@
+//! Selection of a block size for the bundled 2D Euler solver, by timing it
+//! on the current machine. The best block size depends on the cache sizes,
+//! the number of threads, and the executor, so it is found by trying a few:
+//! a blast wave problem is decomposed into square blocks of each candidate
+//! size and advanced for a few iterations, and the rate of zone updates is
+//! measured. Tuning takes a few seconds at production resolutions, so it
+//! only happens when [`tune_block_size`] is called explicitly, e.g. at
+//! startup behind a command line flag.
+
+use crate::error::{Error, Result};
+use crate::hydro::euler2d::Primitive;
+use crate::index_space::range2d;
+use crate::meshing::{Domain, GraphTopology};
+use crate::patch::Patch;
+use crate::rect_map::RectangleMap;
+use crate::solvers::euler2d_pcm::PatchUpdate;
+use std::fmt;
+use std::time::Instant;
+
+/// The problem and the candidates to time.
+///
+#[derive(Clone, Debug)]
+pub struct TuneOptions {
+    /// The number of zones on each side of the square domain.
+    pub grid_resolution: usize,
+
+    /// The block sizes to try. Sizes which do not divide the grid
+    /// resolution, or which leave fewer than two blocks on a side (so the
+    /// blocks have no neighbors to message), are skipped.
+    pub candidates: Vec<usize>,
+
+    /// The number of untimed iterations to run before timing each block
+    /// size, e.g. to let the thread pool start up.
+    pub warmup: usize,
+
+    /// The number of timed iterations for each block size.
+    pub iterations: usize,
+
+    /// The number of workers to distribute the tasks' hints over, which
+    /// should match the executor's number of threads.
+    pub num_workers: usize,
+}
+
+impl Default for TuneOptions {
+    fn default() -> Self {
+        Self {
+            grid_resolution: 1024,
+            candidates: vec![32, 64, 128, 256],
+            warmup: 1,
+            iterations: 5,
+            num_workers: 1,
+        }
+    }
+}
+
+/// The measured performance of one block size.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockSizeTrial {
+    pub block_size: usize,
+    pub num_blocks: usize,
+
+    /// Millions of zone updates per second.
+    pub mzps: f64,
+}
+
+/// The measured performance of each candidate block size.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct TuneReport {
+    pub trials: Vec<BlockSizeTrial>,
+}
+
+impl TuneReport {
+    /// Return the block size with the highest rate of zone updates.
+    ///
+    pub fn best(&self) -> usize {
+        self.trials
+            .iter()
+            .max_by(|a, b| a.mzps.total_cmp(&b.mzps))
+            .map(|trial| trial.block_size)
+            .expect("a tuning report has at least one trial")
+    }
+}
+
+impl fmt::Display for TuneReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let best = self.best();
+        writeln!(fmt, "{:>10} {:>10} {:>10}", "block size", "blocks", "Mzps")?;
+
+        for trial in &self.trials {
+            let marker = if trial.block_size == best { " *" } else { "" };
+            writeln!(fmt, "{:>10} {:>10} {:>10.2}{}", trial.block_size, trial.num_blocks, trial.mzps, marker)?;
+        }
+        Ok(())
+    }
+}
+
+/// Time the bundled solver at each candidate block size, advancing the
+/// tasks by one iteration at a time with the given function (e.g. an
+/// executor), and report the rate of zone updates for each. Returns an error
+/// if none of the candidates is usable at the grid resolution, or if no
+/// iterations are timed.
+///
+pub fn tune_block_size<F>(options: &TuneOptions, mut advance: F) -> Result<TuneReport>
+where
+    F: FnMut(Vec<PatchUpdate>) -> Vec<PatchUpdate>,
+{
+    let n = options.grid_resolution;
+    let candidates: Vec<_> = options.candidates.iter().copied().filter(|&bs| bs > 0 && n.is_multiple_of(bs) && n / bs >= 2).collect();
+
+    if candidates.is_empty() || options.iterations == 0 {
+        return Err(Error::Parameters(format!(
+            "block size tuning needs at least one timed iteration and a candidate which splits {} into blocks (got {:?})",
+            n, options.candidates
+        )));
+    }
+    let mut trials = Vec::new();
+
+    for block_size in candidates {
+        let mut tasks = blast_wave(n, block_size, options.num_workers.max(1));
+        let num_blocks = tasks.len();
+
+        for _ in 0..options.warmup {
+            tasks = advance(tasks)
+        }
+        let start = Instant::now();
+
+        for _ in 0..options.iterations {
+            tasks = advance(tasks)
+        }
+        let seconds = start.elapsed().as_secs_f64() / options.iterations as f64;
+
+        trials.push(BlockSizeTrial {
+            block_size,
+            num_blocks,
+            mzps: (n * n) as f64 / 1e6 / seconds,
+        });
+    }
+    Ok(TuneReport { trials })
+}
+
+/// The tasks for a blast wave on a square grid of the given resolution,
+/// decomposed into blocks of the given size.
+fn blast_wave(resolution: usize, block_size: usize, num_workers: usize) -> Vec<PatchUpdate> {
+    let mesh = Domain::new((-1.0..1.0, -1.0..1.0), (resolution, resolution)).mesh();
+    let bs = block_size as i64;
+    let nb = (resolution / block_size) as i64;
+    let initial_data = |index| {
+        let (x, y) = mesh.cell_center(index);
+        if (x * x + y * y).sqrt() < 0.24 {
+            Primitive::new(1.0, 0.0, 0.0, 1.0).as_array()
+        } else {
+            Primitive::new(0.1, 0.0, 0.0, 0.125).as_array()
+        }
+    };
+    let patches: RectangleMap<_, _> = range2d(0..nb, 0..nb)
+        .iter()
+        .map(|(i, j)| (i * bs..(i + 1) * bs, j * bs..(j + 1) * bs))
+        .map(|rect| Patch::from_vector_function(0, rect, initial_data))
+        .map(|p| (p.high_resolution_rect(), p))
+        .collect();
+    let edges = patches.adjacency_list(1);
+    let dt = mesh.cell_spacing().0 * 0.1;
+
+    patches
+        .into_iter()
+        .enumerate()
+        .map(|(n, (_, patch))| PatchUpdate::new(patch, mesh.clone(), dt, Some(n % num_workers), &edges))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{tune_block_size, TuneOptions};
+    use crate::automaton::execute;
+
+    #[test]
+    fn every_dividing_block_size_is_timed() {
+        let options = TuneOptions {
+            grid_resolution: 32,
+            candidates: vec![8, 12, 16, 32],
+            iterations: 1,
+            ..TuneOptions::default()
+        };
+        let report = tune_block_size(&options, |tasks| execute(tasks).collect()).unwrap();
+        let sizes: Vec<_> = report.trials.iter().map(|trial| (trial.block_size, trial.num_blocks)).collect();
+
+        assert_eq!(sizes, [(8, 16), (16, 4)]);
+        assert!(report.trials.iter().all(|trial| trial.mzps > 0.0));
+        assert!([8, 16].contains(&report.best()));
+        assert!(report.to_string().contains(" *"));
+    }
+
+    #[test]
+    fn tuning_without_candidates_is_an_error() {
+        let options = TuneOptions {
+            grid_resolution: 32,
+            candidates: vec![12, 32],
+            ..TuneOptions::default()
+        };
+        assert!(tune_block_size(&options, |tasks| execute(tasks).collect()).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! A safety net for runs with a fixed time step size. After each step the
+//! CFL number (the largest signal speed times the time step size over the
+//! zone width) is measured; if it exceeds a threshold, the step is undone
+//! and retried with a smaller time step size. The state of every task is
+//! saved before the step so it can be restored.
+
+use crate::automaton::Automaton;
+use crate::error::{Error, Result};
+use core::hash::Hash;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An update task whose step can be undone and retried with a different
+/// time step size.
+///
+pub trait CflMonitored {
+    /// The state needed to undo a step.
+    type Saved;
+
+    /// Save the state of the task before a step.
+    fn save(&self) -> Self::Saved;
+
+    /// Restore the state saved before a step.
+    fn restore(&mut self, saved: Self::Saved);
+
+    /// Return the time step size the task advances by.
+    fn time_step_size(&self) -> f64;
+
+    /// Change the time step size the task advances by.
+    fn set_time_step_size(&mut self, time_step_size: f64);
+
+    /// Return the CFL number of the current state with the current time
+    /// step size.
+    fn cfl_number(&self) -> f64;
+}
+
+/// When and how to retry a step which violates the CFL condition.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct CflPolicy {
+    /// The largest acceptable CFL number.
+    pub threshold: f64,
+
+    /// The factor by which the time step size is multiplied on each retry.
+    pub reduction: f64,
+
+    /// The number of times a step may be retried before giving up.
+    pub max_retries: usize,
+}
+
+impl Default for CflPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            reduction: 0.5,
+            max_retries: 4,
+        }
+    }
+}
+
+/// What happened in a monitored step.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct CflReport {
+    /// The CFL number of the accepted step.
+    pub cfl: f64,
+
+    /// The factor by which the time step sizes of the tasks were reduced,
+    /// which is 1 if the step was not retried.
+    pub time_step_scale: f64,
+
+    /// The CFL number of each rejected attempt, in order.
+    pub rejected: Vec<f64>,
+}
+
+impl CflReport {
+    /// Return whether the step had to be retried.
+    ///
+    pub fn was_retried(&self) -> bool {
+        !self.rejected.is_empty()
+    }
+}
+
+impl fmt::Display for CflReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "CFL {:.3}", self.cfl)?;
+
+        if self.was_retried() {
+            let rejected: Vec<_> = self.rejected.iter().map(|cfl| format!("{:.3}", cfl)).collect();
+            write!(
+                fmt,
+                " after {} retries (rejected {}; dt scaled by {})",
+                self.rejected.len(),
+                rejected.join(", "),
+                self.time_step_scale
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Advance a group of tasks by one step with the given function (e.g. an
+/// executor), and check the CFL number of the result. If it exceeds the
+/// policy's threshold, every task is restored to its state before the step,
+/// its time step size is reduced, and the step is retried. The reduced time
+/// step sizes are kept for subsequent steps; use the report's
+/// `time_step_scale` to account for the shorter step, and reset the step
+/// sizes if desired. If the step still violates the threshold after the
+/// allowed number of retries, the tasks are dropped and a stability error is
+/// returned.
+///
+pub fn advance_monitored<A, K, F>(tasks: Vec<A>, policy: &CflPolicy, mut advance: F) -> Result<(Vec<A>, CflReport)>
+where
+    A: CflMonitored + Automaton<Key = K, Value = A>,
+    K: Hash + Eq,
+    F: FnMut(Vec<A>) -> Vec<A>,
+{
+    let mut saved: HashMap<K, A::Saved> = tasks.iter().map(|task| (task.key(), task.save())).collect();
+    let mut tasks = tasks;
+    let mut scale = 1.0;
+    let mut rejected = Vec::new();
+
+    loop {
+        tasks = advance(tasks);
+
+        let cfl = tasks.iter().map(A::cfl_number).fold(0.0, f64::max);
+
+        if cfl <= policy.threshold {
+            let report = CflReport {
+                cfl,
+                time_step_scale: scale,
+                rejected,
+            };
+            return Ok((tasks, report));
+        }
+        if rejected.len() == policy.max_retries {
+            return Err(Error::Stability {
+                cfl,
+                threshold: policy.threshold,
+                time_step_size: tasks.iter().map(A::time_step_size).fold(0.0, f64::max),
+            });
+        }
+        rejected.push(cfl);
+        scale *= policy.reduction;
+
+        for task in &mut tasks {
+            let state = saved.remove(&task.key()).expect("task was not in the group before the step");
+            task.restore(state);
+            task.set_time_step_size(task.time_step_size() * policy.reduction);
+        }
+        saved = tasks.iter().map(|task| (task.key(), task.save())).collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{advance_monitored, CflMonitored, CflPolicy};
+    use crate::automaton::{execute, Automaton, Status};
+    use crate::error::Error;
+
+    /// A task whose signal speed grows with its time step size, so that
+    /// large steps are rejected. It sends one message, to itself.
+    struct Growing {
+        key: usize,
+        value: f64,
+        time_step_size: f64,
+    }
+
+    impl Automaton for Growing {
+        type Key = usize;
+        type Message = ();
+        type Value = Self;
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            vec![(self.key, ())]
+        }
+
+        fn receive(&mut self, _: Self::Message) -> Status {
+            Status::Eligible
+        }
+
+        fn value(self) -> Self::Value {
+            Self {
+                value: self.value + self.time_step_size * 10.0,
+                ..self
+            }
+        }
+    }
+
+    impl CflMonitored for Growing {
+        type Saved = f64;
+
+        fn save(&self) -> f64 {
+            self.value
+        }
+
+        fn restore(&mut self, saved: f64) {
+            self.value = saved
+        }
+
+        fn time_step_size(&self) -> f64 {
+            self.time_step_size
+        }
+
+        fn set_time_step_size(&mut self, time_step_size: f64) {
+            self.time_step_size = time_step_size
+        }
+
+        fn cfl_number(&self) -> f64 {
+            self.value * self.time_step_size
+        }
+    }
+
+    fn tasks(time_step_size: f64) -> Vec<Growing> {
+        (0..3)
+            .map(|key| Growing {
+                key,
+                value: 1.0 + key as f64,
+                time_step_size,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn violating_steps_are_retried_with_smaller_time_steps() {
+        let (tasks, report) = advance_monitored(tasks(0.2), &CflPolicy::default(), |tasks| execute(tasks).collect()).unwrap();
+
+        // The largest task starts at 3: a step of 0.2 gives 5 * 0.2 = 1.0,
+        // 0.1 gives 4 * 0.1 = 0.4.
+        assert_eq!(report.rejected, [1.0]);
+        assert_eq!(report.time_step_scale, 0.5);
+        assert!((report.cfl - 0.4).abs() < 1e-12);
+        assert!(tasks.iter().all(|task| task.time_step_size == 0.1));
+        assert_eq!(tasks.iter().map(|task| task.value).fold(0.0, f64::max), 4.0);
+        assert!(report.to_string().contains("after 1 retries"));
+    }
+
+    #[test]
+    fn steps_within_the_limit_are_accepted() {
+        let (_, report) = advance_monitored(tasks(0.01), &CflPolicy::default(), |tasks| execute(tasks).collect()).unwrap();
+        assert!(!report.was_retried());
+        assert_eq!(report.time_step_scale, 1.0);
+    }
+
+    #[test]
+    fn exhausted_retries_are_a_stability_error() {
+        let policy = CflPolicy {
+            threshold: 0.01,
+            max_retries: 2,
+            ..CflPolicy::default()
+        };
+        match advance_monitored(tasks(0.2), &policy, |tasks| execute(tasks).collect()) {
+            Err(Error::Stability { time_step_size, .. }) => assert_eq!(time_step_size, 0.05),
+            _ => panic!("expected a stability error"),
+        }
+    }
+}
@@ -1,55 +1,63 @@
 use crate::adjacency_list::AdjacencyList;
+use crate::error::{Error, Result};
 use crate::automaton::{Automaton, Status};
-use crate::hydro::{euler2d, euler2d::Conserved, euler2d::Primitive, geometry::Direction};
+use crate::gravity::PotentialProvider;
+use crate::hydro::{self, euler2d, euler2d::Conserved, euler2d::Primitive, geometry::Direction};
 use crate::index_space::{Axis, IndexSpace};
-use crate::meshing;
-use crate::patch::Patch;
+use crate::meshing::{self, PatchKey, PatchQuery, Periodicity, Translation, WrappedRegion};
+use crate::parameters::Parameters;
+use crate::solvers::cfl::CflMonitored;
+pub use crate::meshing::Mesh;
+use crate::patch::{Patch, Precision, StoredPatch};
 use crate::rect_map::Rectangle;
+use crate::window::{interior_windows, Windowed};
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::sync::Arc;
 
 const NUM_GUARD: i64 = 1;
+const NUM_FIELDS: usize = 4;
 const GAMMA_LAW_INDEX: f64 = 5.0 / 3.0;
 
-/// A simple rectilinear structured mesh
+/// A boundary condition which may depend on time. It is called with the
+/// index of a guard zone outside the domain (at the patch level), the
+/// simulation time, and the primitive variables to be written for that
+/// zone.
 ///
-#[derive(Clone)]
-pub struct Mesh {
-    pub area: Rectangle<f64>,
-    pub size: (usize, usize),
-}
-
-impl Mesh {
-    pub fn cell_spacing(&self) -> (f64, f64) {
-        let d0 = (self.area.0.end - self.area.0.start) / self.size.0 as f64;
-        let d1 = (self.area.1.end - self.area.1.start) / self.size.1 as f64;
-        (d0, d1)
-    }
+pub type BoundaryCondition = Arc<dyn Fn((i64, i64), f64, &mut [f64]) + Send + Sync>;
 
-    pub fn cell_center(&self, index: (i64, i64)) -> (f64, f64) {
-        let (d0, d1) = self.cell_spacing();
-        let x0 = self.area.0.start + d0 * (index.0 as f64 + 0.5);
-        let x1 = self.area.1.start + d1 * (index.1 as f64 + 0.5);
-        (x0, x1)
-    }
-
-    pub fn total_zones(&self) -> usize {
-        self.size.0 * self.size.1
-    }
-}
+/// The source of gravitational acceleration for a [`PatchUpdate`].
+///
+pub type Gravity = dyn PotentialProvider + Send + Sync;
 
 /// A basic first-order update scheme, hard-coded for the 2D euler equations.
+/// If the initial primitive patch has a mask (see [`Patch::with_mask`]), its
+/// solid zones are treated as internal obstacles with reflecting walls. The
+/// update of a large patch can be divided into windows (see [`Windowed`]).
 ///
 pub struct PatchUpdate {
-    conserved: Patch,
-    extended_primitive: Patch,
+    boundary_condition: Option<(String, BoundaryCondition)>,
+    conserved: Arc<Patch>,
+    extended_primitive: Arc<Patch>,
+    failure: Option<((i64, i64), hydro::error::Error)>,
     flux_i: Patch,
     flux_j: Patch,
+    gravity: Option<Arc<Gravity>>,
+    guard_validation: bool,
     incoming_count: usize,
+    incoming_received: usize,
     index_space: IndexSpace,
     level: u32,
     mesh: Mesh,
     neighbor_patches: Vec<Patch>,
-    outgoing_edges: Vec<(Rectangle<i64>, u32)>,
+    outgoing: Vec<Outgoing>,
+    precision: Precision,
+    rim_pieces: Vec<Patch>,
+    speculated: bool,
+    time: f64,
     time_step_size: f64,
+    window_shape: Option<(usize, usize)>,
+    windowed: bool,
     worker_group: Option<usize>,
 }
 
@@ -66,42 +74,563 @@ impl PatchUpdate {
         let nq = primitive.num_fields();
         let index_space = primitive.index_space();
         let conserved = primitive.map(Self::prim_to_cons);
-        let extended_primitive = Patch::extract_from(&primitive, index_space.extend_all(NUM_GUARD));
+        let mut extended_primitive = Patch::extract_from(&primitive, index_space.extend_all(NUM_GUARD));
+        meshing::extend_patch_mut(&mut extended_primitive, &index_space, Self::boundary_value, &Vec::new());
         let flux_i = Patch::zeros(lv, nq, index_space.extend_upper(1, Axis::I));
         let flux_j = Patch::zeros(lv, nq, index_space.extend_upper(1, Axis::J));
-        let incoming_count = edge_list.incoming_edges(&key).count();
+        let incoming_count = distinct(edge_list.incoming_edges(&key)).len();
         let level = primitive.level();
         let neighbor_patches = Vec::new();
-        let outgoing_edges = edge_list.outgoing_edges(&key).cloned().collect();
-        Self {
-            conserved,
-            extended_primitive,
+        let periodicity = Periodicity {
+            domain: IndexSpace::new(0..mesh.size.0 as i64, 0..mesh.size.1 as i64),
+            axes: (false, false),
+        };
+        let mut result = Self {
+            boundary_condition: None,
+            conserved: Arc::new(conserved),
+            extended_primitive: Arc::new(extended_primitive),
+            failure: None,
             flux_i,
             flux_j,
+            gravity: None,
+            guard_validation: false,
             incoming_count,
+            incoming_received: 0,
             index_space,
             level,
             mesh,
             neighbor_patches,
-            outgoing_edges,
+            outgoing: distinct(edge_list.outgoing_edges(&key)).into_iter().map(Outgoing::new).collect(),
+            precision: Precision::Double,
+            rim_pieces: Vec::new(),
+            speculated: false,
+            time: 0.0,
             time_step_size,
+            window_shape: None,
+            windowed: false,
             worker_group,
+        };
+        result.select_outgoing(&periodicity);
+        result
+    }
+
+    /// Make the guard zone exchange of this task periodic. The edge list
+    /// given to [`PatchUpdate::new`] must be the one returned by
+    /// [`meshing::periodic_adjacency_list`] for the same periodicity. Each
+    /// neighbor is sent the parts of this patch which wrap around into its
+    /// guard zones, already translated into place (see
+    /// [`Periodicity::wrap`]).
+    ///
+    pub fn with_periodicity(mut self, periodicity: &Periodicity) -> Self {
+        self.select_outgoing(periodicity);
+        self
+    }
+
+    /// Compute the part of this patch to be sent to each neighbor: the
+    /// neighbor's extended index space, wrapped around the periodic axes,
+    /// restricted to this patch. The topology is fixed for the lifetime of
+    /// the task, so this is done once, and each step's messages are just
+    /// copies of these regions. Pieces which land in the neighbor's
+    /// interior (this patch's own image, if it is upstream of itself) are
+    /// left out.
+    fn select_outgoing(&mut self, periodicity: &Periodicity) {
+        let source = self.index_space.refine_by(1 << self.level);
+
+        for outgoing in &mut self.outgoing {
+            let (rect, level) = &outgoing.key;
+            let target = IndexSpace::from(rect.clone());
+            let extended = target.extend_all(NUM_GUARD * (1 << *level));
+            let pieces = periodicity
+                .wrap(&extended)
+                .restrict(&source)
+                .pieces()
+                .iter()
+                .filter(|(piece, t)| !target.contains_space(&piece.translate_by(*t)))
+                .cloned()
+                .collect();
+            outgoing.region = WrappedRegion::from_pieces(pieces)
+        }
+    }
+
+    /// Declare which neighbors are evaluated in the same process as this
+    /// task, e.g. every neighbor when the whole group is advanced by one
+    /// executor. The messages to them share this task's extended primitive
+    /// data, rather than a copy of the part they need, so the neighbor can
+    /// copy its guard zones straight out of it, and no data is extracted or
+    /// converted to the message precision. Such messages can't be
+    /// serialized. Messages to the other neighbors are unaffected. By
+    /// default, no neighbor is local.
+    ///
+    /// The shared data is updated in place when this task is next
+    /// evaluated, which copies it first if a neighbor still holds a message
+    /// from the previous step (e.g. one held back by the executor).
+    ///
+    pub fn set_local_neighbors<F: Fn(&PatchKey) -> bool>(&mut self, is_local: F) {
+        for outgoing in &mut self.outgoing {
+            outgoing.local = is_local(&outgoing.key)
+        }
+    }
+}
+
+/// The data to be sent to one neighbor on each step.
+///
+struct Outgoing {
+    key: PatchKey,
+    region: WrappedRegion,
+    local: bool,
+}
+
+impl Outgoing {
+    fn new(key: PatchKey) -> Self {
+        Self {
+            key,
+            region: WrappedRegion::default(),
+            local: false,
+        }
+    }
+}
+
+/// The guard zone data sent from a [`PatchUpdate`] to one of its neighbors.
+///
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum GuardData {
+    /// The parts of the sender's patch in the receiver's guard zones,
+    /// translated into place, and stored at the sender's precision.
+    Stored(Vec<StoredPatch>),
+
+    /// The sender's extended primitive data, shared with a neighbor in the
+    /// same process (see [`PatchUpdate::set_local_neighbors`]), and the
+    /// region of it which is to be copied into the receiver's guard zones.
+    #[serde(skip)]
+    Shared(Arc<Patch>, WrappedRegion),
+}
+
+/// Stored guard data is compared piece by piece. Shared data is never sent
+/// to another rank, so it is never the same as a previous message.
+#[cfg(feature = "net")]
+impl crate::message::cache::ContentEq for GuardData {
+    fn content_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Stored(a), Self::Stored(b)) => a.content_eq(b),
+            _ => false,
+        }
+    }
+}
+
+/// Return the distinct keys among the given ones, in order of their first
+/// appearance. Patches which are neighbors through several periodic images
+/// have an edge for each image in the adjacency list, but exchange a single
+/// message.
+///
+fn distinct<'a, I: IntoIterator<Item = &'a PatchKey>>(keys: I) -> Vec<PatchKey> {
+    let mut result: Vec<PatchKey> = Vec::new();
+
+    for key in keys {
+        if !result.contains(key) {
+            result.push(key.clone())
         }
     }
+    result
 }
 
 impl PatchUpdate {
-    fn compute_flux(pe: &Patch, axis: Axis, flux: &mut Patch) {
-        let pl = pe.select(flux.index_space().translate(-1, axis));
-        let pr = pe.select(flux.index_space());
+    /// Return the configuration of this task, to be saved with checkpoints
+    /// and checked on restart. It includes the name of the boundary
+    /// condition, so a restart with a different one is caught, and the
+    /// message precision, which may change across restarts.
+    ///
+    pub fn parameters(&self) -> Parameters {
+        let boundary_condition = match &self.boundary_condition {
+            Some((name, _)) => name.as_str(),
+            None => "fixed-ambient",
+        };
+        let mut parameters = Parameters::new()
+            .fixed("euler2d_pcm.num_guard", NUM_GUARD)
+            .fixed("euler2d_pcm.gamma_law_index", GAMMA_LAW_INDEX)
+            .fixed("euler2d_pcm.riemann_solver", "hlle")
+            .fixed("euler2d_pcm.boundary_condition", boundary_condition);
+        parameters.extend(self.precision.parameters());
+        parameters
+    }
+
+    /// Compute the Godunov fluxes on the faces of the given axis which
+    /// bound the zones in `region`.
+    fn compute_flux(pe: &Patch, axis: Axis, flux: &mut Patch, region: &IndexSpace) {
+        let faces = region.extend_upper(1, axis);
+        let pl = pe.select_rows(faces.translate(-1, axis));
+        let pr = pe.select_rows(faces.clone());
+
+        let dir = Direction::from(axis);
+
+        for (f, (pl, pr)) in flux.select_rows_mut(faces.clone()).zip(pl.zip(pr)) {
+            euler2d::riemann_hlle_row(pl, pr, f, dir, GAMMA_LAW_INDEX)
+        }
+        if pe.mask().is_some() {
+            Self::apply_solid_walls(pe, axis, flux, &faces)
+        }
+    }
+
+    /// Replace the fluxes through the given faces which touch solid zones.
+    /// Faces between a solid and a fluid zone are reflecting walls: the flux
+    /// is computed from the fluid state and its mirror image. Faces between
+    /// two solid zones carry no flux.
+    fn apply_solid_walls(pe: &Patch, axis: Axis, flux: &mut Patch, faces: &IndexSpace) {
+        let dir = Direction::from(axis);
+
+        for (i, j) in faces.iter() {
+            let l = match axis {
+                Axis::I => (i - 1, j),
+                Axis::J => (i, j - 1),
+            };
+            let f = flux.get_slice_mut((i, j));
+
+            match (pe.is_solid(l), pe.is_solid((i, j))) {
+                (false, false) => {}
+                (true, true) => f.fill(0.0),
+                (true, false) => {
+                    let pr = Primitive::from(pe.get_slice((i, j)));
+                    euler2d::riemann_hlle(pr.reflect(dir), pr, dir, GAMMA_LAW_INDEX).write_to_slice(f)
+                }
+                (false, true) => {
+                    let pl = Primitive::from(pe.get_slice(l));
+                    let pr = pl.reflect(dir);
+                    euler2d::riemann_hlle(pl, pr, dir, GAMMA_LAW_INDEX).write_to_slice(f)
+                }
+            }
+        }
+    }
+
+    /// Copy the data from a neighboring patch into the guard zones it
+    /// covers. Like [`meshing::extend_patch_mut`], this fills the guard
+    /// strips along the four sides of the patch, but not its corners.
+    fn copy_guard_zones(&mut self, neighbor: &Patch) {
+        self.copy_guard_zones_translated(neighbor, neighbor.index_space(), (0, 0))
+    }
+
+    /// Like [`PatchUpdate::copy_guard_zones`], but copying only the given
+    /// zones of the neighbor, which are placed in this patch's guard zones
+    /// by the given translation (on the high-resolution index space).
+    fn copy_guard_zones_translated(&mut self, neighbor: &Patch, zones: IndexSpace, translation: Translation) {
+        let factor = 1 << self.level;
+        let offset = (translation.0 / factor, translation.1 / factor);
+        let zones = zones.translate_by(offset);
+
+        for strip in self.guard_strips() {
+            let overlap = strip.intersect(zones.clone());
+
+            if !overlap.is_empty() {
+                let source = neighbor.select_rows(overlap.translate_by((-offset.0, -offset.1)));
+                let target = Arc::make_mut(&mut self.extended_primitive).select_rows_mut(overlap);
+
+                for (target, source) in target.zip(source) {
+                    target.copy_from_slice(source)
+                }
+            }
+        }
+    }
+
+    /// Determine whether data from a neighbor can be copied straight into
+    /// the guard zones: it must be at this patch's level, and neither patch
+    /// may have solid zones. While the update is split into windows, the
+    /// extended primitive data is shared with them, so nothing is copied
+    /// into it.
+    fn can_copy_from(&self, neighbor: &Patch) -> bool {
+        !self.windowed
+            && neighbor.level() == self.level
+            && neighbor.mask().is_none()
+            && self.extended_primitive.mask().is_none()
+    }
+
+    /// Fill the guard zones in a part of the extended primitive data from
+    /// the neighbor data kept by [`Automaton::receive`], like
+    /// [`meshing::extend_patch_mut`] does for the whole patch.
+    fn fill_guard_zones(&self, part: &mut Patch) {
+        for strip in self.guard_strips() {
+            for index in strip.intersect(part.index_space()).iter() {
+                if let Some(neighbor) = self.neighbor_patches.patch_containing_point(index) {
+                    part.get_slice_mut(index).clone_from_slice(neighbor.get_slice(index));
+                    part.set_solid(index, neighbor.is_solid(index))
+                }
+            }
+        }
+    }
+
+    /// Return the guard zones along the four sides of the patch, excluding
+    /// the corners, which are not read by the update.
+    fn guard_strips(&self) -> [IndexSpace; 4] {
+        let (i0, j0) = self.index_space.start();
+        let (i1, j1) = self.index_space.end();
+        let (x0, y0) = self.extended_primitive.index_space().start();
+        let (x1, y1) = self.extended_primitive.index_space().end();
+        [
+            IndexSpace::new(x0..i0, j0..j1),
+            IndexSpace::new(i0..i1, y0..j0),
+            IndexSpace::new(i1..x1, j0..j1),
+            IndexSpace::new(i0..i1, j1..y1),
+        ]
+    }
+
+    /// Return the parts of the guard strips which lie inside the mesh, and
+    /// so must be filled by the neighbors rather than the boundary
+    /// condition.
+    fn neighbor_guard_zones(&self) -> Vec<IndexSpace> {
+        let mesh_space = IndexSpace::new(0..self.mesh.size.0 as i64, 0..self.mesh.size.1 as i64).coarsen_by(1 << self.level);
+        self.guard_strips()
+            .iter()
+            .map(|strip| strip.intersect(mesh_space.clone()))
+            .filter(|strip| !strip.is_empty())
+            .collect()
+    }
+
+    /// Turn guard zone validation on or off. While it's on, the guard zones
+    /// which must be filled by the neighbors are overwritten with
+    /// [`crate::patch::POISON`] after each update, and the update panics if
+    /// any of them is still poisoned when it's about to read them, naming
+    /// the zone. A zone which is never filled usually means an edge is
+    /// missing from the topology. Validation assumes the domain is the whole
+    /// rectangle of the mesh; guard zones outside it are filled by the
+    /// boundary condition and are not checked. It only has an effect in
+    /// builds with debug assertions or the `checks` feature. This must be
+    /// called between executions of the task group.
+    pub fn set_guard_validation(&mut self, enabled: bool) {
+        self.guard_validation = enabled && crate::CHECKS;
+        self.poison_guard_zones()
+    }
+
+    fn poison_guard_zones(&mut self) {
+        if self.guard_validation {
+            for strip in self.neighbor_guard_zones() {
+                Arc::make_mut(&mut self.extended_primitive).poison(strip)
+            }
+        }
+    }
+
+    fn validate_guard_zones(&self, extended_primitive: &Patch) {
+        if self.guard_validation {
+            for strip in self.neighbor_guard_zones() {
+                let strip = strip.intersect(extended_primitive.index_space());
+
+                if strip.is_empty() {
+                    continue;
+                }
+                if let Some(index) = extended_primitive.find_poisoned(strip) {
+                    panic! {
+                        "guard zone ({} {}) of patch {:?} at level {} was not filled by a neighbor (is an edge missing?)",
+                        index.0,
+                        index.1,
+                        self.key(),
+                        self.level
+                    }
+                }
+            }
+        }
+    }
 
-        let dir = match axis {
-            Axis::I => Direction::I,
-            Axis::J => Direction::J,
+    /// Return the zones whose update does not depend on guard zones, i.e.
+    /// those at least `NUM_GUARD` zones in from the edge of the patch.
+    fn interior(&self) -> IndexSpace {
+        self.index_space.trim_all(NUM_GUARD)
+    }
+
+    /// Return the zones which are not in the interior, as four disjoint
+    /// strips. This requires the interior to be non-empty.
+    fn rim(&self) -> [IndexSpace; 4] {
+        let (i0, j0) = self.index_space.start();
+        let (i1, j1) = self.index_space.end();
+        let g = NUM_GUARD;
+        [
+            IndexSpace::new(i0..i0 + g, j0..j1),
+            IndexSpace::new(i1 - g..i1, j0..j1),
+            IndexSpace::new(i0 + g..i1 - g, j0..j0 + g),
+            IndexSpace::new(i0 + g..i1 - g, j1 - g..j1),
+        ]
+    }
+
+    /// Advance the conserved variables in the given region by one time step,
+    /// using the current primitive variables. The primitive variables are
+    /// not updated, so that other regions can still be advanced from them.
+    /// Solid zones are left unchanged.
+    fn advance_region(&mut self, region: &IndexSpace) {
+        let step = Step {
+            cell_spacing: self.mesh.cell_spacing(),
+            time_step_size: self.time_step_size,
+            gravity: self.gravity.as_deref(),
         };
+        let u = Arc::make_mut(&mut self.conserved);
+        Self::advance(&self.extended_primitive, &mut self.flux_i, &mut self.flux_j, u, region, step)
+    }
 
-        for (f, (pl, pr)) in flux.iter_data_mut().zip(pl.zip(pr)) {
-            euler2d::riemann_hlle(pl.into(), pr.into(), dir, GAMMA_LAW_INDEX).write_to_slice(f)
+    /// Like [`PatchUpdate::advance_region`], but return the advanced
+    /// conserved variables in the region as a new patch, leaving the given
+    /// ones unchanged. The primitive variables `pe` must cover the region and
+    /// one zone around it.
+    fn advance_piece(pe: &Patch, conserved: &Patch, region: &IndexSpace, step: Step) -> Patch {
+        let (level, num_fields) = (conserved.level(), conserved.num_fields());
+        let mut flux_i = Patch::zeros(level, num_fields, region.extend_upper(1, Axis::I));
+        let mut flux_j = Patch::zeros(level, num_fields, region.extend_upper(1, Axis::J));
+        let mut u = conserved.extract(region.clone());
+        Self::advance(pe, &mut flux_i, &mut flux_j, &mut u, region, step);
+        u
+    }
+
+    fn advance(pe: &Patch, flux_i: &mut Patch, flux_j: &mut Patch, u: &mut Patch, region: &IndexSpace, step: Step) {
+        Self::compute_flux(pe, Axis::I, flux_i, region);
+        Self::compute_flux(pe, Axis::J, flux_j, region);
+
+        let (dx, dy) = step.cell_spacing;
+        let dt = step.time_step_size;
+
+        let fim = flux_i.select(region.clone());
+        let fip = flux_i.select(region.translate(1, Axis::I));
+        let fjm = flux_j.select(region.clone());
+        let fjp = flux_j.select(region.translate(1, Axis::J));
+        let u = u.select_mut(region.clone());
+
+        let fluxes = fip.zip(fim.zip(fjp.zip(fjm)));
+
+        for (index, ((fip, (fim, (fjp, fjm))), u)) in region.iter().zip(fluxes.zip(u)) {
+            let (fip, fim) = (fixed(fip), fixed(fim));
+            let (fjp, fjm) = (fixed(fjp), fixed(fjm));
+
+            if pe.is_solid(index) {
+                continue;
+            }
+            if let Some(gravity) = step.gravity {
+                let (g1, g2) = gravity.acceleration(index);
+                let source = [0.0, u[0] * g1, u[0] * g2, u[1] * g1 + u[2] * g2];
+
+                for (u, s) in u.iter_mut().zip(source) {
+                    *u += s * dt;
+                }
+            }
+            for (n, u) in u.iter_mut().enumerate() {
+                *u -= (fip[n] - fim[n]) * dt / dx + (fjp[n] - fjm[n]) * dt / dy;
+            }
+        }
+    }
+
+    /// Return the time step size this task advances by.
+    pub fn time_step_size(&self) -> f64 {
+        self.time_step_size
+    }
+
+    /// Change the time step size this task advances by. With local time
+    /// stepping, the caller gives each task the step size of its rung in a
+    /// [`crate::schedule::LocalTimeSchedule`].
+    pub fn set_time_step_size(&mut self, time_step_size: f64) {
+        self.time_step_size = time_step_size
+    }
+
+    /// Return the simulation time of this task. It starts at zero, and is
+    /// advanced by the time step size each time the task is evaluated.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Set the simulation time of this task, e.g. on restart, and write the
+    /// boundary values for that time if the boundary condition depends on
+    /// it. This must be called between executions of the task group.
+    pub fn set_time(&mut self, time: f64) {
+        self.time = time;
+        self.apply_boundary_condition()
+    }
+
+    /// Replace the default (fixed ambient) boundary condition with one
+    /// which may depend on time, e.g. an inflow which ramps up. The boundary
+    /// values are written for the current time, and again after each
+    /// update for the new time, so the tasks do not need to be rebuilt as
+    /// the boundary changes. The name identifies the boundary condition in
+    /// the task's [`PatchUpdate::parameters`]. This must be called between
+    /// executions of the task group.
+    pub fn set_boundary_condition(&mut self, name: &str, boundary_condition: BoundaryCondition) {
+        self.boundary_condition = Some((name.to_string(), boundary_condition));
+        self.apply_boundary_condition()
+    }
+
+    /// Write the boundary values for the current time into every guard zone,
+    /// if there is a boundary condition. The guard zones covered by
+    /// neighbors are overwritten as their messages are received; if guard
+    /// zone validation is on, they are poisoned here.
+    fn apply_boundary_condition(&mut self) {
+        if let Some((_, boundary_condition)) = &self.boundary_condition {
+            let time = self.time;
+            meshing::extend_patch_mut(
+                Arc::make_mut(&mut self.extended_primitive),
+                &self.index_space,
+                |index, p| boundary_condition(index, time, p),
+                &Vec::new(),
+            );
+        }
+        self.poison_guard_zones()
+    }
+
+    /// Set the precision in which guard zone data is sent to neighboring
+    /// patches. Single precision halves the message sizes, and quantized
+    /// precision quarters them; the data is converted back to double
+    /// precision when it's received. The precision for a run can be read
+    /// from its parameters with [`Precision::from_parameters`].
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision
+    }
+
+    /// Set the source of gravitational acceleration, which is applied as a
+    /// source term in each subsequent update. The provider is typically the
+    /// [`crate::gravity::PotentialField`] for this patch, returned by
+    /// [`crate::gravity::solve_potential`] between time steps.
+    pub fn set_gravity(&mut self, gravity: Box<Gravity>) {
+        self.gravity = Some(gravity.into())
+    }
+
+    /// Set the shape of the windows into which the interior of the patch is
+    /// divided when the update is split with [`crate::window::with_windows`].
+    /// By default, the interior is a single window.
+    pub fn set_window_shape(&mut self, window_shape: (usize, usize)) {
+        self.window_shape = Some(window_shape)
+    }
+
+    /// Return an error if primitive variable recovery has failed in any zone
+    /// since this task was created. The primitive variables are not updated
+    /// in zones where recovery fails, so the solution should not be advanced
+    /// further after an error.
+    pub fn check(&self) -> Result<()> {
+        match self.failure {
+            None => Ok(()),
+            Some((index, source)) => Err(Error::Solver {
+                patch: self.key(),
+                level: self.level,
+                index,
+                source,
+            }),
+        }
+    }
+
+    /// Recover the primitive variables from the advanced conserved ones, and
+    /// move on to the next time step.
+    fn finish_step(&mut self) {
+        self.recover_primitive();
+        self.time += self.time_step_size;
+        self.apply_boundary_condition()
+    }
+
+    /// Convert the conserved variables to primitive ones in the patch
+    /// interior, and record the first zone where recovery fails.
+    fn recover_primitive(&mut self) {
+        let failed = Cell::new(false);
+
+        self.conserved
+            .map_into_fixed::<_, NUM_FIELDS>(Arc::make_mut(&mut self.extended_primitive), |u, p| {
+                match Conserved::from(&u[..]).to_primitive(GAMMA_LAW_INDEX) {
+                    Ok(prim) => prim.write_to_slice(p),
+                    Err(_) => failed.set(true),
+                }
+            });
+
+        if failed.get() && self.failure.is_none() {
+            self.failure = self
+                .index_space
+                .iter()
+                .zip(self.conserved.select(self.index_space.clone()))
+                .find_map(|(index, u)| Conserved::from(u).to_primitive(GAMMA_LAW_INDEX).err().map(|e| (index, e)))
         }
     }
 
@@ -130,9 +659,77 @@ impl PatchUpdate {
     }
 }
 
+/// The parameters of the conservative update of a region of a patch.
+///
+#[derive(Clone, Copy)]
+struct Step<'a> {
+    cell_spacing: (f64, f64),
+    time_step_size: f64,
+    gravity: Option<&'a Gravity>,
+}
+
+/// View a slice of flux data as a fixed-length array, so the loops over
+/// fields in the conservative update have a compile-time trip count.
+///
+fn fixed(x: &[f64]) -> &[f64; NUM_FIELDS] {
+    x.try_into().unwrap()
+}
+
+/// The state of a [`PatchUpdate`] before a step, saved so that the step can
+/// be undone if it violates the CFL condition.
+///
+pub struct SavedState {
+    conserved: Arc<Patch>,
+    extended_primitive: Arc<Patch>,
+    failure: Option<((i64, i64), hydro::error::Error)>,
+    time: f64,
+}
+
+impl CflMonitored for PatchUpdate {
+    type Saved = SavedState;
+
+    fn save(&self) -> SavedState {
+        SavedState {
+            conserved: self.conserved.clone(),
+            extended_primitive: self.extended_primitive.clone(),
+            failure: self.failure,
+            time: self.time,
+        }
+    }
+
+    fn restore(&mut self, saved: SavedState) {
+        self.conserved = saved.conserved;
+        self.extended_primitive = saved.extended_primitive;
+        self.failure = saved.failure;
+        self.time = saved.time;
+    }
+
+    fn time_step_size(&self) -> f64 {
+        self.time_step_size
+    }
+
+    fn set_time_step_size(&mut self, time_step_size: f64) {
+        self.time_step_size = time_step_size
+    }
+
+    /// The largest signal speed over the fluid zones of the patch, times the
+    /// time step size over the smaller zone width.
+    fn cfl_number(&self) -> f64 {
+        let (dx, dy) = self.mesh.cell_spacing();
+        let max_speed = self
+            .index_space
+            .iter()
+            .zip(self.extended_primitive.select(self.index_space.clone()))
+            .filter(|(index, _)| !self.extended_primitive.is_solid(*index))
+            .map(|(_, p)| Primitive::from(p).max_signal_speed(GAMMA_LAW_INDEX))
+            .fold(0.0, f64::max);
+        max_speed * self.time_step_size / dx.min(dy)
+    }
+}
+
 impl Automaton for PatchUpdate {
     type Key = Rectangle<i64>;
-    type Message = Patch;
+    type Message = GuardData;
     type Value = Self;
 
     fn key(&self) -> Self::Key {
@@ -140,84 +737,589 @@ impl Automaton for PatchUpdate {
     }
 
     fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
-        self.outgoing_edges
+        self.outgoing
             .iter()
-            .cloned()
-            .map(|(rect, level)| {
-                let overlap = IndexSpace::from(rect.clone())
-                    .extend_all(NUM_GUARD * (1 << level))
-                    .coarsen_by(1 << self.level)
-                    .intersect(self.index_space.clone());
-                (rect, self.extended_primitive.extract(overlap))
+            .map(|outgoing| {
+                let data = if outgoing.local {
+                    GuardData::Shared(self.extended_primitive.clone(), outgoing.region.clone())
+                } else {
+                    let pieces = self.extended_primitive.extract_wrapped(&outgoing.region);
+                    GuardData::Stored(pieces.into_iter().map(|piece| StoredPatch::new(piece, self.precision)).collect())
+                };
+                (outgoing.key.0.clone(), data)
             })
             .collect()
     }
 
-    fn receive(&mut self, patch: Self::Message) -> Status {
-        self.neighbor_patches.push(patch);
-        Status::eligible_if(self.neighbor_patches.len() == self.incoming_count)
+    /// Neighbor data at this patch's level, without solid zones on either
+    /// side, is copied straight into the guard zones as it arrives. Other
+    /// data is kept until the task is evaluated, and then sampled zone by
+    /// zone with [`meshing::extend_patch_mut`].
+    fn receive(&mut self, data: Self::Message) -> Status {
+        match data {
+            GuardData::Stored(pieces) => {
+                for piece in pieces {
+                    let patch = piece.into_patch();
+
+                    if self.can_copy_from(&patch) {
+                        self.copy_guard_zones(&patch)
+                    } else {
+                        self.neighbor_patches.push(patch)
+                    }
+                }
+            }
+            GuardData::Shared(source, region) => {
+                if self.can_copy_from(&source) {
+                    let factor = 1 << self.level;
+
+                    for (zones, t) in region.pieces() {
+                        self.copy_guard_zones_translated(&source, zones.coarsen_by(factor), *t)
+                    }
+                } else {
+                    self.neighbor_patches.extend(source.extract_wrapped(&region))
+                }
+            }
+        }
+        self.incoming_received += 1;
+        Status::eligible_if(self.incoming_received == self.incoming_count)
     }
 
-    fn value(self) -> Self::Value {
-        let Self {
-            mut conserved,
-            mut extended_primitive,
-            mut flux_i,
-            mut flux_j,
-            incoming_count,
-            index_space,
-            level,
-            mesh,
-            mut neighbor_patches,
-            outgoing_edges,
-            time_step_size,
-            worker_group,
-        } = self;
+    fn value(mut self) -> Self::Value {
+        // The boundary values were written to the guard zones when the task
+        // was created (or, for a time-dependent boundary condition, after the
+        // previous update), and are not overwritten by data from the
+        // neighbors, so they need not be written again here.
+        if !self.neighbor_patches.is_empty() {
+            meshing::extend_patch_mut(Arc::make_mut(&mut self.extended_primitive), &self.index_space, |_, _| {}, &self.neighbor_patches);
+            self.neighbor_patches.clear();
+        }
+        self.validate_guard_zones(&self.extended_primitive);
+        self.incoming_received = 0;
 
-        meshing::extend_patch_mut(
-            &mut extended_primitive,
-            &index_space,
-            Self::boundary_value,
-            &neighbor_patches,
-        );
-        neighbor_patches.clear();
+        if self.speculated {
+            for region in &self.rim() {
+                self.advance_region(region)
+            }
+        } else {
+            let index_space = self.index_space.clone();
+            self.advance_region(&index_space)
+        }
+        self.speculated = false;
+        self.finish_step();
+        self
+    }
 
-        Self::compute_flux(&extended_primitive, Axis::I, &mut flux_i);
-        Self::compute_flux(&extended_primitive, Axis::J, &mut flux_j);
+    fn speculate(&mut self) {
+        let interior = self.interior();
 
-        let (dx, dy) = mesh.cell_spacing();
-        let dt = time_step_size;
+        if !interior.is_empty() {
+            self.advance_region(&interior);
+            self.speculated = true;
+        }
+    }
 
-        let fim = flux_i.select(index_space.clone());
-        let fip = flux_i.select(index_space.translate(1, Axis::I));
-        let fjm = flux_j.select(index_space.clone());
-        let fjp = flux_j.select(index_space.translate(1, Axis::J));
-        let u = conserved.iter_data_mut();
+    fn num_incoming(&self) -> Option<usize> {
+        Some(self.incoming_count)
+    }
 
-        for (fip, (fim, (fjp, (fjm, u)))) in fip.zip(fim.zip(fjp.zip(fjm.zip(u)))) {
-            for (n, u) in u.iter_mut().enumerate() {
-                *u -= (fip[n] - fim[n]) * dt / dx + (fjp[n] - fjm[n]) * dt / dy;
+    fn worker_hint(&self) -> Option<usize> {
+        self.worker_group
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        Some(self.index_space.refine_by(1 << self.level).start())
+    }
+
+    fn cost(&self) -> Option<f64> {
+        Some(self.index_space.len() as f64)
+    }
+
+    fn message_size(data: &Self::Message) -> usize {
+        match data {
+            GuardData::Stored(pieces) => pieces.iter().map(StoredPatch::size_in_bytes).sum(),
+            GuardData::Shared(..) => core::mem::size_of_val(data),
+        }
+    }
+}
+
+/// The data shared by the windows of a [`PatchUpdate`]: the task's own
+/// primitive and conserved variables at the start of the step, which are
+/// not copied, and the parameters of the update.
+///
+pub struct WindowBacking {
+    primitive: Arc<Patch>,
+    conserved: Arc<Patch>,
+    cell_spacing: (f64, f64),
+    time_step_size: f64,
+    gravity: Option<Arc<Gravity>>,
+}
+
+impl WindowBacking {
+    fn step(&self) -> Step<'_> {
+        Step {
+            cell_spacing: self.cell_spacing,
+            time_step_size: self.time_step_size,
+            gravity: self.gravity.as_deref(),
+        }
+    }
+}
+
+/// The windows cover the interior of the patch (see
+/// [`PatchUpdate::set_window_shape`]), and read the task's data through a
+/// shared [`WindowBacking`]. While they run, the neighbor data received by
+/// the task is kept aside rather than copied into the guard zones, and the
+/// rim is advanced from small copies of the extended primitive data around
+/// each of its four strips. The results are identical to the ordinary
+/// update. Speculation is not used for windowed updates.
+impl Windowed for PatchUpdate {
+    type Backing = WindowBacking;
+    type Piece = Patch;
+
+    fn split(&mut self) -> (Arc<WindowBacking>, Vec<IndexSpace>) {
+        let window_shape = self.window_shape.unwrap_or_else(|| self.interior().dim());
+        let windows = interior_windows(&self.index_space, NUM_GUARD, window_shape);
+        let backing = WindowBacking {
+            primitive: self.extended_primitive.clone(),
+            conserved: self.conserved.clone(),
+            cell_spacing: self.mesh.cell_spacing(),
+            time_step_size: self.time_step_size,
+            gravity: self.gravity.clone(),
+        };
+        self.windowed = true;
+        (Arc::new(backing), windows)
+    }
+
+    fn update_window(backing: &WindowBacking, window: &IndexSpace) -> Patch {
+        Self::advance_piece(&backing.primitive, &backing.conserved, window, backing.step())
+    }
+
+    fn update_rim(&mut self) {
+        let regions = if self.interior().is_empty() {
+            vec![self.index_space.clone()]
+        } else {
+            self.rim().to_vec()
+        };
+        let step = Step {
+            cell_spacing: self.mesh.cell_spacing(),
+            time_step_size: self.time_step_size,
+            gravity: self.gravity.as_deref(),
+        };
+        let pieces = regions
+            .iter()
+            .map(|region| {
+                let mut pe = Patch::extract_from(&self.extended_primitive, region.extend_all(NUM_GUARD));
+                self.fill_guard_zones(&mut pe);
+                self.validate_guard_zones(&pe);
+                Self::advance_piece(&pe, &self.conserved, region, step)
+            })
+            .collect();
+        self.rim_pieces = pieces;
+        self.neighbor_patches.clear();
+        self.incoming_received = 0;
+    }
+
+    fn assemble(mut self, pieces: Vec<Patch>) -> Self {
+        let conserved = Arc::make_mut(&mut self.conserved);
+
+        for piece in self.rim_pieces.drain(..).chain(pieces) {
+            for (target, source) in conserved.select_rows_mut(piece.index_space()).zip(piece.rows()) {
+                target.copy_from_slice(source)
             }
         }
-        conserved.map_into(&mut extended_primitive, Self::cons_to_prim);
+        self.windowed = false;
+        self.finish_step();
+        self
+    }
+}
 
-        Self {
-            conserved,
-            extended_primitive,
-            flux_i,
-            flux_j,
-            incoming_count,
-            index_space,
-            level,
-            mesh,
-            neighbor_patches,
-            outgoing_edges,
-            time_step_size,
-            worker_group,
+#[cfg(test)]
+mod test {
+
+    use super::{BoundaryCondition, Mesh, PatchUpdate};
+    use crate::solvers::cfl::{advance_monitored, CflPolicy};
+    use crate::error::Error;
+    use crate::automaton::{execute, with_speculation};
+    use crate::window::{join_windows, with_windows, Windowed};
+    use crate::adjacency_list::AdjacencyList;
+    use crate::meshing::{periodic_adjacency_list, GraphTopology, PatchKey, Periodicity};
+    use crate::index_space::IndexSpace;
+    use crate::patch::{Patch, Precision};
+    use crate::rect_map::RectangleMap;
+    use std::sync::Arc;
+
+    fn tasks() -> Vec<PatchUpdate> {
+        tasks_with(|p| p)
+    }
+
+    fn tasks_with<F: Fn(Patch) -> Patch>(prepare: F) -> Vec<PatchUpdate> {
+        tasks_with_topology(prepare, |_| {})
+    }
+
+    /// Like `tasks_with`, but the adjacency list can be edited before the
+    /// tasks are created.
+    fn tasks_with_topology<F, G>(prepare: F, edit: G) -> Vec<PatchUpdate>
+    where
+        F: Fn(Patch) -> Patch,
+        G: Fn(&mut AdjacencyList<PatchKey>),
+    {
+        let mesh = Mesh {
+            area: (0.0..1.0, 0.0..1.0),
+            size: (16, 16),
+        };
+        let patches: RectangleMap<_, _> = (0..4)
+            .map(|n| {
+                let (i0, j0) = ((n / 2) * 8, (n % 2) * 8);
+                Patch::from_vector_function(0, (i0..i0 + 8, j0..j0 + 8), |(i, j)| {
+                    let d = if i + j < 12 { 1.0 } else { 0.1 };
+                    [d, 0.0, 0.0, d]
+                })
+            })
+            .map(|p| (p.high_resolution_rect(), prepare(p)))
+            .collect();
+        let mut edge_list = patches.adjacency_list(1);
+        edit(&mut edge_list);
+        patches
+            .into_iter()
+            .map(|(_, p)| PatchUpdate::new(p, mesh.clone(), 0.01, None, &edge_list))
+            .collect()
+    }
+
+    #[test]
+    fn speculative_update_matches_ordinary_update() {
+        let mut ordinary: Vec<_> = execute(tasks()).collect();
+        let mut speculative: Vec<_> = execute(with_speculation(tasks())).collect();
+        ordinary.sort_by_key(|task| task.primitive().index_space().start());
+        speculative.sort_by_key(|task| task.primitive().index_space().start());
+
+        for (a, b) in ordinary.iter().zip(&speculative) {
+            assert_eq!(a.primitive().data(), b.primitive().data());
         }
     }
 
-    fn worker_hint(&self) -> Option<usize> {
-        self.worker_group
+    #[test]
+    fn periodic_updates_do_not_depend_on_the_decomposition() {
+        let mesh = Mesh {
+            area: (0.0..1.0, 0.0..1.0),
+            size: (16, 16),
+        };
+        let periodicity = Periodicity {
+            domain: IndexSpace::new(0..16, 0..16),
+            axes: (true, true),
+        };
+        let run_with = |block_size: i64, initial: fn((i64, i64)) -> [f64; 4], local: bool| {
+            let n = 16 / block_size;
+            let patches: RectangleMap<_, _> = (0..n * n)
+                .map(|m| {
+                    let (i0, j0) = ((m / n) * block_size, (m % n) * block_size);
+                    Patch::from_vector_function(0, (i0..i0 + block_size, j0..j0 + block_size), initial)
+                })
+                .map(|p| (p.high_resolution_rect(), p))
+                .collect();
+            let (edge_list, _) = periodic_adjacency_list(&patches, 1, &periodicity);
+            let mut tasks: Vec<_> = patches
+                .into_iter()
+                .map(|(_, p)| PatchUpdate::new(p, mesh.clone(), 0.01, None, &edge_list).with_periodicity(&periodicity))
+                .collect();
+
+            for task in &mut tasks {
+                task.set_local_neighbors(|_| local)
+            }
+            for _ in 0..3 {
+                tasks = execute(tasks).collect();
+            }
+            let mut result = Patch::zeros(0, 4, IndexSpace::new(0..16, 0..16));
+
+            for task in tasks {
+                let primitive = task.primitive();
+
+                for index in primitive.index_space().iter() {
+                    result.get_slice_mut(index).copy_from_slice(primitive.get_slice(index))
+                }
+            }
+            result
+        };
+        let run = |block_size, initial| run_with(block_size, initial, false);
+        let varying = |(i, j): (i64, i64)| [1.0 + (i * 3 + j) as f64 / 64.0, 0.5, -0.25, 1.0];
+        let uniform = |_| [1.0, 0.5, -0.25, 1.0];
+
+        assert_eq!(run(16, varying).data(), run(8, varying).data());
+        assert_eq!(run(16, varying).data(), run(4, varying).data());
+        assert!(run(8, uniform).data().chunks(4).all(|p| p == uniform((0, 0))));
+        assert_eq!(run(4, varying).data(), run_with(4, varying, true).data());
+    }
+
+    #[test]
+    fn windowed_update_matches_ordinary_update() {
+        for prepare in [|p| p, |p: Patch| p.with_mask(|(i, j)| i == 3 && j < 5)] {
+            let mut ordinary = tasks_with(prepare);
+            let mut windowed = tasks_with(prepare);
+
+            for (n, task) in windowed.iter_mut().enumerate() {
+                task.set_window_shape((3, 2));
+                task.set_local_neighbors(|_| n % 2 == 0);
+                task.set_guard_validation(true);
+            }
+            for _ in 0..3 {
+                ordinary = execute(ordinary).collect();
+                windowed = join_windows(execute(with_windows(windowed))).collect();
+            }
+            ordinary.sort_by_key(|task| task.primitive().index_space().start());
+            windowed.sort_by_key(|task| task.primitive().index_space().start());
+
+            for (a, b) in ordinary.iter().zip(&windowed) {
+                assert_eq!(a.primitive().data(), b.primitive().data());
+            }
+        }
+    }
+
+    #[test]
+    fn windows_share_the_patch_data() {
+        let mut task = tasks().remove(0);
+        let (backing, windows) = task.split();
+
+        assert_eq!(windows.len(), 1);
+        assert!(Arc::ptr_eq(&backing.primitive, &task.extended_primitive));
+        assert!(Arc::ptr_eq(&backing.conserved, &task.conserved));
+    }
+
+    #[test]
+    fn shared_guard_data_matches_stored_guard_data() {
+        for prepare in [|p| p, |p: Patch| p.with_mask(|_| false)] {
+            let mut stored = tasks_with(prepare);
+            let mut shared = tasks_with(prepare);
+
+            for task in &mut shared {
+                task.set_local_neighbors(|_| true)
+            }
+            for _ in 0..3 {
+                stored = execute(stored).collect();
+                shared = execute(shared).collect();
+            }
+            stored.sort_by_key(|task| task.primitive().index_space().start());
+            shared.sort_by_key(|task| task.primitive().index_space().start());
+
+            for (a, b) in stored.iter().zip(&shared) {
+                assert_eq!(a.primitive().data(), b.primitive().data());
+            }
+        }
+    }
+
+    #[test]
+    fn guard_zones_copied_on_receipt_match_sampled_guard_zones() {
+        // A mask with no solid zones sends every message down the path
+        // which samples guard zones when the task is evaluated.
+        let mut copied = tasks();
+        let mut sampled = tasks_with(|p| p.with_mask(|_| false));
+
+        for _ in 0..3 {
+            copied = execute(copied).collect();
+            sampled = execute(sampled).collect();
+        }
+        copied.sort_by_key(|task| task.primitive().index_space().start());
+        sampled.sort_by_key(|task| task.primitive().index_space().start());
+
+        for (a, b) in copied.iter().zip(&sampled) {
+            assert_eq!(a.primitive().data(), b.primitive().data());
+        }
+    }
+
+    #[test]
+    fn validated_guard_zones_do_not_change_the_result() {
+        for prepare in [|p| p, |p: Patch| p.with_mask(|_| false)] {
+            let mut plain = tasks_with(prepare);
+            let mut validated = tasks_with(prepare);
+
+            for task in &mut validated {
+                task.set_guard_validation(true);
+            }
+            for _ in 0..3 {
+                plain = execute(plain).collect();
+                validated = execute(validated).collect();
+            }
+            plain.sort_by_key(|task| task.primitive().index_space().start());
+            validated.sort_by_key(|task| task.primitive().index_space().start());
+
+            for (a, b) in plain.iter().zip(&validated) {
+                assert_eq!(a.primitive().data(), b.primitive().data());
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "checks"))]
+    #[should_panic(expected = "guard zone (8 0) of patch (0..8, 0..8) at level 0 was not filled by a neighbor")]
+    fn guard_zones_missed_by_the_topology_are_reported() {
+        let mut tasks = tasks_with_topology(|p| p, |edges| edges.remove(((8..16, 0..8), 0), ((0..8, 0..8), 0)));
+
+        for task in &mut tasks {
+            task.set_guard_validation(true);
+        }
+        execute(tasks).for_each(drop);
+    }
+
+    #[test]
+    fn boundary_conditions_can_depend_on_time() {
+        let inflow = |t: f64| [1.0, (10.0 * t).min(0.5), 0.0, 1.0];
+        let boundary: BoundaryCondition = Arc::new(move |(i, _), t, p| {
+            if i < 0 {
+                p.copy_from_slice(&inflow(t))
+            } else {
+                p.copy_from_slice(&[1.0, 0.0, 0.0, 1.0])
+            }
+        });
+        let mut tasks = tasks_with(|p| Patch::from_vector_function(0, p.index_space(), |_| [1.0, 0.0, 0.0, 1.0]));
+
+        let saved = tasks[0].parameters();
+
+        for task in &mut tasks {
+            task.set_boundary_condition("ramped-inflow", boundary.clone());
+        }
+        assert!(tasks[0].parameters().check_restart(&saved).is_err());
+        assert_eq!(tasks[0].parameters().get_text("euler2d_pcm.boundary_condition").unwrap(), "ramped-inflow");
+
+        for _ in 0..3 {
+            tasks = execute(tasks).collect();
+        }
+        let left = tasks.iter().find(|task| task.index_space.start() == (0, 0)).unwrap();
+        assert!((left.time() - 0.03).abs() < 1e-12);
+        assert_eq!(left.extended_primitive.get_slice((-1, 4)), inflow(0.03));
+        assert!(left.primitive().get_slice((0, 4))[1] > 0.0);
+
+        let right = tasks.iter().find(|task| task.index_space.start() == (8, 8)).unwrap();
+        assert!(right.primitive().data().chunks(4).all(|p| p[1] == 0.0));
+    }
+
+    #[test]
+    fn steps_violating_the_cfl_condition_are_retried() {
+        // The zone width is 1/16, and the fastest signal speed is about
+        // 1.3, so a step of 0.05 has a CFL number near 1.
+        let tasks: Vec<_> = tasks()
+            .into_iter()
+            .map(|mut task| {
+                task.set_time_step_size(0.05);
+                task
+            })
+            .collect();
+        let (tasks, report) = advance_monitored(tasks, &CflPolicy::default(), |tasks| execute(tasks).collect()).unwrap();
+        let mut reference: Vec<_> = execute(tasks_with(|p| p).into_iter().map(|mut task| {
+            task.set_time_step_size(0.05 * report.time_step_scale);
+            task
+        }))
+        .collect();
+        let mut tasks = tasks;
+
+        assert!(report.was_retried());
+        assert!(report.cfl <= 0.5);
+        tasks.sort_by_key(|task| task.primitive().index_space().start());
+        reference.sort_by_key(|task| task.primitive().index_space().start());
+
+        for (a, b) in tasks.iter().zip(&reference) {
+            assert_eq!(a.time(), b.time());
+            assert_eq!(a.primitive().data(), b.primitive().data());
+        }
+    }
+
+    #[test]
+    fn single_precision_messages_are_close_to_double() {
+        let mut double: Vec<_> = execute(tasks()).collect();
+        let mut single: Vec<_> = execute(tasks().into_iter().map(|mut task| {
+            task.set_precision(Precision::Single);
+            task
+        }))
+        .collect();
+        double.sort_by_key(|task| task.primitive().index_space().start());
+        single.sort_by_key(|task| task.primitive().index_space().start());
+
+        for (a, b) in double.iter().zip(&single) {
+            for (x, y) in a.primitive().data().iter().zip(b.primitive().data()) {
+                assert!((x - y).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn quantized_messages_are_close_to_double() {
+        let mut double: Vec<_> = execute(tasks()).collect();
+        let mut quantized: Vec<_> = execute(tasks().into_iter().map(|mut task| {
+            task.set_precision(Precision::Quantized);
+            task
+        }))
+        .collect();
+        double.sort_by_key(|task| task.primitive().index_space().start());
+        quantized.sort_by_key(|task| task.primitive().index_space().start());
+
+        for (a, b) in double.iter().zip(&quantized) {
+            for (x, y) in a.primitive().data().iter().zip(b.primitive().data()) {
+                assert!((x - y).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn solid_zones_are_reflecting_obstacles() {
+        let mesh = Mesh {
+            area: (0.0..1.0, 0.0..1.0),
+            size: (16, 16),
+        };
+        let solid = |(i, j): (i64, i64)| (6..10).contains(&i) && (5..11).contains(&j);
+        let patches: RectangleMap<_, _> = (0..4)
+            .map(|n| {
+                let (i0, j0) = ((n / 2) * 8, (n % 2) * 8);
+                Patch::from_vector_function(0, (i0..i0 + 8, j0..j0 + 8), |index| {
+                    if solid(index) {
+                        [10.0, 1.0, 0.0, 10.0]
+                    } else {
+                        [0.1, 0.0, 0.0, 0.125]
+                    }
+                })
+                .with_mask(solid)
+            })
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+        let edge_list = patches.adjacency_list(1);
+        let tasks: Vec<_> = patches
+            .into_iter()
+            .map(|(_, p)| PatchUpdate::new(p, mesh.clone(), 0.01, None, &edge_list))
+            .collect();
+
+        for task in execute(tasks) {
+            let primitive = task.primitive();
+            for index in primitive.index_space().iter() {
+                let expected = if solid(index) { [10.0, 1.0, 0.0, 10.0] } else { [0.1, 0.0, 0.0, 0.125] };
+                for (x, y) in primitive.get_slice(index).iter().zip(expected) {
+                    assert!((x - y).abs() < 1e-12, "zone ({} {}) was disturbed", index.0, index.1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn failed_primitive_recovery_is_reported() {
+        let mesh = Mesh {
+            area: (0.0..1.0, 0.0..1.0),
+            size: (8, 4),
+        };
+        let patches: RectangleMap<_, _> = (0..2)
+            .map(|n| {
+                let p = if n == 0 { 100.0 } else { 1e-3 };
+                Patch::from_vector_function(0, (n * 4..n * 4 + 4, 0..4), |_| [1.0, 0.0, 0.0, p])
+            })
+            .map(|p| (p.high_resolution_rect(), p))
+            .collect();
+        let edge_list = patches.adjacency_list(1);
+        let tasks: Vec<_> = patches
+            .into_iter()
+            .map(|(_, p)| PatchUpdate::new(p, mesh.clone(), 1.0, None, &edge_list))
+            .collect();
+
+        for task in execute(tasks) {
+            match task.check() {
+                Err(Error::Solver { patch, level, index, .. }) => {
+                    assert_eq!(level, 0);
+                    assert!(patch.0.contains(&index.0) && patch.1.contains(&index.1));
+                }
+                _ => panic!("expected a solver error"),
+            }
+        }
     }
 }
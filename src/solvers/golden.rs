@@ -0,0 +1,324 @@
+//! End-to-end regression tests for the solvers. A small blast wave problem is
+//! run for a fixed number of steps on one rank and on four ranks (each rank
+//! on its own thread, connected by a [`LocalCommunicator`]), and the results
+//! are compared bitwise with each other, and against reference data stored
+//! in the `golden` directory next to this file.
+//!
+//! A second run on two ranks exchanges its remote messages through an
+//! [`OrderedCommunicator`], and advances the local tasks with the serial
+//! executor, to check that iteration ordering on the wire and message
+//! delivery in the executor give the same results as a single rank.
+//!
+//! If a change to a solver is meant to alter the results, the reference
+//! data can be regenerated by running the tests with `GRIDIRON_BLESS=1` in
+//! the environment.
+
+use crate::adjacency_list::AdjacencyList;
+use crate::automaton::{execute, Automaton};
+use crate::hydro::euler2d::Primitive;
+use crate::index_space::range2d;
+use crate::meshing::{Domain, GraphTopology, PatchKey};
+use crate::message::comm::Communicator;
+use crate::message::cache::MessageCache;
+use crate::message::envelope::{self, Envelope};
+use crate::message::local::LocalCommunicator;
+use crate::message::ordered::{FanIn, OrderedCommunicator};
+use crate::patch::Patch;
+use crate::rect_map::{Rectangle, RectangleMap};
+use crate::solvers::euler2d_pcm::{GuardData, Mesh, PatchUpdate};
+use crate::thread_pool::ThreadPool;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+const RESOLUTION: i64 = 64;
+const BLOCK_SIZE: i64 = 16;
+const NUM_STEPS: usize = 10;
+const TIME_STEP_SIZE: f64 = 0.004;
+const TOLERANCE: f64 = 1e-12;
+
+type Message = <PatchUpdate as Automaton>::Message;
+
+fn mesh() -> Mesh {
+    Domain::new((-1.0..1.0, -1.0..1.0), (RESOLUTION as usize, RESOLUTION as usize)).mesh()
+}
+
+fn initial_patches() -> RectangleMap<i64, Patch> {
+    let mesh = mesh();
+    let nb = RESOLUTION / BLOCK_SIZE;
+    let initial_data = |index| {
+        let (x, y) = mesh.cell_center(index);
+        if (x * x + y * y).sqrt() < 0.24 {
+            Primitive::new(1.0, 0.0, 0.0, 1.0).as_array()
+        } else {
+            Primitive::new(0.1, 0.0, 0.0, 0.125).as_array()
+        }
+    };
+    range2d(0..nb, 0..nb)
+        .iter()
+        .map(|(i, j)| (i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE, j * BLOCK_SIZE..(j + 1) * BLOCK_SIZE))
+        .map(|rect| Patch::from_vector_function(0, rect, initial_data))
+        .map(|p| (p.high_resolution_rect(), p))
+        .collect()
+}
+
+/// Assign each patch to a rank, by splitting the blocks into contiguous
+/// groups along the `i` axis.
+fn owner(key: &Rectangle<i64>, num_ranks: usize) -> usize {
+    key.0.start as usize * num_ranks / RESOLUTION as usize
+}
+
+/// Advance the patches owned by one rank through the whole run, exchanging
+/// messages addressed to patches on other ranks through the communicator.
+/// The remote messages are encoded on a communication worker. Returns the
+/// final primitive data on this rank's patches.
+fn run_rank<C: Communicator>(comm: C, patches: RectangleMap<i64, Patch>) -> Vec<Patch> {
+    let pool = ThreadPool::unpinned(1).with_comm_threads(1);
+    let edges: AdjacencyList<PatchKey> = patches.adjacency_list(1);
+    let size = comm.size();
+    let rank = comm.rank();
+    let mut tasks: Vec<_> = patches
+        .into_iter()
+        .filter(|(rect, _)| owner(rect, size) == rank)
+        .map(|(_, p)| PatchUpdate::new(p, mesh(), TIME_STEP_SIZE, None, &edges))
+        .collect();
+
+    let num_remote: usize = tasks
+        .iter()
+        .map(|task| {
+            edges
+                .incoming_edges(&(task.key(), 0))
+                .filter(|(rect, _)| owner(rect, size) != rank)
+                .count()
+        })
+        .sum();
+
+    // Ranks are not synchronized, so a message for the next step may arrive
+    // before all of the messages for this one.
+    let mut early: Vec<(Rectangle<i64>, Message)> = Vec::new();
+
+    for step in 0..NUM_STEPS {
+        let mut inbox: HashMap<Rectangle<i64>, Vec<Message>> = HashMap::new();
+        let mut outgoing = Vec::new();
+
+        for task in &tasks {
+            for (dest, message) in task.messages() {
+                let dest_rank = owner(&dest, size);
+
+                if dest_rank == rank {
+                    inbox.entry(dest).or_default().push(message)
+                } else {
+                    outgoing.push((dest_rank, Envelope::new(step as u64, dest, message)))
+                }
+            }
+        }
+        let (dest_ranks, envelopes): (Vec<_>, Vec<_>) = outgoing.into_iter().unzip();
+
+        for (dest_rank, bytes) in dest_ranks.into_iter().zip(envelope::encode_on(&pool, envelopes)) {
+            comm.send(dest_rank, bytes)
+        }
+        let mut num_received = early.len();
+
+        for (dest, message) in early.drain(..) {
+            inbox.entry(dest).or_default().push(message)
+        }
+        while num_received < num_remote {
+            let envelope: Envelope<Rectangle<i64>, Message> = Envelope::decode(&comm.recv()).unwrap();
+
+            if envelope.iteration == step as u64 {
+                inbox.entry(envelope.key).or_default().push(envelope.payload);
+                num_received += 1;
+            } else {
+                early.push((envelope.key, envelope.payload))
+            }
+        }
+        tasks = tasks
+            .into_iter()
+            .map(|mut task| {
+                // Deliver messages in a fixed order, since the order they
+                // arrive in depends on the number of ranks.
+                let mut messages = inbox.remove(&task.key()).unwrap_or_default();
+                messages.sort_by_key(|data| match data {
+                    GuardData::Stored(pieces) => pieces.iter().map(|piece| piece.index_space().start()).collect(),
+                    GuardData::Shared(..) => Vec::new(),
+                });
+
+                for message in messages {
+                    task.receive(message);
+                }
+                task.value()
+            })
+            .collect();
+    }
+    tasks.iter().map(|task| task.primitive()).collect()
+}
+
+/// Like [`run_rank`], but messages to other ranks go through an ordered
+/// communicator, stamped with the step number, so that messages for the
+/// next step which arrive early are held back by the communicator. They
+/// are encoded through a message cache, which re-uses the encoding of
+/// guard data that hasn't changed since the last step (e.g. far from the
+/// blast wave). Remote
+/// messages are counted in by their edges and delivered first, and then the
+/// local tasks are advanced with the serial executor, which delivers the
+/// local messages.
+fn run_rank_ordered<C: Communicator>(comm: OrderedCommunicator<C>, patches: RectangleMap<i64, Patch>) -> Vec<Patch> {
+    let edges: AdjacencyList<PatchKey> = patches.adjacency_list(1);
+    let size = comm.size();
+    let rank = comm.rank();
+    let mut tasks: HashMap<_, _> = patches
+        .into_iter()
+        .filter(|(rect, _)| owner(rect, size) == rank)
+        .map(|(rect, p)| (rect, PatchUpdate::new(p, mesh(), TIME_STEP_SIZE, None, &edges)))
+        .collect();
+
+    let fan_in = FanIn::from_edges(&edges, rank, |(rect, _): &PatchKey| (owner(rect, size), rect.clone()));
+    let mut cache = MessageCache::new();
+
+    for step in 0..NUM_STEPS {
+        for task in tasks.values() {
+            for (dest, message) in task.messages() {
+                let dest_rank = owner(&dest, size);
+
+                if dest_rank != rank {
+                    let bytes = cache.encode((task.key(), dest.clone()), step as u64, &dest, message);
+                    comm.send_at(dest_rank, step as u64, bytes)
+                }
+            }
+        }
+        for bytes in comm.recv_expected(&fan_in, Duration::from_secs(10)).unwrap() {
+            let envelope: Envelope<Rectangle<i64>, Message> = Envelope::decode(&bytes).unwrap();
+            assert_eq!(envelope.iteration, step as u64);
+            tasks.get_mut(&envelope.key).unwrap().receive(envelope.payload);
+        }
+        tasks = execute(tasks.into_values())
+            .map(|task| (task.key(), task))
+            .collect();
+        comm.next_iteration();
+    }
+    assert!(cache.num_hits() > 0);
+    tasks.values().map(|task| task.primitive()).collect()
+}
+
+/// Run the problem on the given number of ranks, and return the final
+/// primitive data on all the patches, sorted by key.
+fn run(num_ranks: usize) -> Vec<Patch> {
+    run_with(num_ranks, run_rank)
+}
+
+/// Run the problem on the given number of ranks, advancing each rank with
+/// the given function, and return the final primitive data on all the
+/// patches, sorted by key.
+fn run_with<F>(num_ranks: usize, run_rank: F) -> Vec<Patch>
+where
+    F: Fn(LocalCommunicator, RectangleMap<i64, Patch>) -> Vec<Patch> + Copy + Send + 'static,
+{
+    let patches = initial_patches();
+    let handles: Vec<_> = LocalCommunicator::group(num_ranks)
+        .into_iter()
+        .map(|comm| {
+            let patches = patches.clone();
+            thread::spawn(move || run_rank(comm, patches))
+        })
+        .collect();
+
+    let mut result: Vec<_> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+    result.sort_by_key(|p| p.index_space().start());
+    result
+}
+
+/// Summary of a solution: an FNV-1a hash of the bit patterns of all the
+/// data, and the sum of each field over all zones.
+struct Summary {
+    hash: u64,
+    sums: Vec<f64>,
+}
+
+impl Summary {
+    fn new(patches: &[Patch]) -> Self {
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        let mut sums = vec![0.0; 4];
+
+        for patch in patches {
+            for zone in patch.data().chunks_exact(4) {
+                for (s, x) in sums.iter_mut().zip(zone) {
+                    *s += x;
+                    for byte in x.to_bits().to_le_bytes() {
+                        hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+                    }
+                }
+            }
+        }
+        Self { hash, sums }
+    }
+
+    fn to_text(&self) -> String {
+        let sums: Vec<_> = self.sums.iter().map(|s| format!("{:.17e}", s)).collect();
+        format!("hash {:016x}\nsums {}\n", self.hash, sums.join(" "))
+    }
+
+    fn from_text(text: &str) -> Self {
+        let mut lines = text.lines();
+        let hash = lines.next().unwrap().trim_start_matches("hash ");
+        let sums = lines.next().unwrap().trim_start_matches("sums ");
+        Self {
+            hash: u64::from_str_radix(hash, 16).unwrap(),
+            sums: sums.split(' ').map(|s| s.parse().unwrap()).collect(),
+        }
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/solvers/golden")
+        .join(name)
+}
+
+/// Compare a result with the named reference data, or overwrite the
+/// reference data if blessing is enabled.
+fn check_against_reference(name: &str, patches: &[Patch]) {
+    let summary = Summary::new(patches);
+    let path = reference_path(name);
+
+    if std::env::var_os("GRIDIRON_BLESS").is_some() {
+        std::fs::write(&path, summary.to_text()).unwrap();
+        return;
+    }
+    let reference = Summary::from_text(&std::fs::read_to_string(&path).unwrap());
+
+    for (field, (a, b)) in summary.sums.iter().zip(&reference.sums).enumerate() {
+        assert! {
+            (a - b).abs() <= TOLERANCE * b.abs().max(1.0),
+            "field {} sums to {:e}, but the reference is {:e}",
+            field,
+            a,
+            b
+        };
+    }
+    assert_eq!(summary.hash, reference.hash, "the result is close to, but not bitwise equal to, the reference");
+}
+
+#[test]
+fn blast_wave_matches_reference_on_one_and_four_ranks() {
+    let serial = run(1);
+    let parallel = run(4);
+
+    assert_eq!(serial.len(), parallel.len());
+    for (a, b) in serial.iter().zip(&parallel) {
+        assert_eq!(a.data(), b.data());
+    }
+    check_against_reference("blast64.txt", &serial);
+}
+
+#[test]
+fn blast_wave_agrees_on_one_and_two_ranks_with_ordered_messages() {
+    let serial = run(1);
+    let parallel = run_with(2, |comm, patches| run_rank_ordered(OrderedCommunicator::new(comm), patches));
+
+    assert_eq!(serial.len(), parallel.len());
+    for (a, b) in serial.iter().zip(&parallel) {
+        assert_eq!(a.high_resolution_rect(), b.high_resolution_rect());
+        assert_eq!(a.data(), b.data());
+    }
+}
@@ -1 +1,9 @@
+pub mod autotune;
+pub mod cfl;
 pub mod euler2d_pcm;
+pub mod euler2d_weno;
+pub mod limiters;
+pub mod mhd2d_ct;
+
+#[cfg(all(test, feature = "net"))]
+mod golden;
@@ -0,0 +1,334 @@
+//! Patches which store data only in their active zones. A fine level which
+//! covers a thin or curved region (e.g. a shock front) can have a bounding
+//! rectangle much larger than the region itself; storing the whole rectangle
+//! as a dense [`Patch`] wastes memory on zones which are never updated. A
+//! [`SparsePatch`] keeps the data of its active zones contiguously, row by
+//! row, with each row described by runs of consecutive active zones.
+//! Traversals visit only the active zones, and extracted subsets (e.g. the
+//! part of a patch sent to a neighbor) carry only active zones as well.
+
+use crate::index_space::IndexSpace;
+use crate::patch::Patch;
+use crate::rect_map::Rectangle;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A run of consecutive active zones in one row of a sparse patch, and the
+/// position of its first zone in the data array.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Run {
+    i: i64,
+    j: Range<i64>,
+    offset: usize,
+}
+
+/// A patch whose data is stored only in its active zones. The active zones
+/// are fixed when the patch is created. Like [`Patch`], it has a level, an
+/// index space, and a runtime number of fields per zone.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SparsePatch {
+    level: u32,
+    rect: Rectangle<i64>,
+    num_fields: usize,
+    runs: Vec<Run>,
+    data: Vec<f64>,
+}
+
+impl SparsePatch {
+    /// Generate a sparse patch at a given level, covering the given space,
+    /// whose active zones are those for which `is_active` returns true. The
+    /// values in the active zones are defined from a closure which operates
+    /// on mutable slices. No memory is allocated for the inactive zones.
+    ///
+    pub fn from_slice_function<I, A, F>(level: u32, space: I, num_fields: usize, is_active: A, f: F) -> Self
+    where
+        I: Into<IndexSpace>,
+        A: Fn((i64, i64)) -> bool,
+        F: Fn((i64, i64), &mut [f64]),
+    {
+        let space: IndexSpace = space.into();
+        let (i0, j0) = space.start();
+        let (i1, j1) = space.end();
+        let mut runs: Vec<Run> = Vec::new();
+        let mut offset = 0;
+
+        for i in i0..i1 {
+            for j in j0..j1 {
+                if !is_active((i, j)) {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some(run) if run.i == i && run.j.end == j => run.j.end += 1,
+                    _ => runs.push(Run { i, j: j..j + 1, offset }),
+                }
+                offset += num_fields;
+            }
+        }
+        let mut data = vec![0.0; offset];
+
+        for (run, chunk) in runs.iter().zip(split_runs(&runs, &mut data, num_fields)) {
+            for (j, slice) in run.j.clone().zip(chunk.chunks_exact_mut(num_fields)) {
+                f((run.i, j), slice)
+            }
+        }
+        Self {
+            level,
+            rect: space.into(),
+            num_fields,
+            runs,
+            data,
+        }
+    }
+
+    /// Copy the zones of a dense patch for which `is_active` returns true
+    /// into a sparse patch with the same level and index space.
+    ///
+    pub fn from_patch<A>(patch: &Patch, is_active: A) -> Self
+    where
+        A: Fn((i64, i64)) -> bool,
+    {
+        Self::from_slice_function(patch.level(), patch.index_space(), patch.num_fields(), is_active, |index, slice| {
+            slice.copy_from_slice(patch.get_slice(index))
+        })
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn num_fields(&self) -> usize {
+        self.num_fields
+    }
+
+    /// Return the index space covered by this patch, including its inactive
+    /// zones.
+    ///
+    pub fn index_space(&self) -> IndexSpace {
+        IndexSpace::from(self.rect.clone())
+    }
+
+    /// Return the number of active zones.
+    ///
+    pub fn num_active(&self) -> usize {
+        self.data.len() / self.num_fields.max(1)
+    }
+
+    /// Return the fraction of the patch's zones which are active.
+    ///
+    pub fn active_fraction(&self) -> f64 {
+        self.num_active() as f64 / self.index_space().len().max(1) as f64
+    }
+
+    /// Return the approximate number of bytes held by the patch, for
+    /// comparison with [`Patch::size_in_bytes`].
+    ///
+    pub fn size_in_bytes(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f64>() + self.runs.len() * std::mem::size_of::<Run>()
+    }
+
+    /// Return true if the zone at the given index is active. Indexes outside
+    /// the patch are never active.
+    ///
+    pub fn is_active(&self, index: (i64, i64)) -> bool {
+        self.find(index).is_some()
+    }
+
+    /// Return the data in the zone at the given index, or `None` if the zone
+    /// is inactive.
+    ///
+    pub fn get_slice(&self, index: (i64, i64)) -> Option<&[f64]> {
+        let n = self.find(index)?;
+        Some(&self.data[n..n + self.num_fields])
+    }
+
+    /// Return the data in the zone at the given index mutably, or `None` if
+    /// the zone is inactive.
+    ///
+    pub fn get_slice_mut(&mut self, index: (i64, i64)) -> Option<&mut [f64]> {
+        let n = self.find(index)?;
+        Some(&mut self.data[n..n + self.num_fields])
+    }
+
+    /// Iterate over the active zones in row-major order, and their data.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = ((i64, i64), &[f64])> {
+        self.runs
+            .iter()
+            .flat_map(|run| run.j.clone().map(move |j| (run.i, j)))
+            .zip(self.data.chunks_exact(self.num_fields))
+    }
+
+    /// Iterate mutably over the active zones in row-major order, and their
+    /// data.
+    ///
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ((i64, i64), &mut [f64])> {
+        self.runs
+            .iter()
+            .flat_map(|run| run.j.clone().map(move |j| (run.i, j)))
+            .zip(self.data.chunks_exact_mut(self.num_fields))
+    }
+
+    /// Extract the active zones in the given subspace into a new sparse
+    /// patch covering the subspace, e.g. the part of this patch needed by a
+    /// neighbor's guard zones.
+    ///
+    pub fn extract<I: Into<IndexSpace>>(&self, subset: I) -> Self {
+        let subset: IndexSpace = subset.into();
+        let (i0, j0) = subset.start();
+        let (i1, j1) = subset.end();
+        let nf = self.num_fields;
+        let mut runs = Vec::new();
+        let mut data = Vec::new();
+
+        for run in self.runs.iter().filter(|run| (i0..i1).contains(&run.i)) {
+            let j = run.j.start.max(j0)..run.j.end.min(j1);
+
+            if !j.is_empty() {
+                let n = run.offset + (j.start - run.j.start) as usize * nf;
+                runs.push(Run {
+                    i: run.i,
+                    j: j.clone(),
+                    offset: data.len(),
+                });
+                data.extend_from_slice(&self.data[n..n + (j.end - j.start) as usize * nf]);
+            }
+        }
+        Self {
+            level: self.level,
+            rect: subset.into(),
+            num_fields: nf,
+            runs,
+            data,
+        }
+    }
+
+    /// Write the active zones of this patch into the zones of a dense patch
+    /// which it overlaps. The patches must be on the same level.
+    ///
+    pub fn write_into(&self, target: &mut Patch) {
+        assert_eq!(self.level, target.level(), "sparse and dense patches must be on the same level");
+        let space = target.index_space();
+
+        for (index, slice) in self.iter() {
+            if space.contains(index) {
+                target.get_slice_mut(index).copy_from_slice(slice)
+            }
+        }
+    }
+
+    /// Return a dense patch with the same level and index space, whose
+    /// inactive zones have the value `fill` in every field.
+    ///
+    pub fn to_patch(&self, fill: f64) -> Patch {
+        let mut patch = Patch::from_slice_function(self.level, self.index_space(), self.num_fields, |_, slice| {
+            slice.fill(fill)
+        });
+        self.write_into(&mut patch);
+        patch
+    }
+
+    /// Return the position in the data array of the zone at the given
+    /// index, if it's active.
+    fn find(&self, (i, j): (i64, i64)) -> Option<usize> {
+        let n = self.runs.partition_point(|run| (run.i, run.j.end) <= (i, j));
+        let run = self.runs.get(n)?;
+
+        if run.i == i && run.j.contains(&j) {
+            Some(run.offset + (j - run.j.start) as usize * self.num_fields)
+        } else {
+            None
+        }
+    }
+}
+
+/// Split the data array of a sparse patch into one mutable chunk per run.
+fn split_runs<'a>(runs: &[Run], mut data: &'a mut [f64], num_fields: usize) -> Vec<&'a mut [f64]> {
+    let mut chunks = Vec::with_capacity(runs.len());
+
+    for run in runs {
+        let (chunk, rest) = data.split_at_mut((run.j.end - run.j.start) as usize * num_fields);
+        chunks.push(chunk);
+        data = rest;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::SparsePatch;
+    use crate::index_space::range2d;
+    use crate::patch::Patch;
+
+    /// A thin ring of active zones in a 32x32 patch.
+    fn ring() -> SparsePatch {
+        let is_active = |(i, j): (i64, i64)| {
+            let r2 = (i - 16).pow(2) + (j - 16).pow(2);
+            (144..=196).contains(&r2)
+        };
+        SparsePatch::from_slice_function(1, range2d(0..32, 0..32), 2, is_active, |(i, j), s| {
+            s[0] = i as f64;
+            s[1] = j as f64;
+        })
+    }
+
+    #[test]
+    fn sparse_patches_store_only_active_zones() {
+        let sparse = ring();
+        let dense = sparse.to_patch(f64::NAN);
+
+        assert!(sparse.active_fraction() < 0.25);
+        assert!(sparse.size_in_bytes() < dense.size_in_bytes() / 2);
+        assert_eq!(sparse.iter().count(), sparse.num_active());
+        assert!(sparse.iter().all(|((i, j), s)| s == [i as f64, j as f64]));
+
+        assert!(sparse.is_active((16, 4)));
+        assert!(!sparse.is_active((16, 16)));
+        assert!(!sparse.is_active((40, 4)));
+        assert_eq!(sparse.get_slice((4, 16)), Some(&[4.0, 16.0][..]));
+        assert_eq!(sparse.get_slice((16, 16)), None);
+        assert_eq!(dense.get_slice((4, 16)), [4.0, 16.0]);
+        assert!(dense.get_slice((16, 16))[0].is_nan());
+    }
+
+    #[test]
+    fn sparse_patches_round_trip_through_dense_patches() {
+        let dense = Patch::from_vector_function(0, range2d(0..8, 0..8), |(i, j)| [(i * 8 + j) as f64]);
+        let mut sparse = SparsePatch::from_patch(&dense, |(i, j)| i == j || j == 7);
+
+        for (_, s) in sparse.iter_mut() {
+            s[0] *= 2.0;
+        }
+        let mut target = Patch::zeros(0, 1, range2d(4..12, 0..8));
+        sparse.write_into(&mut target);
+
+        assert_eq!(sparse.num_active(), 15);
+        assert_eq!(target.get_slice((5, 5)), [90.0]);
+        assert_eq!(target.get_slice((4, 7)), [78.0]);
+        assert_eq!(target.get_slice((5, 6)), [0.0]);
+    }
+
+    #[test]
+    fn extracted_subsets_carry_only_active_zones() {
+        let sparse = ring();
+        let strip = sparse.extract(range2d(0..32, 0..3));
+
+        assert_eq!(strip.index_space().start(), (0, 0));
+        assert_eq!(strip.num_active(), strip.iter().count());
+        assert!(strip.iter().all(|(index, s)| sparse.get_slice(index) == Some(s) && index.1 < 3));
+        assert_eq!(strip.num_active(), sparse.iter().filter(|((_, j), _)| *j < 3).count());
+        assert!(sparse.extract(range2d(14..18, 14..18)).iter().next().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn sparse_patches_survive_serialization() {
+        let sparse = ring().extract(range2d(0..16, 0..32));
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&sparse, &mut bytes).unwrap();
+        let decoded: SparsePatch = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(decoded, sparse);
+    }
+}
@@ -0,0 +1,537 @@
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::index_space::IndexSpace;
+use crate::meshing::PatchKey;
+#[cfg(feature = "net")]
+use crate::message::comm::Communicator;
+use crate::rect_map::RectangleMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Running summary of the values recorded for a named quantity.
+///
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Summary {
+    pub count: u64,
+    pub total: f64,
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+}
+
+impl Summary {
+    fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            total: value,
+            min: value,
+            max: value,
+            last: value,
+        }
+    }
+
+    fn record(&mut self, value: f64) {
+        self.count += 1;
+        self.total += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.last = value;
+    }
+
+    /// Return the mean of the recorded values.
+    ///
+    pub fn mean(&self) -> f64 {
+        self.total / self.count as f64
+    }
+}
+
+/// A minimal, thread-safe registry of named metrics. Each metric is a
+/// [`Summary`] of the values recorded under its name. Metric names are
+/// dot-separated by convention, with the first segment naming the subsystem,
+/// e.g. `automaton.undelivered_bytes`.
+///
+#[derive(Default)]
+pub struct Metrics {
+    entries: Mutex<BTreeMap<String, Summary>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a value for the named metric.
+    ///
+    pub fn record(&self, name: &str, value: f64) {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get_mut(name) {
+            Some(summary) => summary.record(value),
+            None => {
+                entries.insert(name.to_string(), Summary::new(value));
+            }
+        }
+    }
+
+    /// Call `f`, and record the time it took in seconds, measured with the
+    /// given clock, for the named metric. Returns the result of `f`.
+    ///
+    pub fn time<T, F: FnOnce() -> T>(&self, name: &str, clock: &dyn Clock, f: F) -> T {
+        let start = clock.now();
+        let result = f();
+        self.record(name, (clock.now() - start).as_secs_f64());
+        result
+    }
+
+    /// Return the summary for the named metric, if any values have been
+    /// recorded for it.
+    ///
+    pub fn get(&self, name: &str) -> Option<Summary> {
+        self.entries.lock().unwrap().get(name).cloned()
+    }
+
+    /// Return a copy of all the metrics, sorted by name.
+    ///
+    pub fn snapshot(&self) -> BTreeMap<String, Summary> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Remove all of the recorded metrics.
+    ///
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear()
+    }
+
+    /// Collect a snapshot of the metrics on every rank to rank 0, for
+    /// unified reporting. The root returns the snapshots in rank order, and
+    /// the other ranks return `None`. This must be called by every rank.
+    ///
+    #[cfg(feature = "net")]
+    pub fn gather<C: Communicator>(&self, comm: &C) -> Option<Vec<BTreeMap<String, Summary>>> {
+        comm.gather_values(&self.snapshot())
+    }
+
+    /// Write a snapshot of the metrics to this rank's metrics file in the
+    /// given directory (see [`metrics_file_name`]). The snapshot is written
+    /// to a temporary file which then replaces the metrics file, so the
+    /// file always holds a complete snapshot, even if the process dies while
+    /// writing it. Unlike [`Metrics::gather`], this needs no communication,
+    /// so it works when other ranks have exited.
+    ///
+    pub fn flush_to<P: AsRef<Path>>(&self, directory: P, rank: usize) -> Result<()> {
+        let path = directory.as_ref().join(metrics_file_name(rank));
+        let temporary = path.with_extension("cbor.tmp");
+        let mut file = BufWriter::new(File::create(&temporary)?);
+        ciborium::ser::into_writer(&self.snapshot(), &mut file).map_err(invalid_data)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+}
+
+/// Return the name of the file in which the metrics of the given rank are
+/// saved by [`Metrics::flush_to`].
+///
+pub fn metrics_file_name(rank: usize) -> String {
+    format!("metrics.{:04}.cbor", rank)
+}
+
+/// Read a metrics file written by [`Metrics::flush_to`].
+///
+pub fn read_metrics_file<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, Summary>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(ciborium::de::from_reader(file).map_err(invalid_data)?)
+}
+
+fn invalid_data<E: fmt::Debug>(error: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{:?}", error))
+}
+
+/// A background thread which flushes a rank's metrics to disk at a fixed
+/// interval, so that they survive if the rank aborts. The metrics are
+/// flushed a final time when the flusher is dropped, including while the
+/// thread that owns it unwinds from a panic. Failed flushes are reported on
+/// standard error and do not stop the flusher.
+///
+pub struct MetricsFlusher {
+    stop: Option<crossbeam_channel::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MetricsFlusher {
+    /// Start flushing the given metrics to this rank's metrics file in the
+    /// given directory, once every `interval`.
+    ///
+    pub fn start(metrics: Arc<Metrics>, directory: PathBuf, rank: usize, interval: Duration) -> Self {
+        let (stop, stopped) = crossbeam_channel::bounded::<()>(1);
+        let handle = thread::spawn(move || loop {
+            let done = !matches!(stopped.recv_timeout(interval), Err(crossbeam_channel::RecvTimeoutError::Timeout));
+
+            if let Err(e) = metrics.flush_to(&directory, rank) {
+                eprintln!("warning: could not flush the metrics of rank {}: {}", rank, e)
+            }
+            if done {
+                break;
+            }
+        });
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// Flush the metrics a final time, and stop the background thread. This
+    /// is the same as dropping the flusher.
+    ///
+    pub fn finish(self) {}
+}
+
+impl Drop for MetricsFlusher {
+    fn drop(&mut self) {
+        self.stop.take();
+        self.handle.take().unwrap().join().ok();
+    }
+}
+
+/// A report consolidated from the metrics files of every rank in a run, for
+/// post-mortem analysis. Ranks whose file is missing or unreadable (e.g.
+/// because the rank aborted before its first flush) are listed as missing,
+/// and the other ranks are reported as usual.
+///
+#[derive(Clone, Debug)]
+pub struct RunReport {
+    /// The metrics of each rank, or `None` for the missing ranks.
+    pub snapshots: Vec<Option<BTreeMap<String, Summary>>>,
+}
+
+impl RunReport {
+    /// Read the metrics files of the given number of ranks from a
+    /// directory.
+    ///
+    pub fn read<P: AsRef<Path>>(directory: P, num_ranks: usize) -> Self {
+        let snapshots = (0..num_ranks)
+            .map(|rank| read_metrics_file(directory.as_ref().join(metrics_file_name(rank))).ok())
+            .collect();
+        Self { snapshots }
+    }
+
+    /// Return the ranks whose metrics could not be read.
+    ///
+    pub fn missing_ranks(&self) -> Vec<usize> {
+        (0..self.snapshots.len()).filter(|&rank| self.snapshots[rank].is_none()).collect()
+    }
+
+    /// Return the names of the metrics recorded on any rank, sorted.
+    ///
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.snapshots.iter().flatten().flat_map(|s| s.keys().map(String::as_str)).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Return the spread of a metric over the ranks which were read (see
+    /// [`spread`]).
+    ///
+    pub fn spread(&self, name: &str) -> Option<Spread> {
+        let snapshots: Vec<_> = self.snapshots.iter().flatten().cloned().collect();
+        spread(&snapshots, name)
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let missing = self.missing_ranks();
+        writeln!(fmt, "metrics from {} of {} ranks", self.snapshots.len() - missing.len(), self.snapshots.len())?;
+
+        if !missing.is_empty() {
+            let missing: Vec<_> = missing.iter().map(usize::to_string).collect();
+            writeln!(fmt, "missing ranks: {}", missing.join(", "))?;
+        }
+        for name in self.names() {
+            let s = self.spread(name).unwrap();
+            writeln!(
+                fmt,
+                "{}: min {:.6e} max {:.6e} mean {:.6e} imbalance {:.3}",
+                name,
+                s.min,
+                s.max,
+                s.mean,
+                s.imbalance()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The spread of a metric over ranks, returned by [`spread`].
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Spread {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Spread {
+    /// Return the ratio of the maximum to the mean, which is 1 when the
+    /// work is perfectly balanced.
+    ///
+    pub fn imbalance(&self) -> f64 {
+        self.max / self.mean
+    }
+}
+
+/// Compute the spread over ranks of the total of a metric, from the
+/// snapshots returned by [`Metrics::gather`]. Ranks which did not record
+/// the metric count as zero. Returns `None` if no rank recorded it.
+///
+pub fn spread(snapshots: &[BTreeMap<String, Summary>], name: &str) -> Option<Spread> {
+    if snapshots.iter().all(|s| !s.contains_key(name)) {
+        return None;
+    }
+    let totals: Vec<_> = snapshots.iter().map(|s| s.get(name).map_or(0.0, |x| x.total)).collect();
+
+    Some(Spread {
+        min: totals.iter().cloned().fold(f64::INFINITY, f64::min),
+        max: totals.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        mean: totals.iter().sum::<f64>() / totals.len() as f64,
+    })
+}
+
+/// Smoothed estimates of the cost (e.g. the time to update) of each patch,
+/// for load balancing. Each estimate is an exponentially weighted moving
+/// average of the recorded costs. When the mesh is regridded, the estimates
+/// are carried over to the new patches (see [`CostModel::regrid`]), so they
+/// don't have to be learned again from scratch.
+///
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    smoothing: f64,
+    costs: HashMap<PatchKey, f64>,
+}
+
+impl CostModel {
+    /// Create an empty cost model. The smoothing factor is the weight given
+    /// to each newly recorded cost, and must be in `(0, 1]`; a value of 1
+    /// keeps only the last recorded cost.
+    ///
+    pub fn new(smoothing: f64) -> Self {
+        assert!(smoothing > 0.0 && smoothing <= 1.0, "smoothing factor must be in (0, 1]");
+        Self {
+            smoothing,
+            costs: HashMap::new(),
+        }
+    }
+
+    /// Record a measured cost for a patch. The first cost recorded for a
+    /// patch becomes its estimate.
+    ///
+    pub fn record(&mut self, key: PatchKey, cost: f64) {
+        let smoothing = self.smoothing;
+        self.costs
+            .entry(key)
+            .and_modify(|c| *c += smoothing * (cost - *c))
+            .or_insert(cost);
+    }
+
+    /// Return the estimated cost of a patch, if it has one.
+    ///
+    pub fn cost(&self, key: &PatchKey) -> Option<f64> {
+        self.costs.get(key).copied()
+    }
+
+    /// Return an iterator over the patches and their estimated costs, in no
+    /// particular order.
+    ///
+    pub fn costs(&self) -> impl Iterator<Item = (&PatchKey, f64)> + '_ {
+        self.costs.iter().map(|(k, &c)| (k, c))
+    }
+
+    /// Return the sum of the estimated costs of all the patches.
+    ///
+    pub fn total(&self) -> f64 {
+        self.costs.values().sum()
+    }
+
+    /// Replace the patches in the model with the given ones, which cover a
+    /// regridded mesh. Costs are assumed to be uniform over the zones of each
+    /// old patch. A new patch receives the cost of the zones of old patches
+    /// on its level which it overlaps, so costs are split and merged when
+    /// rectangles change. The zones it covers which were not on its level
+    /// before are given the average cost per zone of the overlapping old
+    /// patches on other levels (e.g. the parent region of a new refined
+    /// patch), or failing that, the average cost per zone of the whole
+    /// model. Nothing is inherited if the model is empty.
+    ///
+    pub fn regrid<I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = PatchKey>,
+    {
+        if self.costs.is_empty() {
+            return;
+        }
+        let zones = |space: &IndexSpace, level: u32| space.len() as f64 / (1_u64 << (2 * level)) as f64;
+        let mut levels: BTreeMap<u32, RectangleMap<i64, f64>> = BTreeMap::new();
+        let total_cost = self.total();
+        let mut total_zones = 0.0;
+
+        for ((rect, level), cost) in self.costs.drain() {
+            let space = IndexSpace::from(rect.clone());
+            total_zones += zones(&space, level);
+            levels.entry(level).or_default().insert(rect, cost / zones(&space, level));
+        }
+        let mean = total_cost / total_zones;
+
+        for (rect, level) in keys {
+            let space = IndexSpace::from(rect.clone());
+            let mut cost = 0.0;
+            let mut covered = 0.0;
+            let (mut other_cost, mut other_zones) = (0.0, 0.0);
+
+            for (&l, patches) in &levels {
+                for (r, &per_zone) in patches.query_rect(rect.clone()) {
+                    let overlap = zones(&space.intersect(r), level);
+
+                    if l == level {
+                        cost += overlap * per_zone;
+                        covered += overlap;
+                    } else {
+                        other_cost += overlap * per_zone;
+                        other_zones += overlap;
+                    }
+                }
+            }
+            let remainder = zones(&space, level) - covered;
+
+            if remainder > 0.0 {
+                let per_zone = if other_zones > 0.0 { other_cost / other_zones } else { mean };
+                cost += remainder * per_zone;
+            }
+            self.costs.insert((rect, level), cost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{metrics_file_name, CostModel, Metrics, MetricsFlusher, RunReport};
+    use crate::clock::MockClock;
+    use std::sync::Arc;
+    use std::time::Duration;
+    #[cfg(feature = "net")]
+    use super::spread;
+    #[cfg(feature = "net")]
+    use crate::message::comm::Communicator;
+    #[cfg(feature = "net")]
+    use crate::message::local::LocalCommunicator;
+    #[cfg(feature = "net")]
+    use std::thread;
+
+    #[test]
+    fn metrics_summarize_recorded_values() {
+        let metrics = Metrics::new();
+        metrics.record("a.b", 2.0);
+        metrics.record("a.b", 4.0);
+        metrics.record("a.a", 1.0);
+
+        let summary = metrics.get("a.b").unwrap();
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.mean(), 3.0);
+        assert_eq!(summary.max, 4.0);
+        assert_eq!(summary.last, 4.0);
+        assert!(metrics.get("a.c").is_none());
+        assert_eq!(metrics.snapshot().keys().collect::<Vec<_>>(), ["a.a", "a.b"]);
+    }
+
+    #[test]
+    fn timed_sections_are_recorded_in_seconds() {
+        let clock = MockClock::new();
+        let metrics = Metrics::new();
+        let result = metrics.time("step.seconds", &clock, || {
+            clock.advance(Duration::from_millis(250));
+            7
+        });
+        assert_eq!(result, 7);
+        assert_eq!(metrics.get("step.seconds").unwrap().last, 0.25);
+    }
+
+    #[test]
+    fn flushed_metrics_are_reported_with_missing_ranks() {
+        let directory = std::env::temp_dir().join(format!("gridiron-metrics-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let metrics = Arc::new(Metrics::new());
+        metrics.record("step.seconds", 1.0);
+        let flusher = MetricsFlusher::start(metrics.clone(), directory.clone(), 0, Duration::from_secs(3600));
+        metrics.record("step.seconds", 3.0);
+        flusher.finish();
+
+        let other = Metrics::new();
+        other.record("step.seconds", 8.0);
+        other.flush_to(&directory, 2).unwrap();
+
+        let report = RunReport::read(&directory, 3);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(report.missing_ranks(), [1]);
+        assert_eq!(report.snapshots[0].as_ref().unwrap()["step.seconds"].total, 4.0);
+        assert_eq!(report.spread("step.seconds").unwrap().imbalance(), 8.0 / 6.0);
+        assert!(report.to_string().contains("metrics from 2 of 3 ranks\nmissing ranks: 1\n"));
+        assert_eq!(metrics_file_name(2), "metrics.0002.cbor");
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn gathered_metrics_show_imbalance() {
+        let handles: Vec<_> = LocalCommunicator::group(2)
+            .into_iter()
+            .map(|comm| {
+                thread::spawn(move || {
+                    let metrics = Metrics::new();
+                    metrics.record("step.seconds", 1.0 + comm.rank() as f64 * 2.0);
+                    metrics.gather(&comm)
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let snapshots = results[0].as_ref().unwrap();
+        let s = spread(snapshots, "step.seconds").unwrap();
+
+        assert!(results[1].is_none());
+        assert_eq!((s.min, s.max, s.mean), (1.0, 3.0, 2.0));
+        assert_eq!(s.imbalance(), 1.5);
+        assert!(spread(snapshots, "step.messages").is_none());
+    }
+
+    #[test]
+    fn cost_estimates_are_smoothed_and_survive_regrids() {
+        let mut model = CostModel::new(0.5);
+        model.record(((0..8, 0..8), 0), 4.0);
+        model.record(((0..8, 0..8), 0), 8.0);
+        model.record(((8..16, 0..8), 0), 2.0);
+        assert_eq!(model.cost(&((0..8, 0..8), 0)), Some(6.0));
+
+        // Split the first patch, merge the halves of both patches, and
+        // refine a region of the second one.
+        model.regrid(vec![
+            ((0..8, 0..4), 0),
+            ((0..8, 4..8), 0),
+            ((8..16, 0..8), 1),
+            ((8..12, 0..4), 0),
+        ]);
+        assert_eq!(model.cost(&((0..8, 0..4), 0)), Some(3.0));
+        assert_eq!(model.cost(&((0..8, 4..8), 0)), Some(3.0));
+        assert_eq!(model.cost(&((8..16, 0..8), 1)), Some(0.5));
+        assert_eq!(model.cost(&((8..12, 0..4), 0)), Some(0.5));
+        assert_eq!(model.costs().count(), 4);
+    }
+}
@@ -1,55 +1,363 @@
+use crate::error::{Error, Result};
+use std::any::Any;
 use std::cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use crossbeam_channel::{Sender, Receiver, unbounded};
-use core_affinity::{get_core_ids, set_for_current};
+use core_affinity::{get_core_ids, set_for_current, CoreId};
+use std::str::FromStr;
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+thread_local! {
+    static WORKER_ID: cell::Cell<Option<usize>> = const { cell::Cell::new(None) };
+}
+
+/// Return the index of the thread pool worker running on the current thread,
+/// or `None` if the current thread is not a thread pool worker.
+///
+pub fn current_worker_id() -> Option<usize> {
+    WORKER_ID.with(|id| id.get())
+}
+
+/// A panic which occurred in a job on a thread pool worker. The panic is
+/// caught, so the worker survives it and goes on to run its next job; the
+/// panics are collected with [`ThreadPool::panics`].
+///
+#[derive(Clone, Debug)]
+pub struct JobPanic {
+    /// The worker which was running the job.
+    pub worker_id: usize,
+
+    /// The panic message, if the panic payload was a string.
+    pub message: String,
+}
+
+/// Return the message in a panic payload, if it was a string.
+///
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
 struct Worker {
     handle: Option<thread::JoinHandle<()>>,
     sender: Option<Sender<Job>>,
+    queued: Arc<AtomicUsize>,
+    core_id: Option<CoreId>,
+}
+
+/// How the workers of a [`ThreadPool`] are pinned to CPU cores.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Affinity {
+    /// Pin the workers to the cores reported by the system, in order. If
+    /// the cores cannot be detected (as happens in some containers), a
+    /// warning is printed and the workers are left unpinned. This is the
+    /// default.
+    #[default]
+    Detect,
+
+    /// Leave the workers unpinned.
+    Unpinned,
+
+    /// Pin the workers to the given core ids, in order.
+    Cores(Vec<usize>),
+}
+
+impl Affinity {
+    /// Return the cores the workers may be pinned to, in order, or `None`
+    /// if the workers are to be left unpinned.
+    fn cores(&self) -> Option<Vec<CoreId>> {
+        let cores = match self {
+            Self::Detect => get_core_ids().filter(|cores| !cores.is_empty()).or_else(|| {
+                eprintln!("warning: could not detect the CPU cores; thread pool workers are not pinned");
+                None
+            }),
+            Self::Unpinned => None,
+            Self::Cores(ids) => Some(ids.iter().map(|&id| CoreId { id }).collect()),
+        };
+        cores.filter(|cores| !cores.is_empty())
+    }
+}
+
+impl FromStr for Affinity {
+    type Err = Error;
+
+    /// Parse an affinity from `detect`, `none`, or a comma-separated list of
+    /// core ids, such as `0,2,4,6`.
+    fn from_str(text: &str) -> Result<Self> {
+        match text {
+            "detect" => Ok(Self::Detect),
+            "none" => Ok(Self::Unpinned),
+            _ => text
+                .split(',')
+                .map(|id| id.trim().parse())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map(Self::Cores)
+                .map_err(|_| {
+                    Error::Parameters(format!(
+                        "invalid affinity '{}' (options are detect|none|a list of core ids)",
+                        text
+                    ))
+                }),
+        }
+    }
+}
+
+impl fmt::Display for Affinity {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Detect => write!(fmt, "detect"),
+            Self::Unpinned => write!(fmt, "none"),
+            Self::Cores(ids) => {
+                let ids: Vec<_> = ids.iter().map(usize::to_string).collect();
+                write!(fmt, "{}", ids.join(","))
+            }
+        }
+    }
+}
+
+/// The rule used by a [`ThreadPool`] to decide which worker runs a job.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpawnPolicy {
+    /// Jobs go cyclically to the workers, and worker hints are ignored.
+    RoundRobin,
+
+    /// Jobs go to the hinted worker if there is a hint, and are otherwise
+    /// placed round-robin. This is the default.
+    #[default]
+    HintStrict,
+
+    /// Jobs go to the worker with the fewest queued or running jobs, and
+    /// worker hints are ignored.
+    LeastLoaded,
+
+    /// Jobs with a locality coordinate are placed by their position along a
+    /// Z-order (Morton) curve: the curve is divided into segments of
+    /// `2^block_bits` consecutive points, and all the jobs in a segment go to
+    /// the same worker. Segments are spread over the workers by a hash of
+    /// their position, so that block-aligned coordinates (whose low bits are
+    /// all zero) still use every worker. If `block_bits` is 64 or more, the
+    /// whole curve is one segment. Jobs without a locality coordinate are
+    /// placed as with `HintStrict`.
+    LocalityByCurve { block_bits: u32 },
+}
+
+/// Counters describing where a thread pool has placed its jobs, for tuning
+/// the spawn policy.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Placement {
+    /// The number of jobs given to each worker.
+    pub jobs_per_worker: Vec<usize>,
+
+    /// The number of jobs which had a worker hint and were placed on the
+    /// hinted worker.
+    pub hints_followed: usize,
+
+    /// The number of jobs which had a worker hint and were placed elsewhere.
+    pub hints_ignored: usize,
 }
 
-/// A minimal thread pool implementation with core affinity. No effort is made
-/// to schedule jobs intelligently, it just goes round-robin. Jobs must be
-/// `'static`.
+/// A minimal thread pool implementation with core affinity (see
+/// [`Affinity`]). Jobs are placed
+/// on workers according to a [`SpawnPolicy`], which is round-robin with
+/// worker hints by default. Jobs must be `'static`.
+///
+/// A panic in a job does not bring down its worker. The panic is caught and
+/// reported through [`ThreadPool::panics`].
+///
+/// The pool may also have communication workers (see
+/// [`ThreadPool::with_comm_threads`]), reserved for messaging work like
+/// message serialization, so that it does not compete with compute jobs for
+/// the compute workers' cores.
 ///
 pub struct ThreadPool {
     workers: Vec<Worker>,
+    comm_workers: Vec<Worker>,
+    spare_cores: Vec<CoreId>,
     current_worker_id: cell::Cell<usize>,
+    current_comm_worker_id: cell::Cell<usize>,
+    policy: SpawnPolicy,
+    placement: cell::RefCell<Placement>,
+    panic_sink: Sender<JobPanic>,
+    panics: Receiver<JobPanic>,
+}
+
+impl Worker {
+    /// Start a worker thread with the given id, pinned to the given core if
+    /// there is one.
+    fn start(worker_id: usize, core_id: Option<CoreId>, panics: Sender<JobPanic>) -> Self {
+        let (sender, receiver): (Sender<Job>, Receiver<Job>) = unbounded();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let worker_queued = queued.clone();
+        let handle = thread::spawn(move || {
+            if let Some(core_id) = core_id {
+                set_for_current(core_id);
+            }
+            WORKER_ID.with(|id| id.set(Some(worker_id)));
+            for job in receiver {
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                    let message = panic_message(&*payload);
+                    panics.send(JobPanic { worker_id, message }).ok();
+                }
+                worker_queued.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+        Worker {
+            handle: Some(handle),
+            sender: Some(sender),
+            queued,
+            core_id,
+        }
+    }
+
+    fn send(&self, job: Job) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
 }
 
 impl ThreadPool {
     /// Create a new thread pool with at most the given number of threads. If
     /// the system has fewer physical CPU cores than the requested number of
-    /// threads, then the number of cores is unsed instead.
+    /// threads, then the number of cores is unsed instead. If the cores
+    /// cannot be detected, the pool has the requested number of threads, and
+    /// they are not pinned.
     ///
     pub fn new(num_threads: usize) -> Self {
-        let workers = get_core_ids()
-            .unwrap()
-            .into_iter()
-            .take(num_threads)
-            .map(|core_id| {
-                let (sender, receiver): (Sender<Job>, Receiver<Job>) = unbounded();
-                let handle = thread::spawn(move || {
-                    set_for_current(core_id);
-                    for job in receiver {
-                        job()
-                    }
-                });
-                Worker {
-                    handle: Some(handle),
-                    sender: Some(sender),
-                }
-            })
-            .collect();
+        Self::with_policy(num_threads, SpawnPolicy::default())
+    }
+
+    /// Create a new thread pool with the given number of threads, none of
+    /// which are pinned to a core.
+    ///
+    pub fn unpinned(num_threads: usize) -> Self {
+        Self::with_affinity(num_threads, SpawnPolicy::default(), Affinity::Unpinned)
+    }
+
+    /// Create a new thread pool which places jobs according to the given
+    /// policy.
+    ///
+    pub fn with_policy(num_threads: usize, policy: SpawnPolicy) -> Self {
+        Self::with_affinity(num_threads, policy, Affinity::Detect)
+    }
+
+    /// Create a new thread pool which places jobs according to the given
+    /// policy, and pins its workers according to the given affinity. If the
+    /// workers are pinned, the pool has at most as many threads as there are
+    /// cores to pin them to.
+    ///
+    pub fn with_affinity(num_threads: usize, policy: SpawnPolicy, affinity: Affinity) -> Self {
+        let (panic_sink, panics) = unbounded();
+        let (workers, spare_cores): (Vec<_>, _) = match affinity.cores() {
+            Some(mut cores) => {
+                let spare_cores = cores.split_off(num_threads.min(cores.len()));
+                let workers = cores
+                    .into_iter()
+                    .enumerate()
+                    .map(|(worker_id, core_id)| Worker::start(worker_id, Some(core_id), panic_sink.clone()))
+                    .collect();
+                (workers, spare_cores)
+            }
+            None => {
+                let workers = (0..num_threads)
+                    .map(|worker_id| Worker::start(worker_id, None, panic_sink.clone()))
+                    .collect();
+                (workers, Vec::new())
+            }
+        };
+
+        let placement = Placement {
+            jobs_per_worker: vec![0; workers.len()],
+            ..Placement::default()
+        };
 
         ThreadPool {
             workers,
+            comm_workers: Vec::new(),
+            spare_cores,
             current_worker_id: cell::Cell::new(0),
+            current_comm_worker_id: cell::Cell::new(0),
+            policy,
+            placement: cell::RefCell::new(placement),
+            panic_sink,
+            panics,
         }
     }
 
+    /// Add the given number of communication workers to the pool. They are
+    /// pinned to the cores following those of the compute workers, where
+    /// the pool has spare cores, and are otherwise left unpinned. Their
+    /// worker ids follow those of the compute workers.
+    ///
+    pub fn with_comm_threads(mut self, count: usize) -> Self {
+        let num_compute = self.workers.len();
+        let comm_workers: Vec<_> = (0..count)
+            .map(|n| Worker::start(num_compute + n, self.spare_cores.get(n).copied(), self.panic_sink.clone()))
+            .collect();
+        self.comm_workers.extend(comm_workers);
+        self
+    }
+
+    /// Return the core each compute worker is pinned to, or `None` for
+    /// workers which are not pinned.
+    ///
+    pub fn worker_cores(&self) -> Vec<Option<usize>> {
+        self.workers.iter().map(|w| w.core_id.map(|core| core.id)).collect()
+    }
+
+    /// Return the number of communication workers in the pool.
+    ///
+    pub fn num_comm_threads(&self) -> usize {
+        self.comm_workers.len()
+    }
+
+    /// Spawn a messaging job (e.g. serializing or deserializing a message)
+    /// onto the communication workers, cyclically. If the pool has no
+    /// communication workers, the job is spawned like [`ThreadPool::spawn`].
+    /// Messaging jobs are not counted in the [`Placement`].
+    ///
+    pub fn spawn_comm<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.comm_workers.is_empty() {
+            return self.spawn(job);
+        }
+        let n = self.current_comm_worker_id.get();
+        self.current_comm_worker_id.set((n + 1) % self.comm_workers.len());
+        self.comm_workers[n].send(Box::new(job))
+    }
+
+    /// Return the spawn policy of this pool.
+    ///
+    pub fn policy(&self) -> SpawnPolicy {
+        self.policy
+    }
+
+    /// Return the counters describing where jobs have been placed so far.
+    ///
+    pub fn placement(&self) -> Placement {
+        self.placement.borrow().clone()
+    }
+
+    /// Return the panics which have occurred in jobs since the last call.
+    ///
+    pub fn panics(&self) -> Vec<JobPanic> {
+        self.panics.try_iter().collect()
+    }
+
     /// Return the number of worker threads in the pool.
     ///
     pub fn num_threads(&self) -> usize {
@@ -67,35 +375,257 @@ impl ThreadPool {
         self.spawn_on(None, job)
     }
 
-    /// Spawn a job onto the worker thread with the given index, if it is
-    /// `Some`. The current worker index is not incremented. If the worker
-    /// index is `None`, then the job is run on the current worker index,
-    /// which is then incremented.
+    /// Spawn a job with a hint for the worker thread to run on. With the
+    /// default policy, the job runs on the hinted worker if it is `Some`, and
+    /// the current worker index is not incremented. If the hint is `None`,
+    /// then the job is run on the current worker index, which is then
+    /// incremented. A hint is taken modulo the number of workers, since a
+    /// pinned pool may have fewer workers than were requested.
     ///
     pub fn spawn_on<F>(&self, worker_id: Option<usize>, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let worker_id = if let Some(worker_id) = worker_id {
-            worker_id
-        } else {
-            let worker_id = self.current_worker_id.get();
-            self.current_worker_id
-                .set((worker_id + 1) % self.num_threads());
-            worker_id
+        self.spawn_placed(worker_id, None, job)
+    }
+
+    /// Spawn a job with a worker hint and a locality coordinate (e.g. the
+    /// position of a patch), either of which may be used by the spawn policy
+    /// to decide where the job runs.
+    ///
+    pub fn spawn_placed<F>(&self, hint: Option<usize>, locality: Option<(i64, i64)>, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let loads: Vec<_> = match self.policy {
+            SpawnPolicy::LeastLoaded => self.workers.iter().map(|w| w.queued.load(Ordering::Relaxed)).collect(),
+            _ => Vec::new(),
         };
-        self.workers[worker_id]
-            .sender
-            .as_ref()
-            .unwrap()
-            .send(Box::new(job))
-            .unwrap();
+        let mut next = self.current_worker_id.get();
+        let worker_id = select_worker(self.policy, hint, locality, self.num_threads(), &mut next, &loads);
+        self.current_worker_id.set(next);
+
+        let mut placement = self.placement.borrow_mut();
+        placement.jobs_per_worker[worker_id] += 1;
+        match hint {
+            Some(hint) if hint == worker_id => placement.hints_followed += 1,
+            Some(_) => placement.hints_ignored += 1,
+            None => {}
+        }
+
+        self.workers[worker_id].send(Box::new(job))
+    }
+}
+
+/// Choose a worker for a job according to the spawn policy. The round-robin
+/// cursor `next` is advanced whenever it's used. The `loads` slice contains
+/// the number of queued jobs per worker, and is only needed for the
+/// least-loaded policy.
+///
+fn select_worker(
+    policy: SpawnPolicy,
+    hint: Option<usize>,
+    locality: Option<(i64, i64)>,
+    num_threads: usize,
+    next: &mut usize,
+    loads: &[usize],
+) -> usize {
+    let mut round_robin = || {
+        let worker_id = *next;
+        *next = (worker_id + 1) % num_threads;
+        worker_id
+    };
+    match (policy, hint, locality) {
+        (SpawnPolicy::RoundRobin, _, _) => round_robin(),
+        (SpawnPolicy::LeastLoaded, _, _) => (0..num_threads).min_by_key(|&n| loads[n]).unwrap(),
+        (SpawnPolicy::LocalityByCurve { block_bits }, _, Some((i, j))) => {
+            let segment = morton_index(i, j).checked_shr(block_bits).unwrap_or(0);
+            (mix(segment) % num_threads as u64) as usize
+        }
+        (_, Some(hint), _) => hint % num_threads,
+        (_, None, _) => round_robin(),
     }
 }
 
+/// Return the position of a 2D index along the Z-order curve, by
+/// interleaving the bits of the (32 least significant bits of the) two
+/// coordinates.
+///
+fn morton_index(i: i64, j: i64) -> u64 {
+    let spread = |x: i64| {
+        let mut x = x as u32 as u64;
+        x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+        x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+        x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+        x = (x | (x << 1)) & 0x5555_5555_5555_5555;
+        x
+    };
+    spread(i) << 1 | spread(j)
+}
+
+/// Scramble the bits of a curve segment index (the finalizer of the
+/// SplitMix64 generator), so that segments whose indexes share their low
+/// bits are still spread over the workers.
+///
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
 impl Drop for Worker {
     fn drop(&mut self) {
         self.sender.take().unwrap();
         self.handle.take().unwrap().join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::{current_worker_id, morton_index, select_worker, Affinity, SpawnPolicy, ThreadPool};
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let pool = ThreadPool::new(1);
+        let (sink, source) = crossbeam_channel::unbounded();
+
+        pool.spawn(|| panic!("job failed"));
+        pool.spawn(move || sink.send(current_worker_id()).unwrap());
+
+        assert_eq!(source.recv().unwrap(), Some(0));
+        let panics = pool.panics();
+        assert_eq!(panics.len(), 1);
+        assert_eq!((panics[0].worker_id, panics[0].message.as_str()), (0, "job failed"));
+        assert!(pool.panics().is_empty());
+    }
+
+    #[test]
+    fn morton_index_interleaves_bits() {
+        assert_eq!(morton_index(0, 0), 0);
+        assert_eq!(morton_index(0, 1), 1);
+        assert_eq!(morton_index(1, 0), 2);
+        assert_eq!(morton_index(3, 3), 15);
+        assert_eq!(morton_index(2, 0), 8);
+    }
+
+    #[test]
+    fn spawn_policies_place_jobs() {
+        let mut next = 0;
+        let mut place = |policy, hint, locality, loads: &[usize]| {
+            select_worker(policy, hint, locality, 3, &mut next, loads)
+        };
+        assert_eq!(place(SpawnPolicy::RoundRobin, Some(2), None, &[]), 0);
+        assert_eq!(place(SpawnPolicy::RoundRobin, Some(2), None, &[]), 1);
+        assert_eq!(place(SpawnPolicy::HintStrict, Some(0), None, &[]), 0);
+        assert_eq!(place(SpawnPolicy::HintStrict, None, None, &[]), 2);
+        assert_eq!(place(SpawnPolicy::LeastLoaded, Some(0), None, &[4, 1, 1]), 1);
+
+        let curve = SpawnPolicy::LocalityByCurve { block_bits: 2 };
+        let first = place(curve, None, Some((0, 0)), &[]);
+        assert_eq!(place(curve, None, Some((1, 1)), &[]), first);
+        assert_eq!(place(curve, Some(1), None, &[]), 1);
+
+        let whole = SpawnPolicy::LocalityByCurve { block_bits: 64 };
+        assert_eq!(place(whole, None, Some((0, 0)), &[]), place(whole, None, Some((-5, 70)), &[]));
+    }
+
+    #[test]
+    fn curve_placement_balances_block_aligned_patches() {
+        // A 16 x 16 layout of blocks of 16 x 16 zones. Every block start has
+        // eight trailing zero bits on the curve, so short segments would all
+        // land on one worker if they were dealt out cyclically.
+        for block_bits in [2, 8] {
+            let curve = SpawnPolicy::LocalityByCurve { block_bits };
+            let pool = ThreadPool::with_affinity(4, curve, Affinity::Unpinned);
+
+            for i in 0..16 {
+                for j in 0..16 {
+                    pool.spawn_placed(None, Some((i * 16, j * 16)), || {});
+                }
+            }
+            let jobs_per_worker = pool.placement().jobs_per_worker;
+            assert!(jobs_per_worker.iter().all(|&n| (48..=80).contains(&n)), "{:?}", jobs_per_worker);
+        }
+    }
+
+    #[test]
+    fn thread_pool_counts_placement() {
+        let pool = ThreadPool::with_policy(1, SpawnPolicy::RoundRobin);
+        let (sink, source) = crossbeam_channel::unbounded();
+
+        for n in 0..4 {
+            let sink = sink.clone();
+            pool.spawn_on(Some(n % 2), move || sink.send(n).unwrap());
+        }
+        drop(sink);
+        assert_eq!(source.iter().count(), 4);
+
+        let placement = pool.placement();
+        assert_eq!(placement.jobs_per_worker, [4]);
+        assert_eq!(placement.hints_followed, 2);
+        assert_eq!(placement.hints_ignored, 2);
+    }
+
+    #[test]
+    fn messaging_jobs_run_on_comm_workers() {
+        let pool = ThreadPool::new(1).with_comm_threads(2);
+        let (sink, source) = crossbeam_channel::unbounded();
+
+        for _ in 0..4 {
+            let sink = sink.clone();
+            pool.spawn_comm(move || sink.send(current_worker_id()).unwrap());
+        }
+        let sink_compute = sink.clone();
+        pool.spawn(move || sink_compute.send(current_worker_id()).unwrap());
+        drop(sink);
+
+        let mut ids: Vec<_> = source.iter().map(Option::unwrap).collect();
+        ids.sort_unstable();
+        assert_eq!(pool.num_comm_threads(), 2);
+        assert_eq!(ids, [0, 1, 1, 2, 2]);
+        assert_eq!(pool.placement().jobs_per_worker, [1]);
+    }
+
+    #[test]
+    fn unpinned_pools_have_the_requested_number_of_threads() {
+        let pool = ThreadPool::unpinned(3).with_comm_threads(1);
+        let (sink, source) = crossbeam_channel::unbounded();
+
+        for n in 0..3 {
+            let sink = sink.clone();
+            pool.spawn_on(Some(n), move || sink.send(current_worker_id()).unwrap());
+        }
+        drop(sink);
+        assert_eq!(source.iter().count(), 3);
+        assert_eq!(pool.worker_cores(), [None, None, None]);
+    }
+
+    #[test]
+    fn affinity_is_parsed_from_core_lists() {
+        assert_eq!("detect".parse::<Affinity>().unwrap(), Affinity::Detect);
+        assert_eq!("none".parse::<Affinity>().unwrap(), Affinity::Unpinned);
+        assert_eq!("0, 2,4".parse::<Affinity>().unwrap(), Affinity::Cores(vec![0, 2, 4]));
+        assert_eq!(Affinity::Cores(vec![1, 3]).to_string(), "1,3");
+        assert!("0,x".parse::<Affinity>().is_err());
+        assert!("".parse::<Affinity>().is_err());
+
+        let pool = ThreadPool::with_affinity(4, SpawnPolicy::default(), Affinity::Cores(vec![0]));
+        assert_eq!(pool.worker_cores(), [Some(0)]);
+    }
+
+    #[test]
+    fn hints_beyond_a_short_core_list_wrap_around() {
+        let pool = ThreadPool::with_affinity(4, SpawnPolicy::default(), Affinity::Cores(vec![0, 1]));
+        let (sink, source) = crossbeam_channel::unbounded();
+
+        for n in 0..4 {
+            let sink = sink.clone();
+            pool.spawn_on(Some(n), move || sink.send(current_worker_id()).unwrap());
+        }
+        drop(sink);
+        assert_eq!(source.iter().count(), 4);
+        assert_eq!(pool.placement().jobs_per_worker, [2, 2]);
+    }
+}
@@ -0,0 +1,260 @@
+//! Per-task timing spans, exported in the Chrome trace-event format. The
+//! resulting JSON file can be loaded in `chrome://tracing` (or Perfetto) to
+//! inspect the schedule of a whole frame: which worker ran each task, and
+//! when.
+//!
+//! To trace a group of tasks, wrap them with [`with_tracer`] before passing
+//! them to any of the executors in [`crate::automaton`]. Each task's
+//! evaluation is recorded as a span named by the task key (for patch-based
+//! tasks, the patch rectangle), with the thread id set to the thread pool
+//! worker or Rayon thread that ran it.
+
+use crate::automaton::{Automaton, Status};
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A completed span.
+///
+#[derive(Clone, Debug)]
+pub struct Span {
+    /// The span name, e.g. the key of the task.
+    pub name: String,
+
+    /// The start time in microseconds, relative to the creation of the
+    /// tracer.
+    pub ts: f64,
+
+    /// The duration in microseconds.
+    pub dur: f64,
+
+    /// The index of the worker thread that ran the span.
+    pub tid: usize,
+}
+
+/// A thread-safe collector of spans.
+///
+pub struct Tracer {
+    start: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a span which began and ended at the given instants.
+    ///
+    pub fn record(&self, name: String, tid: usize, begin: Instant, end: Instant) {
+        let span = Span {
+            name,
+            ts: micros(begin.saturating_duration_since(self.start)),
+            dur: micros(end.saturating_duration_since(begin)),
+            tid,
+        };
+        self.spans.lock().unwrap().push(span)
+    }
+
+    /// Run a closure and record it as a span, on the current worker thread.
+    ///
+    pub fn span<F, T>(&self, name: String, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let begin = Instant::now();
+        let result = f();
+        self.record(name, current_thread_id(), begin, Instant::now());
+        result
+    }
+
+    /// Return a copy of the spans recorded so far.
+    ///
+    pub fn spans(&self) -> Vec<Span> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// Remove all of the recorded spans, e.g. at the start of a new frame.
+    ///
+    pub fn clear(&self) {
+        self.spans.lock().unwrap().clear()
+    }
+
+    /// Write the recorded spans as Chrome trace-event JSON, using complete
+    /// (`"ph": "X"`) events.
+    ///
+    pub fn write_chrome_trace<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let spans = self.spans.lock().unwrap();
+        writeln!(writer, "{{\"traceEvents\":[")?;
+
+        for (n, span) in spans.iter().enumerate() {
+            let separator = if n + 1 < spans.len() { "," } else { "" };
+            writeln!(
+                writer,
+                "{{\"name\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":0,\"tid\":{}}}{}",
+                escape(&span.name),
+                span.ts,
+                span.dur,
+                span.tid,
+                separator
+            )?;
+        }
+        writeln!(writer, "]}}")
+    }
+}
+
+/// An adapter which records the evaluation of a task as a span. Created by
+/// [`with_tracer`].
+///
+pub struct Traced<A> {
+    automaton: A,
+    tracer: Arc<Tracer>,
+}
+
+impl<A> Automaton for Traced<A>
+where
+    A: Automaton,
+    A::Key: Debug,
+{
+    type Key = A::Key;
+    type Message = A::Message;
+    type Value = A::Value;
+
+    fn key(&self) -> Self::Key {
+        self.automaton.key()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.automaton.messages()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        self.automaton.receive(message)
+    }
+
+    fn value(self) -> Self::Value {
+        let name = format!("{:?}", self.automaton.key());
+        let automaton = self.automaton;
+        self.tracer.span(name, move || automaton.value())
+    }
+
+    fn speculate(&mut self) {
+        self.automaton.speculate()
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        self.automaton.worker_hint()
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        self.automaton.locality()
+    }
+
+    fn priority(&self) -> Option<usize> {
+        self.automaton.priority()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        self.automaton.cost()
+    }
+
+    fn message_size(message: &Self::Message) -> usize {
+        A::message_size(message)
+    }
+}
+
+/// Wrap each task in a group so that its evaluation is recorded by the given
+/// tracer.
+///
+pub fn with_tracer<I, A>(flow: I, tracer: &Arc<Tracer>) -> impl Iterator<Item = Traced<A>>
+where
+    I: IntoIterator<Item = A>,
+{
+    let tracer = tracer.clone();
+    flow.into_iter().map(move |automaton| Traced {
+        automaton,
+        tracer: tracer.clone(),
+    })
+}
+
+/// Return an index for the current thread: the thread pool worker index, or
+/// else the Rayon worker index, or else zero.
+///
+fn current_thread_id() -> usize {
+    crate::thread_pool::current_worker_id()
+        .or_else(rayon::current_thread_index)
+        .unwrap_or(0)
+}
+
+fn micros(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
+
+fn escape(name: &str) -> String {
+    name.chars()
+        .flat_map(|c| match c {
+            '"' => vec!['\\', '"'],
+            '\\' => vec!['\\', '\\'],
+            c if c.is_control() => vec![' '],
+            c => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{with_tracer, Tracer};
+    use crate::automaton::execute;
+    use crate::compute::{into_automata, Compute};
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Sum(usize);
+
+    impl Compute for Sum {
+        type Key = usize;
+        type Value = usize;
+
+        fn key(&self) -> Self::Key {
+            self.0
+        }
+
+        fn peer_keys(&self) -> Vec<Self::Key> {
+            vec![(self.0 + 1) % 4]
+        }
+
+        fn run(&self, peers: Vec<Self>) -> Self::Value {
+            self.0 + peers[0].0
+        }
+    }
+
+    #[test]
+    fn tasks_are_traced_in_chrome_format() {
+        let tracer = Arc::new(Tracer::new());
+        let tasks = into_automata((0..4).map(Sum).collect());
+        let values: Vec<_> = execute(with_tracer(tasks, &tracer)).collect();
+        assert_eq!(values.len(), 4);
+
+        let spans = tracer.spans();
+        assert_eq!(spans.len(), 4);
+        assert!(spans.iter().all(|s| s.tid == 0 && s.dur >= 0.0));
+
+        let mut json = Vec::new();
+        tracer.write_chrome_trace(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"traceEvents\":["));
+        assert_eq!(json.matches("\"ph\":\"X\"").count(), 4);
+        assert!(json.contains("\"name\":\"2\""));
+    }
+}
@@ -0,0 +1,57 @@
+//! Small utilities for writing initial conditions.
+
+/// Return a pseudo-random number in `[0, 1)` which depends only on the
+/// given index and seed. Since the value is a hash of the index (rather than
+/// the next draw from a random number generator), every domain
+/// decomposition produces the same field, so it can be used in the
+/// initial-condition closures passed to e.g.
+/// [`crate::patch::Patch::from_vector_function`] to seed instabilities
+/// reproducibly across ranks. The index should be measured on a fixed
+/// level, usually the high-resolution one.
+///
+pub fn hash_noise(index: (i64, i64), seed: u64) -> f64 {
+    let h = mix(mix(seed ^ index.0 as u64) ^ index.1 as u64);
+    (h >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// Like [`hash_noise`], but in `[-amplitude, amplitude)`, for perturbing a
+/// field about its mean value.
+///
+pub fn hash_perturbation(index: (i64, i64), seed: u64, amplitude: f64) -> f64 {
+    amplitude * (2.0 * hash_noise(index, seed) - 1.0)
+}
+
+/// The SplitMix64 finalizer: a bijective mixing function with good
+/// avalanche behavior.
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{hash_noise, hash_perturbation};
+    use crate::index_space::range2d;
+    use crate::patch::Patch;
+
+    #[test]
+    fn hash_noise_is_independent_of_decomposition() {
+        let whole = Patch::from_scalar_function(0, (0..8, 0..8), |index| hash_noise(index, 7));
+        let part = Patch::from_scalar_function(0, (4..8, 2..6), |index| hash_noise(index, 7));
+        assert_eq!(whole.extract((4..8, 2..6)).data(), part.data());
+        assert_ne!(hash_noise((1, 2), 7), hash_noise((2, 1), 7));
+        assert_ne!(hash_noise((1, 2), 7), hash_noise((1, 2), 8));
+    }
+
+    #[test]
+    fn hash_noise_is_uniform() {
+        let values: Vec<_> = range2d(-50..50, -50..50).iter().map(|index| hash_noise(index, 1)).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!(values.iter().all(|x| (0.0..1.0).contains(x)));
+        assert!((mean - 0.5).abs() < 0.01);
+        assert!((-0.1..0.1).contains(&hash_perturbation((3, 4), 1, 0.1)));
+    }
+}
@@ -0,0 +1,415 @@
+//! Windowed execution of oversized patch updates. When a patch is too large
+//! for one task, its update can be divided into sub-tasks without re-tiling
+//! the patch: the interior of the patch is covered by disjoint windows,
+//! which read the patch data through a shared, read-only backing, and the
+//! remaining rim (the zones which depend on guard zones from neighboring
+//! patches) is updated by the original task once its messages have
+//! arrived. The interior windows do not wait for any messages, so they
+//! overlap with communication. Whichever sub-task finishes last assembles
+//! the patch's value from the rim and the windows.
+//!
+//! Wrap a group of [`Windowed`] tasks with [`with_windows`] to run them on
+//! any of the executors in the [`automaton`](crate::automaton) module, and
+//! pass the executor's output through [`join_windows`] to obtain one value
+//! per patch.
+
+use crate::automaton::{Automaton, Status};
+use crate::index_space::IndexSpace;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A patch update task which can be divided into interior windows and a
+/// rim.
+///
+pub trait Windowed: Automaton {
+    /// The read-only data shared by the windows, e.g. the patch at the
+    /// start of the step. Every window releases the backing before the
+    /// value is assembled, so data shared between the backing and the task
+    /// (e.g. through an `Arc`) can be updated in place by
+    /// [`Windowed::assemble`] without being copied.
+    type Backing;
+
+    /// The output of one window, e.g. a patch of updated zones covering the
+    /// window.
+    type Piece;
+
+    /// Return the shared backing data, and the windows into which the
+    /// interior of the patch is divided (see [`interior_windows`]). The
+    /// windows must be disjoint, and the update of each window must depend
+    /// only on the backing data, not on incoming messages. This is called
+    /// once, before any messages are delivered.
+    fn split(&mut self) -> (Arc<Self::Backing>, Vec<IndexSpace>);
+
+    /// Update one window.
+    fn update_window(backing: &Self::Backing, window: &IndexSpace) -> Self::Piece;
+
+    /// Update the zones outside the windows. This is called once the task
+    /// has received all of its messages, possibly while windows are still
+    /// being updated.
+    fn update_rim(&mut self);
+
+    /// Combine the updated rim with the windows' pieces, which are given in
+    /// the order the windows were returned from [`Windowed::split`], and
+    /// return the task's value.
+    fn assemble(self, pieces: Vec<Self::Piece>) -> Self::Value;
+}
+
+/// Divide an index space into windows of the given shape, leaving out a rim
+/// of the given width on each side. Windows on the upper edges are truncated
+/// if the window shape does not divide the interior.
+///
+pub fn interior_windows(space: &IndexSpace, rim_width: i64, window_shape: (usize, usize)) -> Vec<IndexSpace> {
+    let interior = space.trim_all(rim_width);
+
+    if interior.is_empty() {
+        Vec::new()
+    } else {
+        interior.tiles(window_shape).collect()
+    }
+}
+
+/// The key of a sub-task of a windowed task: either the rim, which is the
+/// original task and receives its messages, or one of its windows.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SubTaskKey<K> {
+    Rim(K),
+    Window(K, usize),
+}
+
+/// The messages exchanged between sub-tasks: a message from another patch,
+/// which goes to the rim, or the start signal sent by the rim to each of
+/// its windows.
+///
+pub enum SubTaskMessage<M> {
+    Peer(M),
+    Start,
+}
+
+/// The state shared by the sub-tasks of one patch, which counts them down
+/// and holds their results until the last one finishes.
+///
+struct Join<A: Windowed> {
+    remaining: AtomicUsize,
+    rim: Mutex<Option<A>>,
+    pieces: Mutex<Vec<Option<A::Piece>>>,
+}
+
+impl<A: Windowed> Join<A> {
+    /// Count down a finished sub-task, and if it was the last, assemble the
+    /// patch's value.
+    fn finish(&self) -> Option<A::Value> {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return None;
+        }
+        let rim = self.rim.lock().unwrap().take().expect("the rim was not updated");
+        let pieces = self.pieces.lock().unwrap().drain(..).map(Option::unwrap).collect();
+        Some(rim.assemble(pieces))
+    }
+}
+
+/// An adapter which runs either the rim or one window of a [`Windowed`]
+/// task. Created by [`with_windows`]. Its value is `Some` for the sub-task
+/// which assembled the patch's value, and `None` for the others.
+///
+pub struct SubTask<A: Windowed>(Part<A>);
+
+enum Part<A: Windowed> {
+    Rim {
+        automaton: A,
+        num_windows: usize,
+        join: Arc<Join<A>>,
+    },
+    Window {
+        key: A::Key,
+        index: usize,
+        window: IndexSpace,
+        backing: Arc<A::Backing>,
+        join: Arc<Join<A>>,
+    },
+}
+
+impl<A> Automaton for SubTask<A>
+where
+    A: Windowed,
+    A::Key: Clone,
+{
+    type Key = SubTaskKey<A::Key>;
+    type Message = SubTaskMessage<A::Message>;
+    type Value = Option<A::Value>;
+
+    fn key(&self) -> Self::Key {
+        match &self.0 {
+            Part::Rim { automaton, .. } => SubTaskKey::Rim(automaton.key()),
+            Part::Window { key, index, .. } => SubTaskKey::Window(key.clone(), *index),
+        }
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        match &self.0 {
+            Part::Rim { automaton, num_windows, .. } => {
+                let key = automaton.key();
+                let starts = (0..*num_windows).map(|n| (SubTaskKey::Window(key.clone(), n), SubTaskMessage::Start));
+                automaton
+                    .messages()
+                    .into_iter()
+                    .map(|(dest, message)| (SubTaskKey::Rim(dest), SubTaskMessage::Peer(message)))
+                    .chain(starts)
+                    .collect()
+            }
+            Part::Window { .. } => Vec::new(),
+        }
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        match (&mut self.0, message) {
+            (Part::Rim { automaton, .. }, SubTaskMessage::Peer(message)) => automaton.receive(message),
+            (Part::Window { .. }, SubTaskMessage::Start) => Status::Eligible,
+            _ => panic!("a windowed sub-task received a message meant for another kind of sub-task"),
+        }
+    }
+
+    fn value(self) -> Self::Value {
+        match self.0 {
+            Part::Rim { mut automaton, join, .. } => {
+                automaton.update_rim();
+                *join.rim.lock().unwrap() = Some(automaton);
+                join.finish()
+            }
+            Part::Window {
+                index, window, backing, join, ..
+            } => {
+                let piece = A::update_window(&backing, &window);
+                drop(backing);
+                join.pieces.lock().unwrap()[index] = Some(piece);
+                join.finish()
+            }
+        }
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        match &self.0 {
+            Part::Rim { automaton, .. } => automaton.worker_hint(),
+            Part::Window { .. } => None,
+        }
+    }
+
+    fn locality(&self) -> Option<(i64, i64)> {
+        match &self.0 {
+            Part::Rim { automaton, .. } => automaton.locality(),
+            Part::Window { window, .. } => Some(window.start()),
+        }
+    }
+
+    fn priority(&self) -> Option<usize> {
+        match &self.0 {
+            Part::Rim { automaton, .. } => automaton.priority(),
+            Part::Window { .. } => None,
+        }
+    }
+
+    fn cost(&self) -> Option<f64> {
+        match &self.0 {
+            Part::Rim { automaton, .. } => automaton.cost(),
+            Part::Window { window, .. } => Some(window.len() as f64),
+        }
+    }
+
+    fn message_size(message: &Self::Message) -> usize {
+        match message {
+            SubTaskMessage::Peer(message) => A::message_size(message),
+            SubTaskMessage::Start => core::mem::size_of_val(message),
+        }
+    }
+}
+
+/// Divide each task in a group into its rim and interior windows (see
+/// [`Windowed::split`]). The returned sub-tasks can be passed to any
+/// executor; messages between the original tasks are delivered to their
+/// rims, and the windows are eligible as soon as their rim is admitted.
+///
+pub fn with_windows<I, A>(flow: I) -> impl Iterator<Item = SubTask<A>>
+where
+    I: IntoIterator<Item = A>,
+    A: Windowed,
+    A::Key: Clone,
+{
+    flow.into_iter().flat_map(|mut automaton| {
+        let (backing, windows) = automaton.split();
+        let key = automaton.key();
+        let join = Arc::new(Join {
+            remaining: AtomicUsize::new(windows.len() + 1),
+            rim: Mutex::new(None),
+            pieces: Mutex::new(windows.iter().map(|_| None).collect()),
+        });
+        let rim = SubTask(Part::Rim {
+            automaton,
+            num_windows: windows.len(),
+            join: join.clone(),
+        });
+        // The windows are collected, so that this iterator doesn't hold on
+        // to the backing after they're handed out.
+        let windows: Vec<_> = windows
+            .into_iter()
+            .enumerate()
+            .map(|(index, window)| {
+                SubTask(Part::Window {
+                    key: key.clone(),
+                    index,
+                    window,
+                    backing: backing.clone(),
+                    join: join.clone(),
+                })
+            })
+            .collect();
+        std::iter::once(rim).chain(windows)
+    })
+}
+
+/// Collect the values of the patches from the output of an executor running
+/// windowed sub-tasks.
+///
+pub fn join_windows<I, V>(values: I) -> impl Iterator<Item = V>
+where
+    I: IntoIterator<Item = Option<V>>,
+{
+    values.into_iter().flatten()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{interior_windows, join_windows, with_windows, Windowed};
+    use crate::automaton::{execute, execute_par, Automaton, Status};
+    use crate::index_space::{range2d, IndexSpace};
+    use crate::patch::Patch;
+    use std::sync::Arc;
+
+    /// A task which replaces each zone of its patch with the sum of its four
+    /// neighbors. Zones outside the patch have the value sent by the task to
+    /// itself.
+    struct Smooth {
+        key: usize,
+        patch: Patch,
+        guard: Option<f64>,
+        rim: Option<Patch>,
+    }
+
+    impl Smooth {
+        fn new(key: usize, size: i64) -> Self {
+            let patch = Patch::from_scalar_function(0, range2d(0..size, 0..size), |(i, j)| (i * 7 + j * j) as f64);
+            Self {
+                key,
+                patch,
+                guard: None,
+                rim: None,
+            }
+        }
+
+        fn smooth(patch: &Patch, guard: f64, index: (i64, i64)) -> f64 {
+            let space = patch.index_space();
+            let (i, j) = index;
+            [(i - 1, j), (i + 1, j), (i, j - 1), (i, j + 1)]
+                .iter()
+                .map(|&n| if space.contains(n) { patch.get_slice(n)[0] } else { guard })
+                .sum()
+        }
+    }
+
+    impl Automaton for Smooth {
+        type Key = usize;
+        type Message = f64;
+        type Value = Patch;
+
+        fn key(&self) -> Self::Key {
+            self.key
+        }
+
+        fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+            vec![(self.key, self.key as f64)]
+        }
+
+        fn receive(&mut self, guard: Self::Message) -> Status {
+            self.guard = Some(guard);
+            Status::Eligible
+        }
+
+        fn value(self) -> Self::Value {
+            let guard = self.guard.unwrap();
+            Patch::from_scalar_function(0, self.patch.index_space(), |index| Self::smooth(&self.patch, guard, index))
+        }
+    }
+
+    impl Windowed for Smooth {
+        type Backing = Patch;
+        type Piece = Patch;
+
+        fn split(&mut self) -> (Arc<Patch>, Vec<IndexSpace>) {
+            let windows = interior_windows(&self.patch.index_space(), 1, (4, 3));
+            (Arc::new(self.patch.clone()), windows)
+        }
+
+        fn update_window(backing: &Patch, window: &IndexSpace) -> Patch {
+            Patch::from_scalar_function(0, window.clone(), |index| Smooth::smooth(backing, f64::NAN, index))
+        }
+
+        fn update_rim(&mut self) {
+            let guard = self.guard.unwrap();
+            let interior = self.patch.index_space().trim_all(1);
+            let patch = &self.patch;
+            self.rim = Some(Patch::from_scalar_function(0, patch.index_space(), |index| {
+                if interior.contains(index) {
+                    f64::NAN
+                } else {
+                    Self::smooth(patch, guard, index)
+                }
+            }));
+        }
+
+        fn assemble(self, pieces: Vec<Patch>) -> Patch {
+            let mut result = self.rim.unwrap();
+
+            for piece in pieces {
+                for index in piece.index_space().iter() {
+                    result.get_slice_mut(index).copy_from_slice(piece.get_slice(index))
+                }
+            }
+            result
+        }
+    }
+
+    fn tasks() -> Vec<Smooth> {
+        (0..3).map(|key| Smooth::new(key, 10 + key as i64)).collect()
+    }
+
+    fn sorted(mut patches: Vec<Patch>) -> Vec<Vec<f64>> {
+        patches.sort_by_key(|p| p.index_space().len());
+        patches.iter().map(|p| p.data().clone()).collect()
+    }
+
+    #[test]
+    fn interior_windows_cover_the_interior() {
+        let windows = interior_windows(&IndexSpace::new(0..10, 0..10), 1, (4, 3));
+        assert_eq!(windows.len(), 6);
+        assert_eq!(windows.iter().map(IndexSpace::len).sum::<usize>(), 64);
+        assert!(interior_windows(&IndexSpace::new(0..2, 0..10), 1, (4, 3)).is_empty());
+    }
+
+    #[test]
+    fn windowed_updates_match_whole_patch_updates() {
+        let expected = sorted(execute(tasks()).collect());
+        let windowed = sorted(join_windows(execute(with_windows(tasks()))).collect());
+        assert_eq!(windowed.len(), 3);
+        assert_eq!(windowed, expected);
+    }
+
+    #[test]
+    fn windowed_updates_run_in_parallel() {
+        let expected = sorted(execute(tasks()).collect());
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let windowed = pool.install(|| {
+            rayon::scope_fifo(|scope| sorted(join_windows(execute_par(scope, with_windows(tasks()))).collect()))
+        });
+        assert_eq!(windowed, expected);
+    }
+}